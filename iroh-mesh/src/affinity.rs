@@ -0,0 +1,228 @@
+//! Session affinity: consistently routing a client to the same destination cluster across
+//! calls to [`crate::proxy::MeshProxy::dial_service_for`].
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    net::IpAddr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::ClusterInfo;
+
+/// How a [`crate::proxy::MeshProxy`] picks among a service's candidate destinations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionAffinity {
+    /// Consistently pick among the candidates by the client's source IP, so a given client keeps
+    /// hitting the same destination as long as it remains a candidate.
+    ClientIp,
+}
+
+/// Picks a candidate for `client_ip` using rendezvous (highest random weight) hashing: each
+/// candidate is scored by hashing it together with `client_ip`, and the highest-scoring one wins.
+///
+/// Unlike picking by `hash(client_ip) % candidates.len()`, a candidate's score doesn't depend on
+/// which other candidates are present, so adding or removing one candidate only remaps the
+/// clients whose winning score involved it -- every other client's pick is unaffected.
+pub fn pick(client_ip: IpAddr, candidates: &[ClusterInfo]) -> Option<&ClusterInfo> {
+    candidates
+        .iter()
+        .max_by_key(|info| score(client_ip, &info.cluster_id))
+}
+
+fn score(client_ip: IpAddr, cluster_id: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    client_ip.hash(&mut hasher);
+    cluster_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A cluster's share of traffic for a service under weighted load balancing (see
+/// [`crate::config::ProxyConfig::service_weights`] and [`pick_weighted`]).
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct WeightedTarget {
+    /// The cluster this weight applies to.
+    pub cluster: String,
+    /// This cluster's relative share of picks among the other weighted targets for the same
+    /// service. A weight of zero drains the target -- it's still a known candidate, just never
+    /// selected -- without having to remove it from the route.
+    pub weight: u32,
+}
+
+/// Picks among `candidates` with probability proportional to each one's weight in `weights`,
+/// for gradual canary/blue-green rollouts across clusters hosting the same service.
+///
+/// A candidate absent from `weights`, or listed with a weight of zero, is never picked. Returns
+/// `None` if no candidate has a positive weight. `rng` is taken by the caller so picks are
+/// reproducible under a seeded RNG in tests, while production callers pass [`rand::rng`].
+pub fn pick_weighted<'a>(
+    rng: &mut impl rand::Rng,
+    candidates: &'a [ClusterInfo],
+    weights: &[WeightedTarget],
+) -> Option<&'a ClusterInfo> {
+    let total_weight: u32 = candidates
+        .iter()
+        .filter_map(|candidate| weight_of(candidate, weights))
+        .sum();
+    if total_weight == 0 {
+        return None;
+    }
+    let mut remaining = rng.random_range(0..total_weight);
+    for candidate in candidates {
+        let Some(weight) = weight_of(candidate, weights) else {
+            continue;
+        };
+        if remaining < weight {
+            return Some(candidate);
+        }
+        remaining -= weight;
+    }
+    None
+}
+
+fn weight_of(candidate: &ClusterInfo, weights: &[WeightedTarget]) -> Option<u32> {
+    weights
+        .iter()
+        .find(|target| target.cluster == candidate.cluster_id)
+        .map(|target| target.weight)
+        .filter(|&weight| weight > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::Ipv4Addr};
+
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn candidates(cluster_ids: &[&str]) -> Vec<ClusterInfo> {
+        cluster_ids
+            .iter()
+            .map(|id| ClusterInfo {
+                cluster_id: id.to_string(),
+                endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+                relay_url: None,
+                direct_addresses: Vec::new(),
+                services: vec!["svc".to_string()],
+                service_ports: Vec::new(),
+                updated_at: std::time::SystemTime::now(),
+                pinned: false,
+            })
+            .collect()
+    }
+
+    fn client_ips(count: u32) -> Vec<IpAddr> {
+        (0..count)
+            .map(|i| IpAddr::V4(Ipv4Addr::from(i.to_be_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn the_same_client_ip_maps_to_the_same_candidate_across_many_lookups() {
+        let candidates = candidates(&["cluster-a", "cluster-b", "cluster-c"]);
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 42));
+
+        let first_pick = pick(ip, &candidates).unwrap().cluster_id.clone();
+        for _ in 0..100 {
+            assert_eq!(pick(ip, &candidates).unwrap().cluster_id, first_pick);
+        }
+    }
+
+    #[test]
+    fn removing_a_candidate_only_remaps_the_clients_that_were_assigned_to_it() {
+        let before = candidates(&["cluster-a", "cluster-b", "cluster-c"]);
+        let after = candidates(&["cluster-a", "cluster-b"]);
+        let ips = client_ips(500);
+
+        let assignments_before: Vec<String> = ips
+            .iter()
+            .map(|ip| pick(*ip, &before).unwrap().cluster_id.clone())
+            .collect();
+
+        for (ip, previous) in ips.iter().zip(&assignments_before) {
+            let now = pick(*ip, &after).unwrap().cluster_id.clone();
+            if previous == "cluster-c" {
+                assert_ne!(
+                    now, "cluster-c",
+                    "the removed candidate can't be picked anymore"
+                );
+            } else {
+                assert_eq!(
+                    &now, previous,
+                    "a client not assigned to the removed candidate should keep its pick"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pick_weighted_returns_none_without_any_positively_weighted_candidate() {
+        let candidates = candidates(&["cluster-a", "cluster-b"]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        assert!(pick_weighted(&mut rng, &candidates, &[]).is_none());
+        assert!(
+            pick_weighted(
+                &mut rng,
+                &candidates,
+                &[
+                    WeightedTarget {
+                        cluster: "cluster-a".to_string(),
+                        weight: 0,
+                    },
+                    WeightedTarget {
+                        cluster: "cluster-b".to_string(),
+                        weight: 0,
+                    },
+                ],
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn pick_weighted_distribution_approximates_the_configured_weights() {
+        let candidates = candidates(&["stable", "canary", "drained"]);
+        let weights = vec![
+            WeightedTarget {
+                cluster: "stable".to_string(),
+                weight: 90,
+            },
+            WeightedTarget {
+                cluster: "canary".to_string(),
+                weight: 10,
+            },
+            WeightedTarget {
+                cluster: "drained".to_string(),
+                weight: 0,
+            },
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        const SAMPLES: u32 = 100_000;
+        for _ in 0..SAMPLES {
+            let picked = pick_weighted(&mut rng, &candidates, &weights).unwrap();
+            *counts.entry(picked.cluster_id.clone()).or_default() += 1;
+        }
+
+        assert_eq!(
+            counts.get("drained"),
+            None,
+            "a zero-weight target should never be picked"
+        );
+        let stable_share = f64::from(counts["stable"]) / f64::from(SAMPLES);
+        let canary_share = f64::from(counts["canary"]) / f64::from(SAMPLES);
+        assert!(
+            (stable_share - 0.9).abs() < 0.01,
+            "stable's 90% weight should be reflected within 1%, got {stable_share}"
+        );
+        assert!(
+            (canary_share - 0.1).abs() < 0.01,
+            "canary's 10% weight should be reflected within 1%, got {canary_share}"
+        );
+    }
+}
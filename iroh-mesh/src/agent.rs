@@ -0,0 +1,851 @@
+//! The mesh agent: runs alongside a cluster, tracking which other clusters are known to it and
+//! exposing that over an HTTP API.
+
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use hyper::{Request, body::Incoming};
+use iroh::{Endpoint, EndpointId};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, ensure};
+#[cfg(any(test, feature = "test-util"))]
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api,
+    config::TlsConfig,
+    discovery::{DiscoveryConfig, DiscoveryManager},
+    election::{LeaderElection, LeaderElectionHandle, LeaseStore},
+    endpoint_discovery::{self, EndpointDiscoveryConfig},
+    error::{BindEndpointSnafu, InvalidConfigSnafu, Result},
+    health::{HealthChecker, HealthCheckerHandle},
+    proxy,
+    registration::{self, SelfClusterInfo, SelfRegistrationHandle},
+    relay::{self, RelayModeConfig},
+    secret_key::{self, SecretKeySource},
+    standalone::{self, StandaloneFile, StandaloneReloadConfig, StandaloneReloadHandle},
+    versioninfo::VersionInfo,
+};
+
+/// How long [`AdminState::drain`] waits for in-flight API requests to finish before giving up.
+const ADMIN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configuration for a [`MeshAgent`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AgentConfig {
+    /// Address the agent's HTTP API listens on.
+    pub api_addr: SocketAddr,
+    /// Active health checking of known clusters. Disabled (no probing) when absent.
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
+    /// Whether `api_addr` should be bound dual-stack, accepting IPv4 clients (as IPv4-mapped
+    /// addresses) on an IPv6 listener. Only meaningful when `api_addr` is IPv6; rejected at bind
+    /// time otherwise (see [`crate::socket::bind_listener`]).
+    #[serde(default)]
+    pub dual_stack: bool,
+    /// Identifies the logical mesh this agent's health checker probes peers as part of, folded
+    /// into the ALPN it dials with (see [`crate::proxy::mesh_alpn`]). Must match the `mesh_name`
+    /// of the proxies it probes. `None` uses [`crate::proxy::MESH_ALPN`] as-is.
+    #[serde(default)]
+    pub mesh_name: Option<String>,
+    /// Where to load this agent's iroh secret key from, for the endpoint its health checker
+    /// dials other clusters with (see [`Self::discovery`]). A freshly generated, unpersisted key
+    /// is used when absent.
+    #[serde(default)]
+    pub secret_key: Option<SecretKeySource>,
+    /// Bearer token required on the `/admin/drain` and `/admin/reload` requests handled by
+    /// [`api::serve`]. Leaving this unset disables both endpoints (they 404, like any other
+    /// unknown route) rather than leaving them reachable without a credential.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Which of iroh's own endpoint-discovery mechanisms this agent's health-checker endpoint
+    /// publishes to and resolves through (see [`crate::endpoint_discovery`]). Only bound when
+    /// [`Self::discovery`] is set, since an agent that never dials peers never binds an
+    /// endpoint. Defaults to DNS discovery enabled, matching this crate's behavior before this
+    /// setting existed.
+    #[serde(default)]
+    pub endpoint_discovery: EndpointDiscoveryConfig,
+    /// Which relay servers this agent's health-checker endpoint falls back to when it can't
+    /// reach a peer directly (see [`crate::relay`]). Only bound when [`Self::discovery`] is set,
+    /// same as [`Self::endpoint_discovery`]. Defaults to iroh's own production relays, matching
+    /// this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub relay: RelayModeConfig,
+    /// Requires every request to the agent's HTTP API, other than `GET /healthz`, to present a
+    /// matching `Authorization: Bearer` header, compared in constant time. The API has no
+    /// authentication at all when this is unset, matching this crate's behavior before this
+    /// setting existed -- [`Self::admin_token`] still separately gates `/admin/*` regardless.
+    #[serde(default)]
+    pub api_auth: Option<ApiAuth>,
+    /// Serves the HTTP API over HTTPS using this certificate and key, instead of plaintext HTTP.
+    /// Plaintext when absent, matching this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub api_tls: Option<TlsConfig>,
+    /// Periodically re-reads a [`crate::standalone::StandaloneFile`] of peer clusters into
+    /// discovery (see [`standalone::spawn_reload`]). Loaded once at startup and never again when
+    /// absent, matching this crate's behavior before this setting existed -- a deployment that
+    /// wants to pick up edits without this must still call [`crate::standalone::load_peers`]
+    /// itself on whatever other trigger fits.
+    #[serde(default)]
+    pub standalone_reload: Option<StandaloneReloadConfig>,
+    /// Registers this agent's own cluster with the mesh (see [`crate::registration`]), keeping
+    /// its relay url and direct addresses up to date as this agent's endpoint address changes
+    /// instead of registering them once at startup and going stale after a NAT rebind or relay
+    /// switch. Only takes effect when [`Self::discovery`] is also set, since that's what binds
+    /// this agent's iroh endpoint; set without it, this is silently ignored, same as
+    /// [`Self::endpoint_discovery`] and [`Self::relay`]. Absent means this agent never registers
+    /// a cluster of its own, e.g. a pure discovery/HTTP-API deployment with no local services to
+    /// advertise.
+    #[serde(default)]
+    pub self_registration: Option<SelfRegistrationConfig>,
+    /// Address to serve Prometheus/OpenMetrics text on, if any (see [`crate::metrics::Metrics`]
+    /// and [`crate::discovery::DiscoveryManager::metrics`]). Served in the same process as
+    /// [`Self::api_addr`], but on its own port, matching [`crate::config::ProxyConfig::metrics_addr`].
+    #[serde(default)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// Static parts of [`AgentConfig::self_registration`]; see [`crate::registration::SelfClusterInfo`]
+/// for the type this becomes once combined with the endpoint's live address.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SelfRegistrationConfig {
+    /// This agent's own cluster id, as it should appear to the rest of the mesh.
+    pub cluster_id: String,
+    /// Services this cluster advertises. See [`crate::discovery::ClusterRegistration::services`].
+    #[serde(default)]
+    pub services: Vec<String>,
+}
+
+/// Bearer-token authentication for the agent's HTTP API, see [`AgentConfig::api_auth`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ApiAuth {
+    /// Path to a file containing the bearer token clients must present as `Authorization: Bearer
+    /// <token>`. Loaded once at startup, not watched for changes -- mirrors
+    /// [`SecretKeySource::File`] in keeping the credential out of the config file itself, e.g. so
+    /// it can be mounted from a Kubernetes Secret instead.
+    pub bearer_token_path: PathBuf,
+}
+
+/// Runs the agent's discovery state and HTTP API.
+#[derive(Debug)]
+pub struct MeshAgent {
+    config: AgentConfig,
+    discovery: Arc<DiscoveryManager>,
+    /// Held for the agent's lifetime when running with multiple replicas, gating
+    /// [`crate::health::HealthChecker`]'s writes on leadership (see [`crate::election`]). `None`
+    /// runs unreplicated, i.e. this agent is always its own leader.
+    election: Option<Arc<LeaderElection>>,
+    /// Endpoint [`Self::start_health_checker`] dials peers from, if [`Self::from_endpoint`]
+    /// supplied one instead of leaving it to bind its own. `None` preserves this agent's
+    /// original behavior of binding (or, with no [`AgentConfig::discovery`] configured, never
+    /// binding) its own endpoint lazily.
+    shared_endpoint: Option<Endpoint>,
+    /// Cancelled by [`Self::shutdown`] to stop the health checker's probing loop (see
+    /// [`Self::start_health_checker`]).
+    shutdown: CancellationToken,
+    /// Set by [`Self::spawn_admin`] once [`Self::run`]/[`Self::run_on`] has started, so
+    /// [`Self::refresh_now`] can delegate to the same [`AdminState`] the HTTP API's
+    /// `POST /admin/refresh` calls into. `None` before then.
+    admin: Mutex<Option<Arc<AdminState>>>,
+}
+
+impl MeshAgent {
+    /// Creates a new agent from `config`, starting with an empty set of known clusters.
+    pub fn new(config: AgentConfig) -> Self {
+        Self {
+            config,
+            discovery: Arc::new(DiscoveryManager::new()),
+            election: None,
+            shared_endpoint: None,
+            shutdown: CancellationToken::new(),
+            admin: Mutex::new(None),
+        }
+    }
+
+    /// Like [`Self::new`], but dials health probes from an already-bound `endpoint` and shares
+    /// an already-populated `discovery`, instead of binding its own endpoint lazily and starting
+    /// from an empty set of known clusters.
+    ///
+    /// Meant for [`crate::mesh::MeshBuilder`], which binds one endpoint up front for a
+    /// co-located [`MeshAgent`] and [`crate::proxy::MeshProxy`] to share, so the two agree on
+    /// one iroh identity instead of each binding their own.
+    pub(crate) fn from_endpoint(
+        config: AgentConfig,
+        endpoint: Endpoint,
+        discovery: Arc<DiscoveryManager>,
+    ) -> Self {
+        Self {
+            config,
+            discovery,
+            election: None,
+            shared_endpoint: Some(endpoint),
+            shutdown: CancellationToken::new(),
+            admin: Mutex::new(None),
+        }
+    }
+
+    /// Like [`Self::new`], but holds `store`'s lease under `holder_id` for as long as this agent
+    /// runs, renewing it for `ttl` every `renew_interval`, and gates
+    /// [`crate::health::HealthChecker`]'s writes to discovery on holding it.
+    ///
+    /// Use this when running more than one replica of the agent against the same clusters, so
+    /// only the elected leader's probe results reach discovery; followers keep probing so
+    /// they're caught up and ready to take over the moment they win the lease. Leadership is
+    /// reported on the agent's HTTP API (see [`api::serve`]).
+    pub fn with_leader_election(
+        config: AgentConfig,
+        store: Arc<dyn LeaseStore>,
+        holder_id: impl Into<String>,
+        ttl: Duration,
+        renew_interval: Duration,
+    ) -> Self {
+        Self {
+            config,
+            discovery: Arc::new(DiscoveryManager::new()),
+            election: Some(Arc::new(LeaderElection::new(
+                store,
+                holder_id,
+                ttl,
+                renew_interval,
+            ))),
+            shared_endpoint: None,
+            shutdown: CancellationToken::new(),
+            admin: Mutex::new(None),
+        }
+    }
+
+    /// Returns a handle to this agent's discovery state.
+    pub fn discovery(&self) -> Arc<DiscoveryManager> {
+        self.discovery.clone()
+    }
+
+    /// Whether this agent currently holds leadership, or always `true` when
+    /// [`Self::with_leader_election`] wasn't used, since an unreplicated agent is always its own
+    /// leader.
+    pub fn is_leader(&self) -> bool {
+        self.election
+            .as_ref()
+            .is_none_or(|election| election.is_leader())
+    }
+
+    /// Serves the HTTP API until the process is asked to stop, also probing known clusters'
+    /// health in the background if [`AgentConfig::discovery`] is set.
+    pub async fn run(&self) -> Result<()> {
+        let _election_handle = self.spawn_election();
+        let admin = self.spawn_admin().await?;
+        let api_auth = self.load_api_auth().await?;
+        api::serve(
+            self.config.api_addr,
+            self.config.dual_stack,
+            self.discovery.clone(),
+            self.election.clone(),
+            admin,
+            api_auth,
+            self.config.api_tls.clone(),
+        )
+        .await
+    }
+
+    /// Like [`Self::run`], but reuses an already-bound listener.
+    ///
+    /// Useful for tests that need to know the bound address before the agent starts accepting
+    /// connections.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) async fn run_on(&self, listener: TcpListener) -> Result<()> {
+        let _election_handle = self.spawn_election();
+        let admin = self.spawn_admin().await?;
+        let api_auth = self.load_api_auth().await?;
+        api::serve_on(
+            listener,
+            self.discovery.clone(),
+            self.election.clone(),
+            admin,
+            api_auth,
+            self.config.api_tls.clone(),
+        )
+        .await
+    }
+
+    /// Loads the bearer token [`AgentConfig::api_auth`] names, if any, into an [`ApiAuthState`]
+    /// the HTTP API checks every request against.
+    async fn load_api_auth(&self) -> Result<Arc<ApiAuthState>> {
+        let token = match &self.config.api_auth {
+            Some(auth) => Some(load_bearer_token(&auth.bearer_token_path).await?),
+            None => None,
+        };
+        Ok(Arc::new(ApiAuthState::new(token)))
+    }
+
+    /// Starts renewing [`Self::election`]'s lease in the background, if configured. The returned
+    /// handle must be held for as long as this agent should keep contending for leadership.
+    fn spawn_election(&self) -> Option<LeaderElectionHandle> {
+        self.election.clone().map(LeaderElection::spawn)
+    }
+
+    /// Starts the health checker (see [`Self::start_health_checker`]) and wraps it in an
+    /// [`AdminState`] the HTTP API can drain through `/admin/drain`.
+    async fn spawn_admin(&self) -> Result<Arc<AdminState>> {
+        let alpn = proxy::mesh_alpn(self.config.mesh_name.as_deref());
+        let (checker, health_checker, node_id, endpoint) =
+            self.start_health_checker(alpn.clone()).await?;
+        let self_registration = self.start_self_registration(endpoint);
+        let standalone_reload = self.start_standalone_reload()?;
+        self.start_metrics_server();
+        let admin = Arc::new(AdminState::new(
+            self.config.admin_token.clone(),
+            VersionInfo::new(node_id, &alpn),
+            self.config.discovery.is_some(),
+        ));
+        *admin.health_checker.lock().expect("lock poisoned") = health_checker;
+        *admin.refresh_checker.lock().expect("lock poisoned") = checker;
+        *admin.self_registration.lock().expect("lock poisoned") = self_registration;
+        *admin.standalone_reload.lock().expect("lock poisoned") = standalone_reload;
+        *admin.standalone_reload_path.lock().expect("lock poisoned") = self
+            .config
+            .standalone_reload
+            .as_ref()
+            .map(|config| config.path.clone());
+        *self.admin.lock().expect("lock poisoned") = Some(admin.clone());
+        Ok(admin)
+    }
+
+    /// Serves this agent's discovery metrics on [`AgentConfig::metrics_addr`], if set. Fire and
+    /// forget, same as [`proxy::MeshProxy`]'s `status_addr` server -- a stateless read-only
+    /// endpoint just dies with the process, no drain/abort handle needed.
+    fn start_metrics_server(&self) {
+        let Some(metrics_addr) = self.config.metrics_addr else {
+            return;
+        };
+        let mut registry = iroh_metrics::Registry::default();
+        registry.register(self.discovery.metrics());
+        let registry = Arc::new(registry);
+        tokio::spawn(async move {
+            if let Err(err) =
+                iroh_metrics::service::start_metrics_server(metrics_addr, registry).await
+            {
+                tracing::warn!(%err, "metrics server exited");
+            }
+        });
+    }
+
+    /// Starts an active health checker dialing with `alpn` if [`AgentConfig::discovery`] is
+    /// configured, alongside the iroh identity it dials from. The returned handle must be held
+    /// for as long as probing should continue.
+    ///
+    /// This agent has no iroh identity at all until a health checker needs one to dial peers
+    /// with, so the identity is `None` when [`AgentConfig::discovery`] is unset -- an agent that
+    /// only serves the HTTP API never binds one. Reuses [`Self::shared_endpoint`] if
+    /// [`Self::from_endpoint`] supplied one; otherwise binds a freshly generated, unpersisted
+    /// identity unless [`AgentConfig::secret_key`] names a source to load one from.
+    #[allow(clippy::type_complexity)]
+    async fn start_health_checker(
+        &self,
+        alpn: Vec<u8>,
+    ) -> Result<(
+        Option<Arc<HealthChecker>>,
+        Option<HealthCheckerHandle>,
+        Option<EndpointId>,
+        Option<Endpoint>,
+    )> {
+        let Some(discovery_config) = self.config.discovery.clone() else {
+            return Ok((None, None, None, None));
+        };
+        discovery_config.validate()?;
+        let endpoint = match &self.shared_endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => {
+                let relay_mode = relay::resolve(&self.config.relay)?;
+                let mut builder =
+                    endpoint_discovery::builder(&self.config.endpoint_discovery, relay_mode)
+                        .alpns(vec![alpn.clone()]);
+                if let Some(source) = &self.config.secret_key {
+                    builder =
+                        builder.secret_key(secret_key::load_or_create_secret_key(source).await?);
+                }
+                builder
+                    .bind()
+                    .await
+                    .map_err(Box::new)
+                    .context(BindEndpointSnafu)?
+            }
+        };
+        let node_id = endpoint.id();
+        let checker = Arc::new(HealthChecker::new(
+            endpoint.clone(),
+            self.discovery.clone(),
+            discovery_config,
+            alpn,
+            self.election.clone(),
+        ));
+        let handle = checker.clone().spawn(self.shutdown.clone());
+        Ok((Some(checker), Some(handle), Some(node_id), Some(endpoint)))
+    }
+
+    /// Starts [`registration::spawn_self_registration`] if [`AgentConfig::self_registration`] is
+    /// configured and `endpoint` was actually bound (see [`Self::start_health_checker`]'s docs for
+    /// when it isn't). The returned handle must be held for as long as this agent should keep
+    /// re-registering.
+    fn start_self_registration(
+        &self,
+        endpoint: Option<Endpoint>,
+    ) -> Option<SelfRegistrationHandle> {
+        let config = self.config.self_registration.as_ref()?;
+        let endpoint = endpoint?;
+        Some(registration::spawn_self_registration(
+            &endpoint,
+            self.discovery.clone(),
+            SelfClusterInfo {
+                cluster_id: config.cluster_id.clone(),
+                services: config.services.clone(),
+                service_ports: Vec::new(),
+            },
+            self.shutdown.clone(),
+        ))
+    }
+
+    /// Starts periodically reloading [`AgentConfig::standalone_reload`]'s peers file into
+    /// discovery, if configured. The returned handle must be held for as long as reloading should
+    /// continue.
+    fn start_standalone_reload(&self) -> Result<Option<StandaloneReloadHandle>> {
+        let Some(config) = self.config.standalone_reload.clone() else {
+            return Ok(None);
+        };
+        config.validate()?;
+        Ok(Some(standalone::spawn_reload(
+            self.discovery.clone(),
+            config,
+            self.shutdown.clone(),
+        )))
+    }
+
+    /// Stops the health checker's probing loop (see [`Self::start_health_checker`]), letting its
+    /// current probe pass finish rather than aborting it mid-write to discovery.
+    ///
+    /// [`Self::run`]/[`Self::run_on`] don't return once this is called -- unlike
+    /// [`crate::proxy::MeshProxy::shutdown`], which does, since serving the HTTP API (see
+    /// [`api::serve`]) isn't threaded through this token yet. `/admin/drain` (see
+    /// [`AdminState::drain`]) already covers stopping the API side of this agent; that and this
+    /// share the same health checker teardown one call further down, but aren't yet unified
+    /// behind one signal.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// A [`CancellationToken`] cancelled once this agent has been asked to shut down (see
+    /// [`Self::shutdown`]).
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Runs one discovery pass (see [`HealthChecker::probe_once`]) and, if
+    /// [`AgentConfig::standalone_reload`] is configured, one immediate registration reload,
+    /// instead of waiting for their timers -- for an operator who wants to force a mesh-wide
+    /// update during an incident rather than wait out the interval.
+    ///
+    /// Delegates to the same [`AdminState`] that backs `POST /admin/refresh`, so a burst of calls
+    /// from either source coalesces into whichever cycle is already in flight rather than each
+    /// stampeding [`AgentConfig::discovery`]'s peers or re-reading
+    /// [`AgentConfig::standalone_reload`]'s file concurrently.
+    ///
+    /// Returns [`RefreshSummary::default`] if called before [`Self::run`]/[`Self::run_on`] has
+    /// started, since there is nothing yet to refresh.
+    pub async fn refresh_now(&self) -> Result<RefreshSummary> {
+        let admin = self.admin.lock().expect("lock poisoned").clone();
+        match admin {
+            Some(admin) => admin.refresh_now(&self.discovery).await,
+            None => Ok(RefreshSummary::default()),
+        }
+    }
+}
+
+/// What changed during one [`MeshAgent::refresh_now`] cycle.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RefreshSummary {
+    /// Service names known to discovery after this refresh that weren't known before it.
+    pub services_added: Vec<String>,
+    /// Service names known to discovery before this refresh that are no longer known after it.
+    pub services_removed: Vec<String>,
+    /// Whether the standalone reload (see [`AgentConfig::standalone_reload`]) actually changed a
+    /// cluster's stored registration -- `false` when nothing changed, or when standalone reload
+    /// isn't configured at all.
+    pub registration_written: bool,
+}
+
+/// The service names currently known to `discovery`, for diffing across a [`MeshAgent::refresh_now`]
+/// cycle.
+fn known_services(discovery: &DiscoveryManager) -> HashSet<String> {
+    discovery
+        .list_clusters()
+        .into_iter()
+        .flat_map(|cluster| cluster.services)
+        .collect()
+}
+
+/// Shared state behind [`api::serve`]'s `/admin/drain` and `/admin/reload` endpoints.
+///
+/// Gated by [`AgentConfig::admin_token`]: [`Self::is_enabled`] is `false` without one configured,
+/// and callers should treat the admin routes as if they didn't exist (a 404) rather than
+/// reachable-but-locked (a 401), so an agent with no token set doesn't even advertise the
+/// surface.
+#[derive(Debug)]
+pub(crate) struct AdminState {
+    token: Option<String>,
+    draining: AtomicBool,
+    /// Count of non-admin requests currently being handled, so [`Self::drain`] knows when it's
+    /// safe to stop waiting.
+    in_flight: AtomicUsize,
+    drained: Notify,
+    /// Taken (and dropped, stopping probing) by [`Self::drain`].
+    health_checker: Mutex<Option<HealthCheckerHandle>>,
+    /// The [`HealthChecker`] driving [`Self::health_checker`]'s background loop, kept separately
+    /// so [`Self::refresh_now`] can trigger an immediate probe pass without waiting for the timer.
+    /// Cleared alongside [`Self::health_checker`] by [`Self::drain`].
+    refresh_checker: Mutex<Option<Arc<HealthChecker>>>,
+    /// Taken (and dropped, stopping re-registration) by [`Self::drain`].
+    self_registration: Mutex<Option<SelfRegistrationHandle>>,
+    /// Taken (and dropped, stopping reloading) by [`Self::drain`].
+    standalone_reload: Mutex<Option<StandaloneReloadHandle>>,
+    /// Path [`Self::refresh_now`] re-reads directly for an on-demand registration reload. `None`
+    /// when [`AgentConfig::standalone_reload`] isn't configured.
+    standalone_reload_path: Mutex<Option<PathBuf>>,
+    /// Serializes [`Self::refresh_now`] calls so concurrent `POST /admin/refresh` requests (or a
+    /// concurrent [`MeshAgent::refresh_now`] call) coalesce into one cycle at a time instead of
+    /// each dialing every known cluster or re-reading the standalone file in parallel.
+    refresh_lock: tokio::sync::Mutex<()>,
+    /// Served at `/version` (see [`crate::versioninfo`]), built once at [`MeshAgent::run`]/
+    /// [`MeshAgent::run_on`] time since neither the binary's version nor this agent's identity
+    /// change while it's running.
+    pub(crate) version: VersionInfo,
+    /// Whether [`AgentConfig::discovery`] is configured, i.e. whether this agent's health
+    /// checker is expected to have bound an endpoint. Backs [`Self::is_ready`].
+    discovery_configured: bool,
+}
+
+impl AdminState {
+    pub(crate) fn new(
+        token: Option<String>,
+        version: VersionInfo,
+        discovery_configured: bool,
+    ) -> Self {
+        Self {
+            token,
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            drained: Notify::new(),
+            health_checker: Mutex::new(None),
+            refresh_checker: Mutex::new(None),
+            self_registration: Mutex::new(None),
+            standalone_reload: Mutex::new(None),
+            standalone_reload_path: Mutex::new(None),
+            refresh_lock: tokio::sync::Mutex::new(()),
+            version,
+            discovery_configured,
+        }
+    }
+
+    /// Whether this agent is ready to serve, for `GET /readyz` (see [`crate::api::readyz`]).
+    ///
+    /// Reflects whether [`Self::version`]'s `node_id` is set when [`Self::discovery_configured`]
+    /// -- i.e. whether the health checker's endpoint (see [`MeshAgent::start_health_checker`])
+    /// bound successfully. An agent with no [`AgentConfig::discovery`] configured never binds one
+    /// and is always ready, since it's only serving the HTTP API at that point. Unlike
+    /// [`crate::proxy::MeshProxy::is_ready`], this doesn't reflect relay reachability -- this
+    /// agent's health checker dials peers directly and doesn't probe relays the way
+    /// [`crate::proxy::MeshProxy`] does.
+    pub(crate) fn is_ready(&self) -> bool {
+        !self.discovery_configured || self.version.node_id.is_some()
+    }
+
+    /// Whether an admin token is configured at all; `/admin/*` is treated as unrouted when not.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Whether `req` carries this agent's configured token as an `Authorization: Bearer <token>`
+    /// header. Always `false` when [`Self::is_enabled`] is `false`.
+    pub(crate) fn is_authorized(&self, req: &Request<Incoming>) -> bool {
+        let Some(token) = &self.token else {
+            return false;
+        };
+        bearer_token(req)
+            .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+    }
+
+    /// Whether the agent is currently draining; non-admin requests should be refused while this
+    /// holds.
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Guards a single non-admin request's lifetime, so [`Self::drain`] can tell when in-flight
+    /// requests made before draining started have finished.
+    pub(crate) fn track_request(self: &Arc<Self>) -> RequestGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        RequestGuard {
+            admin: self.clone(),
+        }
+    }
+
+    /// Stops accepting new work -- new non-admin requests (see [`Self::is_draining`]), any
+    /// further probing by the health checker, and any further standalone peers reloading -- then
+    /// waits for already-in-flight requests to finish, up to [`ADMIN_DRAIN_TIMEOUT`].
+    pub(crate) async fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.health_checker.lock().expect("lock poisoned").take();
+        self.refresh_checker.lock().expect("lock poisoned").take();
+        self.self_registration.lock().expect("lock poisoned").take();
+        self.standalone_reload.lock().expect("lock poisoned").take();
+        let wait_for_in_flight = async {
+            while self.in_flight.load(Ordering::SeqCst) > 0 {
+                self.drained.notified().await;
+            }
+        };
+        let _ = tokio::time::timeout(ADMIN_DRAIN_TIMEOUT, wait_for_in_flight).await;
+    }
+
+    /// Runs one discovery pass (see [`HealthChecker::probe_once`]) and, if a standalone reload
+    /// path is configured, one immediate registration reload -- see [`MeshAgent::refresh_now`],
+    /// which this backs both directly and through `POST /admin/refresh`.
+    ///
+    /// Concurrent calls serialize on [`Self::refresh_lock`] rather than each running their own
+    /// pass, so a burst of requests during an incident can't stampede every known cluster at once.
+    pub(crate) async fn refresh_now(&self, discovery: &DiscoveryManager) -> Result<RefreshSummary> {
+        let _guard = self.refresh_lock.lock().await;
+        let before = known_services(discovery);
+
+        let checker = self.refresh_checker.lock().expect("lock poisoned").clone();
+        if let Some(checker) = checker {
+            checker.probe_once().await;
+        }
+
+        let mut registration_written = false;
+        let path = self
+            .standalone_reload_path
+            .lock()
+            .expect("lock poisoned")
+            .clone();
+        if let Some(path) = path {
+            let file = StandaloneFile::load(&path).await?;
+            for peer in file.peers {
+                if discovery.register_cluster(peer) {
+                    registration_written = true;
+                }
+            }
+        }
+
+        let after = known_services(discovery);
+        let mut services_added: Vec<String> = after.difference(&before).cloned().collect();
+        services_added.sort();
+        let mut services_removed: Vec<String> = before.difference(&after).cloned().collect();
+        services_removed.sort();
+
+        Ok(RefreshSummary {
+            services_added,
+            services_removed,
+            registration_written,
+        })
+    }
+}
+
+/// See [`AdminState::track_request`].
+pub(crate) struct RequestGuard {
+    admin: Arc<AdminState>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if self.admin.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.admin.drained.notify_waiters();
+        }
+    }
+}
+
+/// Shared state behind [`api::serve`]'s bearer-token gate, see [`AgentConfig::api_auth`].
+#[derive(Debug)]
+pub(crate) struct ApiAuthState {
+    token: Option<String>,
+}
+
+impl ApiAuthState {
+    pub(crate) fn new(token: Option<String>) -> Self {
+        Self { token }
+    }
+
+    /// Whether `req` carries this agent's configured bearer token. Always `true` when no token
+    /// is configured, so the API is open by default, matching this crate's behavior before this
+    /// setting existed.
+    pub(crate) fn is_authorized(&self, req: &Request<Incoming>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        bearer_token(req)
+            .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Extracts the token from an `Authorization: Bearer <token>` header, if present and well formed.
+fn bearer_token(req: &Request<Incoming>) -> Option<&str> {
+    req.headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Compares `a` and `b` for equality without returning as soon as a differing byte is found, so
+/// a wrong bearer token doesn't leak how many of its leading bytes matched through response
+/// timing. This crate has no constant-time-comparison dependency already pulled in, so this is a
+/// small self-contained implementation rather than adding one just for this.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+/// Reads and trims the bearer token at `path`, for [`AgentConfig::api_auth`]. Rejects an empty
+/// file the same way [`secret_key::load_or_create_secret_key`] rejects an empty secret key.
+async fn load_bearer_token(path: &Path) -> Result<String> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    let token = contents.trim().to_string();
+    ensure!(
+        !token.is_empty(),
+        InvalidConfigSnafu {
+            reason: format!("api_auth bearer token file {} is empty", path.display()),
+        }
+    );
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh path under the system temp dir, named after the calling test so parallel tests
+    /// don't collide.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("iroh-mesh-test-agent-{name}.toml"))
+    }
+
+    fn fresh_endpoint_id() -> iroh_base::EndpointId {
+        iroh_base::SecretKey::generate(&mut rand::rng()).public()
+    }
+
+    /// Polls `condition` in a loop with real (unpaused) sleeps between attempts -- the standalone
+    /// reload's file reads run on tokio's blocking thread pool, same as
+    /// [`crate::standalone`]'s own tests.
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never became true within the timeout");
+    }
+
+    fn base_config(
+        api_addr: SocketAddr,
+        standalone_reload: Option<StandaloneReloadConfig>,
+    ) -> AgentConfig {
+        AgentConfig {
+            api_addr,
+            discovery: None,
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: None,
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload,
+            self_registration: None,
+            metrics_addr: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_now_before_the_agent_has_started_reports_nothing_changed() {
+        let agent = MeshAgent::new(base_config("127.0.0.1:0".parse().unwrap(), None));
+        let summary = agent.refresh_now().await.unwrap();
+        assert_eq!(summary, RefreshSummary::default());
+    }
+
+    #[tokio::test]
+    async fn refresh_now_reloads_a_standalone_peer_edit_before_the_reload_timer_would_fire() {
+        let path = temp_path("refresh-now");
+        let endpoint_id = fresh_endpoint_id();
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_addr = listener.local_addr().unwrap();
+        let agent = Arc::new(MeshAgent::new(base_config(
+            api_addr,
+            Some(StandaloneReloadConfig {
+                path: path.clone(),
+                interval: Duration::from_secs(3600),
+            }),
+        )));
+        let task = tokio::spawn({
+            let agent = agent.clone();
+            async move {
+                let _ = agent.run_on(listener).await;
+            }
+        });
+
+        // Wait for the reload's initial load (triggered by starting the agent, not by
+        // `refresh_now`) to land, so the assertions below can tell the two apart.
+        wait_until(|| agent.discovery().find_service("svc").len() == 1).await;
+
+        // Edit the file after the initial load. With a 3600s reload interval, only an explicit
+        // `refresh_now` -- not the timer -- can pick this up before the test ends.
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc", "svc-b"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let summary = agent.refresh_now().await.unwrap();
+        assert!(
+            summary.registration_written,
+            "the edited file should have produced a new registration"
+        );
+        assert_eq!(summary.services_added, vec!["svc-b".to_string()]);
+        assert_eq!(agent.discovery().find_service("svc-b").len(), 1);
+
+        task.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,625 @@
+//! The agent's HTTP API: `/healthz`, `/readyz`, `/clusters`, `/services`, `/routes`, `/status`,
+//! `/version`,
+//! and `/admin/*` (see [`handle`]), backed directly by [`DiscoveryManager`] and
+//! [`crate::election::LeaderElection`] rather than a separate `start_api_server` stub -- there
+//! isn't one, this has always been the real server. It's built directly on [`hyper`] rather than
+//! a framework like `axum`, matching [`crate::proxy::MeshProxy`]'s connection handling elsewhere
+//! in this crate, which also talks to [`hyper`] directly (see e.g. `crate::proxy`'s HTTP path
+//! routing) instead of taking on a routing framework dependency.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use http_body_util::BodyExt;
+use hyper::{Method, Request, StatusCode, body::Incoming};
+use tokio::net::TcpListener;
+use tracing::error;
+
+use crate::{
+    agent::{AdminState, ApiAuthState},
+    config::TlsConfig,
+    discovery::{ClusterRegistration, DiscoveryManager},
+    election::LeaderElection,
+    error::Result,
+    httputil::{self, Body, json_response},
+    proxy, socket,
+};
+
+/// Shared state handed to [`handle`]: discovery, this agent's leader election if it's running
+/// with [`crate::agent::MeshAgent::with_leader_election`], its admin drain/reload state, and its
+/// bearer-token gate.
+type State = (
+    Arc<DiscoveryManager>,
+    Option<Arc<LeaderElection>>,
+    Arc<AdminState>,
+    Arc<ApiAuthState>,
+);
+
+/// Serves the agent HTTP API on `addr` until the process is stopped.
+///
+/// `dual_stack` is forwarded to [`socket::bind_listener`]; see its docs for what it does and
+/// when it's valid. Serves over HTTPS when `tls` is set, see [`crate::agent::AgentConfig::api_tls`].
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    addr: SocketAddr,
+    dual_stack: bool,
+    discovery: Arc<DiscoveryManager>,
+    election: Option<Arc<LeaderElection>>,
+    admin: Arc<AdminState>,
+    api_auth: Arc<ApiAuthState>,
+    tls: Option<TlsConfig>,
+) -> Result<()> {
+    let listener = socket::bind_listener(addr, dual_stack)?;
+    serve_on(listener, discovery, election, admin, api_auth, tls).await
+}
+
+/// Like [`serve`], but reuses an already-bound listener.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn serve_on(
+    listener: TcpListener,
+    discovery: Arc<DiscoveryManager>,
+    election: Option<Arc<LeaderElection>>,
+    admin: Arc<AdminState>,
+    api_auth: Arc<ApiAuthState>,
+    tls: Option<TlsConfig>,
+) -> Result<()> {
+    let state = (discovery, election, admin, api_auth);
+    match tls {
+        Some(tls) => {
+            let acceptor = proxy::build_tls_acceptor(&tls).await?;
+            httputil::serve_on_tls(listener, acceptor, state, handle).await
+        }
+        None => httputil::serve_on(listener, state, handle).await,
+    }
+}
+
+async fn handle(req: Request<Incoming>, state: State) -> hyper::Response<Body> {
+    let (discovery, election, admin, api_auth) = state;
+    let path_segments: Vec<&str> = req.uri().path().trim_matches('/').split('/').collect();
+    if let (&Method::GET, ["healthz"]) = (req.method(), path_segments.as_slice()) {
+        return json_response(StatusCode::OK, &serde_json::json!({"status": "ok"}));
+    }
+    if let (&Method::GET, ["readyz"]) = (req.method(), path_segments.as_slice()) {
+        return readyz(&admin);
+    }
+    if !api_auth.is_authorized(&req) {
+        return json_response(
+            StatusCode::UNAUTHORIZED,
+            &serde_json::json!({"error": "unauthorized"}),
+        );
+    }
+    if admin.is_enabled() {
+        match (req.method(), path_segments.as_slice()) {
+            (&Method::POST, ["admin", "drain"]) => return admin_drain(&req, &admin).await,
+            (&Method::POST, ["admin", "reload"]) => return admin_reload(&req, &admin),
+            (&Method::POST, ["admin", "refresh"]) => {
+                return admin_refresh(&req, &admin, &discovery).await;
+            }
+            _ => {}
+        }
+    }
+    if admin.is_draining() {
+        return json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &serde_json::json!({"error": "draining"}),
+        );
+    }
+    let _guard = admin.track_request();
+    match (req.method(), path_segments.as_slice()) {
+        (&Method::GET, ["clusters"]) => list_clusters(&discovery),
+        (&Method::POST, ["clusters"]) => register_cluster(req, &discovery).await,
+        (&Method::DELETE, ["clusters", id]) => remove_cluster(id, &discovery),
+        (&Method::GET, ["status"]) => status(election.as_deref()),
+        (&Method::GET, ["services"]) => list_services(&discovery),
+        (&Method::GET, ["routes"]) => list_routes(&discovery),
+        (&Method::GET, ["version"]) => json_response(StatusCode::OK, &admin.version),
+        _ => json_response(
+            StatusCode::NOT_FOUND,
+            &serde_json::json!({"error": "not found"}),
+        ),
+    }
+}
+
+/// Reports whether this agent's health-checker endpoint (see [`AdminState::is_ready`]) is bound,
+/// unauthenticated like `/healthz` so orchestrators can probe it without a credential.
+fn readyz(admin: &AdminState) -> hyper::Response<Body> {
+    if admin.is_ready() {
+        json_response(StatusCode::OK, &serde_json::json!({"ready": true}))
+    } else {
+        json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &serde_json::json!({"ready": false}),
+        )
+    }
+}
+
+/// Stops the agent from accepting new non-admin requests and probing further, then waits for
+/// requests already in flight to finish (see [`AdminState::drain`]), before reporting success.
+async fn admin_drain(req: &Request<Incoming>, admin: &AdminState) -> hyper::Response<Body> {
+    if !admin.is_authorized(req) {
+        return json_response(
+            StatusCode::UNAUTHORIZED,
+            &serde_json::json!({"error": "unauthorized"}),
+        );
+    }
+    admin.drain().await;
+    json_response(StatusCode::OK, &serde_json::json!({"status": "draining"}))
+}
+
+/// Re-reads and applies this agent's hot-reloadable configuration.
+///
+/// `AgentConfig` has nothing hot-reloadable yet -- the state operators actually want to change at
+/// runtime (which clusters are known, see [`register_cluster`]/[`remove_cluster`]) is already
+/// dynamic today, not read from static config. This endpoint exists so operators have a stable
+/// place to call once something is, rather than needing to agree on one later.
+fn admin_reload(req: &Request<Incoming>, admin: &AdminState) -> hyper::Response<Body> {
+    if !admin.is_authorized(req) {
+        return json_response(
+            StatusCode::UNAUTHORIZED,
+            &serde_json::json!({"error": "unauthorized"}),
+        );
+    }
+    json_response(StatusCode::OK, &serde_json::json!({"status": "reloaded"}))
+}
+
+/// Runs [`AdminState::refresh_now`] immediately instead of waiting for its timers, reporting a
+/// summary of what changed.
+async fn admin_refresh(
+    req: &Request<Incoming>,
+    admin: &AdminState,
+    discovery: &DiscoveryManager,
+) -> hyper::Response<Body> {
+    if !admin.is_authorized(req) {
+        return json_response(
+            StatusCode::UNAUTHORIZED,
+            &serde_json::json!({"error": "unauthorized"}),
+        );
+    }
+    match admin.refresh_now(discovery).await {
+        Ok(summary) => json_response(StatusCode::OK, &summary),
+        Err(err) => {
+            let status = StatusCode::from_u16(err.http_status()).unwrap_or(StatusCode::BAD_GATEWAY);
+            json_response(status, &serde_json::json!({"error": err.to_string()}))
+        }
+    }
+}
+
+/// Reports whether this agent currently holds leadership, or `null` if leader election isn't
+/// configured (an unreplicated agent is always its own leader, so there's nothing to report).
+fn status(election: Option<&LeaderElection>) -> hyper::Response<Body> {
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({"leader": election.map(LeaderElection::is_leader)}),
+    )
+}
+
+fn list_clusters(discovery: &DiscoveryManager) -> hyper::Response<Body> {
+    json_response(StatusCode::OK, &discovery.list_clusters())
+}
+
+/// Lists every service currently advertised by at least one registered cluster.
+fn list_services(discovery: &DiscoveryManager) -> hyper::Response<Body> {
+    let mut services: Vec<String> = discovery
+        .list_clusters()
+        .into_iter()
+        .flat_map(|cluster| cluster.services)
+        .collect();
+    services.sort_unstable();
+    services.dedup();
+    json_response(StatusCode::OK, &services)
+}
+
+/// Reports, for each known service, which registered clusters currently serve it.
+///
+/// This is this agent's *effective* routing table -- which cluster [`register_cluster`] and
+/// [`remove_cluster`] would currently resolve a service to -- not [`crate::config::PathRoute`],
+/// which configures [`crate::proxy::MeshProxy`]'s path-to-cluster mapping and isn't state this
+/// agent holds.
+fn list_routes(discovery: &DiscoveryManager) -> hyper::Response<Body> {
+    let mut services: Vec<String> = discovery
+        .list_clusters()
+        .into_iter()
+        .flat_map(|cluster| cluster.services)
+        .collect();
+    services.sort_unstable();
+    services.dedup();
+    let routes: Vec<_> = services
+        .into_iter()
+        .map(|service| {
+            let clusters: Vec<String> = discovery
+                .find_service(&service)
+                .into_iter()
+                .map(|cluster| cluster.cluster_id)
+                .collect();
+            serde_json::json!({"service": service, "clusters": clusters})
+        })
+        .collect();
+    json_response(StatusCode::OK, &routes)
+}
+
+async fn register_cluster(
+    req: Request<Incoming>,
+    discovery: &DiscoveryManager,
+) -> hyper::Response<Body> {
+    let body = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            error!(%err, "failed to read request body");
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                &serde_json::json!({"error": "failed to read body"}),
+            );
+        }
+    };
+    match serde_json::from_slice::<ClusterRegistration>(&body) {
+        Ok(registration) => {
+            discovery.register_cluster(registration);
+            json_response(
+                StatusCode::CREATED,
+                &serde_json::json!({"status": "registered"}),
+            )
+        }
+        Err(err) => json_response(
+            StatusCode::BAD_REQUEST,
+            &serde_json::json!({"error": err.to_string()}),
+        ),
+    }
+}
+
+fn remove_cluster(cluster_id: &str, discovery: &DiscoveryManager) -> hyper::Response<Body> {
+    if discovery.remove_cluster(cluster_id) {
+        json_response(StatusCode::NO_CONTENT, &())
+    } else {
+        json_response(
+            StatusCode::NOT_FOUND,
+            &serde_json::json!({"error": "no such cluster"}),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_base::SecretKey;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{discovery::ClusterRegistration, versioninfo::VersionInfo};
+
+    /// A [`VersionInfo`] fixture for tests that don't exercise `/version` itself.
+    fn test_version_info() -> VersionInfo {
+        VersionInfo::new(
+            Some(SecretKey::generate(&mut rand::rng()).public()),
+            b"iroh-mesh/0",
+        )
+    }
+
+    /// Sends a raw HTTP/1.1 request over `addr` and returns `(status, body)`.
+    async fn send(addr: SocketAddr, request: &str) -> (u16, String) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).await.unwrap();
+        let mut parts = raw.splitn(2, "\r\n\r\n");
+        let head = parts.next().unwrap_or_default();
+        let body = parts.next().unwrap_or_default().to_string();
+        let status = head
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn register_then_list_then_remove() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_discovery = discovery.clone();
+        let admin = Arc::new(AdminState::new(None, test_version_info(), false));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (server_discovery, None, admin, api_auth), handle).await
+        });
+
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public();
+        let registration = ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id,
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        };
+        let payload = serde_json::to_string(&registration).unwrap();
+        let request = format!(
+            "POST /clusters HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            payload.len(),
+            payload
+        );
+        let (status, _) = send(addr, &request).await;
+        assert_eq!(status, 201);
+
+        let (status, body) = send(
+            addr,
+            "GET /clusters HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("cluster-a"));
+
+        let (status, _) = send(
+            addr,
+            "DELETE /clusters/cluster-a HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 204);
+        assert!(discovery.get_cluster("cluster-a").is_none());
+    }
+
+    #[tokio::test]
+    async fn services_and_routes_reflect_registered_clusters() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin = Arc::new(AdminState::new(None, test_version_info(), false));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: vec!["web".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+
+        let (status, body) = send(
+            addr,
+            "GET /services HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"["web"]"#);
+
+        let (status, body) = send(
+            addr,
+            "GET /routes HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"[{"clusters":["cluster-a"],"service":"web"}]"#);
+    }
+
+    #[tokio::test]
+    async fn status_reports_no_leader_when_election_is_not_configured() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin = Arc::new(AdminState::new(None, test_version_info(), false));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+
+        let (status, body) = send(
+            addr,
+            "GET /status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"leader":null}"#);
+    }
+
+    #[tokio::test]
+    async fn readyz_is_ready_without_discovery_configured() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin = Arc::new(AdminState::new(None, test_version_info(), false));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+
+        let (status, body) = send(
+            addr,
+            "GET /readyz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"ready":true}"#);
+    }
+
+    #[tokio::test]
+    async fn readyz_is_unready_when_discovery_is_configured_but_no_endpoint_bound() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin = Arc::new(AdminState::new(
+            None,
+            VersionInfo::new(None, b"iroh-mesh/0"),
+            true,
+        ));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+
+        let (status, body) = send(
+            addr,
+            "GET /readyz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 503);
+        assert_eq!(body, r#"{"ready":false}"#);
+    }
+
+    #[tokio::test]
+    async fn version_reports_the_configured_node_id_and_a_non_empty_version() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node_id = SecretKey::generate(&mut rand::rng()).public();
+        let admin = Arc::new(AdminState::new(
+            None,
+            VersionInfo::new(Some(node_id), b"iroh-mesh/0"),
+            false,
+        ));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+
+        let (status, body) = send(
+            addr,
+            "GET /version HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        let info: VersionInfo = serde_json::from_str(&body).unwrap();
+        assert_eq!(info.node_id, Some(node_id));
+        assert_eq!(info.alpn, "iroh-mesh/0");
+        assert!(!info.version.is_empty());
+    }
+
+    /// Starts serving `handle` on an ephemeral loopback port with the given admin token (`None`
+    /// disables the admin endpoints) and an otherwise-empty [`DiscoveryManager`].
+    async fn spawn_with_admin_token(token: Option<&str>) -> SocketAddr {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin = Arc::new(AdminState::new(
+            token.map(str::to_string),
+            test_version_info(),
+            false,
+        ));
+        let api_auth = Arc::new(ApiAuthState::new(None));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+        addr
+    }
+
+    /// Starts serving `handle` on an ephemeral loopback port requiring `token` as the
+    /// [`ApiAuthState`] bearer token (`None` disables the gate), with no admin token configured
+    /// and an otherwise-empty [`DiscoveryManager`].
+    async fn spawn_with_api_auth(token: Option<&str>) -> SocketAddr {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admin = Arc::new(AdminState::new(None, test_version_info(), false));
+        let api_auth = Arc::new(ApiAuthState::new(token.map(str::to_string)));
+        tokio::spawn(async move {
+            httputil::serve_on(listener, (discovery, None, admin, api_auth), handle).await
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn a_request_with_the_configured_bearer_token_is_authorized() {
+        let addr = spawn_with_api_auth(Some("s3cret")).await;
+        let (status, _) = send(
+            addr,
+            "GET /clusters HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cret\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn a_request_with_a_missing_or_wrong_bearer_token_is_rejected() {
+        let addr = spawn_with_api_auth(Some("s3cret")).await;
+
+        let (status, _) = send(
+            addr,
+            "GET /clusters HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 401);
+
+        let (status, _) = send(
+            addr,
+            "GET /clusters HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer wrong\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn the_health_endpoint_is_exempt_from_api_auth() {
+        let addr = spawn_with_api_auth(Some("s3cret")).await;
+        let (status, body) = send(
+            addr,
+            "GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"status":"ok"}"#);
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_are_not_routed_without_a_configured_token() {
+        let addr = spawn_with_admin_token(None).await;
+        let (status, _) = send(
+            addr,
+            "POST /admin/drain HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_reject_a_missing_or_wrong_bearer_token() {
+        let addr = spawn_with_admin_token(Some("s3cret")).await;
+
+        let (status, _) = send(
+            addr,
+            "POST /admin/drain HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 401);
+
+        let (status, _) = send(
+            addr,
+            "POST /admin/drain HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer wrong\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn draining_refuses_new_requests_while_the_endpoint_stays_up() {
+        let addr = spawn_with_admin_token(Some("s3cret")).await;
+
+        let (status, body) = send(
+            addr,
+            "POST /admin/drain HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cret\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"status":"draining"}"#);
+
+        let (status, _) = send(
+            addr,
+            "GET /clusters HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 503);
+
+        // The admin endpoints, and the listener itself, stay reachable after draining.
+        let (status, body) = send(
+            addr,
+            "POST /admin/reload HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cret\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"status":"reloaded"}"#);
+    }
+}
@@ -0,0 +1,149 @@
+//! Per-service authorization policy for cross-cluster dials.
+//!
+//! Checked on the egress side in [`crate::proxy::MeshProxy::dial_service_for`]: this proxy
+//! refuses to dial a candidate its own [`AuthzPolicy`] doesn't allow for the requested service,
+//! before ever opening the connection. Checked again on the accept side in
+//! [`crate::proxy::MeshProxy`]'s mesh accept loop, against whichever cluster identity is
+//! available for an inbound tunnel or datagram, before forwarding it to a local backend -- so a
+//! peer this proxy would never dial out to itself can't reach the same backend by connecting in
+//! instead.
+//!
+//! That accept-side identity is only as trustworthy as its source: an inbound TCP tunnel's
+//! [`crate::route_request::RouteRequest::source_cluster_id`] is whatever the dialing proxy
+//! claims (see that type's "Trust" section), and an inbound UDP datagram carries no cluster
+//! identity at all, so that path falls back to the dialing connection's iroh node id, which
+//! won't match a rule scoped to a specific `cluster_id`. Either way this is defense in depth
+//! alongside the egress check above, not a substitute for it.
+
+use serde::{Deserialize, Serialize};
+
+/// What a matching [`AuthzRule`] does with a dial.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthzEffect {
+    /// Let the dial through.
+    #[default]
+    Allow,
+    /// Refuse the dial.
+    Deny,
+}
+
+/// One rule in an [`AuthzPolicy`], matching a dial by the cluster and/or service it targets.
+///
+/// A `None` field matches anything, so e.g. a rule with `cluster: None` applies to `service`
+/// regardless of which cluster currently serves it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AuthzRule {
+    /// Only matches a dial to this cluster id, if set.
+    #[serde(default)]
+    pub cluster: Option<String>,
+    /// Only matches a dial for this service name, if set.
+    #[serde(default)]
+    pub service: Option<String>,
+    /// What to do with a dial this rule matches.
+    pub effect: AuthzEffect,
+}
+
+impl AuthzRule {
+    fn matches(&self, cluster_id: &str, service: &str) -> bool {
+        self.cluster.as_deref().is_none_or(|c| c == cluster_id)
+            && self.service.as_deref().is_none_or(|s| s == service)
+    }
+}
+
+/// Policy governing which services [`crate::proxy::MeshProxy::dial_service_for`] is allowed to
+/// dial. Absent from [`crate::config::ProxyConfig`] by default, matching this crate's behavior
+/// before this setting existed: every dial allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AuthzPolicy {
+    /// What happens to a dial no rule in `rules` matches. Defaults to
+    /// [`AuthzEffect::Allow`], so adding a narrowly scoped `deny` rule doesn't silently lock out
+    /// every service it doesn't mention.
+    #[serde(default)]
+    pub default: AuthzEffect,
+    /// Rules evaluated in order; the first one matching a dial decides its outcome. Falls back
+    /// to `default` if none match.
+    #[serde(default)]
+    pub rules: Vec<AuthzRule>,
+}
+
+impl AuthzPolicy {
+    /// Returns whether a dial to `cluster_id` for `service` is allowed.
+    pub fn is_allowed(&self, cluster_id: &str, service: &str) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(cluster_id, service))
+            .map_or(self.default, |rule| rule.effect)
+            == AuthzEffect::Allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_empty_policy_allows_everything() {
+        let policy = AuthzPolicy::default();
+        assert!(policy.is_allowed("cluster-a", "svc"));
+    }
+
+    #[test]
+    fn a_matching_allow_rule_permits_a_call_a_default_deny_policy_would_otherwise_refuse() {
+        let policy = AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: vec![AuthzRule {
+                cluster: Some("cluster-b".to_string()),
+                service: Some("svc-x".to_string()),
+                effect: AuthzEffect::Allow,
+            }],
+        };
+        assert!(policy.is_allowed("cluster-b", "svc-x"));
+    }
+
+    #[test]
+    fn a_matching_deny_rule_refuses_a_call_a_default_allow_policy_would_otherwise_permit() {
+        let policy = AuthzPolicy {
+            default: AuthzEffect::Allow,
+            rules: vec![AuthzRule {
+                cluster: Some("cluster-b".to_string()),
+                service: Some("svc-y".to_string()),
+                effect: AuthzEffect::Deny,
+            }],
+        };
+        assert!(!policy.is_allowed("cluster-b", "svc-y"));
+        assert!(
+            policy.is_allowed("cluster-b", "svc-x"),
+            "a rule naming a different service shouldn't match"
+        );
+    }
+
+    #[test]
+    fn an_unmatched_call_falls_back_to_the_configured_default() {
+        let policy = AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: vec![AuthzRule {
+                cluster: None,
+                service: Some("svc-x".to_string()),
+                effect: AuthzEffect::Allow,
+            }],
+        };
+        assert!(!policy.is_allowed("cluster-a", "svc-y"));
+    }
+
+    #[test]
+    fn a_rule_with_no_cluster_matches_any_cluster_serving_the_named_service() {
+        let policy = AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: vec![AuthzRule {
+                cluster: None,
+                service: Some("svc-x".to_string()),
+                effect: AuthzEffect::Allow,
+            }],
+        };
+        assert!(policy.is_allowed("cluster-a", "svc-x"));
+        assert!(policy.is_allowed("cluster-b", "svc-x"));
+    }
+}
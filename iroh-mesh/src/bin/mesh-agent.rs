@@ -0,0 +1,130 @@
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use iroh_mesh::{AgentConfig, EffectiveConfig, MeshAgent, Overridable, doctor, error::Result};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// CLI flags overriding fields of a loaded [`AgentConfig`], shared by every subcommand that takes
+/// a config. See [`resolve_config`].
+#[derive(clap::Args, Debug, Default)]
+struct ConfigOverrides {
+    /// Overrides `api_addr` from the config file. Also settable with `MESH_AGENT_API_ADDR`.
+    #[clap(long)]
+    api_addr: Option<SocketAddr>,
+    /// Overrides `mesh_name` from the config file. Also settable with `MESH_AGENT_MESH_NAME`.
+    #[clap(long)]
+    mesh_name: Option<String>,
+    /// Overrides `admin_token` from the config file. Also settable with
+    /// `MESH_AGENT_ADMIN_TOKEN`.
+    #[clap(long)]
+    admin_token: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serves the agent's HTTP API until stopped.
+    Run {
+        /// Path to a TOML config file.
+        #[clap(short, long)]
+        config: PathBuf,
+        #[clap(flatten)]
+        overrides: ConfigOverrides,
+    },
+    /// Validates configuration and connectivity (secret key, endpoint bind) without serving.
+    Doctor {
+        /// Path to a TOML config file.
+        #[clap(short, long)]
+        config: PathBuf,
+        #[clap(flatten)]
+        overrides: ConfigOverrides,
+    },
+    /// Prints the JSON Schema for the agent's config file format, for validation tooling.
+    Schema,
+}
+
+fn load_config(path: &PathBuf) -> Result<AgentConfig> {
+    let raw = std::fs::read_to_string(path)?;
+    toml::from_str(&raw).map_err(|e| {
+        iroh_mesh::error::InvalidConfigSnafu {
+            reason: e.to_string(),
+        }
+        .build()
+    })
+}
+
+fn env_socket_addr(name: &str) -> Option<SocketAddr> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+fn env_string(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Loads the config file at `path` and layers `overrides` and environment variables on top,
+/// following [`EffectiveConfig::resolve`]'s precedence (CLI > env > file > default). The only
+/// fields overridable this way are `api_addr`, `mesh_name` and `admin_token`; everything else is
+/// only settable in the config file.
+fn resolve_config(
+    path: &PathBuf,
+    overrides: ConfigOverrides,
+) -> Result<EffectiveConfig<AgentConfig>> {
+    let mut config = load_config(path)?;
+    Ok(EffectiveConfig::resolve(|sources| {
+        config.api_addr = Overridable {
+            file: Some(config.api_addr),
+            env: env_socket_addr("MESH_AGENT_API_ADDR"),
+            cli: overrides.api_addr,
+        }
+        .resolve("api_addr", config.api_addr, sources);
+        config.mesh_name = Overridable {
+            file: Some(config.mesh_name.clone()),
+            env: env_string("MESH_AGENT_MESH_NAME").map(Some),
+            cli: overrides.mesh_name.map(Some),
+        }
+        .resolve("mesh_name", None, sources);
+        config.admin_token = Overridable {
+            file: Some(config.admin_token.clone()),
+            env: env_string("MESH_AGENT_ADMIN_TOKEN").map(Some),
+            cli: overrides.admin_token.map(Some),
+        }
+        .resolve("admin_token", None, sources);
+        config
+    }))
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    tracing_subscriber::fmt::init();
+    match Cli::parse().command {
+        Command::Run { config, overrides } => {
+            let effective = resolve_config(&config, overrides)?;
+            tracing::info!(sources = %effective.describe_sources(), "resolved config");
+            MeshAgent::new(effective.config).run().await?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Doctor { config, overrides } => {
+            let effective = resolve_config(&config, overrides)?;
+            let report = doctor::check_agent(&effective.config).await;
+            report.print();
+            println!("-- config sources --\n{}", effective.describe_sources());
+            Ok(if report.is_ok() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+        Command::Schema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&iroh_mesh::agent_config_schema())
+                    .expect("schema serializes")
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
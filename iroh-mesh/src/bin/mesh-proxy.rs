@@ -0,0 +1,173 @@
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode, sync::Arc};
+
+use clap::{Parser, Subcommand};
+use iroh_mesh::{
+    DiscoveryManager, EffectiveConfig, Overridable, ProxyConfig, ResolvedRoute, RoutingKey, doctor,
+    error::Result,
+};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// CLI flags overriding fields of a loaded [`ProxyConfig`], shared by every subcommand that takes
+/// a config. See [`resolve_config`].
+#[derive(clap::Args, Debug, Default)]
+struct ConfigOverrides {
+    /// Overrides `listen_addr` from the config file. Also settable with `MESH_PROXY_LISTEN_ADDR`.
+    #[clap(long)]
+    listen_addr: Option<SocketAddr>,
+    /// Overrides `status_addr` from the config file. Also settable with `MESH_PROXY_STATUS_ADDR`.
+    #[clap(long)]
+    status_addr: Option<SocketAddr>,
+    /// Overrides `mesh_name` from the config file. Also settable with `MESH_PROXY_MESH_NAME`.
+    #[clap(long)]
+    mesh_name: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Binds and serves the proxy until stopped.
+    Run {
+        /// Path to a TOML config file.
+        #[clap(short, long)]
+        config: PathBuf,
+        #[clap(flatten)]
+        overrides: ConfigOverrides,
+    },
+    /// Validates configuration and connectivity (secret key, endpoint bind) without serving.
+    Doctor {
+        /// Path to a TOML config file.
+        #[clap(short, long)]
+        config: PathBuf,
+        #[clap(flatten)]
+        overrides: ConfigOverrides,
+    },
+    /// Reports where a routing key would currently be forwarded, without opening any
+    /// connection. Only sees static routes and the config's own authz policy: this binds a
+    /// fresh proxy with an empty discovery state rather than attaching to a running one, so a
+    /// key that resolves through discovery (a service name, under a live agent's registrations)
+    /// always reports "no route" here.
+    Resolve {
+        /// Path to a TOML config file.
+        #[clap(short, long)]
+        config: PathBuf,
+        /// The routing key to resolve, e.g. a `Host` header value under `ProxyMode::Http`.
+        key: String,
+    },
+    /// Prints the JSON Schema for the proxy's config file format, for validation tooling.
+    Schema,
+}
+
+fn load_config(path: &PathBuf) -> Result<ProxyConfig> {
+    let raw = std::fs::read_to_string(path)?;
+    toml::from_str(&raw).map_err(|e| {
+        iroh_mesh::error::InvalidConfigSnafu {
+            reason: e.to_string(),
+        }
+        .build()
+    })
+}
+
+fn env_socket_addr(name: &str) -> Option<SocketAddr> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+fn env_string(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Loads the config file at `path` and layers `overrides` and environment variables on top,
+/// following [`EffectiveConfig::resolve`]'s precedence (CLI > env > file > default). The only
+/// fields overridable this way are `listen_addr`, `status_addr` and `mesh_name`; everything else
+/// is only settable in the config file.
+fn resolve_config(
+    path: &PathBuf,
+    overrides: ConfigOverrides,
+) -> Result<EffectiveConfig<ProxyConfig>> {
+    let mut config = load_config(path)?;
+    Ok(EffectiveConfig::resolve(|sources| {
+        config.listen_addr = Overridable {
+            file: Some(config.listen_addr),
+            env: env_socket_addr("MESH_PROXY_LISTEN_ADDR"),
+            cli: overrides.listen_addr,
+        }
+        .resolve("listen_addr", config.listen_addr, sources);
+        config.status_addr = Overridable {
+            file: Some(config.status_addr),
+            env: env_socket_addr("MESH_PROXY_STATUS_ADDR").map(Some),
+            cli: overrides.status_addr.map(Some),
+        }
+        .resolve("status_addr", None, sources);
+        config.mesh_name = Overridable {
+            file: Some(config.mesh_name.clone()),
+            env: env_string("MESH_PROXY_MESH_NAME").map(Some),
+            cli: overrides.mesh_name.map(Some),
+        }
+        .resolve("mesh_name", None, sources);
+        config
+    }))
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    tracing_subscriber::fmt::init();
+    match Cli::parse().command {
+        Command::Run { config, overrides } => {
+            let effective = resolve_config(&config, overrides)?;
+            tracing::info!(sources = %effective.describe_sources(), "resolved config");
+            let discovery = Arc::new(DiscoveryManager::new());
+            iroh_mesh::MeshProxy::bind(effective.config, discovery)
+                .await?
+                .run()
+                .await?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Doctor { config, overrides } => {
+            let effective = resolve_config(&config, overrides)?;
+            let report = doctor::check_proxy(&effective.config).await;
+            report.print();
+            println!("-- config sources --\n{}", effective.describe_sources());
+            Ok(if report.is_ok() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+        Command::Resolve { config, key } => {
+            let config = load_config(&config)?;
+            let discovery = Arc::new(DiscoveryManager::new());
+            let proxy = iroh_mesh::MeshProxy::bind(config, discovery).await?;
+            match proxy.resolve_route(&RoutingKey::new(key)) {
+                Ok(ResolvedRoute::Backend(addr)) => {
+                    println!("static route -> backend {addr}");
+                    Ok(ExitCode::SUCCESS)
+                }
+                Ok(ResolvedRoute::Cluster {
+                    cluster_id,
+                    endpoint_id,
+                    pool_size,
+                }) => {
+                    println!(
+                        "discovery -> cluster {cluster_id} ({endpoint_id}), {pool_size} candidate(s)"
+                    );
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(err) => {
+                    eprintln!("no route: {err}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Schema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&iroh_mesh::proxy_config_schema())
+                    .expect("schema serializes")
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Recording cluster registration lifecycle transitions as Kubernetes-style `Event` objects.
+//!
+//! This crate has no Kubernetes client dependency (no `kube`, no generated CRD types -- see
+//! [`crate::election`]'s module docs for the same situation), so there is no bundled
+//! [`ClusterEventRecorder`] that actually posts a `core/v1` `Event` referencing a
+//! `ClusterRegistration` CRD yet; [`ClusterEventRecorder`] is the extension point such an
+//! implementation would fill in. Until one exists, [`ClusterEventNotifier`] is exercised by the
+//! fake recorder in this module's tests, and callers wanting events posted to a real API server
+//! need to bring their own [`ClusterEventRecorder`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The Kubernetes `Event` `type` field: `Normal` for expected lifecycle transitions, `Warning`
+/// for ones an operator should look into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSeverity {
+    /// An expected transition, e.g. a cluster being discovered or cleanly removed.
+    Normal,
+    /// A transition that degrades the mesh, e.g. a cluster becoming unreachable.
+    Warning,
+}
+
+/// Posts a single cluster lifecycle event, modeled after a Kubernetes `core/v1` `Event`'s
+/// `type`/`reason`/`message` fields.
+pub trait ClusterEventRecorder: Send + Sync + fmt::Debug {
+    /// Records that `cluster_id` experienced the transition described by `severity`, `reason`
+    /// (a short, `CamelCase` identifier the way `kubectl get events`'s `REASON` column expects,
+    /// e.g. `"ClusterUnreachable"`) and `message` (a human readable sentence).
+    fn record(&self, cluster_id: &str, severity: EventSeverity, reason: &str, message: &str);
+}
+
+/// Throttles and dispatches [`ClusterEventRecorder`] calls for [`crate::discovery::DiscoveryManager`]'s
+/// add/remove/unreachable transitions.
+///
+/// A flapping peer (rapidly cycling through a transition, e.g. going unreachable and reachable
+/// again every few seconds) would otherwise flood `kubectl get events` with one entry per cycle;
+/// this drops repeats of the *same* `(cluster_id, reason)` pair within `flap_window` rather than
+/// posting every one of them, the same way [`crate::logsample::LogSampler`] throttles noisy log
+/// lines.
+#[derive(Debug)]
+pub struct ClusterEventNotifier {
+    recorder: std::sync::Arc<dyn ClusterEventRecorder>,
+    flap_window: Duration,
+    last_emitted: Mutex<HashMap<(String, &'static str), Instant>>,
+}
+
+impl ClusterEventNotifier {
+    /// Creates a notifier that dispatches to `recorder`, suppressing repeats of the same
+    /// transition for the same cluster within `flap_window`.
+    pub fn new(recorder: std::sync::Arc<dyn ClusterEventRecorder>, flap_window: Duration) -> Self {
+        Self {
+            recorder,
+            flap_window,
+            last_emitted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A cluster was newly discovered (see [`crate::discovery::DiscoveryManager::register_cluster`]).
+    pub fn notify_added(&self, cluster_id: &str) {
+        self.notify(
+            cluster_id,
+            EventSeverity::Normal,
+            "ClusterAdded",
+            &format!("cluster {cluster_id} was discovered"),
+        );
+    }
+
+    /// A cluster was removed (see [`crate::discovery::DiscoveryManager::remove_cluster`]).
+    pub fn notify_removed(&self, cluster_id: &str) {
+        self.notify(
+            cluster_id,
+            EventSeverity::Normal,
+            "ClusterRemoved",
+            &format!("cluster {cluster_id} was removed"),
+        );
+    }
+
+    /// A cluster failed a health probe (see [`crate::discovery::DiscoveryManager::set_cluster_health`]).
+    pub fn notify_unreachable(&self, cluster_id: &str) {
+        self.notify(
+            cluster_id,
+            EventSeverity::Warning,
+            "ClusterUnreachable",
+            &format!("cluster {cluster_id} is unreachable"),
+        );
+    }
+
+    /// A registration arrived for `cluster_id` reporting a different endpoint than the one
+    /// already registered under it (see
+    /// [`crate::discovery::DiscoveryManager::set_cluster_id_collision_policy`]).
+    pub fn notify_collision(&self, cluster_id: &str) {
+        self.notify(
+            cluster_id,
+            EventSeverity::Warning,
+            "ClusterIdCollision",
+            &format!("cluster id {cluster_id} was registered with two different endpoints"),
+        );
+    }
+
+    fn notify(
+        &self,
+        cluster_id: &str,
+        severity: EventSeverity,
+        reason: &'static str,
+        message: &str,
+    ) {
+        let key = (cluster_id.to_string(), reason);
+        let now = Instant::now();
+        let mut last_emitted = self.last_emitted.lock().expect("lock poisoned");
+        if let Some(last) = last_emitted.get(&key) {
+            if now.duration_since(*last) < self.flap_window {
+                return;
+            }
+        }
+        last_emitted.insert(key, now);
+        drop(last_emitted);
+        self.recorder.record(cluster_id, severity, reason, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct FakeEventRecorder {
+        events: StdMutex<Vec<(String, EventSeverity, String)>>,
+    }
+
+    impl ClusterEventRecorder for FakeEventRecorder {
+        fn record(&self, cluster_id: &str, severity: EventSeverity, reason: &str, _message: &str) {
+            self.events.lock().expect("lock poisoned").push((
+                cluster_id.to_string(),
+                severity,
+                reason.to_string(),
+            ));
+        }
+    }
+
+    #[test]
+    fn a_removal_posts_a_normal_event_naming_the_cluster() {
+        let recorder = Arc::new(FakeEventRecorder::default());
+        let notifier = ClusterEventNotifier::new(recorder.clone(), Duration::from_secs(60));
+
+        notifier.notify_removed("cluster-a");
+
+        let events = recorder.events.lock().expect("lock poisoned");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, "cluster-a");
+        assert_eq!(events[0].1, EventSeverity::Normal);
+        assert_eq!(events[0].2, "ClusterRemoved");
+    }
+
+    #[test]
+    fn an_unreachable_transition_posts_a_warning_event() {
+        let recorder = Arc::new(FakeEventRecorder::default());
+        let notifier = ClusterEventNotifier::new(recorder.clone(), Duration::from_secs(60));
+
+        notifier.notify_unreachable("cluster-a");
+
+        let events = recorder.events.lock().expect("lock poisoned");
+        assert_eq!(events[0].1, EventSeverity::Warning);
+    }
+
+    #[test]
+    fn a_collision_posts_a_warning_event_naming_the_cluster() {
+        let recorder = Arc::new(FakeEventRecorder::default());
+        let notifier = ClusterEventNotifier::new(recorder.clone(), Duration::from_secs(60));
+
+        notifier.notify_collision("cluster-a");
+
+        let events = recorder.events.lock().expect("lock poisoned");
+        assert_eq!(events[0].0, "cluster-a");
+        assert_eq!(events[0].1, EventSeverity::Warning);
+        assert_eq!(events[0].2, "ClusterIdCollision");
+    }
+
+    #[test]
+    fn repeated_transitions_for_a_flapping_peer_are_throttled_within_the_window() {
+        let recorder = Arc::new(FakeEventRecorder::default());
+        let notifier = ClusterEventNotifier::new(recorder.clone(), Duration::from_secs(60));
+
+        for _ in 0..5 {
+            notifier.notify_unreachable("cluster-a");
+        }
+
+        assert_eq!(
+            recorder.events.lock().expect("lock poisoned").len(),
+            1,
+            "repeats within the flap window should be dropped, not posted every time"
+        );
+    }
+
+    #[test]
+    fn distinct_clusters_and_reasons_are_throttled_independently() {
+        let recorder = Arc::new(FakeEventRecorder::default());
+        let notifier = ClusterEventNotifier::new(recorder.clone(), Duration::from_secs(60));
+
+        notifier.notify_unreachable("cluster-a");
+        notifier.notify_unreachable("cluster-b");
+        notifier.notify_added("cluster-a");
+
+        assert_eq!(recorder.events.lock().expect("lock poisoned").len(), 3);
+    }
+}
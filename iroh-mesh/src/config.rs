@@ -0,0 +1,916 @@
+//! Configuration for [`crate::proxy::MeshProxy`].
+
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    affinity::{SessionAffinity, WeightedTarget},
+    authz::AuthzPolicy,
+    endpoint_discovery::EndpointDiscoveryConfig,
+    outlier::OutlierDetectionConfig,
+    ratelimit::ConnectionRateLimitConfig,
+    relay::RelayModeConfig,
+    routing::RoutingStrategyConfig,
+    secret_key::SecretKeySource,
+    socket::SocketOptions,
+};
+
+/// How a [`crate::proxy::MeshProxy`] picks the route to forward an accepted connection to.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyMode {
+    /// Forward every connection to the single configured route, without inspecting its bytes.
+    #[default]
+    Tcp,
+    /// Parse each connection's first HTTP/1.1 request far enough to read its `Host` header, and
+    /// route by it for the lifetime of the connection.
+    Http,
+}
+
+/// Configuration for a [`crate::proxy::MeshProxy`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ProxyConfig {
+    /// Address the proxy accepts client connections on.
+    pub listen_addr: SocketAddr,
+    /// How connections are matched to a route.
+    #[serde(default)]
+    pub mode: ProxyMode,
+    /// Static route table, mapping a route key (a hostname in [`ProxyMode::Http`], otherwise
+    /// ignored beyond the single entry used) to the backend address it currently forwards to.
+    #[serde(default)]
+    pub routes: HashMap<String, SocketAddr>,
+    /// TCP socket options applied to both the accepted client socket and the backend socket
+    /// opened while forwarding.
+    #[serde(default)]
+    pub socket: SocketOptions,
+    /// Address to serve the JSON status API on, if any.
+    #[serde(default)]
+    pub status_addr: Option<SocketAddr>,
+    /// Address to serve Prometheus/OpenMetrics text on, if any (see [`crate::metrics::Metrics`]).
+    /// Served in the same process as `listen_addr`, but on its own port, so a scrape doesn't
+    /// compete with `status_addr`'s JSON for the same listener.
+    #[serde(default)]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Maximum number of client connections forwarded at once. Once reached, the proxy stops
+    /// accepting new connections until one finishes, relying on the listen backlog to queue
+    /// the rest. `None` means unbounded.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Per-source-IP connection rate limiting (see [`crate::ratelimit::ConnectionRateLimiter`]),
+    /// applied in addition to [`Self::max_connections`]'s cap across every source combined.
+    /// Disabled (no per-IP limiting) when absent, matching this crate's behavior before this
+    /// setting existed.
+    #[serde(default)]
+    pub rate_limit: Option<ConnectionRateLimitConfig>,
+    /// Outlier detection for cross-cluster dials made through [`crate::proxy::MeshProxy::dial_service`].
+    /// Disabled (no ejection) when absent.
+    #[serde(default)]
+    pub outlier_detection: Option<OutlierDetectionConfig>,
+    /// How [`crate::proxy::MeshProxy::dial_service_for`] picks among a service's candidate
+    /// destinations. Stateless (first healthy, non-ejected candidate) when absent.
+    #[serde(default)]
+    pub session_affinity: Option<SessionAffinity>,
+    /// Per-service weighted targets for gradual canary/blue-green rollouts across clusters
+    /// hosting the same service, keyed by service name. A service with an entry here is picked
+    /// by weight (see [`crate::affinity::pick_weighted`]) instead of by `session_affinity`; a
+    /// service with no entry falls back to `session_affinity`, or the first candidate, as before.
+    #[serde(default)]
+    pub service_weights: HashMap<String, Vec<WeightedTarget>>,
+    /// Bounds how long a proxied exchange between a client and its backend may run before it's
+    /// torn down. `None` means unbounded.
+    ///
+    /// This only bounds the local client-to-backend forward. The deadline is not carried in the
+    /// [`crate::route_request::RouteRequest`] sent across a mesh hop, and a tunnel accepted from
+    /// another cluster (see `MeshProxy::accept_mesh_connections`) is forwarded to its local
+    /// backend without a time bound.
+    #[serde(with = "humantime_serde::option", default)]
+    #[schemars(with = "Option<String>")]
+    pub request_timeout: Option<Duration>,
+    /// Whether to prepend a PROXY protocol v2 header (see [`crate::proxy_protocol`]) to each
+    /// backend connection, carrying the original client's address so the backend can see past
+    /// the proxy. Off by default, since a backend that isn't expecting it will fail to parse the
+    /// header as its own protocol's traffic.
+    #[serde(default)]
+    pub send_proxy_protocol: bool,
+    /// Whether `listen_addr` should be bound dual-stack, accepting IPv4 clients (as IPv4-mapped
+    /// addresses) on an IPv6 listener. Only meaningful when `listen_addr` is IPv6, e.g.
+    /// `[::]:port`; rejected at bind time otherwise (see [`crate::socket::bind_listener`]).
+    #[serde(default)]
+    pub dual_stack: bool,
+    /// Identifies the logical mesh this proxy belongs to, folded into the ALPN used for mesh
+    /// hops (see [`crate::proxy::mesh_alpn`]) so that independent meshes sharing infrastructure
+    /// can't accept each other's connections. `None` uses [`crate::proxy::MESH_ALPN`] as-is.
+    #[serde(default)]
+    pub mesh_name: Option<String>,
+    /// Size, in bytes, of the buffer used to copy data between a client and its backend while
+    /// forwarding (see [`crate::proxy::splice`]). Larger values reduce the number of syscalls
+    /// needed to move a given amount of data, at the cost of more memory held per in-flight
+    /// connection -- worth raising above the default for high-throughput flows.
+    #[serde(default = "default_io_buffer_size")]
+    pub io_buffer_size: usize,
+    /// Terminates TLS locally using this certificate and key before routing the decrypted
+    /// stream by its `Host` header, instead of passing encrypted bytes straight through to the
+    /// backend. Requires [`ProxyMode::Http`] -- this crate has no separate SNI-passthrough mode
+    /// for TLS to be mutually exclusive with; [`ProxyMode::Tcp`] always forwards bytes verbatim,
+    /// encrypted or not.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Where to load this proxy's iroh secret key from. A freshly generated, unpersisted key is
+    /// used when absent, matching this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub secret_key: Option<SecretKeySource>,
+    /// Restricts which services [`crate::proxy::MeshProxy::dial_service_for`] is allowed to
+    /// dial. Every dial is allowed when absent, matching this crate's behavior before this
+    /// setting existed.
+    #[serde(default)]
+    pub authz: Option<AuthzPolicy>,
+    /// Default destinations for services [`crate::discovery::DiscoveryManager::find_service`]
+    /// has no candidate for, as `(pattern, cluster_id)` pairs tried in order against the service
+    /// name -- the first pattern that matches wins. `pattern` may contain `*` wildcards (e.g.
+    /// `"prod-*"`), matched with [`glob_match`].
+    ///
+    /// This crate has no separate namespace to match against: [`crate::discovery`]'s own docs
+    /// note that a service is scoped by the cluster that advertises it, not a namespace alongside
+    /// it, so a pattern here matches against the service name itself -- an operator wanting
+    /// `"anything in namespace prod"` names that convention in the service names it dials, e.g.
+    /// `"prod-*"`. A service with no matching pattern here still fails with
+    /// [`crate::error::MeshError::NoRoute`], exactly as before this setting existed.
+    #[serde(default)]
+    pub fallthrough_routes: Vec<(String, String)>,
+    /// Fallback backend addresses for a route in `routes`, tried in order if connecting to the
+    /// route's primary backend fails, bounded by [`Self::retry`]. A route with no entry here
+    /// isn't retried, regardless of `retry`.
+    #[serde(default)]
+    pub retry_routes: HashMap<String, Vec<SocketAddr>>,
+    /// Enables retrying a route's failed backend connection against `retry_routes`. Retrying is
+    /// disabled (the first failure is final) when absent, matching this crate's behavior before
+    /// this setting existed.
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+    /// Replaces the backend address a route in `routes` resolves to, keyed by the same route key
+    /// (a `Host` header value under [`ProxyMode::Http`], otherwise the single route forwarded
+    /// to), applied just before the backend connection is opened. Lets a deployment where the
+    /// advertised route address isn't actually dialable as-is -- e.g. an app that only binds a
+    /// loopback address behind a sidecar -- redirect to the address that is, without touching
+    /// `routes` itself. A route with no entry here connects to its `routes` address unchanged.
+    #[serde(default)]
+    pub backend_address_override: HashMap<String, SocketAddr>,
+    /// Originates TLS to a route's backend instead of forwarding plaintext, keyed by the same
+    /// route key as [`Self::routes`]. A route with no entry here forwards bytes to its backend
+    /// unchanged, regardless of whether they're themselves already encrypted.
+    #[serde(default)]
+    pub backend_tls: HashMap<String, BackendTlsConfig>,
+    /// Gates [`crate::proxy::MeshProxy::is_ready`] on successfully probing at least one cluster
+    /// known to discovery, re-evaluated continuously so readiness flips back if every peer later
+    /// becomes unreachable. The proxy reports ready immediately when this is `false`, matching
+    /// this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub require_peer_for_ready: bool,
+    /// Lets [`crate::proxy::MeshProxy::bind`] survive its iroh endpoint failing to bind (e.g. no
+    /// UDP egress, a restricted sandbox) instead of returning an error and taking the whole
+    /// process down with it. A degraded proxy still serves [`Self::routes`] and
+    /// [`Self::path_routes`] normally, since those never touch the iroh endpoint; anything
+    /// requiring one -- [`crate::proxy::MeshProxy::dial_cluster`], `dial_service`,
+    /// `dial_service_for` -- fails with [`crate::error::MeshError::MeshUnavailable`] (a 503)
+    /// instead. `false` by default, matching this crate's behavior before this setting existed:
+    /// a bind failure is fatal.
+    #[serde(default)]
+    pub allow_degraded: bool,
+    /// Minimum time between emitted lines for a given high-frequency per-connection log (e.g.
+    /// "accepted client connection"), dropping the rest rather than flooding stdout at high
+    /// connection rates. Error-level logging is never sampled. `None` logs every occurrence,
+    /// matching this crate's behavior before this setting existed.
+    #[serde(with = "humantime_serde::option", default)]
+    #[schemars(with = "Option<String>")]
+    pub log_sampling: Option<Duration>,
+    /// Additional addresses to accept connections on, each alongside `listen_addr` (see
+    /// [`crate::proxy::MeshProxy::run`]). Empty by default, matching this crate's behavior before
+    /// multiple listeners existed.
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// How often to send a QUIC-level keepalive ping on an otherwise idle mesh connection (see
+    /// [`crate::proxy::MeshProxy::dial_cluster`]), so a peer that's silently gone -- e.g. after a
+    /// network change -- is noticed by [`Self::keepalive_timeout`] instead of only surfacing when
+    /// a proxied byte fails to send. `None` uses iroh's own default keepalive behavior, matching
+    /// this crate's behavior before this setting existed.
+    #[serde(with = "humantime_serde::option", default)]
+    #[schemars(with = "Option<String>")]
+    pub keepalive_interval: Option<Duration>,
+    /// How long a mesh connection may go without receiving anything from its peer -- including
+    /// replies to [`Self::keepalive_interval`]'s pings -- before it's considered dead and closed.
+    /// Has no effect unless `keepalive_interval` is also set: a ping that's never sent can't be
+    /// missed. `None` uses iroh's own default idle timeout.
+    #[serde(with = "humantime_serde::option", default)]
+    #[schemars(with = "Option<String>")]
+    pub keepalive_timeout: Option<Duration>,
+    /// How connections are matched to a key in [`Self::routes`] under [`ProxyMode::Http`].
+    /// Defaults to [`RoutingStrategyConfig::Host`], matching this crate's behavior before this
+    /// setting existed. Ignored under [`ProxyMode::Tcp`], and by [`Self::tls`]'s local TLS
+    /// termination, which always routes the decrypted stream by its `Host` header regardless of
+    /// this setting (see [`crate::routing`]'s module docs for why).
+    #[serde(default)]
+    pub routing: RoutingStrategyConfig,
+    /// Looks up each accepted connection's pre-NAT destination via `SO_ORIGINAL_DST` (see
+    /// [`crate::socket::original_dst`]) before routing it, for deployments that intercept
+    /// arbitrary TCP traffic with an iptables `REDIRECT` rule rather than pointing clients at this
+    /// proxy directly. Only takes effect under [`ProxyMode::Http`] with [`Self::routing`] set to
+    /// [`RoutingStrategyConfig::OriginalDestinationPort`] -- every other strategy ignores the
+    /// original destination it's given, and [`ProxyMode::Tcp`] has no per-connection routing to
+    /// feed it into. Linux-only; a no-op elsewhere, since `SO_ORIGINAL_DST` is netfilter-specific.
+    /// `false` by default, matching this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub enable_interception: bool,
+    /// Which of iroh's own endpoint-discovery mechanisms this proxy's endpoint publishes to and
+    /// resolves through, used by [`crate::proxy::MeshProxy::dial_cluster`] as a fallback to find
+    /// a cluster's live addresses when discovery knows its endpoint id but not a direct address
+    /// or relay URL for it. Defaults to DNS discovery enabled, matching this crate's behavior
+    /// before this setting existed.
+    #[serde(default)]
+    pub endpoint_discovery: EndpointDiscoveryConfig,
+    /// Which relay servers this proxy's endpoint falls back to when it can't reach a peer
+    /// directly. Defaults to iroh's own production relays, matching this crate's behavior
+    /// before this setting existed. The endpoint's advertised address (see
+    /// [`crate::proxy::MeshProxy::dial_cluster`]'s doc comment) always reflects whatever this
+    /// resolves to.
+    #[serde(default)]
+    pub relay: RelayModeConfig,
+    /// Maximum number of bidirectional QUIC streams a single remote peer may have open at once
+    /// on a connection into this proxy's endpoint, enforced by iroh's own transport layer rather
+    /// than any bookkeeping of this crate's: the mesh accept loop (see
+    /// `MeshProxy::accept_mesh_connections`) neither counts nor logs the streams it accepts, so
+    /// nothing here rejects an `accept_bi` call -- a peer over the limit simply can't open another
+    /// stream until one of its existing ones finishes. Scoped per connection, which in practice means per
+    /// remote peer, since each inbound connection belongs to exactly one. `None` uses iroh's own
+    /// default, matching this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub max_streams_per_connection: Option<u32>,
+    /// Caps on the attacker-controlled buffers this proxy's data-plane parsers grow while
+    /// looking for a complete HTTP header block or TLS `ClientHello`, so a peer that never sends
+    /// one can't force unbounded memory growth. Defaults to
+    /// [`crate::httpsniff::MAX_HEADER_BYTES`] for both, matching this crate's behavior before
+    /// this setting existed. See [`ProtocolLimits`].
+    #[serde(default)]
+    pub limits: ProtocolLimits,
+    /// Fraction of connections, from `0.0` to `1.0`, sampled at accept time for detailed
+    /// per-stream tracing spans (see [`crate::proxy::MeshProxy::set_trace_sample_rate`] for
+    /// adjusting this at runtime). Every connection updates its metrics and coarse per-connection
+    /// span regardless of sampling; only the finer-grained splice spans are gated by it, since
+    /// those are the ones expensive enough at high connection rates to need throttling. `None`
+    /// samples nothing, matching this crate's behavior before this setting existed.
+    #[serde(default)]
+    pub trace_sampling: Option<f64>,
+    /// Mirrors a copy of a route's client-to-backend bytes to a secondary destination, keyed by
+    /// the same route key as [`Self::routes`], for shadow testing and canary analysis. Mirroring
+    /// is fire-and-forget: the mirror destination's responses (if any) are never read, let alone
+    /// forwarded back to the client, and a mirror destination that's slow, unreachable, or
+    /// refuses the connection outright never affects the primary connection it was mirrored
+    /// from. A route with no entry here isn't mirrored, matching this crate's behavior before
+    /// this setting existed.
+    #[serde(default)]
+    pub mirror: HashMap<String, MirrorConfig>,
+    /// Routes a request to a different backend based on its path, layered on top of
+    /// [`Self::routes`]'s host-only routing under [`ProxyMode::Http`], keyed by the same host as
+    /// `routes`. Within a host's rules, the longest [`PathRoute::path_prefix`] matching the
+    /// request's path wins; a host with no entries here, or whose request path matches none of
+    /// them, falls back to `routes`'s host-only backend. Ignored under [`ProxyMode::Tcp`], and
+    /// (like `routes` itself keyed by a `Host` value) by every [`Self::routing`] strategy other
+    /// than the default [`RoutingStrategyConfig::Host`].
+    #[serde(default)]
+    pub path_routes: HashMap<String, Vec<PathRoute>>,
+    /// How long a connection [`crate::connpool::ConnectionPool`] is holding onto for
+    /// [`crate::proxy::MeshProxy::forward_tcp_to_service`] may sit unused before it's dialed
+    /// again rather than reused. `None` disables pooling entirely -- every tunneled session opens
+    /// its own connection -- matching this crate's behavior before this setting existed. Distinct
+    /// from [`Self::max_streams_per_connection`], which caps *inbound* streams a remote peer may
+    /// open on a connection into this proxy rather than anything about connections this proxy
+    /// dials out.
+    #[serde(with = "humantime_serde::option", default)]
+    #[schemars(with = "Option<String>")]
+    pub pool_idle_timeout: Option<Duration>,
+    /// Caps how many bidirectional streams [`crate::connpool::ConnectionPool`] multiplexes onto a
+    /// single pooled outbound connection at once; a session past the cap waits for one of the
+    /// others to finish rather than failing. Only takes effect alongside
+    /// [`Self::pool_idle_timeout`]; `None` multiplexes without limit.
+    #[serde(default)]
+    pub pool_max_streams_per_connection: Option<u32>,
+    /// UDP listeners forwarding to a cross-cluster service over QUIC unreliable datagrams (see
+    /// [`crate::proxy::MeshProxy::forward_udp_to_service`]), each independent of `listen_addr`,
+    /// `mode` and `routes`, which only ever deal in TCP. Empty by default, matching this crate's
+    /// behavior before UDP forwarding existed.
+    #[serde(default)]
+    pub udp_listeners: Vec<UdpListenerConfig>,
+}
+
+/// An additional listen address for a [`crate::proxy::MeshProxy`], see [`ProxyConfig::listeners`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ListenerConfig {
+    /// Address this listener accepts client connections on.
+    pub bind_address: SocketAddr,
+    /// Backend every connection accepted on this listener forwards to, bypassing
+    /// [`ProxyConfig::mode`]'s usual routing entirely. `None` routes connections the same way as
+    /// `listen_addr`, according to `mode`.
+    #[serde(default)]
+    pub route: Option<SocketAddr>,
+}
+
+impl ListenerConfig {
+    /// Creates a new listener bound to `bind_address` with no pinned route.
+    pub fn new(bind_address: SocketAddr) -> Self {
+        Self {
+            bind_address,
+            route: None,
+        }
+    }
+
+    /// Pins every connection accepted on this listener to `route`, returning `self` for chaining.
+    /// See [`Self::route`].
+    pub fn with_route(mut self, route: SocketAddr) -> Self {
+        self.route = Some(route);
+        self
+    }
+}
+
+/// A UDP listener for a [`crate::proxy::MeshProxy`], see [`ProxyConfig::udp_listeners`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct UdpListenerConfig {
+    /// Address this listener accepts client datagrams on.
+    pub bind_address: SocketAddr,
+    /// The service every datagram accepted on this listener is forwarded to, resolved the same
+    /// way [`crate::proxy::MeshProxy::dial_service`] resolves any other service name.
+    pub service: String,
+}
+
+impl UdpListenerConfig {
+    /// Creates a new UDP listener bound to `bind_address`, forwarding to `service`.
+    pub fn new(bind_address: SocketAddr, service: impl Into<String>) -> Self {
+        Self {
+            bind_address,
+            service: service.into(),
+        }
+    }
+}
+
+/// Configuration for retrying a route's failed backend connection, see
+/// [`ProxyConfig::retry_routes`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RetryConfig {
+    /// Maximum number of [`ProxyConfig::retry_routes`] candidates tried for a route after its
+    /// primary backend's connection fails, before giving up.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+        }
+    }
+}
+
+fn default_max_retries() -> u32 {
+    1
+}
+
+/// Caps on the attacker-controlled lengths this proxy's data-plane parsers will buffer before
+/// giving up, see [`ProxyConfig::limits`]. Exceeding [`Self::max_header_bytes`] fails with
+/// [`crate::error::MeshError::HeaderTooLarge`]; exceeding [`Self::max_sni_peek`] (or any other
+/// strategy's own limit, which also defaults to [`Self::max_header_bytes`] -- see
+/// [`crate::routing::RoutingStrategy::max_peek_bytes`]) fails with
+/// [`crate::error::MeshError::RoutingKeyNotFound`].
+///
+/// Mesh frame sizes are not configurable here: a [`crate::route_request::RouteRequest`] is capped
+/// by the fixed [`crate::route_request::MAX_ROUTE_REQUEST_BYTES`], and a
+/// [`crate::protocol::MeshRequest`] or [`crate::protocol::MeshResponse`] by the fixed
+/// [`crate::protocol::MAX_FRAME_BYTES`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct ProtocolLimits {
+    /// Maximum size of an HTTP/1.1 request-line-and-headers block
+    /// [`crate::httpsniff::read_host_header`] will buffer looking for a complete header block.
+    /// Also the default for every [`crate::routing::RoutingStrategy`] that doesn't override
+    /// [`crate::routing::RoutingStrategy::max_peek_bytes`].
+    pub max_header_bytes: usize,
+    /// Maximum number of bytes [`crate::routing::SniStrategy`] will buffer looking for a
+    /// complete TLS `ClientHello`.
+    pub max_sni_peek: usize,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: crate::httpsniff::MAX_HEADER_BYTES,
+            max_sni_peek: crate::httpsniff::MAX_HEADER_BYTES,
+        }
+    }
+}
+
+/// Certificate and private key paths for [`ProxyConfig::tls`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct TlsConfig {
+    /// Path to a PEM-encoded certificate chain.
+    pub cert_path: PathBuf,
+    /// Path to a PEM-encoded private key, in PKCS#1, PKCS#8 or SEC1 format.
+    pub key_path: PathBuf,
+}
+
+/// Per-route TLS origination to the backend, configured in [`ProxyConfig::backend_tls`].
+///
+/// Distinct from [`ProxyConfig::tls`], which terminates TLS presented by the client; this
+/// originates TLS on the other side, for a backend that requires it even on this internal hop
+/// (e.g. a database requiring TLS in transit). When a route has an entry here,
+/// [`crate::proxy::MeshProxy`] wraps the connected backend `TcpStream` in a TLS client session
+/// before splicing, instead of forwarding plaintext bytes straight through.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct BackendTlsConfig {
+    /// Server name sent in the TLS `ClientHello` and checked against the backend's certificate,
+    /// independent of the address actually dialed (see [`ProxyConfig::backend_address_override`]).
+    pub server_name: String,
+    /// Path to a PEM-encoded CA certificate to trust instead of the usual public roots, for a
+    /// backend using a private or self-signed CA. Read and parsed once, eagerly, by
+    /// [`crate::proxy::build_backend_tls_connector`] so a bad path or unparsable certificate
+    /// fails before any connection is accepted rather than on the first dial. The public roots
+    /// bundled by the `webpki-roots` crate are trusted when absent.
+    #[serde(default)]
+    pub ca_path: Option<PathBuf>,
+    /// Skips verifying the backend's certificate entirely, trusting whatever is presented. Only
+    /// meant for a backend whose certificate can't be validated any other way in a test
+    /// environment -- this accepts a certificate for the wrong host, expired, or signed by an
+    /// untrusted CA alike, so it should never be set for a production route.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+/// Where and how much of a route's traffic to mirror, see [`ProxyConfig::mirror`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct MirrorConfig {
+    /// Destination the mirrored bytes are copied to.
+    pub addr: SocketAddr,
+    /// Fraction of connections on the mirrored route, from `0.0` to `1.0`, that are actually
+    /// mirrored -- sampled once per connection, the same as [`ProxyConfig::trace_sampling`], so a
+    /// high-traffic route can be shadow-tested without doubling its backend's real load.
+    #[serde(default = "default_mirror_sample_rate")]
+    pub sample_rate: f64,
+}
+
+impl MirrorConfig {
+    /// Mirrors every connection on the route to `addr`.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            sample_rate: default_mirror_sample_rate(),
+        }
+    }
+
+    /// Mirrors only a sampled fraction of connections on the route to `addr`, returning `self`
+    /// for chaining. See [`Self::sample_rate`].
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+}
+
+fn default_mirror_sample_rate() -> f64 {
+    1.0
+}
+
+/// A path-prefix rule layered on top of [`ProxyConfig::routes`]'s host-only routing, see
+/// [`ProxyConfig::path_routes`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct PathRoute {
+    /// Prefix of the request path this rule matches.
+    pub path_prefix: String,
+    /// Backend this rule forwards to.
+    pub backend: SocketAddr,
+}
+
+impl PathRoute {
+    /// Creates a rule matching `path_prefix`, forwarding to `backend`.
+    pub fn new(path_prefix: impl Into<String>, backend: SocketAddr) -> Self {
+        Self {
+            path_prefix: path_prefix.into(),
+            backend,
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Creates a new config listening on `listen_addr` with an empty route table.
+    pub fn new(listen_addr: SocketAddr) -> Self {
+        Self {
+            listen_addr,
+            mode: ProxyMode::default(),
+            routes: HashMap::new(),
+            socket: SocketOptions::default(),
+            status_addr: None,
+            metrics_addr: None,
+            max_connections: None,
+            rate_limit: None,
+            outlier_detection: None,
+            session_affinity: None,
+            service_weights: HashMap::new(),
+            request_timeout: None,
+            send_proxy_protocol: false,
+            dual_stack: false,
+            mesh_name: None,
+            io_buffer_size: default_io_buffer_size(),
+            tls: None,
+            secret_key: None,
+            authz: None,
+            fallthrough_routes: Vec::new(),
+            retry_routes: HashMap::new(),
+            backend_address_override: HashMap::new(),
+            backend_tls: HashMap::new(),
+            retry: None,
+            require_peer_for_ready: false,
+            allow_degraded: false,
+            log_sampling: None,
+            listeners: Vec::new(),
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            routing: RoutingStrategyConfig::default(),
+            enable_interception: false,
+            endpoint_discovery: EndpointDiscoveryConfig::default(),
+            relay: RelayModeConfig::default(),
+            max_streams_per_connection: None,
+            limits: ProtocolLimits::default(),
+            trace_sampling: None,
+            mirror: HashMap::new(),
+            path_routes: HashMap::new(),
+            pool_idle_timeout: None,
+            pool_max_streams_per_connection: None,
+            udp_listeners: Vec::new(),
+        }
+    }
+
+    /// Adds an additional listen address, returning `self` for chaining. See
+    /// [`Self::listeners`].
+    pub fn with_listener(mut self, listener: ListenerConfig) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Adds a UDP listener, returning `self` for chaining. See [`Self::udp_listeners`].
+    pub fn with_udp_listener(mut self, listener: UdpListenerConfig) -> Self {
+        self.udp_listeners.push(listener);
+        self
+    }
+
+    /// Sends a QUIC keepalive ping every `interval` on an idle mesh connection, closing it if
+    /// nothing is heard back within `timeout`, returning `self` for chaining. See
+    /// [`Self::keepalive_interval`] and [`Self::keepalive_timeout`].
+    pub fn with_keepalive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self.keepalive_timeout = Some(timeout);
+        self
+    }
+
+    /// Caps how many bidirectional streams a single remote peer may have open at once on a
+    /// connection into this proxy, returning `self` for chaining. See
+    /// [`Self::max_streams_per_connection`].
+    pub fn with_max_streams_per_connection(mut self, max_streams_per_connection: u32) -> Self {
+        self.max_streams_per_connection = Some(max_streams_per_connection);
+        self
+    }
+
+    /// Enables outbound connection pooling, evicting a pooled connection once it's sat unused for
+    /// `idle_timeout`, returning `self` for chaining. See [`Self::pool_idle_timeout`].
+    pub fn with_connection_pool(mut self, idle_timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Caps how many bidirectional streams may be multiplexed onto a single pooled outbound
+    /// connection at once, returning `self` for chaining. See
+    /// [`Self::pool_max_streams_per_connection`].
+    pub fn with_pool_max_streams_per_connection(mut self, max_streams_per_connection: u32) -> Self {
+        self.pool_max_streams_per_connection = Some(max_streams_per_connection);
+        self
+    }
+
+    /// Restricts which services this proxy is allowed to dial, returning `self` for chaining.
+    pub fn with_authz(mut self, authz: AuthzPolicy) -> Self {
+        self.authz = Some(authz);
+        self
+    }
+
+    /// Adds a default destination for services matching `pattern` that discovery has no
+    /// candidate for, returning `self` for chaining. See [`Self::fallthrough_routes`].
+    pub fn with_fallthrough_route(
+        mut self,
+        pattern: impl Into<String>,
+        cluster_id: impl Into<String>,
+    ) -> Self {
+        self.fallthrough_routes
+            .push((pattern.into(), cluster_id.into()));
+        self
+    }
+
+    /// Finds the first [`Self::fallthrough_routes`] pattern matching `service`, returning its
+    /// cluster id.
+    pub(crate) fn fallthrough_cluster_for(&self, service: &str) -> Option<&str> {
+        self.fallthrough_routes
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, service))
+            .map(|(_, cluster_id)| cluster_id.as_str())
+    }
+
+    /// Adds fallback backends for `route`, tried in order if its primary backend (from
+    /// [`Self::with_route`]) fails to connect, returning `self` for chaining. Has no effect
+    /// unless [`Self::with_retry`] is also set.
+    pub fn with_retry_route(
+        mut self,
+        route: impl Into<String>,
+        candidates: Vec<SocketAddr>,
+    ) -> Self {
+        self.retry_routes.insert(route.into(), candidates);
+        self
+    }
+
+    /// Redirects `route`'s backend connection to `addr` instead of whatever [`Self::with_route`]
+    /// configured for it, returning `self` for chaining. See [`Self::backend_address_override`].
+    pub fn with_backend_address_override(
+        mut self,
+        route: impl Into<String>,
+        addr: SocketAddr,
+    ) -> Self {
+        self.backend_address_override.insert(route.into(), addr);
+        self
+    }
+
+    /// Originates TLS to `route`'s backend instead of forwarding plaintext, returning `self` for
+    /// chaining. See [`Self::backend_tls`].
+    pub fn with_backend_tls(mut self, route: impl Into<String>, tls: BackendTlsConfig) -> Self {
+        self.backend_tls.insert(route.into(), tls);
+        self
+    }
+
+    /// Mirrors a copy of `route`'s client-to-backend bytes to a secondary destination, returning
+    /// `self` for chaining. See [`Self::mirror`].
+    pub fn with_mirror(mut self, route: impl Into<String>, mirror: MirrorConfig) -> Self {
+        self.mirror.insert(route.into(), mirror);
+        self
+    }
+
+    /// Adds a path-prefix rule for `host`, returning `self` for chaining. See
+    /// [`Self::path_routes`].
+    pub fn with_path_route(mut self, host: impl Into<String>, rule: PathRoute) -> Self {
+        self.path_routes.entry(host.into()).or_default().push(rule);
+        self
+    }
+
+    /// Enables retrying routes configured with [`Self::with_retry_route`], returning `self` for
+    /// chaining.
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Requires [`crate::proxy::MeshProxy::is_ready`] to have successfully probed at least one
+    /// known peer before reporting ready, returning `self` for chaining. See
+    /// [`Self::require_peer_for_ready`].
+    pub fn with_require_peer_for_ready(mut self, require_peer_for_ready: bool) -> Self {
+        self.require_peer_for_ready = require_peer_for_ready;
+        self
+    }
+
+    /// Lets [`crate::proxy::MeshProxy::bind`] degrade to local-routes-only instead of failing
+    /// outright when its iroh endpoint can't bind, returning `self` for chaining. See
+    /// [`Self::allow_degraded`].
+    pub fn with_allow_degraded(mut self, allow_degraded: bool) -> Self {
+        self.allow_degraded = allow_degraded;
+        self
+    }
+
+    /// Caps high-frequency per-connection logging to at most one line per `interval` for a given
+    /// log site, returning `self` for chaining. See [`Self::log_sampling`].
+    pub fn with_log_sampling(mut self, interval: Duration) -> Self {
+        self.log_sampling = Some(interval);
+        self
+    }
+
+    /// Sets the starting fraction of connections sampled for detailed tracing, returning `self`
+    /// for chaining. See [`Self::trace_sampling`]; [`crate::proxy::MeshProxy::set_trace_sample_rate`]
+    /// adjusts this after the proxy is already running.
+    pub fn with_trace_sampling(mut self, rate: f64) -> Self {
+        self.trace_sampling = Some(rate);
+        self
+    }
+
+    /// Sets the maximum number of concurrently forwarded connections, returning `self` for
+    /// chaining.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Enables per-source-IP connection rate limiting, returning `self` for chaining. See
+    /// [`Self::rate_limit`].
+    pub fn with_rate_limit(mut self, rate_limit: ConnectionRateLimitConfig) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Enables outlier detection for cross-cluster dials, returning `self` for chaining.
+    pub fn with_outlier_detection(mut self, outlier_detection: OutlierDetectionConfig) -> Self {
+        self.outlier_detection = Some(outlier_detection);
+        self
+    }
+
+    /// Sets how a service's candidate destinations are picked among, returning `self` for
+    /// chaining.
+    pub fn with_session_affinity(mut self, session_affinity: SessionAffinity) -> Self {
+        self.session_affinity = Some(session_affinity);
+        self
+    }
+
+    /// Sets the weighted targets a service is load balanced across, returning `self` for
+    /// chaining. See [`Self::service_weights`].
+    pub fn with_service_weights(
+        mut self,
+        service: impl Into<String>,
+        weights: Vec<WeightedTarget>,
+    ) -> Self {
+        self.service_weights.insert(service.into(), weights);
+        self
+    }
+
+    /// Bounds how long a proxied exchange may run before it's torn down, returning `self` for
+    /// chaining.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    /// Enables sending a PROXY protocol v2 header ahead of each backend connection, returning
+    /// `self` for chaining.
+    pub fn with_send_proxy_protocol(mut self, send_proxy_protocol: bool) -> Self {
+        self.send_proxy_protocol = send_proxy_protocol;
+        self
+    }
+
+    /// Binds `listen_addr` dual-stack (both IPv4 and IPv6 on one IPv6 socket), returning `self`
+    /// for chaining.
+    pub fn with_dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    /// Sets the logical mesh this proxy belongs to, returning `self` for chaining.
+    pub fn with_mesh_name(mut self, mesh_name: impl Into<String>) -> Self {
+        self.mesh_name = Some(mesh_name.into());
+        self
+    }
+
+    /// Sets the routing mode, returning `self` for chaining.
+    pub fn with_mode(mut self, mode: ProxyMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the status API address, returning `self` for chaining.
+    pub fn with_status_addr(mut self, status_addr: SocketAddr) -> Self {
+        self.status_addr = Some(status_addr);
+        self
+    }
+
+    /// Adds a route to the route table, returning `self` for chaining.
+    pub fn with_route(mut self, key: impl Into<String>, backend: SocketAddr) -> Self {
+        self.routes.insert(key.into(), backend);
+        self
+    }
+
+    /// Sets the buffer size used to copy data between a client and its backend, returning
+    /// `self` for chaining.
+    pub fn with_io_buffer_size(mut self, io_buffer_size: usize) -> Self {
+        self.io_buffer_size = io_buffer_size;
+        self
+    }
+
+    /// Terminates TLS locally using `tls`'s certificate and key, returning `self` for chaining.
+    /// Only takes effect with [`ProxyMode::Http`].
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets where this proxy's iroh secret key is loaded from, returning `self` for chaining.
+    pub fn with_secret_key(mut self, secret_key: SecretKeySource) -> Self {
+        self.secret_key = Some(secret_key);
+        self
+    }
+
+    /// Sets how connections are matched to a route under [`ProxyMode::Http`], returning `self`
+    /// for chaining. See [`Self::routing`].
+    pub fn with_routing(mut self, routing: RoutingStrategyConfig) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Enables `SO_ORIGINAL_DST` interception, returning `self` for chaining. See
+    /// [`Self::enable_interception`].
+    pub fn with_enable_interception(mut self, enable_interception: bool) -> Self {
+        self.enable_interception = enable_interception;
+        self
+    }
+
+    /// Sets which of iroh's own endpoint-discovery mechanisms this proxy's endpoint uses,
+    /// returning `self` for chaining. See [`Self::endpoint_discovery`].
+    pub fn with_endpoint_discovery(mut self, endpoint_discovery: EndpointDiscoveryConfig) -> Self {
+        self.endpoint_discovery = endpoint_discovery;
+        self
+    }
+
+    /// Sets which relay servers this proxy's endpoint falls back to, returning `self` for
+    /// chaining. See [`Self::relay`].
+    pub fn with_relay(mut self, relay: RelayModeConfig) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    /// Sets the caps on the data-plane parsers' attacker-controlled buffers, returning `self`
+    /// for chaining. See [`Self::limits`].
+    pub fn with_limits(mut self, limits: ProtocolLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+fn default_io_buffer_size() -> usize {
+    16 * 1024
+}
+
+/// Matches `value` against `pattern`, where `*` in `pattern` matches any run of characters
+/// (including none). The whole of `value` must match, not just a substring.
+///
+/// This crate has no prior glob-matching code to share, so this is a self-contained
+/// implementation: split `pattern` on `*` and check each literal segment is found in `value` in
+/// order, anchoring the first segment to the start and the last to the end (unless `pattern`
+/// itself starts or ends with `*`).
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == value;
+    }
+
+    let starts_with_star = pattern.starts_with('*');
+    let ends_with_star = pattern.ends_with('*');
+    let segments: Vec<&str> = pattern.split('*').filter(|s| !s.is_empty()).collect();
+
+    let mut rest = value;
+    for (i, segment) in segments.iter().enumerate() {
+        let anchored_start = i == 0 && !starts_with_star;
+        let anchored_end = i == segments.len() - 1 && !ends_with_star;
+        if anchored_end {
+            // Check the end of what's left rather than the segment's first occurrence, which may
+            // sit earlier than the one that ends `value`.
+            return rest.ends_with(segment);
+        }
+        match rest.find(segment) {
+            Some(pos) if anchored_start && pos != 0 => return false,
+            Some(pos) => {
+                rest = &rest[pos + segment.len()..];
+            }
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod glob_tests {
+    use super::glob_match;
+
+    #[test]
+    fn exact_pattern_requires_an_exact_match() {
+        assert!(glob_match("prod-api", "prod-api"));
+        assert!(!glob_match("prod-api", "prod-apis"));
+    }
+
+    #[test]
+    fn star_suffix_matches_a_prefix() {
+        assert!(glob_match("prod-*", "prod-api"));
+        assert!(glob_match("prod-*", "prod-"));
+        assert!(!glob_match("prod-*", "staging-api"));
+    }
+
+    #[test]
+    fn star_prefix_matches_a_suffix() {
+        assert!(glob_match("*-internal", "billing-internal"));
+        assert!(!glob_match("*-internal", "billing-external"));
+    }
+
+    #[test]
+    fn star_in_the_middle_matches_both_sides() {
+        assert!(glob_match("prod-*-internal", "prod-billing-internal"));
+        assert!(!glob_match("prod-*-internal", "staging-billing-internal"));
+    }
+
+    #[test]
+    fn lone_star_matches_anything() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*", ""));
+    }
+}
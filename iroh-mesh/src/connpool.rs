@@ -0,0 +1,341 @@
+//! Reuses a dialed cluster's iroh connection across multiple proxied TCP sessions instead of
+//! opening a fresh one for each, see [`ConnectionPool`] and
+//! [`crate::proxy::MeshProxy::forward_tcp_to_service`].
+//!
+//! Keyed by cluster id rather than the raw `EndpointId` a connection resolves to underneath,
+//! matching every other per-peer map already in [`crate::proxy::MeshProxy`] (`peer_paths`,
+//! `conn_stats`, `dial_exemplars`), all of which key on the stable cluster id a destination is
+//! dialed by. That id is known before a dial is attempted (it comes straight out of discovery,
+//! see [`crate::discovery::DiscoveryManager`]); the `EndpointId` a cluster currently resolves to
+//! is only known once [`crate::proxy::MeshProxy::dial_cluster`] has already looked it up, which
+//! is too late for a pool whose entire point is skipping that lookup on a hit.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use snafu::ResultExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::{OpenStreamSnafu, Result};
+
+/// Configuration for [`ConnectionPool`], see [`crate::config::ProxyConfig::pool_idle_timeout`]
+/// and [`crate::config::ProxyConfig::pool_max_streams_per_connection`].
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionPoolConfig {
+    /// How long a pooled connection may go unused before [`ConnectionPool::get_or_dial`] treats
+    /// it as stale and dials a fresh one instead of handing it out again.
+    pub idle_timeout: Duration,
+    /// Caps how many bidirectional streams [`PooledConnection::open_bi`] will multiplex onto a
+    /// single pooled connection at once. `None` multiplexes without limit.
+    pub max_streams_per_connection: Option<u32>,
+}
+
+/// A pooled connection and the multiplexing slots still available on it.
+#[derive(Debug)]
+struct Entry {
+    conn: Connection,
+    stream_slots: Option<Arc<Semaphore>>,
+    last_used: Instant,
+}
+
+/// Reuses already-dialed cluster connections across proxied TCP sessions, so tunneling another
+/// session to a cluster this proxy already has a live connection to multiplexes a new stream
+/// onto it instead of paying for another QUIC handshake.
+#[derive(Debug)]
+pub struct ConnectionPool {
+    config: ConnectionPoolConfig,
+    entries: RwLock<HashMap<String, Entry>>,
+}
+
+impl ConnectionPool {
+    /// Creates an empty pool with the given configuration.
+    pub fn new(config: ConnectionPoolConfig) -> Self {
+        Self {
+            config,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a connection for `cluster_id`, reused from the pool if one is still open and
+    /// wasn't last used more than [`ConnectionPoolConfig::idle_timeout`] ago; otherwise dials a
+    /// fresh one via `dial` and pools it for later calls.
+    pub async fn get_or_dial<F, Fut>(&self, cluster_id: &str, dial: F) -> Result<PooledConnection>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Connection>>,
+    {
+        if let Some(pooled) = self.take_reusable(cluster_id) {
+            return Ok(pooled);
+        }
+
+        let conn = dial().await?;
+        let stream_slots = self
+            .config
+            .max_streams_per_connection
+            .map(|n| Arc::new(Semaphore::new(n as usize)));
+        self.entries.write().expect("lock poisoned").insert(
+            cluster_id.to_string(),
+            Entry {
+                conn: conn.clone(),
+                stream_slots: stream_slots.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(PooledConnection { conn, stream_slots })
+    }
+
+    /// Removes and returns `cluster_id`'s pooled entry if it's still usable, bumping its
+    /// `last_used` time; evicts (and returns `None` for) an entry that's already closed or has
+    /// sat idle past [`ConnectionPoolConfig::idle_timeout`].
+    fn take_reusable(&self, cluster_id: &str) -> Option<PooledConnection> {
+        let mut entries = self.entries.write().expect("lock poisoned");
+        let entry = entries.get_mut(cluster_id)?;
+        if entry.conn.close_reason().is_some()
+            || entry.last_used.elapsed() > self.config.idle_timeout
+        {
+            entries.remove(cluster_id);
+            return None;
+        }
+        entry.last_used = Instant::now();
+        Some(PooledConnection {
+            conn: entry.conn.clone(),
+            stream_slots: entry.stream_slots.clone(),
+        })
+    }
+
+    /// Number of connections currently pooled, live or not -- exposed for tests.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.read().expect("lock poisoned").len()
+    }
+}
+
+/// A connection handed out by [`ConnectionPool::get_or_dial`], either reused from the pool or
+/// freshly dialed and inserted into it.
+#[derive(Debug)]
+pub struct PooledConnection {
+    conn: Connection,
+    stream_slots: Option<Arc<Semaphore>>,
+}
+
+impl PooledConnection {
+    /// The underlying iroh connection, e.g. to send a [`crate::route_request::RouteRequest`] on
+    /// before calling [`Self::open_bi`].
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Opens a new bidirectional stream on this connection for one proxied TCP session, waiting
+    /// for a free multiplexing slot first if [`ConnectionPoolConfig::max_streams_per_connection`]
+    /// caps concurrent streams -- callers wait rather than error past the cap, the same way
+    /// [`crate::proxy::MeshProxy`]'s own [`ProxyConfig::max_connections`](crate::config::ProxyConfig::max_connections)
+    /// semaphore backs off instead of rejecting.
+    pub async fn open_bi(&self, target: &str) -> Result<(SendStream, RecvStream, StreamSlot)> {
+        let slot = match &self.stream_slots {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed"),
+            ),
+            None => None,
+        };
+        let (send, recv) =
+            self.conn
+                .open_bi()
+                .await
+                .map_err(Box::new)
+                .context(OpenStreamSnafu {
+                    target: target.to_string(),
+                })?;
+        Ok((send, recv, StreamSlot(slot)))
+    }
+}
+
+/// Held alongside a stream opened via [`PooledConnection::open_bi`] for as long as the caller
+/// uses it; releases its multiplexing slot back to the pooled connection on drop. `None` when
+/// [`ConnectionPoolConfig::max_streams_per_connection`] isn't capping this connection, in which
+/// case there's no slot to release.
+#[derive(Debug)]
+pub struct StreamSlot(#[allow(dead_code)] Option<OwnedSemaphorePermit>);
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use iroh::{Endpoint, RelayMode};
+    use snafu::ResultExt;
+
+    use super::*;
+    use crate::error::BindEndpointSnafu;
+
+    const TEST_ALPN: &[u8] = b"connpool-test";
+
+    async fn bound_endpoint() -> Result<Endpoint> {
+        Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)
+    }
+
+    #[tokio::test]
+    async fn a_second_get_or_dial_reuses_the_pooled_connection() -> Result<()> {
+        let remote = bound_endpoint().await?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn(async move {
+            let incoming = remote.accept().await.expect("endpoint closed");
+            incoming.await.expect("handshake failed")
+        });
+
+        let client = bound_endpoint().await?;
+        let pool = ConnectionPool::new(ConnectionPoolConfig {
+            idle_timeout: Duration::from_secs(60),
+            max_streams_per_connection: None,
+        });
+
+        let mut dials = 0;
+        for _ in 0..2 {
+            pool.get_or_dial("cluster-a", || {
+                dials += 1;
+                let client = client.clone();
+                let remote_addr = remote_addr.clone();
+                async move {
+                    client
+                        .connect(remote_addr, TEST_ALPN)
+                        .await
+                        .map_err(Box::new)
+                        .context(crate::error::ConnectSnafu {
+                            cluster_id: "cluster-a",
+                        })
+                }
+            })
+            .await?;
+        }
+
+        assert_eq!(
+            dials, 1,
+            "the second call should reuse the pooled connection"
+        );
+        assert_eq!(pool.len(), 1);
+
+        tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_dial_past_the_idle_timeout_is_not_reused() -> Result<()> {
+        let remote = bound_endpoint().await?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn(async move {
+            let mut conns = Vec::new();
+            for _ in 0..2 {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                conns.push(incoming.await.expect("handshake failed"));
+            }
+            conns
+        });
+
+        let client = bound_endpoint().await?;
+        let pool = ConnectionPool::new(ConnectionPoolConfig {
+            idle_timeout: Duration::from_millis(1),
+            max_streams_per_connection: None,
+        });
+
+        let mut dials = 0;
+        for _ in 0..2 {
+            pool.get_or_dial("cluster-a", || {
+                dials += 1;
+                let client = client.clone();
+                let remote_addr = remote_addr.clone();
+                async move {
+                    client
+                        .connect(remote_addr, TEST_ALPN)
+                        .await
+                        .map_err(Box::new)
+                        .context(crate::error::ConnectSnafu {
+                            cluster_id: "cluster-a",
+                        })
+                }
+            })
+            .await?;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(dials, 2, "an idle-expired entry should be dialed again");
+
+        tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn open_bi_blocks_past_the_stream_cap_until_a_slot_frees_up() -> Result<()> {
+        let remote = bound_endpoint().await?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn(async move {
+            let incoming = remote.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let mut streams = Vec::new();
+            for _ in 0..2 {
+                streams.push(conn.accept_bi().await.expect("no bi stream arrived"));
+            }
+            (conn, streams)
+        });
+
+        let client = bound_endpoint().await?;
+        let pool = ConnectionPool::new(ConnectionPoolConfig {
+            idle_timeout: Duration::from_secs(60),
+            max_streams_per_connection: Some(1),
+        });
+        let pooled = pool
+            .get_or_dial("cluster-a", || async {
+                client
+                    .connect(remote_addr, TEST_ALPN)
+                    .await
+                    .map_err(Box::new)
+                    .context(crate::error::ConnectSnafu {
+                        cluster_id: "cluster-a",
+                    })
+            })
+            .await?;
+
+        let (mut send_one, recv_one, slot_one) = pooled.open_bi("cluster-a").await?;
+        // A stream opened but never written to is invisible to the peer's `accept_bi` -- a QUIC
+        // stream only exists on the wire once a frame is actually sent on it.
+        send_one.write_all(b"one").await.expect("write failed");
+
+        let second =
+            tokio::time::timeout(Duration::from_millis(100), pooled.open_bi("cluster-a")).await;
+        assert!(
+            second.is_err(),
+            "a second stream should block while the only slot is held"
+        );
+
+        drop(slot_one);
+        let (mut send_two, recv_two, _slot_two) = pooled.open_bi("cluster-a").await?;
+        send_two.write_all(b"two").await.expect("write failed");
+
+        drop(send_one);
+        drop(recv_one);
+        drop(send_two);
+        drop(recv_two);
+        let (_conn, _streams) = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked");
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! A generic consistent-hash ring with virtual nodes, mapping keys to nodes while minimizing how
+//! many keys move when a node is added or removed.
+//!
+//! A focused building block for whichever load-balancing or affinity feature wants it --
+//! [`crate::affinity::pick`] already gets the same minimal-remap property from rendezvous hashing
+//! for its own narrower case (picking among a service's live candidates by client IP), so nothing
+//! in this crate is switched over to [`HashRing`] by this module existing; it's here so a future
+//! feature needing an explicit ring (e.g. one that must enumerate or rebalance virtual nodes
+//! directly, which rendezvous hashing has no notion of) doesn't reimplement one from scratch.
+
+use std::{
+    collections::{BTreeMap, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+};
+
+/// Default number of virtual nodes placed per added node, trading ring size for a more even key
+/// distribution -- more virtual nodes smooth out the luck of individual hash values at the cost
+/// of a larger ring to search.
+const DEFAULT_VIRTUAL_NODES: usize = 100;
+
+/// Maps keys to nodes with consistent hashing: each node occupies [`Self::virtual_nodes`] points
+/// scattered around a hash ring, and a key maps to whichever point is nearest going clockwise
+/// from the key's own hash. Adding or removing a node only remaps the keys that land in its
+/// points, not the whole key space.
+#[derive(Debug, Clone)]
+pub struct HashRing<T> {
+    virtual_nodes: usize,
+    ring: BTreeMap<u64, T>,
+}
+
+impl<T> Default for HashRing<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> HashRing<T> {
+    /// Creates an empty ring with [`DEFAULT_VIRTUAL_NODES`] virtual nodes per added node.
+    pub fn new() -> Self {
+        Self::with_virtual_nodes(DEFAULT_VIRTUAL_NODES)
+    }
+
+    /// Creates an empty ring placing `virtual_nodes` points per added node (at least one).
+    /// Fewer virtual nodes means a smaller ring but a less even distribution across nodes.
+    pub fn with_virtual_nodes(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the ring has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
+
+impl<T: Clone + Eq + Hash> HashRing<T> {
+    /// Adds `node` to the ring at [`Self::virtual_nodes`] points, replacing any points already
+    /// occupied by a node that compares equal to it.
+    pub fn add(&mut self, node: T) {
+        self.remove(&node);
+        for i in 0..self.virtual_nodes {
+            self.ring.insert(hash_virtual_node(&node, i), node.clone());
+        }
+    }
+
+    /// Removes `node` and all of its virtual points from the ring.
+    pub fn remove(&mut self, node: &T) {
+        self.ring.retain(|_, existing| existing != node);
+    }
+
+    /// Returns the node `key` maps to: the node at the first point at or after `key`'s hash,
+    /// wrapping around to the ring's smallest point if `key` hashes past every point. `None` if
+    /// the ring has no nodes.
+    pub fn get<K: Hash>(&self, key: &K) -> Option<&T> {
+        let hash = hash_key(key);
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node)
+    }
+}
+
+fn hash_virtual_node<T: Hash>(node: &T, index: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    node.hash(&mut hasher);
+    index.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn keys(count: u32) -> Vec<String> {
+        (0..count).map(|i| format!("key-{i}")).collect()
+    }
+
+    #[test]
+    fn an_empty_ring_maps_nothing() {
+        let ring: HashRing<&str> = HashRing::new();
+        assert_eq!(ring.get(&"anything"), None);
+    }
+
+    #[test]
+    fn the_same_key_maps_to_the_same_node_across_many_lookups() {
+        let mut ring = HashRing::new();
+        ring.add("node-a");
+        ring.add("node-b");
+        ring.add("node-c");
+
+        let first = *ring.get(&"a-key").unwrap();
+        for _ in 0..100 {
+            assert_eq!(*ring.get(&"a-key").unwrap(), first);
+        }
+    }
+
+    #[test]
+    fn keys_distribute_reasonably_evenly_across_nodes() {
+        let mut ring = HashRing::new();
+        let nodes = ["node-a", "node-b", "node-c", "node-d"];
+        for node in nodes {
+            ring.add(node);
+        }
+
+        let mut counts: HashMap<&str, u32> = HashMap::new();
+        const SAMPLES: u32 = 20_000;
+        for key in keys(SAMPLES) {
+            *counts.entry(*ring.get(&key).unwrap()).or_default() += 1;
+        }
+
+        let expected = f64::from(SAMPLES) / nodes.len() as f64;
+        for node in nodes {
+            let share = f64::from(counts.get(node).copied().unwrap_or(0)) / expected;
+            assert!(
+                (0.8..1.2).contains(&share),
+                "{node} should get roughly an even share of keys, got {share:.2}x expected"
+            );
+        }
+    }
+
+    #[test]
+    fn adding_a_node_only_remaps_a_fraction_of_keys() {
+        let mut ring = HashRing::new();
+        for node in ["node-a", "node-b", "node-c"] {
+            ring.add(node);
+        }
+        let sample = keys(10_000);
+        let before: Vec<&str> = sample.iter().map(|k| *ring.get(k).unwrap()).collect();
+
+        ring.add("node-d");
+        let after: Vec<&str> = sample.iter().map(|k| *ring.get(k).unwrap()).collect();
+
+        let moved = before.iter().zip(&after).filter(|(b, a)| b != a).count();
+        let moved_fraction = f64::from(u32::try_from(moved).unwrap()) / sample.len() as f64;
+        // With 4 nodes afterward, an even remapping would move about 1/4 of keys (whatever lands
+        // on the new node); a full reshuffle would move close to all of them.
+        assert!(
+            moved_fraction < 0.4,
+            "adding a node should only remap a fraction of keys, moved {moved_fraction:.2}"
+        );
+        for (before_node, after_node) in before.iter().zip(&after) {
+            if before_node != after_node {
+                assert_eq!(
+                    *after_node, "node-d",
+                    "a key should only move to the newly added node, not to an existing one"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn removing_a_node_only_remaps_the_keys_that_were_assigned_to_it() {
+        let mut ring = HashRing::new();
+        for node in ["node-a", "node-b", "node-c"] {
+            ring.add(node);
+        }
+        let sample = keys(10_000);
+        let before: HashMap<&String, &str> =
+            sample.iter().map(|k| (k, *ring.get(k).unwrap())).collect();
+
+        ring.remove(&"node-b");
+
+        for key in &sample {
+            let after = *ring.get(key).unwrap();
+            let previous = before[key];
+            if previous == "node-b" {
+                assert_ne!(after, "node-b", "the removed node can't be picked anymore");
+            } else {
+                assert_eq!(
+                    after, previous,
+                    "a key not assigned to the removed node should keep its mapping"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Coalescing rapid writes into bounded-rate, eventually-consistent writes.
+//!
+//! Motivated by [`crate::clusterevents`]'s and [`crate::election`]'s Kubernetes extension
+//! points: once something actually patches a CRD on every local service change, a rollout
+//! restarting many pods at once would otherwise trigger one API server write per pod.
+//! [`DebouncedWriter`] sits in front of any [`RegistrationWriter`], coalescing writes that arrive
+//! within a short window into a single write of the latest state, backed off by a hard minimum
+//! interval between writes -- while still guaranteeing the final state is eventually persisted,
+//! even if updates stop arriving mid-window.
+
+use std::{fmt, sync::Mutex};
+
+use tokio::time::{Duration, Instant};
+
+/// Persists a single state update, replacing whatever was written before. The extension point
+/// [`DebouncedWriter`] coalesces calls to.
+///
+/// This crate has no Kubernetes client dependency (no `kube`, no generated CRD types -- see
+/// [`crate::election`]'s module docs for the same situation), so nothing currently implements
+/// this against a real CRD; it's exercised by the fake writer in this module's tests.
+pub trait RegistrationWriter<T>: Send + Sync + fmt::Debug {
+    /// Persists `state`.
+    fn write(&self, state: T);
+}
+
+#[derive(Debug)]
+struct PendingState<T> {
+    /// The latest update still waiting to be flushed, replaced (not queued) by every
+    /// [`DebouncedWriter::update`] call until a flush takes it.
+    pending: Option<T>,
+    /// Whether a flush is already scheduled, so a burst of updates within one window doesn't
+    /// schedule more than one.
+    flush_scheduled: bool,
+    last_write: Option<Instant>,
+}
+
+/// Coalesces rapid [`Self::update`] calls into at most one [`RegistrationWriter::write`] per
+/// `debounce_window`, never writing more often than `min_interval`.
+#[derive(Debug)]
+pub struct DebouncedWriter<T> {
+    writer: std::sync::Arc<dyn RegistrationWriter<T>>,
+    debounce_window: Duration,
+    min_interval: Duration,
+    state: std::sync::Arc<Mutex<PendingState<T>>>,
+}
+
+impl<T: Send + 'static> DebouncedWriter<T> {
+    /// Creates a writer that coalesces updates within `debounce_window` into a single write to
+    /// `writer`, never writing more often than `min_interval`.
+    pub fn new(
+        writer: std::sync::Arc<dyn RegistrationWriter<T>>,
+        debounce_window: Duration,
+        min_interval: Duration,
+    ) -> Self {
+        Self {
+            writer,
+            debounce_window,
+            min_interval,
+            state: std::sync::Arc::new(Mutex::new(PendingState {
+                pending: None,
+                flush_scheduled: false,
+                last_write: None,
+            })),
+        }
+    }
+
+    /// Queues `value` to be written, replacing any update still waiting to be flushed.
+    ///
+    /// Schedules a flush after [`Self::debounce_window`] (or later, if needed to respect
+    /// [`Self::min_interval`] since the last write) unless one is already scheduled, so a burst
+    /// of updates within one window produces exactly one write of the latest state.
+    pub fn update(&self, value: T) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.pending = Some(value);
+        if state.flush_scheduled {
+            return;
+        }
+        state.flush_scheduled = true;
+        let delay = match state.last_write {
+            Some(last_write) => self
+                .debounce_window
+                .max(self.min_interval.saturating_sub(last_write.elapsed())),
+            None => self.debounce_window,
+        };
+        drop(state);
+
+        let writer = self.writer.clone();
+        let state_handle = self.state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let value = {
+                let mut state = state_handle.lock().expect("lock poisoned");
+                let value = state.pending.take();
+                state.flush_scheduled = false;
+                state.last_write = Some(Instant::now());
+                value
+            };
+            if let Some(value) = value {
+                writer.write(value);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct FakeWriter {
+        writes: StdMutex<Vec<u32>>,
+    }
+
+    impl RegistrationWriter<u32> for FakeWriter {
+        fn write(&self, state: u32) {
+            self.writes.lock().expect("lock poisoned").push(state);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_burst_of_rapid_updates_produces_one_write_of_the_final_state() {
+        let writer = Arc::new(FakeWriter::default());
+        let debounced = DebouncedWriter::new(
+            writer.clone(),
+            Duration::from_millis(50),
+            Duration::from_millis(50),
+        );
+
+        for value in 0..50 {
+            debounced.update(value);
+        }
+        // Let the flush task reach its `sleep` and register its timer before advancing the
+        // clock, since the deadline is computed from whenever the task is first polled.
+        tokio::task::yield_now().await;
+
+        tokio::time::advance(Duration::from_millis(60)).await;
+        tokio::task::yield_now().await;
+
+        let writes = writer.writes.lock().expect("lock poisoned").clone();
+        assert_eq!(
+            writes,
+            vec![49],
+            "exactly one write should have occurred, carrying the last queued value"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_second_burst_waits_out_the_minimum_interval_before_writing_again() {
+        let writer = Arc::new(FakeWriter::default());
+        let debounced = DebouncedWriter::new(
+            writer.clone(),
+            Duration::from_millis(10),
+            Duration::from_millis(200),
+        );
+
+        debounced.update(1);
+        tokio::task::yield_now().await;
+        tokio::time::advance(Duration::from_millis(20)).await;
+        tokio::task::yield_now().await;
+        assert_eq!(*writer.writes.lock().expect("lock poisoned"), vec![1]);
+
+        // A second update arrives well before `min_interval` has elapsed since the first write.
+        tokio::time::advance(Duration::from_millis(20)).await;
+        debounced.update(2);
+        tokio::task::yield_now().await;
+        tokio::time::advance(Duration::from_millis(20)).await;
+        tokio::task::yield_now().await;
+        assert_eq!(
+            *writer.writes.lock().expect("lock poisoned"),
+            vec![1],
+            "the second write shouldn't happen until min_interval has elapsed since the first"
+        );
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        tokio::task::yield_now().await;
+        assert_eq!(*writer.writes.lock().expect("lock poisoned"), vec![1, 2]);
+    }
+}
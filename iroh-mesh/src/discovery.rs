@@ -0,0 +1,1331 @@
+//! Tracks clusters known to a [`crate::agent::MeshAgent`].
+//!
+//! [`DiscoveryManager::register_cluster`]'s own docs already treat a "kube CRD watch" as just
+//! another source feeding it registrations, alongside gossip and static config -- so a real
+//! `meshclusters.iroh.dev` CRD reconciler, once one exists, only needs a [`ClusterStore`] to read
+//! peers' registrations from and [`DiscoveryManager::sync_from_store`] to apply them, plus
+//! whatever writes this agent's own registration back to the CRD on its own. This crate has no
+//! Kubernetes client of its own (see [`crate::election`]'s module docs for the same gap), so
+//! nothing implements [`ClusterStore`] against a real CRD yet -- it's exercised by the fake store
+//! in this module's tests.
+//!
+//! **Status: partial, not a CRD controller.** There is no `meshclusters.iroh.dev` CRD, no watch
+//! against one, and nothing writing this agent's own registration to a shared store -- only the
+//! read side ([`ClusterStore`]) and apply step ([`DiscoveryManager::sync_from_store`]) a
+//! reconciler built on top of a real `kube` dependency would still need to supply.
+
+use std::{
+    collections::{HashMap, hash_map::DefaultHasher},
+    fmt,
+    hash::{Hash, Hasher},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use iroh::EndpointAddr;
+use iroh_base::EndpointId;
+use n0_watcher::{Watchable, Watcher};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, ensure};
+
+use crate::{
+    clusterevents::{ClusterEventNotifier, ClusterEventRecorder},
+    error::{InvalidConfigSnafu, InvalidRelayUrlSnafu, Result},
+    metrics::DiscoveryMetrics,
+};
+
+/// Smallest non-zero [`DiscoveryConfig::probe_interval`] [`DiscoveryConfig::validate`] accepts,
+/// short of disabling the probe loop outright with [`Duration::ZERO`]. Guards against a
+/// misconfigured agent hammering every known cluster in a tight loop.
+const MIN_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lists the [`ClusterRegistration`]s a CRD-backed cluster registry currently holds, for
+/// [`DiscoveryManager::sync_from_store`] to register. See the [module docs](self) for what's still
+/// missing to back this with a real `meshclusters.iroh.dev` CRD.
+pub trait ClusterStore: Send + Sync + fmt::Debug {
+    /// Returns every registration the store currently holds, hitting whatever backs it.
+    fn list(&self) -> Vec<ClusterRegistration>;
+}
+
+/// A request to register a remote cluster with a [`DiscoveryManager`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterRegistration {
+    /// Unique identifier of the cluster, e.g. its Kubernetes cluster name.
+    pub cluster_id: String,
+    /// The iroh endpoint that terminates mesh connections for this cluster.
+    pub endpoint_id: EndpointId,
+    /// The relay URL the cluster's endpoint is reachable through, if any.
+    #[serde(default)]
+    pub relay_url: Option<String>,
+    /// Direct addresses the cluster's endpoint has advertised.
+    #[serde(default)]
+    pub direct_addresses: Vec<SocketAddr>,
+    /// Services this cluster advertises, e.g. route keys its proxy can forward to.
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// Named ports for services in [`Self::services`], e.g. a Kubernetes service's `http` or
+    /// `grpc` port. A service with no entries here can still be found by
+    /// [`DiscoveryManager::find_service`]; it just has no port a caller can resolve by name.
+    #[serde(default)]
+    pub service_ports: Vec<ServicePort>,
+    /// When this snapshot of the cluster was produced by its source, used by
+    /// [`DiscoveryManager::register_cluster`] to resolve conflicting registrations of the same
+    /// cluster id from different sources. Defaults to the time the registration is received,
+    /// which is right for a source with no better timestamp of its own to report.
+    #[serde(default = "SystemTime::now")]
+    pub updated_at: SystemTime,
+    /// Marks this as a statically configured registration that shouldn't be overwritten by a
+    /// dynamic source's (gossip, health-derived, etc.) possibly-stale view of the same cluster.
+    /// See [`DiscoveryManager::register_cluster`].
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Everything the proxy knows about a remote cluster.
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ClusterInfo {
+    /// Unique identifier of the cluster.
+    pub cluster_id: String,
+    /// The iroh endpoint that terminates mesh connections for this cluster.
+    pub endpoint_id: EndpointId,
+    /// The relay URL the cluster's endpoint is reachable through, if any.
+    pub relay_url: Option<String>,
+    /// Direct addresses the cluster's endpoint has advertised.
+    pub direct_addresses: Vec<SocketAddr>,
+    /// Services this cluster advertises.
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// Named ports for services in [`Self::services`]. See
+    /// [`ClusterRegistration::service_ports`].
+    #[serde(default)]
+    pub service_ports: Vec<ServicePort>,
+    /// See [`ClusterRegistration::updated_at`].
+    #[serde(default = "SystemTime::now")]
+    pub updated_at: SystemTime,
+    /// See [`ClusterRegistration::pinned`].
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl ClusterInfo {
+    /// Reconstructs the [`EndpointAddr`] a dial to this cluster should use, parsing
+    /// [`Self::relay_url`] and attaching [`Self::direct_addresses`].
+    ///
+    /// Centralizes the reassembly every dial site used to do by hand, so callers don't each parse
+    /// [`Self::relay_url`] themselves -- and, unlike those hand-rolled versions, surfaces a
+    /// malformed relay URL as [`MeshError::InvalidRelayUrl`](crate::error::MeshError::InvalidRelayUrl)
+    /// instead of silently dialing without one. A [`Self::relay_url`] of `None` (an endpoint id
+    /// known only from gossip or a static registration that never learned its addresses) simply
+    /// produces an [`EndpointAddr`] with no relay, which iroh's own endpoint discovery then tries
+    /// to resolve on its own.
+    pub fn endpoint_addr(&self) -> Result<EndpointAddr> {
+        let mut addr = EndpointAddr::new(self.endpoint_id)
+            .with_direct_addresses(self.direct_addresses.iter().copied());
+        if let Some(relay_url) = &self.relay_url {
+            let relay_url: iroh::RelayUrl =
+                relay_url.parse().with_context(|_| InvalidRelayUrlSnafu {
+                    cluster_id: self.cluster_id.clone(),
+                    relay_url: relay_url.clone(),
+                })?;
+            addr = addr.with_relay_url(relay_url);
+        }
+        Ok(addr)
+    }
+}
+
+impl From<ClusterRegistration> for ClusterInfo {
+    fn from(reg: ClusterRegistration) -> Self {
+        Self {
+            cluster_id: reg.cluster_id,
+            endpoint_id: reg.endpoint_id,
+            relay_url: reg.relay_url,
+            direct_addresses: filter_routable_addresses(reg.direct_addresses),
+            services: reg.services,
+            service_ports: reg.service_ports,
+            updated_at: reg.updated_at,
+            pinned: reg.pinned,
+        }
+    }
+}
+
+/// Drops loopback, unspecified and link-local addresses out of a registration's direct
+/// addresses, in order and without deduplicating (that's [`merge_cluster_info`]'s job).
+///
+/// None of these are reachable from another cluster's proxy, so advertising them just adds a
+/// dial candidate to [`ClusterInfo::endpoint_addr`] that can never succeed -- at best a wasted
+/// connection attempt, at worst (a loopback or unspecified address that happens to also be
+/// listening on the far side) a dial to the wrong host entirely.
+///
+/// Nothing in this crate needs a link-local address to be dialable today (that would mean two
+/// clusters sharing a link, e.g. bare-metal boxes on the same L2 segment), so there's no override
+/// for it yet -- add one to [`DiscoveryConfig`] if a caller grows that need.
+fn filter_routable_addresses(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    addrs
+        .into_iter()
+        .filter(|addr| {
+            let ip = addr.ip();
+            !ip.is_loopback() && !ip.is_unspecified() && !is_link_local(ip)
+        })
+        .collect()
+}
+
+/// Whether `ip` is link-local, i.e. only reachable from a host sharing the same link.
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_unicast_link_local(),
+    }
+}
+
+/// Merges `incoming` into `existing` for the same cluster id: direct addresses are unioned (so a
+/// source that hasn't heard about a newly advertised address yet doesn't cause it to be
+/// forgotten), and every other field comes from whichever of the two wins.
+///
+/// A [`ClusterInfo::pinned`] entry always wins over a non-pinned one regardless of
+/// [`ClusterInfo::updated_at`], so a statically configured cluster can't be clobbered by a
+/// flappy dynamic source; between two pinned or two non-pinned entries, the newer `updated_at`
+/// wins.
+fn merge_cluster_info(existing: &ClusterInfo, incoming: &ClusterInfo) -> ClusterInfo {
+    let direct_addresses = existing
+        .direct_addresses
+        .iter()
+        .chain(incoming.direct_addresses.iter())
+        .fold(Vec::new(), |mut addrs, &addr| {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+            addrs
+        });
+    let winner = match (existing.pinned, incoming.pinned) {
+        (true, false) => existing,
+        (false, true) => incoming,
+        _ if incoming.updated_at >= existing.updated_at => incoming,
+        _ => existing,
+    };
+    ClusterInfo {
+        direct_addresses,
+        ..winner.clone()
+    }
+}
+
+/// A named port on a service a cluster advertises, e.g. `("http", 8080)` the way a Kubernetes
+/// `Service` resource names its ports.
+///
+/// This crate has no Kubernetes client of its own (see [`crate::election`]'s module docs for the
+/// same limitation elsewhere), so nothing here populates this automatically from a live cluster
+/// yet -- callers that discover services from Kubernetes are expected to fill it in themselves
+/// when building a [`ClusterRegistration`], e.g. from that `Service` resource's own port-mapping
+/// annotations.
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ServicePort {
+    /// Name of the service this port belongs to, matching an entry in
+    /// [`ClusterRegistration::services`].
+    pub service: String,
+    /// Name of the port, e.g. `"http"` or `"grpc"`.
+    pub name: String,
+    /// The port number `name` resolves to mesh-wide -- what [`DiscoveryManager::find_service_port`]
+    /// returns to a caller elsewhere in the mesh, and what this cluster's `ClusterInfo` advertises
+    /// it as. May differ from [`Self::internal_port`], letting a service listening on one port
+    /// locally (e.g. `8080`) be exposed under another across the mesh (e.g. `80`), or letting only
+    /// a subset of a service's actual ports be exported at all -- a port never added here is
+    /// simply not advertised, regardless of what the service itself listens on.
+    pub port: u16,
+    /// The port this service's backend actually listens on, if different from [`Self::port`].
+    /// `None` means `port` is both the advertised and the actual port -- no remapping.
+    /// [`Self::backend_port`] computes that translation; the inbound tunnel handler (see
+    /// `MeshProxy::accept_mesh_connections`) does not call it, and forwards to whatever address
+    /// [`crate::config::ProxyConfig::routes`] maps the service name to.
+    #[serde(default)]
+    pub internal_port: Option<u16>,
+}
+
+impl ServicePort {
+    /// Advertises `service`'s `name` port as `port`, mesh-wide, with no remapping: the service's
+    /// backend actually listens on `port` too. Use [`Self::remapped`] instead when the advertised
+    /// and internal port numbers differ.
+    pub fn new(service: impl Into<String>, name: impl Into<String>, port: u16) -> Self {
+        Self {
+            service: service.into(),
+            name: name.into(),
+            port,
+            internal_port: None,
+        }
+    }
+
+    /// Advertises `service`'s `name` port as `advertised_port` mesh-wide, while the service's
+    /// backend actually listens on `internal_port`. See [`Self::backend_port`] for translating
+    /// back.
+    pub fn remapped(
+        service: impl Into<String>,
+        name: impl Into<String>,
+        internal_port: u16,
+        advertised_port: u16,
+    ) -> Self {
+        Self {
+            service: service.into(),
+            name: name.into(),
+            port: advertised_port,
+            internal_port: Some(internal_port),
+        }
+    }
+
+    /// The port a connection for this service should actually be forwarded to locally:
+    /// [`Self::internal_port`] if this port is remapped, otherwise the advertised [`Self::port`]
+    /// itself.
+    pub fn backend_port(&self) -> u16 {
+        self.internal_port.unwrap_or(self.port)
+    }
+}
+
+/// Configuration for active health checking of discovered clusters' services.
+///
+/// Probing is scoped to whole clusters: this crate has no per-service liveness protocol, so a
+/// probe's result is applied to every service the probed cluster advertises.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DiscoveryConfig {
+    /// How often each known cluster is probed.
+    ///
+    /// [`Duration::ZERO`] disables periodic probing entirely, relying solely on event-driven
+    /// updates -- an unhealthy cluster is only cleared once it re-registers (see
+    /// [`DiscoveryManager::register_cluster`]) rather than on its next successful probe. Any other
+    /// value below [`Self::validate`]'s minimum is rejected rather than silently clamped.
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub probe_interval: Duration,
+    /// Consecutive failed probes before a cluster's services are marked unhealthy. A single
+    /// successful probe clears the count and marks them healthy again.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Maximum number of clusters [`crate::health::HealthChecker::probe_once`] probes
+    /// concurrently in a single pass, bounding how much load one probing cycle places on this
+    /// agent and the probed clusters at once.
+    #[serde(default = "default_max_concurrent_probes")]
+    pub max_concurrent_probes: usize,
+    /// Overall time budget for a single [`crate::health::HealthChecker::probe_once`] pass across
+    /// every known cluster, so a handful of slow or unreachable clusters can't push the whole
+    /// cycle past [`Self::probe_interval`]. Probes still in flight when the budget runs out are
+    /// abandoned; whatever results arrived before then are still recorded.
+    #[serde(with = "humantime_serde", default = "default_probe_budget")]
+    #[schemars(with = "String")]
+    pub probe_budget: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_secs(15),
+            failure_threshold: default_failure_threshold(),
+            max_concurrent_probes: default_max_concurrent_probes(),
+            probe_budget: default_probe_budget(),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// Rejects a [`Self::probe_interval`] that would either hammer known clusters in a tight loop
+    /// (anything nonzero below [`MIN_PROBE_INTERVAL`]) or never probe by mistake -- callers that
+    /// actually want probing disabled should set it to exactly [`Duration::ZERO`], not some
+    /// implausibly small value that was really meant to mean "as often as possible".
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.probe_interval.is_zero() || self.probe_interval >= MIN_PROBE_INTERVAL,
+            InvalidConfigSnafu {
+                reason: format!(
+                    "discovery probe_interval {:?} is too small; use {:?} or larger, or {:?} to \
+                     disable periodic probing",
+                    self.probe_interval,
+                    MIN_PROBE_INTERVAL,
+                    Duration::ZERO,
+                ),
+            }
+        );
+        Ok(())
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_max_concurrent_probes() -> usize {
+    16
+}
+
+fn default_probe_budget() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Hashes the fields of `info` that matter for change detection in
+/// [`DiscoveryManager::register_cluster`].
+///
+/// Sensitive to the order of `direct_addresses` and `services`: a registration that reports the
+/// same set in a different order is treated as a change. Sources register a small, typically
+/// stably-ordered list, so this is simpler than normalizing order for a cost not worth paying
+/// here.
+fn snapshot_hash(info: &ClusterInfo) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    info.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What [`DiscoveryManager::register_cluster`] does when an incoming registration reports the
+/// same [`ClusterRegistration::cluster_id`] as an existing entry but a different
+/// [`ClusterRegistration::endpoint_id`] -- two clusters misconfigured with the same id, which
+/// [`merge_cluster_info`]'s usual address-union merge would otherwise resolve by silently
+/// picking one endpoint's identity over the other's, leaving routing to nondeterministically
+/// favor whichever last happened to win.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClusterIdCollisionPolicy {
+    /// Keep whichever entry is already registered and ignore the colliding one. The safer
+    /// default: a newly misconfigured cluster can't silently take over an id a correctly
+    /// configured one already holds.
+    #[default]
+    KeepExisting,
+    /// Accept the incoming registration, replacing the existing entry outright rather than
+    /// merging the two (merging would union direct addresses that belong to two different
+    /// endpoints, which is never correct).
+    Overwrite,
+}
+
+/// Builds a [`DiscoveryManager`] pre-seeded with an initial cluster set, see
+/// [`DiscoveryManager::builder`].
+///
+/// This crate has no kube client of its own (see [`crate::election`]'s module docs for the same
+/// gap): [`DiscoveryManager`] is always the plain in-memory registry defined below, with no
+/// pluggable backing store to inject one into, so this builder only covers pre-seeding that
+/// registry's initial contents deterministically rather than also accepting a store
+/// implementation.
+#[derive(Debug, Default)]
+pub struct DiscoveryManagerBuilder {
+    clusters: Vec<ClusterRegistration>,
+}
+
+impl DiscoveryManagerBuilder {
+    /// Adds `cluster` to the initial set [`Self::build`] registers before returning.
+    pub fn with_cluster(mut self, cluster: ClusterRegistration) -> Self {
+        self.clusters.push(cluster);
+        self
+    }
+
+    /// Builds the discovery manager, registering every cluster added via [`Self::with_cluster`]
+    /// in order (see [`DiscoveryManager::register_cluster`]).
+    pub fn build(self) -> DiscoveryManager {
+        let manager = DiscoveryManager::new();
+        for cluster in self.clusters {
+            manager.register_cluster(cluster);
+        }
+        manager
+    }
+}
+
+/// In-memory registry of the clusters known to this agent.
+///
+/// Entries are added either by discovery or, for bootstrapping and debugging, by manual
+/// registration through the agent HTTP API.
+#[derive(Debug, Default, Clone)]
+pub struct DiscoveryManager {
+    clusters: Arc<RwLock<HashMap<String, ClusterInfo>>>,
+    /// Health of a cluster's advertised services, keyed by `(cluster_id, service)`. Absent
+    /// entries are treated as healthy, so routing isn't blocked on health checking being
+    /// enabled at all.
+    service_health: Arc<RwLock<HashMap<(String, String), bool>>>,
+    /// When a cluster was last registered, i.e. the most recent successful discovery pass.
+    /// `None` until the first registration.
+    last_registered_at: Arc<RwLock<Option<SystemTime>>>,
+    /// Hash of the last registered [`ClusterInfo`] per cluster id, used by
+    /// [`Self::register_cluster`] to tell a real change from a repeat of the same snapshot.
+    last_snapshot_hashes: Arc<RwLock<HashMap<String, u64>>>,
+    /// Bumped whenever [`Self::register_cluster`] or [`Self::remove_cluster`] actually changes
+    /// what's known, for [`Self::watch_changes`] to notify on.
+    changes: Watchable<u64>,
+    /// Posts add/remove/unreachable transitions as events (see [`crate::clusterevents`]). Unset
+    /// (no events posted) when absent, matching this crate's behavior before event recording
+    /// existed.
+    event_notifier: Arc<RwLock<Option<Arc<ClusterEventNotifier>>>>,
+    /// See [`Self::set_cluster_id_collision_policy`].
+    collision_policy: Arc<RwLock<ClusterIdCollisionPolicy>>,
+    /// Known cluster count and cluster id collisions (see [`Self::register_cluster`]), also
+    /// readable directly via [`Self::cluster_id_collisions_total`] for tests and callers that
+    /// don't want to stand up a metrics registry just to read one counter. See [`Self::metrics`]
+    /// for exposing these on a `/metrics` endpoint alongside [`crate::metrics::Metrics`].
+    metrics: Arc<DiscoveryMetrics>,
+}
+
+impl DiscoveryManager {
+    /// Creates an empty discovery manager.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts building a discovery manager pre-seeded with an initial cluster set instead of an
+    /// empty one, for tests and embedders that want deterministic state without waiting on a real
+    /// discovery source to populate it. See [`DiscoveryManagerBuilder`].
+    pub fn builder() -> DiscoveryManagerBuilder {
+        DiscoveryManagerBuilder::default()
+    }
+
+    /// Posts [`ClusterEventNotifier::notify_added`]/`notify_removed`/`notify_unreachable` calls
+    /// to `recorder` from now on, suppressing repeats of the same transition for the same cluster
+    /// within `flap_window`. See [`crate::clusterevents`].
+    pub fn set_event_recorder(
+        &self,
+        recorder: Arc<dyn ClusterEventRecorder>,
+        flap_window: Duration,
+    ) {
+        *self.event_notifier.write().expect("lock poisoned") =
+            Some(Arc::new(ClusterEventNotifier::new(recorder, flap_window)));
+    }
+
+    /// The currently configured event notifier, if any (see [`Self::set_event_recorder`]).
+    fn event_notifier(&self) -> Option<Arc<ClusterEventNotifier>> {
+        self.event_notifier.read().expect("lock poisoned").clone()
+    }
+
+    /// Sets how [`Self::register_cluster`] resolves a cluster id collision (see
+    /// [`ClusterIdCollisionPolicy`]) from now on. Defaults to
+    /// [`ClusterIdCollisionPolicy::KeepExisting`].
+    pub fn set_cluster_id_collision_policy(&self, policy: ClusterIdCollisionPolicy) {
+        *self.collision_policy.write().expect("lock poisoned") = policy;
+    }
+
+    /// How many cluster id collisions [`Self::register_cluster`] has detected so far (see
+    /// [`ClusterIdCollisionPolicy`]). Corresponds to [`Self::metrics`]'s
+    /// `mesh_discovery_cluster_id_collisions_total` counter.
+    pub fn cluster_id_collisions_total(&self) -> u64 {
+        self.metrics.cluster_id_collisions_total.get()
+    }
+
+    /// This discovery manager's metrics group, for registering into an
+    /// [`iroh_metrics::Registry`] served on a `/metrics` endpoint (see
+    /// [`crate::agent::AgentConfig::metrics_addr`]).
+    pub fn metrics(&self) -> Arc<DiscoveryMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Registers a cluster, always refreshing [`Self::last_registered_at`] as a heartbeat.
+    ///
+    /// When a cluster with this id is already known -- e.g. reported by another discovery source
+    /// -- and reports the *same* endpoint, the two registrations are merged rather than one
+    /// clobbering the other (see [`merge_cluster_info`]), so that multiple sources (a kube CRD
+    /// watch, gossip, a static config entry, ...) disagreeing or racing on the same cluster id
+    /// don't fight: the union of both sources' direct addresses is kept, and a pinned or more
+    /// recently updated source wins on everything else.
+    ///
+    /// When an already-known cluster id is instead reported with a *different* endpoint -- two
+    /// clusters misconfigured with the same id -- this logs a warning, records a cluster id
+    /// collision event (see [`crate::clusterevents::ClusterEventNotifier::notify_collision`]),
+    /// increments [`Self::cluster_id_collisions_total`], and resolves the collision per
+    /// [`Self::set_cluster_id_collision_policy`] instead of merging the two.
+    ///
+    /// Returns whether the stored snapshot changed as a result. A caller that reacts to real
+    /// changes (e.g. writing a CRD or emitting a change notification) should gate that work on
+    /// this return value rather than on every call, so that a cluster re-announcing itself
+    /// unchanged doesn't cause needless churn; a caller that only needs the heartbeat (e.g. to
+    /// keep the cluster from being seen as stale) can ignore it.
+    pub fn register_cluster(&self, registration: ClusterRegistration) -> bool {
+        let incoming = ClusterInfo::from(registration);
+        *self.last_registered_at.write().expect("lock poisoned") = Some(SystemTime::now());
+
+        let mut clusters = self.clusters.write().expect("lock poisoned");
+        let is_new = !clusters.contains_key(&incoming.cluster_id);
+        let merged = match clusters.get(&incoming.cluster_id) {
+            Some(existing) if existing.endpoint_id != incoming.endpoint_id => {
+                self.metrics.cluster_id_collisions_total.inc();
+                let policy = *self.collision_policy.read().expect("lock poisoned");
+                tracing::warn!(
+                    cluster_id = %incoming.cluster_id,
+                    existing_endpoint = %existing.endpoint_id,
+                    incoming_endpoint = %incoming.endpoint_id,
+                    ?policy,
+                    "cluster id collision: same cluster id registered under two different endpoints",
+                );
+                if let Some(notifier) = self.event_notifier() {
+                    notifier.notify_collision(&incoming.cluster_id);
+                }
+                match policy {
+                    ClusterIdCollisionPolicy::KeepExisting => existing.clone(),
+                    ClusterIdCollisionPolicy::Overwrite => incoming,
+                }
+            }
+            Some(existing) => merge_cluster_info(existing, &incoming),
+            None => incoming,
+        };
+        let hash = snapshot_hash(&merged);
+
+        let mut hashes = self.last_snapshot_hashes.write().expect("lock poisoned");
+        if hashes.get(&merged.cluster_id) == Some(&hash) {
+            return false;
+        }
+        hashes.insert(merged.cluster_id.clone(), hash);
+        drop(hashes);
+
+        let cluster_id = merged.cluster_id.clone();
+        clusters.insert(cluster_id.clone(), merged);
+        drop(clusters);
+        self.bump_changes();
+        if is_new {
+            self.metrics.known_clusters.inc();
+            if let Some(notifier) = self.event_notifier() {
+                notifier.notify_added(&cluster_id);
+            }
+        }
+        true
+    }
+
+    /// Registers every entry [`ClusterStore::list`] currently reports, via [`Self::register_cluster`]
+    /// -- the piece a CRD reconciler's watch loop would call on each event (or a periodic relist)
+    /// to keep this manager in sync with peers' registrations from the shared store. Returns how
+    /// many of them actually changed something, per [`Self::register_cluster`]'s own return value.
+    pub fn sync_from_store(&self, store: &dyn ClusterStore) -> usize {
+        store
+            .list()
+            .into_iter()
+            .filter(|registration| self.register_cluster(registration.clone()))
+            .count()
+    }
+
+    /// Removes a cluster by id, returning whether it was present.
+    pub fn remove_cluster(&self, cluster_id: &str) -> bool {
+        self.service_health
+            .write()
+            .expect("lock poisoned")
+            .retain(|(id, _), _| id != cluster_id);
+        self.last_snapshot_hashes
+            .write()
+            .expect("lock poisoned")
+            .remove(cluster_id);
+        let removed = self
+            .clusters
+            .write()
+            .expect("lock poisoned")
+            .remove(cluster_id)
+            .is_some();
+        if removed {
+            self.metrics.known_clusters.dec();
+            self.bump_changes();
+            if let Some(notifier) = self.event_notifier() {
+                notifier.notify_removed(cluster_id);
+            }
+        }
+        removed
+    }
+
+    /// Bumps [`Self::changes`], waking anything returned by [`Self::watch_changes`].
+    fn bump_changes(&self) {
+        let next = self.changes.get() + 1;
+        let _ = self.changes.set(next);
+    }
+
+    /// Returns a [`Watcher`] that updates whenever [`Self::register_cluster`] or
+    /// [`Self::remove_cluster`] actually changes what's known, for
+    /// [`crate::proxy::MeshProxy::routing_table_watcher`] to recompute its snapshot from.
+    pub(crate) fn watch_changes(&self) -> impl Watcher<Value = u64> + use<> {
+        self.changes.watch()
+    }
+
+    /// Returns the currently known clusters.
+    pub fn list_clusters(&self) -> Vec<ClusterInfo> {
+        self.clusters
+            .read()
+            .expect("lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Looks up a single cluster by id.
+    pub fn get_cluster(&self, cluster_id: &str) -> Option<ClusterInfo> {
+        self.clusters
+            .read()
+            .expect("lock poisoned")
+            .get(cluster_id)
+            .cloned()
+    }
+
+    /// Waits until `cluster_id` is registered, or `timeout` elapses.
+    ///
+    /// Resolves immediately if the cluster is already known. Otherwise waits on
+    /// [`Self::watch_changes`] -- the same change notification
+    /// [`crate::proxy::MeshProxy::routing_table_watcher`] reacts to -- instead of polling
+    /// [`Self::get_cluster`] in a loop, so this doesn't add load proportional to how long the
+    /// caller ends up waiting. Meant for test harnesses and orchestrators that need to block on
+    /// startup ordering (e.g. "don't dial until the peer cluster has registered") rather than
+    /// spin on [`Self::list_clusters`] themselves.
+    ///
+    /// Returns [`crate::error::MeshError::Timeout`] if `cluster_id` still isn't known once
+    /// `timeout` elapses.
+    pub async fn wait_for_cluster(
+        &self,
+        cluster_id: &str,
+        timeout: Duration,
+    ) -> Result<ClusterInfo> {
+        if let Some(info) = self.get_cluster(cluster_id) {
+            return Ok(info);
+        }
+        let mut watcher = self.watch_changes();
+        tokio::time::timeout(timeout, async {
+            loop {
+                watcher.updated().await.expect("disconnected");
+                if let Some(info) = self.get_cluster(cluster_id) {
+                    return info;
+                }
+            }
+        })
+        .await
+        .ok()
+        .context(crate::error::TimeoutSnafu {
+            what: format!("cluster {cluster_id}"),
+        })
+    }
+
+    /// Records the result of a health probe for every service `cluster_id` advertises.
+    pub fn set_cluster_health(&self, cluster_id: &str, healthy: bool) {
+        let services = self
+            .get_cluster(cluster_id)
+            .map(|info| info.services)
+            .unwrap_or_default();
+        let mut health = self.service_health.write().expect("lock poisoned");
+        for service in services {
+            health.insert((cluster_id.to_string(), service), healthy);
+        }
+        drop(health);
+        if !healthy {
+            if let Some(notifier) = self.event_notifier() {
+                notifier.notify_unreachable(cluster_id);
+            }
+        }
+    }
+
+    /// Whether `service` on `cluster_id` is currently known to be healthy.
+    ///
+    /// Defaults to healthy for services that haven't been probed yet, so routing isn't blocked
+    /// on health checking being enabled.
+    pub fn is_service_healthy(&self, cluster_id: &str, service: &str) -> bool {
+        self.service_health
+            .read()
+            .expect("lock poisoned")
+            .get(&(cluster_id.to_string(), service.to_string()))
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Returns the known clusters that advertise `service` and aren't currently marked
+    /// unhealthy for it.
+    pub fn find_service(&self, service: &str) -> Vec<ClusterInfo> {
+        self.clusters
+            .read()
+            .expect("lock poisoned")
+            .values()
+            .filter(|info| info.services.iter().any(|s| s == service))
+            .filter(|info| self.is_service_healthy(&info.cluster_id, service))
+            .cloned()
+            .collect()
+    }
+
+    /// Resolves `port_name` on `service` to a concrete port number, returning the first healthy
+    /// cluster that advertises it alongside that number.
+    ///
+    /// Unlike [`Self::find_service`], this doesn't return every matching cluster: a caller
+    /// resolving a port wants something to dial, not a candidate pool, so the first match wins.
+    /// There's no namespace to disambiguate same-named services across, the way a Kubernetes
+    /// `Service` lookup would take one -- this crate scopes a service by cluster id, not by a
+    /// separate namespace, so `service` alone is enough to find it within a cluster.
+    pub fn find_service_port(&self, service: &str, port_name: &str) -> Option<(ClusterInfo, u16)> {
+        self.find_service(service).into_iter().find_map(|info| {
+            let port = info
+                .service_ports
+                .iter()
+                .find(|sp| sp.service == service && sp.name == port_name)?
+                .port;
+            Some((info, port))
+        })
+    }
+
+    /// Whether `port` is one a known cluster has actually advertised for `service`, narrowing a
+    /// caller-supplied target down to only ports this discovery state already knows are exposed
+    /// by a real, named service.
+    ///
+    /// The inbound tunnel handler (see `MeshProxy::accept_mesh_connections`) does not call this:
+    /// a [`crate::route_request::RouteRequest`] names only a service, no port, and that service is
+    /// looked up in the accepting proxy's own [`crate::config::ProxyConfig::routes`].
+    /// `enforce: false` (for local development, where discovery may not be populated at all)
+    /// always returns `true` instead of checking.
+    pub fn is_known_service_port(
+        &self,
+        cluster_id: &str,
+        service: &str,
+        port: u16,
+        enforce: bool,
+    ) -> bool {
+        if !enforce {
+            return true;
+        }
+        self.find_service(service)
+            .iter()
+            .filter(|info| info.cluster_id == cluster_id)
+            .any(|info| {
+                info.service_ports
+                    .iter()
+                    .any(|sp| sp.service == service && sp.port == port)
+            })
+    }
+
+    /// When the most recent cluster registration was received. `None` if none has been yet.
+    pub fn last_registered_at(&self) -> Option<SystemTime> {
+        *self.last_registered_at.read().expect("lock poisoned")
+    }
+
+    /// Whether it's been longer than `threshold` since the last registration -- or there's never
+    /// been one -- suggesting whatever feeds this manager has gone silent.
+    pub fn is_stale(&self, threshold: Duration) -> bool {
+        match self.last_registered_at() {
+            Some(at) => at.elapsed().unwrap_or(Duration::ZERO) > threshold,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(cluster_id: &str) -> ClusterRegistration {
+        ClusterRegistration {
+            cluster_id: cluster_id.to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: SystemTime::now(),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn builder_preseeds_list_clusters_with_the_injected_clusters() {
+        let manager = DiscoveryManager::builder()
+            .with_cluster(registration("cluster-a"))
+            .with_cluster(registration("cluster-b"))
+            .build();
+
+        let mut ids: Vec<String> = manager
+            .list_clusters()
+            .into_iter()
+            .map(|cluster| cluster.cluster_id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["cluster-a".to_string(), "cluster-b".to_string()]);
+    }
+
+    #[test]
+    fn endpoint_addr_carries_neither_relay_url_nor_direct_addresses_when_neither_is_known() {
+        let info = ClusterInfo::from(registration("cluster-a"));
+        let addr = info.endpoint_addr().unwrap();
+        assert_eq!(addr.endpoint_id, info.endpoint_id);
+        assert_eq!(addr.relay_url, None);
+        assert!(addr.direct_addresses.is_empty());
+    }
+
+    #[test]
+    fn endpoint_addr_round_trips_a_relay_url_and_direct_addresses() {
+        let mut reg = registration("cluster-a");
+        reg.relay_url = Some("https://relay.example.com".to_string());
+        reg.direct_addresses = vec!["203.0.113.5:1234".parse().unwrap()];
+        let info = ClusterInfo::from(reg);
+
+        let addr = info.endpoint_addr().unwrap();
+        assert_eq!(addr.endpoint_id, info.endpoint_id);
+        assert_eq!(
+            addr.relay_url,
+            Some("https://relay.example.com/".parse().unwrap())
+        );
+        assert_eq!(
+            addr.direct_addresses.into_iter().collect::<Vec<_>>(),
+            vec!["203.0.113.5:1234".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn registering_a_cluster_drops_unroutable_direct_addresses() {
+        let mut reg = registration("cluster-a");
+        reg.direct_addresses = vec![
+            "127.0.0.1:1234".parse().unwrap(),
+            "[::1]:1234".parse().unwrap(),
+            "0.0.0.0:1234".parse().unwrap(),
+            "169.254.1.1:1234".parse().unwrap(),
+            "[fe80::1]:1234".parse().unwrap(),
+            "203.0.113.5:1234".parse().unwrap(),
+        ];
+        let info = ClusterInfo::from(reg);
+
+        assert_eq!(
+            info.direct_addresses,
+            vec!["203.0.113.5:1234".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn endpoint_addr_rejects_a_malformed_relay_url() {
+        let mut reg = registration("cluster-a");
+        reg.relay_url = Some("not a url".to_string());
+        let info = ClusterInfo::from(reg);
+
+        let err = info.endpoint_addr().unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::MeshError::InvalidRelayUrl { .. }
+        ));
+    }
+
+    #[test]
+    fn a_manager_with_no_registrations_yet_is_stale() {
+        let discovery = DiscoveryManager::new();
+        assert!(discovery.last_registered_at().is_none());
+        assert!(discovery.is_stale(Duration::from_secs(3600)));
+    }
+
+    #[tokio::test]
+    async fn staleness_tracks_how_long_it_has_been_since_the_last_registration() {
+        let discovery = DiscoveryManager::new();
+
+        discovery.register_cluster(registration("cluster-a"));
+        assert!(discovery.last_registered_at().is_some());
+        assert!(
+            !discovery.is_stale(Duration::from_secs(3600)),
+            "a registration that just happened shouldn't be stale against a generous threshold"
+        );
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(
+            discovery.is_stale(Duration::from_millis(10)),
+            "a registration older than the threshold should be stale"
+        );
+
+        // A fresh registration resets the clock.
+        discovery.register_cluster(registration("cluster-b"));
+        assert!(!discovery.is_stale(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn an_unchanged_registration_reports_no_change_but_a_changed_one_does() {
+        let discovery = DiscoveryManager::new();
+        let mut reg = registration("cluster-a");
+        reg.services = vec!["svc-a".to_string()];
+
+        assert!(
+            discovery.register_cluster(reg.clone()),
+            "the first registration of a cluster is always a change"
+        );
+        assert!(
+            !discovery.register_cluster(reg.clone()),
+            "re-registering the exact same snapshot shouldn't report a change"
+        );
+
+        reg.services.push("svc-b".to_string());
+        assert!(
+            discovery.register_cluster(reg.clone()),
+            "a changed service set should report a change"
+        );
+
+        let stored = discovery
+            .get_cluster("cluster-a")
+            .expect("cluster-a was registered");
+        assert_eq!(
+            stored.services,
+            vec!["svc-a".to_string(), "svc-b".to_string()]
+        );
+    }
+
+    #[test]
+    fn a_newer_registration_wins_over_an_older_one_regardless_of_arrival_order() {
+        let older_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let newer_addr: SocketAddr = "10.0.0.2:2000".parse().unwrap();
+        let now = SystemTime::now();
+        let endpoint_id = iroh_base::SecretKey::generate(&mut rand::rng()).public();
+
+        let mut older = registration("cluster-a");
+        older.endpoint_id = endpoint_id;
+        older.direct_addresses = vec![older_addr];
+        older.updated_at = now;
+
+        let mut newer = registration("cluster-a");
+        newer.endpoint_id = endpoint_id;
+        newer.direct_addresses = vec![newer_addr];
+        newer.updated_at = now + Duration::from_secs(60);
+
+        // Older arrives first, then newer: newer's fields win.
+        let discovery = DiscoveryManager::new();
+        discovery.register_cluster(older.clone());
+        discovery.register_cluster(newer.clone());
+        let stored = discovery.get_cluster("cluster-a").unwrap();
+        assert_eq!(stored.endpoint_id, newer.endpoint_id);
+        assert_eq!(stored.direct_addresses, vec![older_addr, newer_addr]);
+
+        // Newer arrives first, then older: newer's fields still win, since it's newer.
+        let discovery = DiscoveryManager::new();
+        discovery.register_cluster(newer.clone());
+        discovery.register_cluster(older.clone());
+        let stored = discovery.get_cluster("cluster-a").unwrap();
+        assert_eq!(stored.endpoint_id, newer.endpoint_id);
+        assert_eq!(stored.direct_addresses, vec![newer_addr, older_addr]);
+    }
+
+    #[test]
+    fn a_pinned_registration_is_not_overwritten_by_a_newer_unpinned_one() {
+        let now = SystemTime::now();
+        let endpoint_id = iroh_base::SecretKey::generate(&mut rand::rng()).public();
+
+        let mut pinned = registration("cluster-a");
+        pinned.endpoint_id = endpoint_id;
+        pinned.pinned = true;
+        pinned.updated_at = now;
+
+        let mut dynamic = registration("cluster-a");
+        dynamic.endpoint_id = endpoint_id;
+        dynamic.updated_at = now + Duration::from_secs(60);
+
+        let discovery = DiscoveryManager::new();
+        discovery.register_cluster(pinned);
+        discovery.register_cluster(dynamic);
+
+        let stored = discovery.get_cluster("cluster-a").unwrap();
+        assert_eq!(stored.endpoint_id, endpoint_id);
+        assert!(stored.pinned);
+    }
+
+    #[test]
+    fn a_colliding_endpoint_is_rejected_and_counted_by_default() {
+        let discovery = DiscoveryManager::new();
+        let original = registration("cluster-a");
+        let original_endpoint = original.endpoint_id;
+
+        assert!(discovery.register_cluster(original));
+        assert_eq!(discovery.cluster_id_collisions_total(), 0);
+
+        let colliding = registration("cluster-a");
+        assert_ne!(colliding.endpoint_id, original_endpoint);
+        assert!(
+            !discovery.register_cluster(colliding),
+            "a colliding registration shouldn't be treated as a real change under the default policy"
+        );
+
+        assert_eq!(discovery.cluster_id_collisions_total(), 1);
+        let stored = discovery.get_cluster("cluster-a").unwrap();
+        assert_eq!(
+            stored.endpoint_id, original_endpoint,
+            "the original entry should be kept under the default KeepExisting policy"
+        );
+    }
+
+    #[test]
+    fn known_clusters_gauge_tracks_registration_and_removal() {
+        let discovery = DiscoveryManager::new();
+        assert_eq!(discovery.metrics().known_clusters.get(), 0);
+
+        discovery.register_cluster(registration("cluster-a"));
+        assert_eq!(discovery.metrics().known_clusters.get(), 1);
+
+        discovery.register_cluster(registration("cluster-b"));
+        assert_eq!(discovery.metrics().known_clusters.get(), 2);
+
+        // Re-registering an existing cluster doesn't double-count it.
+        discovery.register_cluster(registration("cluster-a"));
+        assert_eq!(discovery.metrics().known_clusters.get(), 2);
+
+        assert!(discovery.remove_cluster("cluster-a"));
+        assert_eq!(discovery.metrics().known_clusters.get(), 1);
+    }
+
+    #[test]
+    fn the_overwrite_policy_accepts_a_colliding_registration_instead() {
+        let discovery = DiscoveryManager::new();
+        discovery.set_cluster_id_collision_policy(ClusterIdCollisionPolicy::Overwrite);
+
+        discovery.register_cluster(registration("cluster-a"));
+        let colliding = registration("cluster-a");
+        let colliding_endpoint = colliding.endpoint_id;
+
+        assert!(discovery.register_cluster(colliding));
+        assert_eq!(discovery.cluster_id_collisions_total(), 1);
+        let stored = discovery.get_cluster("cluster-a").unwrap();
+        assert_eq!(stored.endpoint_id, colliding_endpoint);
+    }
+
+    #[test]
+    fn removing_a_cluster_forgets_its_snapshot_hash_so_re_registering_it_counts_as_a_change() {
+        let discovery = DiscoveryManager::new();
+        let reg = registration("cluster-a");
+
+        discovery.register_cluster(reg.clone());
+        discovery.remove_cluster("cluster-a");
+
+        assert!(
+            discovery.register_cluster(reg),
+            "re-registering a removed cluster should count as a change even if the snapshot \
+             is identical to the one that was removed"
+        );
+    }
+
+    #[test]
+    fn find_service_port_resolves_a_named_port_to_its_number() {
+        let discovery = DiscoveryManager::new();
+        let mut reg = registration("cluster-a");
+        reg.services = vec!["svc".to_string()];
+        reg.service_ports = vec![ServicePort::new("svc", "http", 8080)];
+        discovery.register_cluster(reg);
+
+        let (info, port) = discovery
+            .find_service_port("svc", "http")
+            .expect("svc advertises an http port");
+        assert_eq!(info.cluster_id, "cluster-a");
+        assert_eq!(port, 8080);
+    }
+
+    #[test]
+    fn find_service_port_returns_none_for_an_unknown_service_or_port_name() {
+        let discovery = DiscoveryManager::new();
+        let mut reg = registration("cluster-a");
+        reg.services = vec!["svc".to_string()];
+        reg.service_ports = vec![ServicePort::new("svc", "http", 8080)];
+        discovery.register_cluster(reg);
+
+        assert!(discovery.find_service_port("svc", "grpc").is_none());
+        assert!(discovery.find_service_port("other-svc", "http").is_none());
+    }
+
+    #[test]
+    fn is_known_service_port_allows_a_port_the_cluster_actually_advertises() {
+        let discovery = DiscoveryManager::new();
+        let mut reg = registration("cluster-a");
+        reg.services = vec!["svc".to_string()];
+        reg.service_ports = vec![ServicePort::new("svc", "http", 8080)];
+        discovery.register_cluster(reg);
+
+        assert!(discovery.is_known_service_port("cluster-a", "svc", 8080, true));
+    }
+
+    #[test]
+    fn is_known_service_port_rejects_an_arbitrary_port_the_cluster_never_advertised() {
+        let discovery = DiscoveryManager::new();
+        let mut reg = registration("cluster-a");
+        reg.services = vec!["svc".to_string()];
+        reg.service_ports = vec![ServicePort::new("svc", "http", 8080)];
+        discovery.register_cluster(reg);
+
+        assert!(!discovery.is_known_service_port("cluster-a", "svc", 22, true));
+    }
+
+    #[test]
+    fn is_known_service_port_allows_anything_when_enforcement_is_off() {
+        let discovery = DiscoveryManager::new();
+        assert!(discovery.is_known_service_port("cluster-a", "svc", 22, false));
+    }
+
+    #[test]
+    fn a_remapped_port_advertises_the_external_number_and_backend_ports_translate_to_the_internal_one()
+     {
+        let discovery = DiscoveryManager::new();
+        let mut reg = registration("cluster-a");
+        reg.services = vec!["svc".to_string()];
+        reg.service_ports = vec![ServicePort::remapped("svc", "http", 8080, 80)];
+        discovery.register_cluster(reg);
+
+        let (_, advertised) = discovery
+            .find_service_port("svc", "http")
+            .expect("svc advertises an http port");
+        assert_eq!(advertised, 80, "the mesh should see the advertised port");
+
+        let backend_port = discovery
+            .find_service("svc")
+            .into_iter()
+            .find_map(|info| {
+                info.service_ports
+                    .iter()
+                    .find(|sp| sp.service == "svc" && sp.name == "http")
+                    .map(ServicePort::backend_port)
+            })
+            .expect("svc advertises an http port");
+        assert_eq!(
+            backend_port, 8080,
+            "connecting to the backend should use the internal port"
+        );
+    }
+
+    #[test]
+    fn an_unmapped_port_has_the_same_backend_port_as_its_advertised_one() {
+        let port = ServicePort::new("svc", "http", 8080);
+        assert_eq!(port.backend_port(), 8080);
+    }
+
+    #[derive(Debug, Default)]
+    struct FakeEventRecorder {
+        events: std::sync::Mutex<Vec<(String, crate::clusterevents::EventSeverity, String)>>,
+    }
+
+    impl crate::clusterevents::ClusterEventRecorder for FakeEventRecorder {
+        fn record(
+            &self,
+            cluster_id: &str,
+            severity: crate::clusterevents::EventSeverity,
+            reason: &str,
+            _message: &str,
+        ) {
+            self.events.lock().expect("lock poisoned").push((
+                cluster_id.to_string(),
+                severity,
+                reason.to_string(),
+            ));
+        }
+    }
+
+    #[test]
+    fn removing_a_cluster_posts_a_removal_event_when_a_recorder_is_set() {
+        let discovery = DiscoveryManager::new();
+        let recorder = Arc::new(FakeEventRecorder::default());
+        discovery.set_event_recorder(recorder.clone(), Duration::from_secs(60));
+
+        discovery.register_cluster(registration("cluster-a"));
+        discovery.remove_cluster("cluster-a");
+
+        let events = recorder.events.lock().expect("lock poisoned");
+        assert!(
+            events.iter().any(|(id, severity, reason)| id == "cluster-a"
+                && *severity == crate::clusterevents::EventSeverity::Normal
+                && reason == "ClusterRemoved"),
+            "expected a ClusterRemoved event, got {events:?}"
+        );
+    }
+
+    #[test]
+    fn no_events_are_posted_without_a_recorder_configured() {
+        let discovery = DiscoveryManager::new();
+        discovery.register_cluster(registration("cluster-a"));
+        // Nothing to assert on directly -- this just exercises that register/remove don't panic
+        // or require a recorder to function, matching this crate's behavior before event
+        // recording existed.
+        assert!(discovery.remove_cluster("cluster-a"));
+    }
+
+    #[tokio::test]
+    async fn wait_for_cluster_resolves_immediately_when_already_registered() {
+        let discovery = DiscoveryManager::new();
+        discovery.register_cluster(registration("cluster-a"));
+
+        let info = discovery
+            .wait_for_cluster("cluster-a", Duration::from_secs(5))
+            .await
+            .expect("cluster is already known");
+        assert_eq!(info.cluster_id, "cluster-a");
+    }
+
+    #[tokio::test]
+    async fn wait_for_cluster_resolves_once_registered_after_the_wait_started() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let waiter = tokio::spawn({
+            let discovery = discovery.clone();
+            async move {
+                discovery
+                    .wait_for_cluster("cluster-a", Duration::from_secs(5))
+                    .await
+            }
+        });
+
+        // Give the waiter a chance to start waiting on the still-unregistered cluster before it
+        // shows up, so this actually exercises the wait path rather than the immediate-hit one.
+        tokio::task::yield_now().await;
+        discovery.register_cluster(registration("cluster-a"));
+
+        let info = waiter
+            .await
+            .expect("waiter task panicked")
+            .expect("cluster registered before the timeout");
+        assert_eq!(info.cluster_id, "cluster-a");
+    }
+
+    #[tokio::test]
+    async fn wait_for_cluster_times_out_when_the_cluster_never_registers() {
+        let discovery = DiscoveryManager::new();
+        let err = discovery
+            .wait_for_cluster("cluster-a", Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::Timeout { .. }));
+    }
+
+    #[test]
+    fn probe_interval_of_zero_is_valid() {
+        let config = DiscoveryConfig {
+            probe_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn a_nonzero_probe_interval_below_the_minimum_is_rejected() {
+        let config = DiscoveryConfig {
+            probe_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn a_nonzero_probe_interval_at_or_above_the_minimum_is_valid() {
+        let config = DiscoveryConfig {
+            probe_interval: MIN_PROBE_INTERVAL,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[derive(Debug, Default)]
+    struct FakeClusterStore {
+        registrations: std::sync::Mutex<Vec<ClusterRegistration>>,
+    }
+
+    impl ClusterStore for FakeClusterStore {
+        fn list(&self) -> Vec<ClusterRegistration> {
+            self.registrations.lock().expect("lock poisoned").clone()
+        }
+    }
+
+    #[test]
+    fn sync_from_store_registers_every_entry_the_store_reports() {
+        let manager = DiscoveryManager::new();
+        let store = FakeClusterStore {
+            registrations: vec![registration("cluster-a"), registration("cluster-b")].into(),
+        };
+
+        let changed = manager.sync_from_store(&store);
+
+        assert_eq!(changed, 2);
+        let ids: std::collections::HashSet<_> = manager
+            .list_clusters()
+            .into_iter()
+            .map(|c| c.cluster_id)
+            .collect();
+        assert_eq!(
+            ids,
+            ["cluster-a", "cluster-b"]
+                .into_iter()
+                .map(str::to_string)
+                .collect()
+        );
+    }
+
+    #[test]
+    fn sync_from_store_reports_zero_changed_once_the_manager_already_matches_the_store() {
+        let manager = DiscoveryManager::new();
+        let store = FakeClusterStore {
+            registrations: vec![registration("cluster-a")].into(),
+        };
+
+        manager.sync_from_store(&store);
+        let changed = manager.sync_from_store(&store);
+
+        assert_eq!(changed, 0);
+    }
+}
@@ -0,0 +1,223 @@
+//! Startup self-check for [`ProxyConfig`] and [`AgentConfig`], exercising the same init paths
+//! `mesh-proxy run`/`mesh-agent run` use -- binding an iroh endpoint, loading or creating a
+//! secret key -- without ever starting to serve, so a bad port, an unreadable key file, or an
+//! unbindable endpoint surfaces as a clear pass/fail report instead of a cryptic failure partway
+//! into startup.
+//!
+//! This crate has no Kubernetes client of its own (see [`crate::election`]'s module docs), so
+//! there's no separate "Kubernetes access" check here: nothing in [`ProxyConfig`] or
+//! [`AgentConfig`] talks to a cluster API directly, and a [`Report`] says so explicitly rather
+//! than silently skipping a check a user might expect.
+
+use std::sync::Arc;
+
+use crate::{
+    agent::AgentConfig, config::ProxyConfig, discovery::DiscoveryManager, endpoint_discovery,
+    proxy, proxy::MeshProxy, relay, secret_key,
+};
+
+/// The outcome of one named check in a [`Report`].
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// Short name of what was checked, e.g. `"bind endpoint"`.
+    pub name: &'static str,
+    /// Whether the check passed.
+    pub ok: bool,
+    /// Human readable detail: what passed, or why it failed.
+    pub detail: String,
+}
+
+/// Every check run against a config by [`check_proxy`] or [`check_agent`], in the order they ran.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// The checks that were run.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, name: &'static str, ok: bool, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            ok,
+            detail: detail.into(),
+        });
+    }
+
+    /// Whether every check in this report passed.
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.ok)
+    }
+
+    /// Prints one `PASS`/`FAIL`-prefixed line per check to stdout.
+    pub fn print(&self) {
+        for check in &self.checks {
+            let status = if check.ok { "PASS" } else { "FAIL" };
+            println!("[{status}] {}: {}", check.name, check.detail);
+        }
+    }
+}
+
+/// Runs every startup check for a [`ProxyConfig`]: that its secret key (if any) is readable or
+/// can be created, and that an iroh endpoint can actually be bound with it.
+///
+/// Never binds `config.listen_addr` or `config.status_addr` -- this only exercises
+/// [`MeshProxy::bind`], not [`MeshProxy::run`].
+pub async fn check_proxy(config: &ProxyConfig) -> Report {
+    let mut report = Report::default();
+    check_secret_key(&mut report, config.secret_key.as_ref()).await;
+    match MeshProxy::bind(config.clone(), Arc::new(DiscoveryManager::new())).await {
+        Ok(_) => report.push("bind endpoint", true, "iroh endpoint bound successfully"),
+        Err(err) => report.push("bind endpoint", false, err.to_string()),
+    }
+    push_kubernetes_note(&mut report);
+    report
+}
+
+/// Runs every startup check for an [`AgentConfig`]: that its secret key (if any) is readable or
+/// can be created, and that an iroh endpoint can actually be bound with it -- skipped when
+/// [`AgentConfig::discovery`] is unset, since [`crate::agent::MeshAgent`] doesn't bind one in
+/// that case either (see [`crate::agent::MeshAgent::run`]).
+///
+/// Never binds `config.api_addr`.
+pub async fn check_agent(config: &AgentConfig) -> Report {
+    let mut report = Report::default();
+    if config.discovery.is_none() {
+        report.push(
+            "bind endpoint",
+            true,
+            "no discovery config set, the health checker (and its endpoint) is disabled",
+        );
+        push_kubernetes_note(&mut report);
+        return report;
+    }
+
+    check_secret_key(&mut report, config.secret_key.as_ref()).await;
+    let alpn = proxy::mesh_alpn(config.mesh_name.as_deref());
+    match relay::resolve(&config.relay) {
+        Ok(relay_mode) => {
+            let mut builder = endpoint_discovery::builder(&config.endpoint_discovery, relay_mode)
+                .alpns(vec![alpn]);
+            if let Some(source) = &config.secret_key {
+                if let Ok(key) = secret_key::load_or_create_secret_key(source).await {
+                    builder = builder.secret_key(key);
+                }
+            }
+            match builder.bind().await {
+                Ok(_) => report.push("bind endpoint", true, "iroh endpoint bound successfully"),
+                Err(err) => report.push("bind endpoint", false, err.to_string()),
+            }
+        }
+        Err(err) => report.push("bind endpoint", false, err.to_string()),
+    }
+    push_kubernetes_note(&mut report);
+    report
+}
+
+/// Checks that `source` (if set) names a secret key this process can read or create, recording
+/// the result as a `"secret key"` check.
+async fn check_secret_key(report: &mut Report, source: Option<&secret_key::SecretKeySource>) {
+    match source {
+        None => report.push(
+            "secret key",
+            true,
+            "none configured, a fresh unpersisted key will be used",
+        ),
+        Some(source) => match secret_key::load_or_create_secret_key(source).await {
+            Ok(_) => report.push("secret key", true, "readable or created successfully"),
+            Err(err) => report.push("secret key", false, err.to_string()),
+        },
+    }
+}
+
+/// Records that there's nothing Kubernetes-specific to check in this config (see the module
+/// docs).
+fn push_kubernetes_note(report: &mut Report) {
+    report.push(
+        "kubernetes access",
+        true,
+        "not applicable: this crate has no Kubernetes client integration, discovery is fed externally",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_valid_proxy_config_passes_every_check() {
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let report = check_proxy(&config).await;
+        assert!(report.is_ok(), "{report:?}");
+        assert!(report.checks.iter().any(|c| c.name == "secret key"));
+        assert!(report.checks.iter().any(|c| c.name == "bind endpoint"));
+        assert!(report.checks.iter().any(|c| c.name == "kubernetes access"));
+    }
+
+    #[tokio::test]
+    async fn a_proxy_config_naming_an_empty_inline_secret_key_fails_the_secret_key_check() {
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_secret_key(secret_key::SecretKeySource::Inline(String::new()));
+        let report = check_proxy(&config).await;
+        assert!(!report.is_ok());
+        let secret_check = report
+            .checks
+            .iter()
+            .find(|c| c.name == "secret key")
+            .expect("secret key check should have run");
+        assert!(!secret_check.ok);
+    }
+
+    #[tokio::test]
+    async fn an_agent_config_with_no_discovery_configured_skips_the_endpoint_bind() {
+        let config = AgentConfig {
+            api_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery: None,
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: None,
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload: None,
+            self_registration: None,
+            metrics_addr: None,
+        };
+        let report = check_agent(&config).await;
+        assert!(report.is_ok(), "{report:?}");
+    }
+
+    #[tokio::test]
+    async fn an_agent_config_naming_a_missing_env_var_fails_the_secret_key_check() {
+        let config = AgentConfig {
+            api_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery: Some(crate::discovery::DiscoveryConfig {
+                probe_interval: std::time::Duration::from_secs(3600),
+                failure_threshold: 1,
+                ..Default::default()
+            }),
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: Some(secret_key::SecretKeySource::Env(
+                "IROH_MESH_DOCTOR_TEST_MISSING_VAR".to_string(),
+            )),
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload: None,
+            self_registration: None,
+            metrics_addr: None,
+        };
+        let report = check_agent(&config).await;
+        assert!(!report.is_ok());
+        let secret_check = report
+            .checks
+            .iter()
+            .find(|c| c.name == "secret key")
+            .expect("secret key check should have run");
+        assert!(!secret_check.ok);
+    }
+}
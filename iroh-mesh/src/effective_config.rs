@@ -0,0 +1,186 @@
+//! A single, deterministic merge of a config file, environment variables and CLI flags into the
+//! config a binary actually runs with.
+//!
+//! `mesh-proxy` and `mesh-agent` each build their config from up to three layers on top of
+//! [`Default`], and used to do that merge ad hoc and separately in each binary -- a recipe for
+//! the two quietly disagreeing about precedence as fields were added. [`EffectiveConfig::resolve`]
+//! defines the precedence once (highest to lowest: CLI flag > environment variable > config file >
+//! default) and is used by both, so a value's source is predictable and, via [`Self::sources`],
+//! inspectable -- e.g. for `doctor`/`Command::Schema`-style diagnostic output.
+//!
+//! This module only holds the merge machinery; each binary still owns the small, binary-specific
+//! list of which fields are overridable and what their CLI flags and environment variable names
+//! are.
+
+use std::collections::BTreeMap;
+
+/// Which layer supplied a config field's resolved value, see [`EffectiveConfig::sources`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Neither the config file, an environment variable, nor a CLI flag set this field; it's
+    /// using its [`Default`].
+    Default,
+    /// Came from the config file.
+    File,
+    /// Came from an environment variable.
+    Env,
+    /// Came from a command-line flag.
+    Cli,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConfigSource::Default => "default",
+            ConfigSource::File => "file",
+            ConfigSource::Env => "env",
+            ConfigSource::Cli => "cli",
+        })
+    }
+}
+
+/// One field's value as it may have been set at each layer [`EffectiveConfig`] merges, from
+/// lowest to highest precedence. `None` at a layer means "not set here" -- a layer can only
+/// override a lower one, never explicitly restore a value the layer above it changed.
+#[derive(Debug, Clone, Default)]
+pub struct Overridable<T> {
+    /// Value from the config file, if it set this field.
+    pub file: Option<T>,
+    /// Value from an environment variable, if one was set and parsed.
+    pub env: Option<T>,
+    /// Value from a CLI flag, if one was passed.
+    pub cli: Option<T>,
+}
+
+impl<T> Overridable<T> {
+    /// Picks the highest-precedence value that's set (CLI, then env, then file), falling back to
+    /// `default`, and records which layer won into `sources` under `field`.
+    pub fn resolve(
+        self,
+        field: &'static str,
+        default: T,
+        sources: &mut BTreeMap<&'static str, ConfigSource>,
+    ) -> T {
+        let (value, source) = match (self.cli, self.env, self.file) {
+            (Some(v), _, _) => (v, ConfigSource::Cli),
+            (None, Some(v), _) => (v, ConfigSource::Env),
+            (None, None, Some(v)) => (v, ConfigSource::File),
+            (None, None, None) => (default, ConfigSource::Default),
+        };
+        sources.insert(field, source);
+        value
+    }
+}
+
+/// A fully merged config, plus a record of which layer supplied each overridable field, see
+/// [`Self::resolve`].
+#[derive(Debug, Clone)]
+pub struct EffectiveConfig<T> {
+    /// The merged config, ready to run with.
+    pub config: T,
+    /// Which layer supplied each overridable field, keyed by field name. Only covers fields a
+    /// binary actually exposed as CLI flags or environment variables -- a field only settable in
+    /// the config file has no entry here rather than an entry that's always [`ConfigSource::File`]
+    /// or [`ConfigSource::Default`].
+    pub sources: BTreeMap<&'static str, ConfigSource>,
+}
+
+impl<T> EffectiveConfig<T> {
+    /// Merges CLI, environment and file layers into `T` by running `merge`, which resolves each
+    /// overridable field (typically via repeated calls to [`Overridable::resolve`]) and returns
+    /// the merged config. The precedence itself lives in [`Overridable::resolve`], not here or in
+    /// `merge` -- this just collects the result and its provenance into one value.
+    pub fn resolve(merge: impl FnOnce(&mut BTreeMap<&'static str, ConfigSource>) -> T) -> Self {
+        let mut sources = BTreeMap::new();
+        let config = merge(&mut sources);
+        Self { config, sources }
+    }
+
+    /// Formats `self.sources` as `"field=source"` pairs, one per line, for diagnostic output.
+    pub fn describe_sources(&self) -> String {
+        self.sources
+            .iter()
+            .map(|(field, source)| format!("{field}={source}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_wins_over_env_file_and_default() {
+        let mut sources = BTreeMap::new();
+        let value = Overridable {
+            file: Some(1),
+            env: Some(2),
+            cli: Some(3),
+        }
+        .resolve("field", 0, &mut sources);
+        assert_eq!(value, 3);
+        assert_eq!(sources["field"], ConfigSource::Cli);
+    }
+
+    #[test]
+    fn env_wins_over_file_and_default_when_no_cli_flag() {
+        let mut sources = BTreeMap::new();
+        let value = Overridable {
+            file: Some(1),
+            env: Some(2),
+            cli: None,
+        }
+        .resolve("field", 0, &mut sources);
+        assert_eq!(value, 2);
+        assert_eq!(sources["field"], ConfigSource::Env);
+    }
+
+    #[test]
+    fn file_wins_over_default_when_no_cli_flag_or_env_var() {
+        let mut sources = BTreeMap::new();
+        let value = Overridable {
+            file: Some(1),
+            env: None,
+            cli: None,
+        }
+        .resolve("field", 0, &mut sources);
+        assert_eq!(value, 1);
+        assert_eq!(sources["field"], ConfigSource::File);
+    }
+
+    #[test]
+    fn default_is_used_and_recorded_when_nothing_set_the_field() {
+        let mut sources = BTreeMap::new();
+        let value = Overridable::<i32> {
+            file: None,
+            env: None,
+            cli: None,
+        }
+        .resolve("field", 42, &mut sources);
+        assert_eq!(value, 42);
+        assert_eq!(sources["field"], ConfigSource::Default);
+    }
+
+    #[test]
+    fn resolve_collects_sources_from_the_merge_closure() {
+        let effective = EffectiveConfig::resolve(|sources| {
+            let a = Overridable {
+                file: Some("file-a"),
+                env: None,
+                cli: None,
+            }
+            .resolve("a", "default-a", sources);
+            let b = Overridable {
+                file: None,
+                env: None,
+                cli: Some("cli-b"),
+            }
+            .resolve("b", "default-b", sources);
+            (a, b)
+        });
+        assert_eq!(effective.config, ("file-a", "cli-b"));
+        assert_eq!(effective.sources["a"], ConfigSource::File);
+        assert_eq!(effective.sources["b"], ConfigSource::Cli);
+    }
+}
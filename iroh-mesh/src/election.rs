@@ -0,0 +1,191 @@
+//! Optional leader election for agents run as multiple replicas (e.g. a Kubernetes `Deployment`
+//! scaled past one pod), so only the elected leader performs writes that shouldn't happen
+//! concurrently from every replica -- currently, [`crate::health::HealthChecker`] recording
+//! probe results into discovery.
+//!
+//! This crate has no Kubernetes client dependency (no `kube`, no generated CRD types), so there
+//! is no bundled [`LeaseStore`] backed by a real `coordination.k8s.io/v1` `Lease` object yet;
+//! [`LeaseStore`] is the extension point such an implementation would fill in. Until one exists,
+//! [`LeaderElection`] is exercised by the fake store in this module's tests, and callers wanting
+//! this in production need to bring their own [`LeaseStore`].
+
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use tracing::{debug, warn};
+
+/// A backing store for leader election, modeled after a single conditional update: whoever
+/// currently holds the lease (or finds it expired) becomes `holder` for the next `ttl`.
+///
+/// A Kubernetes-backed implementation would map this onto a `Lease` object, conditionally
+/// updating `holderIdentity` and `renewTime` in a single API call.
+pub trait LeaseStore: Send + Sync + fmt::Debug {
+    /// Attempts to acquire or renew the lease for `holder`, valid for `ttl` from now.
+    ///
+    /// Returns whether `holder` holds the lease afterward. [`LeaderElection`] calls this on a
+    /// timer, so renewal is just the current holder calling this again before its own `ttl`
+    /// lapses.
+    fn try_acquire(&self, holder: &str, ttl: Duration) -> bool;
+}
+
+/// Tracks whether this process currently holds leadership of a [`LeaseStore`]-backed lease.
+#[derive(Debug)]
+pub struct LeaderElection {
+    store: Arc<dyn LeaseStore>,
+    holder_id: String,
+    ttl: Duration,
+    renew_interval: Duration,
+    is_leader: AtomicBool,
+}
+
+impl LeaderElection {
+    /// Creates a leader election that will attempt to hold `store`'s lease as `holder_id`,
+    /// renewing it for `ttl` every `renew_interval` once [`Self::spawn`] is called.
+    ///
+    /// Starts out not holding leadership; the first tick after [`Self::spawn`] makes the first
+    /// acquisition attempt. `renew_interval` should be comfortably shorter than `ttl` (a third
+    /// of it is a common choice) so that a slow renewal or a missed tick doesn't immediately
+    /// cost the lease.
+    pub fn new(
+        store: Arc<dyn LeaseStore>,
+        holder_id: impl Into<String>,
+        ttl: Duration,
+        renew_interval: Duration,
+    ) -> Self {
+        Self {
+            store,
+            holder_id: holder_id.into(),
+            ttl,
+            renew_interval,
+            is_leader: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether this process currently believes it holds leadership.
+    ///
+    /// May be momentarily stale: true until the next failed renewal, false until the next
+    /// successful acquisition.
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::SeqCst)
+    }
+
+    /// Attempts to acquire or renew the lease once, updating [`Self::is_leader`].
+    fn try_acquire_or_renew(&self) {
+        let acquired = self.store.try_acquire(&self.holder_id, self.ttl);
+        let was_leader = self.is_leader.swap(acquired, Ordering::SeqCst);
+        if acquired && !was_leader {
+            debug!(holder = %self.holder_id, "acquired leader election lease");
+        } else if !acquired && was_leader {
+            warn!(holder = %self.holder_id, "lost leader election lease");
+        }
+    }
+
+    /// Spawns a background task that attempts to acquire or renew the lease every
+    /// [`Self::renew_interval`], starting immediately. Dropping the returned handle stops it and
+    /// abandons leadership (the lease itself still has to expire on the backing store's side
+    /// before another holder can take over).
+    pub fn spawn(self: Arc<Self>) -> LeaderElectionHandle {
+        let mut ticker = tokio::time::interval(self.renew_interval);
+        let election = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                election.try_acquire_or_renew();
+            }
+        });
+        LeaderElectionHandle { task }
+    }
+}
+
+/// Handle to a [`LeaderElection`] spawned with [`LeaderElection::spawn`]; dropping it stops
+/// renewing the lease.
+#[derive(Debug)]
+pub struct LeaderElectionHandle {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for LeaderElectionHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Instant};
+
+    use super::*;
+
+    /// An in-memory fake of a `Lease`-backed store: acquisition succeeds for a new holder only
+    /// once the current holder's `ttl` has elapsed, the same conditional-update semantics a real
+    /// Kubernetes `Lease` API call would enforce.
+    #[derive(Debug, Default)]
+    struct FakeLeaseStore {
+        state: Mutex<Option<(String, Instant)>>,
+    }
+
+    impl LeaseStore for FakeLeaseStore {
+        fn try_acquire(&self, holder: &str, ttl: Duration) -> bool {
+            let mut state = self.state.lock().expect("lock poisoned");
+            let now = Instant::now();
+            let held_by_someone_else = matches!(
+                &*state,
+                Some((current_holder, expires_at)) if current_holder != holder && *expires_at > now
+            );
+            if held_by_someone_else {
+                return false;
+            }
+            *state = Some((holder.to_string(), now + ttl));
+            true
+        }
+    }
+
+    #[test]
+    fn acquires_renews_and_fails_over_when_the_leader_stops_renewing() {
+        let store = Arc::new(FakeLeaseStore::default());
+        let ttl = Duration::from_millis(50);
+        let leader = LeaderElection::new(store.clone(), "pod-a", ttl, Duration::from_millis(10));
+        let follower = LeaderElection::new(store.clone(), "pod-b", ttl, Duration::from_millis(10));
+
+        leader.try_acquire_or_renew();
+        follower.try_acquire_or_renew();
+        assert!(
+            leader.is_leader(),
+            "the first caller to acquire an unheld lease should become leader"
+        );
+        assert!(
+            !follower.is_leader(),
+            "a second caller shouldn't steal a lease that's still live"
+        );
+
+        // Renewal: the leader keeps the lease across repeated calls while it's still live.
+        std::thread::sleep(ttl / 2);
+        leader.try_acquire_or_renew();
+        follower.try_acquire_or_renew();
+        assert!(
+            leader.is_leader(),
+            "renewing before expiry should keep leadership"
+        );
+        assert!(!follower.is_leader());
+
+        // Failover: once the leader stops renewing and the lease expires, the follower can take
+        // over, and the old leader doesn't reclaim it by calling in afterward.
+        std::thread::sleep(ttl * 2);
+        follower.try_acquire_or_renew();
+        assert!(
+            follower.is_leader(),
+            "the follower should take over a lease the old leader let expire"
+        );
+        leader.try_acquire_or_renew();
+        assert!(
+            !leader.is_leader(),
+            "the old leader shouldn't reclaim a lease someone else now holds"
+        );
+    }
+}
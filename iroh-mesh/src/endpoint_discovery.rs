@@ -0,0 +1,68 @@
+//! Configures which of iroh's own endpoint-discovery mechanisms -- resolving a peer's live
+//! addresses from just its [`iroh_base::EndpointId`] -- a [`crate::proxy::MeshProxy`] or
+//! [`crate::agent::MeshAgent`] endpoint publishes to and resolves through.
+//!
+//! This is unrelated to [`crate::discovery::DiscoveryManager`], which tracks which *service*
+//! each cluster serves; it's the lower layer that turns a cluster's [`iroh_base::EndpointId`]
+//! into something dialable once [`crate::discovery::DiscoveryManager`] has named one, and the
+//! fallback [`crate::proxy::MeshProxy::dial_cluster`] relies on when a
+//! [`crate::discovery::ClusterInfo`] carries an endpoint id but no direct addresses or relay
+//! URL.
+
+use iroh::{
+    Endpoint, RelayMode,
+    discovery::{dns::DnsDiscovery, pkarr::PkarrPublisher},
+    endpoint::Builder,
+};
+use serde::{Deserialize, Serialize};
+
+/// See this module's docs.
+///
+/// `iroh` also has mDNS (local network) and Mainline DHT discovery, but both need Cargo
+/// features this crate doesn't enable, so there's nothing for a config flag to turn on for
+/// either yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct EndpointDiscoveryConfig {
+    /// Publish to and resolve from Number 0's public DNS/pkarr server, the same pair
+    /// [`iroh::endpoint::presets::N0`] (this crate's previous, unconditional behavior) bundles
+    /// together.
+    #[serde(default = "default_dns")]
+    pub dns: bool,
+}
+
+impl Default for EndpointDiscoveryConfig {
+    fn default() -> Self {
+        Self { dns: default_dns() }
+    }
+}
+
+fn default_dns() -> bool {
+    true
+}
+
+/// Builds an [`Endpoint`] builder configured per `config`, relaying through `relay_mode` (see
+/// [`crate::relay`]).
+///
+/// Replicates [`iroh::endpoint::presets::N0`]'s discovery setup exactly when `config.dns` is set,
+/// matching this crate's behavior before this setting existed; starts from
+/// [`Endpoint::empty_builder`] instead so `config.dns` being unset can skip the discovery
+/// services that preset adds.
+pub(crate) fn builder(config: &EndpointDiscoveryConfig, relay_mode: RelayMode) -> Builder {
+    let mut builder = Endpoint::empty_builder(relay_mode);
+    if config.dns {
+        builder = builder
+            .discovery(PkarrPublisher::n0_dns())
+            .discovery(DnsDiscovery::n0_dns());
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dns_is_enabled_by_default() {
+        assert!(EndpointDiscoveryConfig::default().dns);
+    }
+}
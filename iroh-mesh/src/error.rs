@@ -0,0 +1,401 @@
+//! Error types for the mesh proxy and agent.
+
+use nested_enum_utils::common_fields;
+use snafu::{Backtrace, Snafu};
+
+/// Result alias using [`MeshError`].
+pub type Result<T, E = MeshError> = std::result::Result<T, E>;
+
+/// Errors returned by this crate.
+#[common_fields({
+    #[snafu(implicit)]
+    backtrace: Backtrace,
+})]
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+#[snafu(visibility(pub))]
+pub enum MeshError {
+    /// An I/O error occurred.
+    #[snafu(transparent)]
+    Io { source: std::io::Error },
+    /// No route is configured for the requested target.
+    #[snafu(display("no route configured for {target}"))]
+    NoRoute {
+        /// The route key that could not be resolved.
+        target: String,
+    },
+    /// Configuration could not be parsed or was invalid.
+    #[snafu(display("invalid configuration: {reason}"))]
+    InvalidConfig {
+        /// Human readable reason the configuration was rejected.
+        reason: String,
+    },
+    /// Binding the proxy's or agent's iroh endpoint failed.
+    #[snafu(display("failed to bind iroh endpoint"))]
+    BindEndpoint {
+        /// Underlying iroh error.
+        source: Box<iroh::endpoint::BindError>,
+    },
+    /// Dialing a remote cluster over iroh failed.
+    #[snafu(display("failed to connect to cluster {cluster_id}"))]
+    Connect {
+        /// The cluster that could not be reached.
+        cluster_id: String,
+        /// Underlying iroh error.
+        source: Box<iroh::endpoint::ConnectError>,
+    },
+    /// Dialing a remote cluster over iroh did not complete within the allotted time.
+    #[snafu(display("dial to cluster {cluster_id} timed out"))]
+    DialTimeout {
+        /// The cluster that could not be reached.
+        cluster_id: String,
+    },
+    /// An HTTP request's header block exceeded the size cap before a terminator was seen.
+    #[snafu(display("HTTP header block exceeded {limit} bytes without a terminator"))]
+    HeaderTooLarge {
+        /// The size cap that was exceeded.
+        limit: usize,
+    },
+    /// A connection's `Host` header changed between requests on the same connection.
+    #[snafu(display("host changed from {expected} to {found} on the same connection"))]
+    HostChanged {
+        /// The host the connection was originally routed to.
+        expected: String,
+        /// The host a later request on the same connection carried instead.
+        found: String,
+    },
+    /// A connection's routing key changed between requests on the same connection, under a
+    /// [`crate::routing::RoutingStrategyConfig`] other than the default `Host` (which produces
+    /// [`Self::HostChanged`] instead, unchanged from before routing strategies existed).
+    #[snafu(display("routing key changed from {expected} to {found} on the same connection"))]
+    RouteChanged {
+        /// The key the connection was originally routed by.
+        expected: String,
+        /// The key a later request on the same connection carried instead.
+        found: String,
+    },
+    /// No [`crate::routing::RoutingStrategy`] could extract a routing key from a connection
+    /// before its read size cap was reached.
+    #[snafu(display("no routing key found within {limit} bytes of the connection"))]
+    RoutingKeyNotFound {
+        /// The size cap that was exceeded without a routing key being found.
+        limit: usize,
+    },
+    /// [`crate::discovery::ClusterInfo::endpoint_addr`] was asked to reconstruct an address from
+    /// a relay URL that doesn't parse, most likely a hand-edited static registration or data
+    /// corrupted in transit -- a live registration's relay URL always round-trips cleanly, since
+    /// it was itself parsed from a [`iroh::RelayUrl`] by the endpoint that advertised it.
+    #[snafu(display("cluster {cluster_id} has an invalid relay URL {relay_url:?}"))]
+    InvalidRelayUrl {
+        /// The cluster whose relay URL failed to parse.
+        cluster_id: String,
+        /// The relay URL that failed to parse.
+        relay_url: String,
+        /// Underlying parse error.
+        source: iroh::RelayUrlParseError,
+    },
+    /// [`crate::config::ProxyConfig::authz`] refused a dial to this service.
+    #[snafu(display("dial to cluster {cluster_id} for service {service} denied by authz policy"))]
+    AuthzDenied {
+        /// The cluster the denied dial would have reached.
+        cluster_id: String,
+        /// The service that was dialed.
+        service: String,
+    },
+    /// A dial to a remote cluster spoke a newer version of a framed request protocol than the
+    /// peer understood.
+    ///
+    /// [`crate::proxy::MeshProxy::dial_cluster`] itself hands back a raw
+    /// [`iroh::endpoint::Connection`] with nothing written to or read from it. The frames this
+    /// crate does define each lead with a version number, and this variant reports one newer than
+    /// this build supports: [`crate::route_request::RouteRequest`]'s 4-byte version (see
+    /// [`crate::route_request::RouteRequest::read_from`]) and the 1-byte version of
+    /// [`crate::protocol::MeshRequest`] and [`crate::protocol::MeshResponse`] (see
+    /// [`crate::protocol::MeshRequest::read_from`]).
+    #[snafu(display(
+        "protocol mismatch: peer supports up to version {peer_max}, we support up to {ours}"
+    ))]
+    ProtocolMismatch {
+        /// The highest protocol version the peer reported supporting.
+        peer_max: u32,
+        /// The highest protocol version this side supports.
+        ours: u32,
+    },
+    /// Sending a [`crate::route_request::RouteRequest`] frame on a dialed connection failed.
+    #[snafu(display("failed to send RouteRequest frame"))]
+    RouteRequestSend {
+        /// Underlying iroh stream error.
+        source: Box<iroh::endpoint::WriteError>,
+    },
+    /// Reading a [`crate::route_request::RouteRequest`] frame off a connection failed.
+    #[snafu(display("failed to read RouteRequest frame"))]
+    RouteRequestRead {
+        /// Underlying iroh stream error.
+        source: Box<iroh::endpoint::ReadToEndError>,
+    },
+    /// A [`crate::route_request::RouteRequest`] frame's body could not be parsed once read.
+    #[snafu(display("failed to decode RouteRequest frame"))]
+    RouteRequestDecode {
+        /// Underlying JSON decode error.
+        source: serde_json::Error,
+    },
+    /// A Kubernetes API call was rejected because the caller's `ServiceAccount` lacks the RBAC
+    /// permission to access `resource`.
+    ///
+    /// This crate has no Kubernetes client of its own yet (see [`crate::election`]'s module
+    /// docs), so nothing currently constructs this variant -- it exists so that whichever future
+    /// integration adds one (and the `discover_local_services` it would back) can distinguish a
+    /// 403/forbidden response from other failures instead of surfacing it as an opaque transport
+    /// error, and point the operator at the exact missing permission rather than a stack trace.
+    #[snafu(display(
+        "permission denied listing {resource}: grant the ServiceAccount RBAC to list/watch {resource}"
+    ))]
+    KubePermissionDenied {
+        /// The Kubernetes resource type the caller was forbidden from accessing, e.g.
+        /// `"services"`.
+        resource: String,
+    },
+    /// [`crate::discovery::DiscoveryManager::wait_for_cluster`] or
+    /// [`crate::proxy::MeshProxy::wait_for_service`] gave up before `what` became known.
+    #[snafu(display("timed out waiting for {what}"))]
+    Timeout {
+        /// What the caller was waiting for, e.g. `"cluster prod-us"` or `"service checkout"`.
+        what: String,
+    },
+    /// [`crate::outlier::OutlierDetector`] has ejected every known destination for `service`.
+    ///
+    /// Distinguished from [`Self::NoRoute`], which means no candidate was ever known at all --
+    /// this means candidates exist but every one of them is currently being avoided for having
+    /// failed too often, so retrying immediately would just fail again.
+    #[snafu(display(
+        "every destination for service {service} is currently ejected by outlier detection"
+    ))]
+    CircuitOpen {
+        /// The service every known destination was ejected for.
+        service: String,
+    },
+    /// A cross-cluster dial was attempted while running in degraded mode (see
+    /// [`crate::config::ProxyConfig::allow_degraded`]), which has no iroh endpoint to dial with.
+    #[snafu(display("mesh endpoint unavailable: running in degraded, local-routes-only mode"))]
+    MeshUnavailable {},
+    /// Opening a bidirectional stream on an already-dialed cross-cluster connection failed.
+    #[snafu(display("failed to open a tunnel stream to {target}"))]
+    OpenStream {
+        /// The service or cluster the stream was opened against.
+        target: String,
+        /// Underlying iroh connection error.
+        source: Box<iroh::endpoint::ConnectionError>,
+    },
+    /// A [`crate::protocol`] frame's declared length exceeded [`crate::protocol::MAX_FRAME_BYTES`]
+    /// before any attempt to read or parse its body.
+    #[snafu(display("frame length {len} exceeds the {limit} byte cap"))]
+    FrameTooLarge {
+        /// The length the frame's header declared.
+        len: u32,
+        /// The size cap that was exceeded.
+        limit: usize,
+    },
+    /// Sending a [`crate::protocol::MeshRequest`] frame on a dialed stream failed.
+    #[snafu(display("failed to send MeshRequest frame"))]
+    MeshRequestSend {
+        /// Underlying iroh stream error.
+        source: Box<iroh::endpoint::WriteError>,
+    },
+    /// Reading a [`crate::protocol::MeshRequest`] frame off a stream failed.
+    #[snafu(display("failed to read MeshRequest frame"))]
+    MeshRequestRead {
+        /// Underlying iroh stream error.
+        source: Box<iroh::endpoint::ReadExactError>,
+    },
+    /// A [`crate::protocol::MeshRequest`] frame's body could not be parsed once read.
+    #[snafu(display("failed to decode MeshRequest frame"))]
+    MeshRequestDecode {
+        /// Underlying JSON decode error.
+        source: serde_json::Error,
+    },
+    /// Sending a [`crate::protocol::MeshResponse`] frame on a stream failed.
+    #[snafu(display("failed to send MeshResponse frame"))]
+    MeshResponseSend {
+        /// Underlying iroh stream error.
+        source: Box<iroh::endpoint::WriteError>,
+    },
+    /// Reading a [`crate::protocol::MeshResponse`] frame off a stream failed.
+    #[snafu(display("failed to read MeshResponse frame"))]
+    MeshResponseRead {
+        /// Underlying iroh stream error.
+        source: Box<iroh::endpoint::ReadExactError>,
+    },
+    /// A [`crate::protocol::MeshResponse`] frame's body could not be parsed once read.
+    #[snafu(display("failed to decode MeshResponse frame"))]
+    MeshResponseDecode {
+        /// Underlying JSON decode error.
+        source: serde_json::Error,
+    },
+    /// A [`crate::gossip::GossipAnnouncement`] received off a gossip topic could not be parsed.
+    #[snafu(display("failed to decode gossip announcement"))]
+    GossipAnnouncementDecode {
+        /// Underlying JSON decode error.
+        source: serde_json::Error,
+    },
+    /// Sending a [`crate::udp_datagram`] frame as a QUIC unreliable datagram failed.
+    #[snafu(display("failed to send UDP datagram to {target}"))]
+    UdpDatagramSend {
+        /// The service the datagram was addressed to.
+        target: String,
+        /// Underlying iroh datagram error.
+        source: Box<iroh::endpoint::SendDatagramError>,
+    },
+    /// Reading a QUIC unreliable datagram off a dialed connection failed.
+    #[snafu(display("failed to read UDP datagram from {target}"))]
+    UdpDatagramRead {
+        /// The service the datagram was expected from.
+        target: String,
+        /// Underlying iroh connection error.
+        source: Box<iroh::endpoint::ConnectionError>,
+    },
+    /// A [`crate::udp_datagram`] frame's header could not be parsed once received.
+    #[snafu(display("failed to decode UDP datagram header: {reason}"))]
+    UdpDatagramDecode {
+        /// Human readable reason the header was rejected.
+        reason: String,
+    },
+}
+
+impl MeshError {
+    /// Stable, machine-readable identifier for this error, one of `"no-route"`,
+    /// `"cluster-unreachable"`, `"authz-denied"`, `"timeout"`, `"circuit-open"` or
+    /// `"mesh-unavailable"`.
+    ///
+    /// [`crate::proxy`]'s HTTP-mode forwarding path sends this back to clients as the
+    /// `X-Iroh-Mesh-Error` header, alongside [`Self::http_status`], so operators can alert and
+    /// debug on a stable code instead of parsing [`Self::to_string`]'s prose. The mapping is
+    /// deliberately many-to-one: new variants should pick whichever of these six buckets an
+    /// operator would actually act on rather than growing the set.
+    pub fn reason_code(&self) -> &'static str {
+        match self {
+            Self::NoRoute { .. }
+            | Self::HostChanged { .. }
+            | Self::RouteChanged { .. }
+            | Self::RoutingKeyNotFound { .. }
+            | Self::HeaderTooLarge { .. } => "no-route",
+            Self::AuthzDenied { .. } | Self::KubePermissionDenied { .. } => "authz-denied",
+            Self::DialTimeout { .. } | Self::Timeout { .. } => "timeout",
+            Self::CircuitOpen { .. } => "circuit-open",
+            Self::MeshUnavailable { .. } => "mesh-unavailable",
+            Self::Io { .. }
+            | Self::InvalidConfig { .. }
+            | Self::BindEndpoint { .. }
+            | Self::Connect { .. }
+            | Self::InvalidRelayUrl { .. }
+            | Self::ProtocolMismatch { .. }
+            | Self::RouteRequestSend { .. }
+            | Self::RouteRequestRead { .. }
+            | Self::RouteRequestDecode { .. }
+            | Self::OpenStream { .. }
+            | Self::FrameTooLarge { .. }
+            | Self::MeshRequestSend { .. }
+            | Self::MeshRequestRead { .. }
+            | Self::MeshRequestDecode { .. }
+            | Self::MeshResponseSend { .. }
+            | Self::MeshResponseRead { .. }
+            | Self::MeshResponseDecode { .. }
+            | Self::GossipAnnouncementDecode { .. }
+            | Self::UdpDatagramSend { .. }
+            | Self::UdpDatagramRead { .. }
+            | Self::UdpDatagramDecode { .. } => "cluster-unreachable",
+        }
+    }
+
+    /// The HTTP status code [`Self::reason_code`] maps to: 404/502/403/504/503/503.
+    pub fn http_status(&self) -> u16 {
+        match self.reason_code() {
+            "no-route" => 404,
+            "cluster-unreachable" => 502,
+            "authz-denied" => 403,
+            "timeout" => 504,
+            "circuit-open" | "mesh-unavailable" => 503,
+            other => unreachable!("reason_code returned an unmapped code: {other}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kube_permission_denied_names_the_missing_resource_and_verb() {
+        let err = KubePermissionDeniedSnafu {
+            resource: "services",
+        }
+        .build();
+        let message = err.to_string();
+        assert!(message.contains("services"), "{message}");
+        assert!(message.contains("list/watch"), "{message}");
+    }
+
+    #[test]
+    fn protocol_mismatch_names_both_versions() {
+        let err = ProtocolMismatchSnafu {
+            peer_max: 1u32,
+            ours: 2u32,
+        }
+        .build();
+        let message = err.to_string();
+        assert!(message.contains('1'), "{message}");
+        assert!(message.contains('2'), "{message}");
+    }
+
+    #[test]
+    fn no_route_maps_to_the_no_route_reason_and_a_404() {
+        let err = NoRouteSnafu { target: "checkout" }.build();
+        assert_eq!(err.reason_code(), "no-route");
+        assert_eq!(err.http_status(), 404);
+    }
+
+    #[test]
+    fn an_io_error_maps_to_the_cluster_unreachable_reason_and_a_502() {
+        let err = std::io::Error::other("boom");
+        let err = MeshError::from(err);
+        assert_eq!(err.reason_code(), "cluster-unreachable");
+        assert_eq!(err.http_status(), 502);
+    }
+
+    #[test]
+    fn authz_denied_maps_to_the_authz_denied_reason_and_a_403() {
+        let err = AuthzDeniedSnafu {
+            cluster_id: "cluster-a",
+            service: "checkout",
+        }
+        .build();
+        assert_eq!(err.reason_code(), "authz-denied");
+        assert_eq!(err.http_status(), 403);
+    }
+
+    #[test]
+    fn dial_timeout_maps_to_the_timeout_reason_and_a_504() {
+        let err = DialTimeoutSnafu {
+            cluster_id: "cluster-a",
+        }
+        .build();
+        assert_eq!(err.reason_code(), "timeout");
+        assert_eq!(err.http_status(), 504);
+    }
+
+    #[test]
+    fn circuit_open_maps_to_the_circuit_open_reason_and_a_503() {
+        let err = CircuitOpenSnafu {
+            service: "checkout",
+        }
+        .build();
+        assert_eq!(err.reason_code(), "circuit-open");
+        assert_eq!(err.http_status(), 503);
+    }
+
+    #[test]
+    fn mesh_unavailable_maps_to_the_mesh_unavailable_reason_and_a_503() {
+        let err = MeshUnavailableSnafu.build();
+        assert_eq!(err.reason_code(), "mesh-unavailable");
+        assert_eq!(err.http_status(), 503);
+    }
+}
@@ -0,0 +1,138 @@
+//! Cluster membership learned over gossip instead of a shared registry -- so two clusters with no
+//! Kubernetes API server in common (unlike [`crate::discovery::ClusterStore`]'s CRD case) can still
+//! find each other, purely over the iroh network.
+//!
+//! **Status: blocked, not just unfinished.** Nothing in this module joins a real gossip topic or
+//! puts an announcement on the wire -- see below for why an `iroh-gossip` dependency can't be
+//! added as-is. Don't read [`GossipAnnouncement`]/[`apply_announcement`] existing as evidence that
+//! gossip-based discovery works end to end; they're the wire format and apply step only, unit
+//! tested against each other with no network underneath.
+//!
+//! The plan this crate's own doc comments already anticipate (see [`crate::discovery`]'s and
+//! [`crate::proxy`]'s references to "gossip" as an existing registration source) is: join an
+//! `iroh-gossip` topic per mesh, broadcast this agent's [`ClusterRegistration`] on it as a
+//! [`GossipAnnouncement`], and apply whatever peers broadcast back into a shared
+//! [`crate::discovery::DiscoveryManager`] via [`apply_announcement`]. Unlike the `kube` gap
+//! documented in [`crate::election`] and [`crate::service_cache`], nothing rules out an
+//! `iroh-gossip` dependency here on principle -- but this workspace's `iroh-mesh` pins the same
+//! local `iroh` crate the rest of the workspace builds against, and the `iroh-gossip` release
+//! compatible with that version pulls in a *different*, crates.io-published `iroh` as its own
+//! dependency: two copies of the same crate at different versions, whose `Endpoint` and
+//! `EndpointId` types don't unify. Wiring an actual `iroh_gossip::net::Gossip` onto this crate's
+//! endpoint needs a workspace-wide `[patch]` pinning that dependency to the local `iroh` first,
+//! which is out of scope for this module alone.
+//!
+//! [`GossipAnnouncement`] and [`apply_announcement`] are the half of this that doesn't depend on
+//! that: the wire format a topic message carries, and what a receiver does with one once decoded.
+//! They're exercised directly in this module's tests, without a real gossip network underneath.
+
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use crate::{
+    discovery::{ClusterRegistration, DiscoveryManager},
+    error::{GossipAnnouncementDecodeSnafu, Result},
+};
+
+/// A [`ClusterRegistration`] broadcast on a mesh's gossip topic.
+///
+/// This is the only variant today; a wrapping enum rather than the bare registration leaves room
+/// to add e.g. a departure announcement later without changing [`GossipAnnouncement::encode`]'s
+/// callers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GossipAnnouncement {
+    /// A cluster is present with (or has updated) the enclosed registration.
+    Registered(ClusterRegistration),
+}
+
+impl GossipAnnouncement {
+    /// Announces `registration`, ready to broadcast on a gossip topic.
+    pub fn registered(registration: ClusterRegistration) -> Self {
+        Self::Registered(registration)
+    }
+
+    /// Encodes this announcement as the bytes a gossip topic message would carry.
+    ///
+    /// Infallible in practice -- every field of [`ClusterRegistration`] serializes -- so this
+    /// returns the bytes directly rather than a [`crate::error::Result`], matching
+    /// [`crate::protocol::MeshRequest`]'s frame encoding.
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("announcement always serializes")
+    }
+
+    /// Decodes a gossip topic message previously produced by [`Self::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).context(GossipAnnouncementDecodeSnafu)
+    }
+}
+
+/// Decodes `bytes` as a [`GossipAnnouncement`] and applies it to `discovery`.
+///
+/// This is what a gossip topic's receive loop would call for every message it gets, once one
+/// exists (see the [module docs](self) for what's still missing to run a real one). Returns
+/// whether the announcement actually changed `discovery`'s state, same as
+/// [`DiscoveryManager::register_cluster`] -- so a future receive loop can use it identically for
+/// logging or metrics.
+pub fn apply_announcement(discovery: &DiscoveryManager, bytes: &[u8]) -> Result<bool> {
+    let GossipAnnouncement::Registered(registration) = GossipAnnouncement::decode(bytes)?;
+    Ok(discovery.register_cluster(registration))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn registration(cluster_id: &str) -> ClusterRegistration {
+        ClusterRegistration {
+            cluster_id: cluster_id.to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: SystemTime::now(),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn an_announcement_round_trips_through_encode_and_decode() {
+        let announcement = GossipAnnouncement::registered(registration("cluster-a"));
+
+        let decoded = GossipAnnouncement::decode(&announcement.encode()).unwrap();
+
+        let GossipAnnouncement::Registered(registration) = decoded;
+        assert_eq!(registration.cluster_id, "cluster-a");
+    }
+
+    #[test]
+    fn decoding_garbage_bytes_fails() {
+        let err = GossipAnnouncement::decode(b"not json").unwrap_err();
+
+        assert_eq!(err.reason_code(), "cluster-unreachable");
+    }
+
+    #[test]
+    fn applying_an_announcement_registers_the_cluster() {
+        let discovery = DiscoveryManager::new();
+        let announcement = GossipAnnouncement::registered(registration("cluster-a"));
+
+        let changed = apply_announcement(&discovery, &announcement.encode()).unwrap();
+
+        assert!(changed);
+        assert!(discovery.get_cluster("cluster-a").is_some());
+    }
+
+    #[test]
+    fn applying_the_same_announcement_twice_reports_no_change_the_second_time() {
+        let discovery = DiscoveryManager::new();
+        let bytes = GossipAnnouncement::registered(registration("cluster-a")).encode();
+
+        apply_announcement(&discovery, &bytes).unwrap();
+        let changed_again = apply_announcement(&discovery, &bytes).unwrap();
+
+        assert!(!changed_again);
+    }
+}
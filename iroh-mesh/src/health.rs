@@ -0,0 +1,528 @@
+//! Active health checking of clusters known to a [`DiscoveryManager`].
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use iroh::Endpoint;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::{
+    discovery::{ClusterInfo, DiscoveryConfig, DiscoveryManager},
+    election::LeaderElection,
+};
+
+/// How long a single probe connection is given to establish before being treated as a failure.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Dials `cluster` and opens (then immediately finishes) a uni stream as a liveness probe,
+/// giving up after `timeout`. Shared by [`HealthChecker::probe_once`] and
+/// [`crate::proxy::MeshProxy`]'s readiness probing: both just need "is this cluster reachable
+/// right now", with no side effects on discovery or metrics beyond the dial itself.
+pub(crate) async fn probe_cluster_reachable(
+    endpoint: &Endpoint,
+    alpn: &[u8],
+    cluster: &ClusterInfo,
+    timeout: Duration,
+) -> bool {
+    // A malformed relay URL fails the probe the same as an unreachable one -- there's no
+    // "probably fine, try anyway" middle ground worth a special case here.
+    let Ok(addr) = cluster.endpoint_addr() else {
+        return false;
+    };
+
+    let attempt = tokio::time::timeout(timeout, async {
+        let conn = endpoint.connect(addr, alpn).await.ok()?;
+        let mut stream = conn.open_uni().await.ok()?;
+        stream.finish().ok()?;
+        conn.close(0u32.into(), b"probe complete");
+        Some(())
+    })
+    .await;
+    matches!(attempt, Ok(Some(())))
+}
+
+/// Periodically probes every cluster known to a [`DiscoveryManager`] and marks its services
+/// healthy or unhealthy based on reachability.
+///
+/// A probe is scoped to a whole cluster, not a single service: this crate has no per-service
+/// liveness protocol, so a cluster's reachability is applied to every service it advertises (see
+/// [`DiscoveryManager::set_cluster_health`]).
+#[derive(Debug)]
+pub struct HealthChecker {
+    endpoint: Endpoint,
+    /// ALPN probes are dialed with, matching the probed clusters' proxies (see
+    /// [`crate::proxy::mesh_alpn`]).
+    alpn: Vec<u8>,
+    discovery: Arc<DiscoveryManager>,
+    config: DiscoveryConfig,
+    /// Consecutive failed probes per cluster, reset on the first success.
+    failures: RwLock<HashMap<String, u32>>,
+    /// Gates [`Self::record_result`]'s writes to discovery when multiple agent replicas are
+    /// probing the same clusters, so only the elected leader's results stick (see
+    /// [`crate::election`]). `None` runs unreplicated, i.e. always writes.
+    election: Option<Arc<LeaderElection>>,
+}
+
+impl HealthChecker {
+    /// Creates a health checker that dials peers through `endpoint` using `alpn`.
+    pub fn new(
+        endpoint: Endpoint,
+        discovery: Arc<DiscoveryManager>,
+        config: DiscoveryConfig,
+        alpn: Vec<u8>,
+        election: Option<Arc<LeaderElection>>,
+    ) -> Self {
+        Self {
+            endpoint,
+            alpn,
+            discovery,
+            config,
+            failures: RwLock::new(HashMap::new()),
+            election,
+        }
+    }
+
+    /// Probes every currently known cluster once and updates their health in [`DiscoveryManager`].
+    ///
+    /// Probes run concurrently, up to [`DiscoveryConfig::max_concurrent_probes`] at a time, within
+    /// an overall [`DiscoveryConfig::probe_budget`] for the whole pass: a handful of slow or
+    /// unreachable clusters delay only themselves, not the rest, and whichever results arrive
+    /// before the budget runs out are still recorded.
+    pub async fn probe_once(&self) {
+        let mut pending = self.discovery.list_clusters().into_iter();
+        let mut in_flight = JoinSet::new();
+        for cluster in pending
+            .by_ref()
+            .take(self.config.max_concurrent_probes.max(1))
+        {
+            self.spawn_probe(&mut in_flight, cluster);
+        }
+
+        let deadline = tokio::time::Instant::now() + self.config.probe_budget;
+        while !in_flight.is_empty() {
+            let Some(remaining) = deadline.checked_duration_since(tokio::time::Instant::now())
+            else {
+                break;
+            };
+            let Ok(Some(joined)) = tokio::time::timeout(remaining, in_flight.join_next()).await
+            else {
+                // Either the budget ran out, or every spawned probe already finished.
+                break;
+            };
+            let Ok((cluster_id, healthy)) = joined else {
+                // The probe task panicked; nothing to record for it.
+                continue;
+            };
+            debug!(cluster = %cluster_id, healthy, "mesh health probe");
+            self.record_result(&cluster_id, healthy);
+            if let Some(cluster) = pending.next() {
+                self.spawn_probe(&mut in_flight, cluster);
+            }
+        }
+    }
+
+    /// Spawns one cluster's probe onto `in_flight`, cloning what the task needs so it can run
+    /// independently of `self`'s lifetime.
+    fn spawn_probe(&self, in_flight: &mut JoinSet<(String, bool)>, cluster: ClusterInfo) {
+        let endpoint = self.endpoint.clone();
+        let alpn = self.alpn.clone();
+        in_flight.spawn(async move {
+            let healthy = probe_cluster_reachable(&endpoint, &alpn, &cluster, PROBE_TIMEOUT).await;
+            (cluster.cluster_id, healthy)
+        });
+    }
+
+    /// Applies a probe result, marking `cluster_id` unhealthy once its consecutive failure count
+    /// reaches [`DiscoveryConfig::failure_threshold`], or healthy again on the first success.
+    ///
+    /// Still probes and tracks its own failure count even when [`Self::election`] says this
+    /// replica isn't the leader, so it's caught up and ready to take over the moment it is; it
+    /// just skips the write to shared discovery state that a leader's result would make.
+    fn record_result(&self, cluster_id: &str, healthy: bool) {
+        let mut failures = self.failures.write().expect("lock poisoned");
+        let is_leader = self
+            .election
+            .as_ref()
+            .is_none_or(|election| election.is_leader());
+        if healthy {
+            failures.remove(cluster_id);
+            if is_leader {
+                self.discovery.set_cluster_health(cluster_id, true);
+            }
+            return;
+        }
+        let count = failures.entry(cluster_id.to_string()).or_insert(0);
+        *count += 1;
+        if *count >= self.config.failure_threshold && is_leader {
+            self.discovery.set_cluster_health(cluster_id, false);
+        }
+    }
+
+    /// Spawns a background task that calls [`Self::probe_once`] on
+    /// [`DiscoveryConfig::probe_interval`] until the returned handle is dropped or `shutdown` is
+    /// cancelled.
+    ///
+    /// `shutdown` lets a caller stop this task and wait for its current probe pass to finish
+    /// before moving on -- e.g. [`crate::agent::MeshAgent::shutdown`] -- rather than only being
+    /// able to abort it outright by dropping the returned handle, which could cut a probe pass
+    /// off mid-write to discovery.
+    ///
+    /// A [`DiscoveryConfig::probe_interval`] of [`Duration::ZERO`] disables the periodic probe
+    /// entirely: the spawned task never calls [`Self::probe_once`], only waiting on `shutdown` so
+    /// the returned handle behaves the same either way. Callers should validate `self.config` with
+    /// [`DiscoveryConfig::validate`] before spawning; this doesn't re-validate it.
+    pub fn spawn(self: Arc<Self>, shutdown: CancellationToken) -> HealthCheckerHandle {
+        if self.config.probe_interval.is_zero() {
+            let task = tokio::spawn(async move { shutdown.cancelled().await });
+            return HealthCheckerHandle { task };
+        }
+        let mut ticker = tokio::time::interval(self.config.probe_interval);
+        let checker = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    biased;
+                    () = shutdown.cancelled() => return,
+                    _ = ticker.tick() => {}
+                }
+                checker.probe_once().await;
+            }
+        });
+        HealthCheckerHandle { task }
+    }
+}
+
+/// Handle to a [`HealthChecker`] spawned with [`HealthChecker::spawn`]; dropping it stops probing.
+#[derive(Debug)]
+pub struct HealthCheckerHandle {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for HealthCheckerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::RelayMode;
+    use snafu::ResultExt;
+
+    use super::*;
+    use crate::{
+        discovery::ClusterRegistration, election::LeaseStore, error::BindEndpointSnafu,
+        proxy::MESH_ALPN,
+    };
+
+    async fn bind_probed_endpoint() -> crate::error::Result<Endpoint> {
+        Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)
+    }
+
+    #[tokio::test]
+    async fn probing_an_unreachable_cluster_marks_its_services_unhealthy()
+    -> crate::error::Result<()> {
+        let cluster_a = bind_probed_endpoint().await?;
+        let cluster_b = bind_probed_endpoint().await?;
+        let prober = bind_probed_endpoint().await?;
+
+        for endpoint in [&cluster_a, &cluster_b] {
+            let endpoint = endpoint.clone();
+            tokio::spawn(async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    tokio::spawn(async move {
+                        let _ = incoming.await;
+                    });
+                }
+            });
+        }
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: cluster_a.id(),
+            relay_url: None,
+            direct_addresses: cluster_a.addr().direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: cluster_b.id(),
+            relay_url: None,
+            direct_addresses: cluster_b.addr().direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let checker = HealthChecker::new(
+            prober,
+            discovery.clone(),
+            DiscoveryConfig {
+                probe_interval: Duration::from_secs(3600),
+                failure_threshold: 1,
+                ..Default::default()
+            },
+            MESH_ALPN.to_vec(),
+            None,
+        );
+
+        checker.probe_once().await;
+        let mut healthy: Vec<_> = discovery
+            .find_service("svc")
+            .into_iter()
+            .map(|info| info.cluster_id)
+            .collect();
+        healthy.sort();
+        assert_eq!(
+            healthy,
+            vec!["cluster-a".to_string(), "cluster-b".to_string()],
+            "both clusters should be healthy before cluster-a goes down"
+        );
+
+        // Take cluster-a's endpoint down, so the next probe against it fails outright.
+        cluster_a.close().await;
+        checker.probe_once().await;
+
+        let remaining: Vec<_> = discovery
+            .find_service("svc")
+            .into_iter()
+            .map(|info| info.cluster_id)
+            .collect();
+        assert_eq!(
+            remaining,
+            vec!["cluster-b".to_string()],
+            "routing should avoid the unreachable cluster's copy of the service"
+        );
+
+        Ok(())
+    }
+
+    /// A [`LeaseStore`] that never grants the lease, for exercising a perpetual follower.
+    #[derive(Debug, Default)]
+    struct NeverLeader;
+
+    impl LeaseStore for NeverLeader {
+        fn try_acquire(&self, _holder: &str, _ttl: Duration) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn a_follower_probes_but_does_not_write_health_results() -> crate::error::Result<()> {
+        let cluster_a = bind_probed_endpoint().await?;
+        let prober = bind_probed_endpoint().await?;
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: cluster_a.id(),
+            relay_url: None,
+            direct_addresses: cluster_a.addr().direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        cluster_a.close().await;
+
+        let election = Arc::new(LeaderElection::new(
+            Arc::new(NeverLeader),
+            "follower",
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        ));
+        let checker = HealthChecker::new(
+            prober,
+            discovery.clone(),
+            DiscoveryConfig {
+                probe_interval: Duration::from_secs(3600),
+                failure_threshold: 1,
+                ..Default::default()
+            },
+            MESH_ALPN.to_vec(),
+            Some(election),
+        );
+
+        checker.probe_once().await;
+
+        assert_eq!(
+            discovery.find_service("svc").len(),
+            1,
+            "a follower's failed probe shouldn't mark the cluster unhealthy"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn probe_once_completes_within_budget_and_updates_only_the_peers_that_answered_in_time()
+    -> crate::error::Result<()> {
+        const FAST_COUNT: usize = 8;
+        const SLOW_COUNT: usize = 4;
+
+        let prober = bind_probed_endpoint().await?;
+        let discovery = Arc::new(DiscoveryManager::new());
+
+        let mut fast_ids = Vec::new();
+        for i in 0..FAST_COUNT {
+            let cluster = bind_probed_endpoint().await?;
+            tokio::spawn({
+                let cluster = cluster.clone();
+                async move {
+                    while let Some(incoming) = cluster.accept().await {
+                        tokio::spawn(async move {
+                            let _ = incoming.await;
+                        });
+                    }
+                }
+            });
+            let cluster_id = format!("fast-{i}");
+            discovery.register_cluster(ClusterRegistration {
+                cluster_id: cluster_id.clone(),
+                endpoint_id: cluster.id(),
+                relay_url: None,
+                direct_addresses: cluster.addr().direct_addresses().copied().collect(),
+                services: vec!["svc".to_string()],
+                service_ports: Vec::new(),
+                updated_at: std::time::SystemTime::now(),
+                pinned: false,
+            });
+            discovery.set_cluster_health(&cluster_id, false);
+            fast_ids.push(cluster_id);
+        }
+
+        let mut slow_ids = Vec::new();
+        for i in 0..SLOW_COUNT {
+            let cluster = bind_probed_endpoint().await?;
+            tokio::spawn({
+                let cluster = cluster.clone();
+                async move {
+                    while let Some(incoming) = cluster.accept().await {
+                        tokio::spawn(async move {
+                            // Delays the handshake well past the test's probe budget, so this
+                            // peer never gets to answer before the pass moves on.
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            let _ = incoming.await;
+                        });
+                    }
+                }
+            });
+            let cluster_id = format!("slow-{i}");
+            discovery.register_cluster(ClusterRegistration {
+                cluster_id: cluster_id.clone(),
+                endpoint_id: cluster.id(),
+                relay_url: None,
+                direct_addresses: cluster.addr().direct_addresses().copied().collect(),
+                services: vec!["svc".to_string()],
+                service_ports: Vec::new(),
+                updated_at: std::time::SystemTime::now(),
+                pinned: false,
+            });
+            discovery.set_cluster_health(&cluster_id, false);
+            slow_ids.push(cluster_id);
+        }
+
+        let checker = HealthChecker::new(
+            prober,
+            discovery.clone(),
+            DiscoveryConfig {
+                probe_interval: Duration::from_secs(3600),
+                failure_threshold: 1,
+                max_concurrent_probes: FAST_COUNT + SLOW_COUNT,
+                probe_budget: Duration::from_secs(2),
+            },
+            MESH_ALPN.to_vec(),
+            None,
+        );
+
+        let started = std::time::Instant::now();
+        checker.probe_once().await;
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed < Duration::from_secs(4),
+            "a 2s budget probing concurrently shouldn't take anywhere near as long as \
+             probing every peer serially with a {PROBE_TIMEOUT:?} per-probe timeout, took {elapsed:?}"
+        );
+
+        let healthy: std::collections::HashSet<_> = discovery
+            .find_service("svc")
+            .into_iter()
+            .map(|info| info.cluster_id)
+            .collect();
+        for cluster_id in &fast_ids {
+            assert!(
+                healthy.contains(cluster_id),
+                "{cluster_id} answered promptly and should have been marked healthy"
+            );
+        }
+        for cluster_id in &slow_ids {
+            assert!(
+                !healthy.contains(cluster_id),
+                "{cluster_id} didn't answer within the budget and shouldn't have been marked \
+                 healthy"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_zero_probe_interval_never_probes_until_shutdown() -> crate::error::Result<()> {
+        let cluster = bind_probed_endpoint().await?;
+        let prober = bind_probed_endpoint().await?;
+        cluster.close().await;
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: cluster.id(),
+            relay_url: None,
+            direct_addresses: cluster.addr().direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let checker = Arc::new(HealthChecker::new(
+            prober,
+            discovery.clone(),
+            DiscoveryConfig {
+                probe_interval: Duration::ZERO,
+                failure_threshold: 1,
+                ..Default::default()
+            },
+            MESH_ALPN.to_vec(),
+            None,
+        ));
+        let shutdown = CancellationToken::new();
+        let handle = checker.spawn(shutdown.clone());
+
+        // The registered cluster is unreachable, so if the spawned task probed at all it would
+        // mark it unhealthy well within an hour of advancing this paused clock.
+        tokio::time::advance(Duration::from_secs(3600)).await;
+        assert_eq!(
+            discovery.find_service("svc").len(),
+            1,
+            "a zero probe_interval should disable periodic probing entirely"
+        );
+
+        shutdown.cancel();
+        drop(handle);
+        Ok(())
+    }
+}
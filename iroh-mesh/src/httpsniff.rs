@@ -0,0 +1,279 @@
+//! Minimal HTTP/1.1 request-line and header parsing used to route TCP connections by their
+//! `Host` header, without pulling in a full HTTP server stack for the proxy's data plane.
+//!
+//! This only looks far enough into a connection to bind it to a route; once that's done the
+//! proxy goes back to splicing bytes verbatim (see [`crate::proxy`]).
+
+use snafu::ensure;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::{HeaderTooLargeSnafu, Result};
+
+/// Header blocks larger than this are rejected rather than buffered indefinitely.
+pub const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// The result of reading up to and including the first blank line of an HTTP/1.1 request.
+#[derive(Debug)]
+pub struct ParsedRequest {
+    /// The value of the request's `Host` header, if present.
+    pub host: Option<String>,
+    /// The request line's target path, without its query string, if the request line could be
+    /// parsed at all (see [`crate::config::ProxyConfig::path_routes`]).
+    pub path: Option<String>,
+    /// Whether this request asked to switch protocols (`Connection: Upgrade` alongside an
+    /// `Upgrade` header), e.g. a WebSocket handshake.
+    pub is_upgrade: bool,
+    /// The request line and headers exactly as read, including the terminating blank line.
+    pub head: Vec<u8>,
+    /// Bytes already read past the end of the header block: the start of the request body, or
+    /// of a pipelined request that arrived in the same read(s).
+    pub trailing: Vec<u8>,
+}
+
+/// Reads from `stream` until a full header block (`\r\n\r\n`) has been seen, looping over
+/// partial reads as needed, and returns the request's `Host` header along with any bytes read
+/// past the header block.
+///
+/// Fails with [`crate::error::MeshError::HeaderTooLarge`] if no header terminator appears within
+/// `max_header_bytes` (see [`crate::config::ProtocolLimits::max_header_bytes`]).
+pub async fn read_host_header<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    max_header_bytes: usize,
+) -> Result<ParsedRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = find_header_end(&buf) {
+            let host = parse_host(&buf[..end]);
+            let path = parse_request_path(&buf[..end]);
+            let is_upgrade = is_upgrade_request(&buf[..end]);
+            let trailing = buf.split_off(end + 4);
+            return Ok(ParsedRequest {
+                host,
+                path,
+                is_upgrade,
+                head: buf,
+                trailing,
+            });
+        }
+        ensure!(
+            buf.len() < max_header_bytes,
+            HeaderTooLargeSnafu {
+                limit: max_header_bytes,
+            }
+        );
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(ParsedRequest {
+                host: None,
+                path: None,
+                is_upgrade: false,
+                head: buf,
+                trailing: Vec::new(),
+            });
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Scans `trailing` for any further complete header blocks and returns the `Host` value of the
+/// first one that differs from `expected`.
+///
+/// Only covers header blocks already present in bytes read alongside the first request; a
+/// connection that pipelines slowly enough that later requests arrive after routing has already
+/// handed the socket off to the backend is not re-checked.
+pub fn find_mismatched_host(trailing: &[u8], expected: &str) -> Option<String> {
+    find_mismatched_header(trailing, "host", expected)
+}
+
+/// Like [`find_mismatched_host`], but for an arbitrary header name instead of `Host`. Backs
+/// [`crate::routing`]'s header-based strategies.
+pub(crate) fn find_mismatched_header(
+    trailing: &[u8],
+    name: &str,
+    expected: &str,
+) -> Option<String> {
+    let mut rest = trailing;
+    while let Some(end) = find_header_end(rest) {
+        if let Some(value) = header_value(&rest[..end], name) {
+            if value != expected {
+                return Some(value);
+            }
+        }
+        rest = &rest[end + 4..];
+    }
+    None
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+/// Returns the slice of `buf` up to (not including) its first complete header block's
+/// terminating blank line, if one has arrived yet. Backs [`crate::routing`]'s strategies, which
+/// see a connection's bytes incrementally rather than a known-complete block.
+pub(crate) fn first_header_block(buf: &[u8]) -> Option<&[u8]> {
+    find_header_end(buf).map(|end| &buf[..end])
+}
+
+/// Returns the bytes of `buf` past its first complete header block, if one has arrived yet --
+/// the counterpart to [`first_header_block`].
+pub(crate) fn trailing_after_first_header_block(buf: &[u8]) -> Option<&[u8]> {
+    find_header_end(buf).map(|end| &buf[end + 4..])
+}
+
+/// Looks up `name`'s value in `buf`, but only once a full header block has arrived, unlike
+/// [`header_value`], which assumes `buf` already ends right before one. Backs
+/// [`crate::routing`]'s header-based strategies.
+pub(crate) fn header_value_in_complete_block(buf: &[u8], name: &str) -> Option<String> {
+    header_value(first_header_block(buf)?, name)
+}
+
+fn parse_host(head: &[u8]) -> Option<String> {
+    header_value(head, "host")
+}
+
+/// Extracts a request line's target path, without its query string, e.g. `/api/foo` from
+/// `GET /api/foo?id=1 HTTP/1.1`. Backs [`crate::config::ProxyConfig::path_routes`]; `None` if
+/// `head` doesn't start with a well-formed request line.
+fn parse_request_path(head: &[u8]) -> Option<String> {
+    let first_line = head.split(|&b| b == b'\n').next()?;
+    let first_line = String::from_utf8_lossy(first_line);
+    let target = first_line.trim_end_matches('\r').split(' ').nth(1)?;
+    Some(target.split('?').next().unwrap_or(target).to_string())
+}
+
+/// Whether `head` carries a `Connection: Upgrade` header alongside an `Upgrade` header, the
+/// handshake shape used to switch a connection to another protocol (e.g. WebSockets).
+pub(crate) fn is_upgrade_request(head: &[u8]) -> bool {
+    let connection_has_upgrade = header_value(head, "connection").is_some_and(|value| {
+        value
+            .split(',')
+            .any(|v| v.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    connection_has_upgrade && header_value(head, "upgrade").is_some()
+}
+
+fn header_value(head: &[u8], name: &str) -> Option<String> {
+    String::from_utf8_lossy(head)
+        .lines()
+        .find_map(|line| {
+            line.split_once(':')
+                .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+        })
+        .map(|(_, value)| value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, duplex};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_a_header_split_across_multiple_writes() {
+        let (mut client, mut server) = duplex(64);
+        let reader =
+            tokio::spawn(async move { read_host_header(&mut server, MAX_HEADER_BYTES).await });
+
+        client.write_all(b"GET /svc HTTP/1.1\r\n").await.unwrap();
+        client.write_all(b"Host: svc-a\r\n").await.unwrap();
+        client.write_all(b"\r\nbody-bytes").await.unwrap();
+
+        let parsed = reader.await.unwrap().unwrap();
+        assert_eq!(parsed.host.as_deref(), Some("svc-a"));
+        assert_eq!(parsed.trailing, b"body-bytes");
+    }
+
+    #[tokio::test]
+    async fn rejects_a_header_block_over_the_size_cap() {
+        let (mut client, mut server) = duplex(MAX_HEADER_BYTES + 4096);
+        let reader =
+            tokio::spawn(async move { read_host_header(&mut server, MAX_HEADER_BYTES).await });
+
+        // No "\r\n\r\n" anywhere in this, so the cap is hit before a terminator is ever seen.
+        let oversized = vec![b'a'; MAX_HEADER_BYTES + 1];
+        client.write_all(&oversized).await.unwrap();
+
+        let err = reader.await.unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::MeshError::HeaderTooLarge { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_header_block_just_over_a_configured_cap() {
+        const CAP: usize = 64;
+        let (mut client, mut server) = duplex(CAP + 64);
+        let reader = tokio::spawn(async move { read_host_header(&mut server, CAP).await });
+
+        // One byte over CAP, and no terminator anywhere in it.
+        let oversized = vec![b'a'; CAP + 1];
+        client.write_all(&oversized).await.unwrap();
+
+        let err = reader.await.unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::MeshError::HeaderTooLarge { limit: CAP, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn detects_a_websocket_upgrade_handshake() {
+        let (mut client, mut server) = duplex(256);
+        let reader =
+            tokio::spawn(async move { read_host_header(&mut server, MAX_HEADER_BYTES).await });
+
+        client
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nHost: svc-a\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let parsed = reader.await.unwrap().unwrap();
+        assert_eq!(parsed.host.as_deref(), Some("svc-a"));
+        assert!(parsed.is_upgrade);
+    }
+
+    #[tokio::test]
+    async fn a_plain_request_is_not_an_upgrade() {
+        let (mut client, mut server) = duplex(256);
+        let reader =
+            tokio::spawn(async move { read_host_header(&mut server, MAX_HEADER_BYTES).await });
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: svc-a\r\n\r\n")
+            .await
+            .unwrap();
+
+        let parsed = reader.await.unwrap().unwrap();
+        assert!(!parsed.is_upgrade);
+    }
+
+    #[tokio::test]
+    async fn reads_the_request_path_without_its_query_string() {
+        let (mut client, mut server) = duplex(256);
+        let reader =
+            tokio::spawn(async move { read_host_header(&mut server, MAX_HEADER_BYTES).await });
+
+        client
+            .write_all(b"GET /api/foo?id=1 HTTP/1.1\r\nHost: svc-a\r\n\r\n")
+            .await
+            .unwrap();
+
+        let parsed = reader.await.unwrap().unwrap();
+        assert_eq!(parsed.path.as_deref(), Some("/api/foo"));
+    }
+
+    #[test]
+    fn find_mismatched_host_reports_the_differing_request() {
+        let trailing = b"GET /other HTTP/1.1\r\nHost: svc-b\r\n\r\n";
+        assert_eq!(
+            find_mismatched_host(trailing, "svc-a").as_deref(),
+            Some("svc-b")
+        );
+        assert_eq!(find_mismatched_host(trailing, "svc-b"), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Small hand-rolled HTTP server helper shared by the agent and proxy status APIs.
+//!
+//! This crate doesn't (yet) depend on a full web framework, so routing is just a match on
+//! method and path segments in each caller's handler function.
+
+use std::{convert::Infallible, future::Future, net::SocketAddr};
+
+use http_body_util::Full;
+use hyper::{
+    Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tracing::warn;
+
+use crate::error::Result;
+
+/// Response body type used by all handlers.
+pub type Body = Full<Bytes>;
+
+/// Serves `handler` on `addr` until the process is asked to stop.
+pub async fn serve<S, F, Fut>(addr: SocketAddr, state: S, handler: F) -> Result<()>
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn(Request<Incoming>, S) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send,
+{
+    let listener = TcpListener::bind(addr).await?;
+    serve_on(listener, state, handler).await
+}
+
+/// Like [`serve`], but reuses an already-bound listener.
+///
+/// Mainly useful for tests that need to know the bound address before the server starts
+/// accepting connections.
+pub async fn serve_on<S, F, Fut>(listener: TcpListener, state: S, handler: F) -> Result<()>
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn(Request<Incoming>, S) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send,
+{
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let state = state.clone();
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let io = TokioIo::new(stream);
+            let service = service_fn(move |req| {
+                let state = state.clone();
+                let handler = handler.clone();
+                async move { Ok::<_, Infallible>(handler(req, state).await) }
+            });
+            if let Err(err) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                warn!(%peer, %err, "http connection error");
+            }
+        });
+    }
+}
+
+/// Like [`serve_on`], but terminates TLS using `tls_acceptor` on each accepted connection before
+/// serving HTTP over the decrypted stream, for a handler that needs HTTPS (see
+/// [`crate::agent::AgentConfig::api_tls`]). A connection whose handshake fails is dropped rather
+/// than ending the whole server.
+pub async fn serve_on_tls<S, F, Fut>(
+    listener: TcpListener,
+    tls_acceptor: tokio_rustls::TlsAcceptor,
+    state: S,
+    handler: F,
+) -> Result<()>
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn(Request<Incoming>, S) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send,
+{
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let tls_acceptor = tls_acceptor.clone();
+        let state = state.clone();
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let stream = match tls_acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(%peer, %err, "tls handshake failed");
+                    return;
+                }
+            };
+            let io = TokioIo::new(stream);
+            let service = service_fn(move |req| {
+                let state = state.clone();
+                let handler = handler.clone();
+                async move { Ok::<_, Infallible>(handler(req, state).await) }
+            });
+            if let Err(err) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                warn!(%peer, %err, "http connection error");
+            }
+        });
+    }
+}
+
+/// Builds a JSON response with the given status code.
+pub fn json_response(status: StatusCode, body: &impl serde::Serialize) -> Response<Body> {
+    let bytes = serde_json::to_vec(body).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(bytes)))
+        .expect("building a response from static parts cannot fail")
+}
+
+/// Builds a plain-text response with the given status code and content type.
+pub fn text_response(
+    status: StatusCode,
+    content_type: &'static str,
+    body: String,
+) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .body(Full::new(Bytes::from(body)))
+        .expect("building a response from static parts cannot fail")
+}
+
+/// Returns whether `req`'s query string contains `key=value`.
+///
+/// This crate doesn't depend on a query-string parser, so this is a minimal substring-free scan
+/// good enough for the handful of single-value flags the status endpoints accept.
+pub fn query_param_is(query: Option<&str>, key: &str, value: &str) -> bool {
+    query
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .any(|pair| pair.split_once('=') == Some((key, value)))
+}
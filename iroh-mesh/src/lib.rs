@@ -0,0 +1,97 @@
+//! A cross-cluster mesh proxy built on top of [`iroh`].
+//!
+//! This crate provides two cooperating roles:
+//!
+//! - [`proxy::MeshProxy`]: accepts traffic on behalf of local clients and forwards it to the
+//!   backend that currently serves a route.
+//! - [`agent::MeshAgent`]: runs alongside a cluster, discovers the services it exposes and
+//!   registers them with the mesh so that other clusters' proxies can reach them.
+//!
+//! Both roles share the [`discovery`] and [`error`] modules. [`mesh::MeshBuilder`] embeds both
+//! in one process sharing a single iroh endpoint, for callers that want to co-locate them
+//! instead of running this crate's separate `mesh-proxy` and `mesh-agent` binaries.
+
+pub mod affinity;
+pub mod agent;
+mod api;
+pub mod authz;
+pub mod clusterevents;
+pub mod config;
+pub mod connpool;
+pub mod consistent_hash;
+pub mod debounce;
+pub mod discovery;
+pub mod doctor;
+pub mod effective_config;
+pub mod election;
+pub mod endpoint_discovery;
+pub mod error;
+pub mod gossip;
+mod health;
+mod httpsniff;
+mod httputil;
+mod logsample;
+pub mod mesh;
+pub mod metrics;
+pub mod outlier;
+mod peek;
+pub mod protocol;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod ratelimit;
+pub mod registration;
+pub mod relay;
+pub mod route_request;
+pub mod routing;
+pub mod schema;
+pub mod secret_key;
+pub mod selector;
+pub mod service_cache;
+pub mod socket;
+pub mod standalone;
+pub mod status;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod topology;
+mod tracesample;
+pub mod udp_datagram;
+pub mod versioninfo;
+
+pub use affinity::SessionAffinity;
+pub use agent::{AgentConfig, MeshAgent, RefreshSummary, SelfRegistrationConfig};
+pub use authz::{AuthzEffect, AuthzPolicy, AuthzRule};
+pub use config::{MirrorConfig, PathRoute, ProxyConfig};
+pub use discovery::{
+    ClusterIdCollisionPolicy, ClusterInfo, ClusterRegistration, ClusterStore, DiscoveryConfig,
+    DiscoveryManager, DiscoveryManagerBuilder,
+};
+pub use doctor::{Check, Report};
+pub use effective_config::{ConfigSource, EffectiveConfig, Overridable};
+pub use election::{LeaderElection, LeaderElectionHandle, LeaseStore};
+pub use endpoint_discovery::EndpointDiscoveryConfig;
+pub use error::{MeshError, Result};
+pub use gossip::{GossipAnnouncement, apply_announcement};
+pub use mesh::{MeshBuilder, MeshHandle};
+pub use metrics::TraceExemplar;
+pub use outlier::OutlierDetectionConfig;
+pub use protocol::{MeshRequest, MeshResponse, TransportProtocol};
+pub use proxy::{
+    AcceptHookFn, ConnectionOutcome, ConnectionSummary, KeyRotationHandle, MeshProxy, ResolvedRoute,
+};
+pub use ratelimit::{ConnectionRateLimitConfig, ConnectionRateLimiter};
+pub use registration::{SelfClusterInfo, SelfRegistrationHandle, spawn_self_registration};
+pub use relay::RelayModeConfig;
+pub use route_request::RouteRequest;
+pub use routing::{RoutingKey, RoutingStrategyConfig};
+pub use schema::{agent_config_schema, proxy_config_schema};
+pub use secret_key::{SecretKeySource, load_or_create_secret_key};
+pub use selector::LabelSelector;
+pub use service_cache::{CachedServiceSource, ServiceSource};
+pub use socket::SocketOptions;
+pub use standalone::{
+    StandaloneFile, StandaloneReloadConfig, StandaloneReloadHandle, StandaloneServiceSource,
+    load_peers, spawn_reload,
+};
+pub use status::ClusterStatusResponse;
+pub use topology::TopologyGraph;
+pub use versioninfo::VersionInfo;
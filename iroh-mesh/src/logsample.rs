@@ -0,0 +1,87 @@
+//! A lightweight rate limiter for noisy per-connection log lines.
+//!
+//! Debug-level lines like "accepted client connection" are useful for following a single
+//! connection, but at high connection rates logging every one of them can dominate CPU and drown
+//! out everything else in the log stream. [`LogSampler`] caps how often a given log site is
+//! actually emitted without silencing it outright, so logs stay representative under load instead
+//! of disappearing entirely. It only gates the call sites that use it -- error-level logging in
+//! [`crate::proxy`] is never routed through a sampler.
+
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+/// Allows at most one log line per `interval`, across however many calls to [`Self::allow`]
+/// arrive in between.
+///
+/// Cheap to check on every connection (a single mutex lock, no allocation), and meant to be
+/// shared across connections via `&LogSampler` or `Arc<LogSampler>` -- each independent
+/// [`LogSampler`] has its own budget, so a separate instance per log site samples each
+/// independently of the others.
+#[derive(Debug)]
+pub struct LogSampler {
+    interval: Duration,
+    last_allowed: Mutex<Option<Instant>>,
+}
+
+impl LogSampler {
+    /// Allows at most one log line per `interval`. An `interval` of [`Duration::ZERO`] allows
+    /// every call through, matching this crate's behavior before sampling existed.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_allowed: Mutex::new(None),
+        }
+    }
+
+    /// Whether a log line should be emitted right now.
+    pub fn allow(&self) -> bool {
+        if self.interval.is_zero() {
+            return true;
+        }
+        let now = Instant::now();
+        let mut last_allowed = self.last_allowed.lock().expect("lock poisoned");
+        if last_allowed.is_none_or(|last| now.duration_since(last) >= self.interval) {
+            *last_allowed = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn allows_one_line_per_interval_and_resumes_after_it_elapses() {
+        let sampler = LogSampler::new(Duration::from_secs(1));
+
+        assert!(sampler.allow(), "the first call always goes through");
+        for _ in 0..10 {
+            assert!(
+                !sampler.allow(),
+                "further calls within the interval are dropped"
+            );
+        }
+
+        tokio::time::advance(Duration::from_millis(999)).await;
+        assert!(!sampler.allow(), "not quite a full interval yet");
+
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert!(sampler.allow(), "a full interval has now elapsed");
+        assert!(
+            !sampler.allow(),
+            "the budget resets on each allowed call, not continuously"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_zero_interval_allows_every_call() {
+        let sampler = LogSampler::new(Duration::ZERO);
+        for _ in 0..100 {
+            assert!(sampler.allow());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Embeds a [`MeshProxy`] and [`MeshAgent`] in one process sharing a single iroh [`Endpoint`]
+//! and [`DiscoveryManager`], for callers that want to co-locate both roles instead of running
+//! them as this crate's separate `mesh-proxy` and `mesh-agent` binaries, each binding its own
+//! endpoint and starting from its own empty discovery state.
+
+use std::sync::Arc;
+
+use iroh::{Endpoint, EndpointId};
+use tokio::task::JoinHandle;
+
+use crate::{
+    agent::{AgentConfig, MeshAgent},
+    config::ProxyConfig,
+    discovery::DiscoveryManager,
+    error::Result,
+    proxy::{self, MeshProxy},
+};
+
+/// Binds one shared [`Endpoint`] and [`DiscoveryManager`] and builds a [`MeshProxy`] and/or
+/// [`MeshAgent`] on top of them, so co-located roles agree on one iroh identity and one view of
+/// cluster membership instead of each binding their own.
+///
+/// `proxy_config` supplies the shared endpoint's identity, endpoint-discovery, relay, and
+/// stream-concurrency settings (see [`ProxyConfig::secret_key`], [`ProxyConfig::mesh_name`],
+/// [`ProxyConfig::endpoint_discovery`], [`ProxyConfig::relay`],
+/// [`ProxyConfig::max_streams_per_connection`]) -- the same fields [`MeshProxy::bind`] would use
+/// to bind its own. An embedder that only ever runs one role with no co-located peer should use
+/// [`MeshProxy::bind`] or [`MeshAgent::new`] directly instead of this builder.
+#[derive(Debug)]
+pub struct MeshBuilder {
+    proxy_config: ProxyConfig,
+    endpoint: Endpoint,
+    discovery: Arc<DiscoveryManager>,
+}
+
+impl MeshBuilder {
+    /// Binds the shared endpoint from `proxy_config` (see [`Self`]'s docs) and starts with an
+    /// empty, shared [`DiscoveryManager`].
+    pub async fn bind(proxy_config: ProxyConfig) -> Result<Self> {
+        let endpoint = proxy::bind_endpoint(&proxy_config).await?;
+        Ok(Self {
+            proxy_config,
+            endpoint,
+            discovery: Arc::new(DiscoveryManager::new()),
+        })
+    }
+
+    /// The iroh identity every component built from this builder shares.
+    pub fn node_id(&self) -> EndpointId {
+        self.endpoint.id()
+    }
+
+    /// The [`DiscoveryManager`] every component built from this builder shares.
+    pub fn discovery(&self) -> Arc<DiscoveryManager> {
+        self.discovery.clone()
+    }
+
+    /// Builds a [`MeshProxy`] on the shared endpoint and discovery manager, using [`Self::bind`]'s
+    /// `proxy_config`.
+    pub fn build_proxy(&self) -> MeshProxy {
+        MeshProxy::from_endpoint(
+            self.proxy_config.clone(),
+            self.endpoint.clone(),
+            self.discovery.clone(),
+        )
+    }
+
+    /// Builds a [`MeshAgent`] on the shared endpoint and discovery manager.
+    ///
+    /// `agent_config` should name the same [`AgentConfig::mesh_name`] as [`Self::bind`]'s
+    /// `proxy_config` did, so the shared endpoint's ALPN matches what the agent's health checker
+    /// (see [`AgentConfig::discovery`]) expects to dial with.
+    pub fn build_agent(&self, agent_config: AgentConfig) -> MeshAgent {
+        MeshAgent::from_endpoint(agent_config, self.endpoint.clone(), self.discovery.clone())
+    }
+
+    /// Builds a [`MeshProxy`] and [`MeshAgent`] on the shared endpoint and discovery manager, and
+    /// spawns both running in the background until [`MeshHandle::shutdown`] is called.
+    pub fn spawn(self, agent_config: AgentConfig) -> MeshHandle {
+        let node_id = self.node_id();
+        let proxy = Arc::new(self.build_proxy());
+        let agent = Arc::new(self.build_agent(agent_config));
+        let proxy_task = tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run().await }
+        });
+        let agent_task = tokio::spawn({
+            let agent = agent.clone();
+            async move { agent.run().await }
+        });
+        MeshHandle {
+            node_id,
+            proxy,
+            agent,
+            proxy_task,
+            agent_task,
+        }
+    }
+}
+
+/// A co-located [`MeshProxy`] and [`MeshAgent`] spawned by [`MeshBuilder::spawn`], sharing one
+/// iroh identity.
+#[derive(Debug)]
+pub struct MeshHandle {
+    node_id: EndpointId,
+    proxy: Arc<MeshProxy>,
+    agent: Arc<MeshAgent>,
+    proxy_task: JoinHandle<Result<()>>,
+    agent_task: JoinHandle<Result<()>>,
+}
+
+impl MeshHandle {
+    /// The iroh identity the proxy and agent share.
+    pub fn node_id(&self) -> EndpointId {
+        self.node_id
+    }
+
+    /// The running proxy.
+    pub fn proxy(&self) -> &MeshProxy {
+        &self.proxy
+    }
+
+    /// The running agent.
+    pub fn agent(&self) -> &MeshAgent {
+        &self.agent
+    }
+
+    /// Stops both the proxy's and agent's accept loops.
+    ///
+    /// Aborts both tasks outright rather than waiting for existing connections to finish: it
+    /// does not go through [`MeshProxy::shutdown`], which lets [`MeshProxy::run`] return only
+    /// once in-flight connections have finished forwarding, nor [`MeshAgent::shutdown`], after
+    /// which [`MeshAgent::run`] keeps serving its HTTP API.
+    pub fn shutdown(self) {
+        self.proxy_task.abort();
+        self.agent_task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::discovery::DiscoveryConfig;
+
+    #[tokio::test]
+    async fn a_combined_mesh_node_shares_one_node_id_across_proxy_and_agent() -> Result<()> {
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let proxy_config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr());
+        let builder = MeshBuilder::bind(proxy_config).await?;
+        let node_id = builder.node_id();
+
+        let proxy = builder.build_proxy();
+        assert_eq!(
+            proxy
+                .endpoint()
+                .expect("MeshBuilder always binds a real endpoint")
+                .id(),
+            node_id
+        );
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let proxy_addr = proxy_listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(proxy_listener).await });
+
+        let agent_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let agent_addr = agent_listener.local_addr()?;
+        let agent = builder.build_agent(AgentConfig {
+            api_addr: agent_addr,
+            discovery: Some(DiscoveryConfig::default()),
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: None,
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload: None,
+            self_registration: None,
+            metrics_addr: None,
+        });
+        tokio::spawn(async move { agent.run_on(agent_listener).await });
+
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut client = tokio::net::TcpStream::connect(proxy_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+
+        let mut http_client = tokio::net::TcpStream::connect(agent_addr).await?;
+        http_client
+            .write_all(b"GET /version HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut raw = Vec::new();
+        http_client.read_to_end(&mut raw).await?;
+        let response = String::from_utf8_lossy(&raw);
+        let body = response.split("\r\n\r\n").nth(1).expect("missing body");
+        let info: crate::versioninfo::VersionInfo = serde_json::from_str(body).unwrap();
+        assert_eq!(info.node_id, Some(node_id));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Metrics for the mesh proxy.
+
+use std::time::Duration;
+
+use iroh_metrics::{Counter, Gauge, Histogram, MetricsGroup};
+
+use crate::routing::ConnectionMode;
+
+/// Bucket upper bounds (seconds) for [`Metrics::dial_duration_seconds`], sized for P2P/WAN dial
+/// latencies: sub-millisecond buckets would be noise for a QUIC handshake that has to at least
+/// race a direct path against a relay, while the tail covers a relay fallback over a slow link.
+fn dial_duration_buckets() -> Vec<f64> {
+    vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+}
+
+/// Bucket upper bounds (seconds) for [`Metrics::connection_duration_seconds`]. Mesh connections
+/// are expected to be held open for a health probe's lifetime or longer, so this covers a wider
+/// range than [`dial_duration_buckets`].
+fn connection_duration_buckets() -> Vec<f64> {
+    vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0]
+}
+
+/// A sampled dial's id, standing in for a real OpenMetrics exemplar on
+/// [`Metrics::dial_duration_seconds`].
+///
+/// A real exemplar would attach a trace/span id straight to the histogram observation it
+/// accompanied, retrievable from the same registry that serves the histogram itself. Neither
+/// half of that exists here: [`iroh_metrics::Histogram`] has no exemplar-attachment API, and this
+/// crate has no OTLP integration to source a real trace/span id from in the first place. This
+/// only remembers the sampled dial's id (see [`crate::proxy::MeshProxy::dial_trace_exemplars`])
+/// next to the duration it observed, for callers willing to correlate through logs instead of a
+/// real exemplar link -- reusing [`crate::tracesample::TraceSampler`], the one "sampled for
+/// tracing" decision this crate does make, to decide which dials qualify.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceExemplar {
+    /// The sampled dial's correlation id (see `generate_conn_id` in [`crate::proxy`]).
+    pub trace_id: String,
+    /// The duration observed into [`Metrics::dial_duration_seconds`] alongside `trace_id`.
+    pub duration: Duration,
+}
+
+/// Metrics for [`crate::proxy::MeshProxy`].
+#[derive(Debug, MetricsGroup)]
+#[metrics(name = "mesh", default)]
+pub struct Metrics {
+    /// Cross-cluster connections whose path is direct.
+    pub connection_path_direct: Counter,
+    /// Cross-cluster connections bouncing through a relay.
+    pub connection_path_relay: Counter,
+    /// Cross-cluster connections using a mix of direct and relayed paths.
+    pub connection_path_mixed: Counter,
+    /// Cross-cluster connections with no established path yet.
+    pub connection_path_none: Counter,
+    /// Dials whose first established path was not direct, i.e. iroh had to fall back off the
+    /// direct path (to a relay, or a mix of the two).
+    pub dial_relay_fallback: Counter,
+    /// Connections that transitioned onto a direct path after starting elsewhere, i.e. a
+    /// hole-punch succeeded mid-connection.
+    pub holepunch_success: Counter,
+    /// Connections that transitioned off a direct path onto a relay, i.e. a hole-punch that had
+    /// previously succeeded stopped working and the connection fell back.
+    pub holepunch_fallback: Counter,
+    /// Client connections currently being forwarded.
+    pub active_connections: Gauge,
+    /// Proxied exchanges torn down for running past [`crate::config::ProxyConfig::request_timeout`].
+    pub request_timeouts: Counter,
+    /// How long a cross-cluster dial (see [`crate::proxy::MeshProxy::dial_cluster`]) takes to
+    /// establish, from the call to [`iroh::Endpoint::connect`] to the connection being usable.
+    #[default(Histogram::new(dial_duration_buckets()))]
+    pub dial_duration_seconds: Histogram,
+    /// How long a dialed cross-cluster connection stays open, from establishment to close.
+    #[default(Histogram::new(connection_duration_buckets()))]
+    pub connection_duration_seconds: Histogram,
+    /// Dials refused by [`crate::config::ProxyConfig::authz`].
+    pub authz_denied_total: Counter,
+    /// Accepted connections forwarded as transparent TCP, without being parsed as any
+    /// application protocol.
+    pub connection_mode_transparent_tcp: Counter,
+    /// Accepted connections routed as HTTP, whether or not TLS was terminated first.
+    pub connection_mode_http: Counter,
+    /// Accepted connections routed by SNI without TLS being terminated.
+    pub connection_mode_tls_passthrough: Counter,
+    /// Accepted connections tagged as SOCKS5. Always zero today -- see
+    /// [`crate::routing::ConnectionMode::Socks5`].
+    pub connection_mode_socks5: Counter,
+    /// Accepted connections tagged as raw UDP. Always zero today -- see
+    /// [`crate::routing::ConnectionMode::Udp`].
+    pub connection_mode_udp: Counter,
+    /// Round-trip time last sampled off a dialed cross-cluster connection (see
+    /// [`crate::proxy::MeshProxy::dial_cluster`]'s periodic stats sampler), in microseconds.
+    ///
+    /// Named in microseconds rather than the usual `_seconds` convention because [`Gauge`] only
+    /// holds whole numbers -- a `_seconds` gauge would round every realistic RTT down to zero.
+    /// [`crate::status::ConnQuality::rtt_seconds`] carries the same sample as a float instead.
+    pub conn_rtt_micros: Gauge,
+    /// Cumulative lost packets last sampled off a dialed cross-cluster connection.
+    pub conn_lost_packets: Gauge,
+    /// [`crate::proxy::ConnectionSummary`]s dropped because
+    /// [`crate::proxy::MeshProxy::with_connection_summary_channel`]'s channel was full when a
+    /// connection closed. A nonzero rate means the consumer on the other end isn't keeping up.
+    pub connection_summaries_dropped: Counter,
+    /// Bytes forwarded from a client to its backend, across every spliced connection.
+    pub bytes_sent_total: Counter,
+    /// Bytes forwarded from a backend back to its client, across every spliced connection.
+    pub bytes_received_total: Counter,
+}
+
+/// Metrics for [`crate::discovery::DiscoveryManager`].
+#[derive(Debug, MetricsGroup)]
+#[metrics(name = "mesh_discovery", default)]
+pub struct DiscoveryMetrics {
+    /// Clusters currently known to discovery.
+    pub known_clusters: Gauge,
+    /// Cluster id collisions detected by [`crate::discovery::DiscoveryManager::register_cluster`].
+    pub cluster_id_collisions_total: Counter,
+}
+
+impl Metrics {
+    /// Records an observed connection path, keyed by `label` (`"direct"`, `"relay"`,
+    /// `"mixed"` or `"none"`).
+    ///
+    /// The underlying metrics crate does not yet support per-series labels (see
+    /// `mesh_connection_path{cluster, type}` in the tracking issue), so for now this
+    /// increments an aggregate counter per path type; `cluster_id` is only used for the
+    /// accompanying log line.
+    pub fn record_path(&self, cluster_id: &str, label: &str) {
+        let counter = match label {
+            "direct" => &self.connection_path_direct,
+            "relay" => &self.connection_path_relay,
+            "mixed" => &self.connection_path_mixed,
+            _ => &self.connection_path_none,
+        };
+        counter.inc();
+        tracing::debug!(cluster = cluster_id, path = label, "mesh connection path");
+    }
+
+    /// Records that a dial to `cluster_id` did not establish on the direct path.
+    pub fn record_dial_fallback(&self, cluster_id: &str) {
+        self.dial_relay_fallback.inc();
+        tracing::debug!(
+            cluster = cluster_id,
+            "mesh dial fell back off the direct path"
+        );
+    }
+
+    /// Records that a connection to `cluster_id` hole-punched onto a direct path after starting
+    /// on a relay or mixed path.
+    pub fn record_holepunch_success(&self, cluster_id: &str) {
+        self.holepunch_success.inc();
+        tracing::info!(cluster = cluster_id, "mesh connection hole-punch succeeded");
+    }
+
+    /// Records that a connection to `cluster_id` fell back off a direct path onto a relay.
+    pub fn record_holepunch_fallback(&self, cluster_id: &str) {
+        self.holepunch_fallback.inc();
+        tracing::info!(
+            cluster = cluster_id,
+            "mesh connection fell back off direct path"
+        );
+    }
+
+    /// Records that a client connection started being forwarded.
+    pub fn record_connection_opened(&self) {
+        self.active_connections.inc();
+    }
+
+    /// Records that a forwarded client connection finished.
+    pub fn record_connection_closed(&self) {
+        self.active_connections.dec();
+    }
+
+    /// Records that a proxied exchange was torn down for running past its request timeout.
+    pub fn record_request_timeout(&self) {
+        self.request_timeouts.inc();
+    }
+
+    /// Records how long a dial to `cluster_id` took to establish.
+    ///
+    /// Per-series labels aren't supported yet (see [`Self::record_path`]), so `cluster_id` is
+    /// only used for the accompanying log line; all dials land in the same histogram. Sampled
+    /// dials additionally get a [`TraceExemplar`] recorded by
+    /// [`crate::proxy::MeshProxy::dial_cluster`] -- see [`TraceExemplar`] for why that lives
+    /// alongside this histogram rather than attached to it.
+    pub fn record_dial_duration(&self, cluster_id: &str, duration: Duration) {
+        self.dial_duration_seconds.observe(duration.as_secs_f64());
+        tracing::debug!(cluster = cluster_id, ?duration, "mesh dial duration");
+    }
+
+    /// Records how long a dialed connection to `cluster_id` stayed open before closing.
+    ///
+    /// Per-series labels aren't supported yet (see [`Self::record_path`]), so `cluster_id` is
+    /// only used for the accompanying log line; all connections land in the same histogram.
+    pub fn record_connection_duration(&self, cluster_id: &str, duration: Duration) {
+        self.connection_duration_seconds
+            .observe(duration.as_secs_f64());
+        tracing::debug!(cluster = cluster_id, ?duration, "mesh connection duration");
+    }
+
+    /// Records an accepted connection's detected [`ConnectionMode`] (see
+    /// [`crate::routing::RoutingStrategy::connection_mode`]).
+    ///
+    /// Per-series labels aren't supported yet (see [`Self::record_path`]), so this increments an
+    /// aggregate counter per mode; the real value is carried on this call's log line as well as
+    /// the connection's own access-log line in `crate::proxy`, so it isn't lost.
+    pub fn record_connection_mode(&self, mode: ConnectionMode) {
+        let counter = match mode {
+            ConnectionMode::TransparentTcp => &self.connection_mode_transparent_tcp,
+            ConnectionMode::Http => &self.connection_mode_http,
+            ConnectionMode::TlsPassthrough => &self.connection_mode_tls_passthrough,
+            ConnectionMode::Socks5 => &self.connection_mode_socks5,
+            ConnectionMode::Udp => &self.connection_mode_udp,
+        };
+        counter.inc();
+        tracing::debug!(%mode, "mesh connection mode");
+    }
+
+    /// Records a QUIC stats sample taken off a dialed connection to `cluster_id`.
+    ///
+    /// Per-series labels aren't supported yet (see [`Self::record_path`]), so this sets an
+    /// aggregate gauge reflecting whichever sampled connection reported last; per-cluster values
+    /// are instead kept by [`crate::proxy::MeshProxy`] itself for
+    /// [`crate::status::ClusterStatusResponse::conn_stats`]. `cluster_id` is only used for the
+    /// accompanying log line here.
+    pub fn record_conn_stats(&self, cluster_id: &str, rtt: Duration, lost_packets: u64) {
+        self.conn_rtt_micros
+            .set(i64::try_from(rtt.as_micros()).unwrap_or(i64::MAX));
+        self.conn_lost_packets
+            .set(i64::try_from(lost_packets).unwrap_or(i64::MAX));
+        tracing::debug!(
+            cluster = cluster_id,
+            ?rtt,
+            lost_packets,
+            "mesh connection stats sample"
+        );
+    }
+
+    /// Records that a dial to `cluster_id` for `service` was refused by authz policy.
+    pub fn record_authz_denied(&self, cluster_id: &str, service: &str) {
+        self.authz_denied_total.inc();
+        tracing::warn!(
+            cluster = cluster_id,
+            service,
+            "mesh dial denied by authz policy"
+        );
+    }
+
+    /// Records a spliced connection's byte counts, once forwarding to `route_key`'s target
+    /// finishes.
+    ///
+    /// Per-series labels aren't supported yet (see [`Self::record_path`]), so `route_key` is only
+    /// used for the accompanying log line; every connection's bytes land in the same two
+    /// aggregate counters.
+    pub fn record_bytes(&self, route_key: Option<&str>, sent: u64, received: u64) {
+        self.bytes_sent_total.inc_by(sent);
+        self.bytes_received_total.inc_by(received);
+        tracing::trace!(?route_key, sent, received, "mesh connection bytes");
+    }
+}
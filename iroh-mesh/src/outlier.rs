@@ -0,0 +1,231 @@
+//! Outlier detection: temporarily ejects destination clusters that are erroring repeatedly,
+//! mirroring Envoy's consecutive-error outlier detector.
+
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::ClusterInfo;
+
+/// Configuration for [`OutlierDetector`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct OutlierDetectionConfig {
+    /// Consecutive proxied-connection failures to a destination before it's ejected.
+    #[serde(default = "default_consecutive_errors")]
+    pub consecutive_errors: u32,
+    /// How long a destination is ejected for the first time it's ejected. Each ejection that
+    /// follows while the destination keeps failing lasts one more multiple of this: the n-th
+    /// ejection lasts n times this long.
+    #[serde(with = "humantime_serde", default = "default_base_ejection_time")]
+    #[schemars(with = "String")]
+    pub base_ejection_time: Duration,
+    /// Maximum percentage of a candidate pool that may be ejected at once. Once reached, a
+    /// destination that would otherwise be ejected is left in the pool instead, so a correlated
+    /// failure across a service's backends can't eject all of them.
+    #[serde(default = "default_max_ejection_percent")]
+    pub max_ejection_percent: u8,
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_errors: default_consecutive_errors(),
+            base_ejection_time: default_base_ejection_time(),
+            max_ejection_percent: default_max_ejection_percent(),
+        }
+    }
+}
+
+fn default_consecutive_errors() -> u32 {
+    5
+}
+
+fn default_base_ejection_time() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_ejection_percent() -> u8 {
+    50
+}
+
+/// Per-destination error streak and ejection state.
+#[derive(Debug, Default)]
+struct DestinationState {
+    consecutive_errors: u32,
+    ejected_until: Option<Instant>,
+    ejection_count: u32,
+}
+
+impl DestinationState {
+    fn is_ejected(&self) -> bool {
+        self.ejected_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+/// Tracks consecutive proxied-connection errors per destination cluster and temporarily ejects
+/// destinations that cross [`OutlierDetectionConfig::consecutive_errors`], mirroring Envoy's
+/// consecutive-error outlier detector.
+#[derive(Debug)]
+pub struct OutlierDetector {
+    config: OutlierDetectionConfig,
+    destinations: RwLock<HashMap<String, DestinationState>>,
+}
+
+impl OutlierDetector {
+    /// Creates a detector with the given configuration and no destinations yet observed.
+    pub fn new(config: OutlierDetectionConfig) -> Self {
+        Self {
+            config,
+            destinations: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Records a successful proxied connection to `cluster_id`, clearing its error streak and
+    /// lifting any ejection immediately.
+    pub fn record_success(&self, cluster_id: &str) {
+        let mut destinations = self.destinations.write().expect("lock poisoned");
+        if let Some(state) = destinations.get_mut(cluster_id) {
+            state.consecutive_errors = 0;
+            state.ejected_until = None;
+        }
+    }
+
+    /// Records a failed proxied connection to `cluster_id`, ejecting it once its consecutive
+    /// error count reaches [`OutlierDetectionConfig::consecutive_errors`] -- unless that would
+    /// eject more than [`OutlierDetectionConfig::max_ejection_percent`] of `pool_size`, the
+    /// candidate pool `cluster_id` was picked from.
+    ///
+    /// The n-th ejection of a destination lasts n times
+    /// [`OutlierDetectionConfig::base_ejection_time`], so a destination that keeps failing gets
+    /// pushed out of rotation for progressively longer.
+    pub fn record_failure(&self, cluster_id: &str, pool_size: usize) {
+        let mut destinations = self.destinations.write().expect("lock poisoned");
+        let already_ejected = destinations.values().filter(|s| s.is_ejected()).count();
+        let state = destinations.entry(cluster_id.to_string()).or_default();
+        state.consecutive_errors += 1;
+        if state.is_ejected() || state.consecutive_errors < self.config.consecutive_errors {
+            return;
+        }
+        if already_ejected >= max_ejected_for_pool(pool_size, self.config.max_ejection_percent) {
+            return;
+        }
+        state.ejection_count += 1;
+        state.ejected_until =
+            Some(Instant::now() + self.config.base_ejection_time * state.ejection_count);
+    }
+
+    /// Whether `cluster_id` is currently ejected.
+    pub fn is_ejected(&self, cluster_id: &str) -> bool {
+        self.destinations
+            .read()
+            .expect("lock poisoned")
+            .get(cluster_id)
+            .is_some_and(DestinationState::is_ejected)
+    }
+
+    /// Filters `candidates` down to those not currently ejected.
+    pub fn filter_candidates(&self, candidates: Vec<ClusterInfo>) -> Vec<ClusterInfo> {
+        candidates
+            .into_iter()
+            .filter(|info| !self.is_ejected(&info.cluster_id))
+            .collect()
+    }
+}
+
+/// The number of destinations in a pool of `pool_size` that may be ejected at once, always at
+/// least one so a single misbehaving destination in a small pool can still be ejected.
+fn max_ejected_for_pool(pool_size: usize, max_ejection_percent: u8) -> usize {
+    (pool_size * max_ejection_percent as usize / 100).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config(consecutive_errors: u32, base_ejection_time: Duration) -> OutlierDetectionConfig {
+        OutlierDetectionConfig {
+            consecutive_errors,
+            base_ejection_time,
+            max_ejection_percent: 100,
+        }
+    }
+
+    #[test]
+    fn ejects_after_reaching_the_consecutive_error_threshold() {
+        let detector = OutlierDetector::new(config(3, Duration::from_secs(60)));
+
+        detector.record_failure("cluster-a", 2);
+        detector.record_failure("cluster-a", 2);
+        assert!(!detector.is_ejected("cluster-a"), "below threshold yet");
+
+        detector.record_failure("cluster-a", 2);
+        assert!(detector.is_ejected("cluster-a"), "threshold reached");
+    }
+
+    #[test]
+    fn max_ejection_percent_caps_how_many_destinations_can_be_ejected_at_once() {
+        let detector = OutlierDetector::new(OutlierDetectionConfig {
+            consecutive_errors: 1,
+            base_ejection_time: Duration::from_secs(60),
+            max_ejection_percent: 50,
+        });
+
+        // A pool of 2: only one (50%) may be ejected at a time.
+        detector.record_failure("cluster-a", 2);
+        assert!(detector.is_ejected("cluster-a"));
+
+        detector.record_failure("cluster-b", 2);
+        assert!(
+            !detector.is_ejected("cluster-b"),
+            "ejecting a second destination would exceed max_ejection_percent of the pool"
+        );
+    }
+
+    #[test]
+    fn a_success_clears_the_error_streak_and_lifts_an_ejection() {
+        let detector = OutlierDetector::new(config(1, Duration::from_secs(60)));
+
+        detector.record_failure("cluster-a", 1);
+        assert!(detector.is_ejected("cluster-a"));
+
+        detector.record_success("cluster-a");
+        assert!(
+            !detector.is_ejected("cluster-a"),
+            "a success should reinstate the destination immediately"
+        );
+    }
+
+    #[tokio::test]
+    async fn an_ejection_expires_and_a_repeated_ejection_lasts_longer() {
+        let detector = OutlierDetector::new(config(1, Duration::from_millis(20)));
+
+        // Poll for expiry instead of asserting on fixed sleeps, so this isn't flaky under
+        // scheduler jitter; what matters is that the second ejection outlasts the first.
+        let first = time_until_not_ejected(&detector, "cluster-a").await;
+        let second = time_until_not_ejected(&detector, "cluster-a").await;
+
+        assert!(
+            second > first,
+            "a repeated ejection of the same destination should last longer than the last \
+             (first: {first:?}, second: {second:?})"
+        );
+    }
+
+    /// Records a failure against `cluster_id` and returns how long it stayed ejected for.
+    async fn time_until_not_ejected(detector: &OutlierDetector, cluster_id: &str) -> Duration {
+        let start = tokio::time::Instant::now();
+        detector.record_failure(cluster_id, 1);
+        assert!(detector.is_ejected(cluster_id));
+        while detector.is_ejected(cluster_id) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        start.elapsed()
+    }
+}
@@ -0,0 +1,120 @@
+//! A stream wrapper that replays an already-consumed prefix of bytes before falling through to
+//! the stream itself.
+//!
+//! Sniffing a connection's protocol -- reading its [`crate::httpsniff`] header block to pick a
+//! route, or (in the future) reading a TLS `ClientHello` for its SNI -- consumes bytes that still
+//! need to reach the backend once routing is done. Rather than every sniff having to manually
+//! re-forward what it read before handing the connection off to [`crate::proxy::splice`],
+//! [`PeekStream`] wraps the original stream with the consumed bytes so splicing it is
+//! indistinguishable from splicing the untouched connection. This is also what lets independent
+//! sniffing and forwarding features (Host-header routing, PROXY protocol, a future SNI sniff)
+//! compose without each one needing to know what bytes an earlier one already consumed.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Wraps `S`, replaying a buffered `prefix` to the first read(s) before falling through to `S`.
+///
+/// Writes and shutdown pass straight through to `S` -- only reads are affected.
+pub struct PeekStream<S> {
+    inner: S,
+    prefix: Vec<u8>,
+    prefix_pos: usize,
+}
+
+impl<S> PeekStream<S> {
+    /// Wraps `inner`, replaying `prefix` before reads fall through to `inner`. An empty `prefix`
+    /// makes this a zero-cost passthrough.
+    pub fn new(inner: S, prefix: Vec<u8>) -> Self {
+        Self {
+            inner,
+            prefix,
+            prefix_pos: 0,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PeekStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.prefix_pos < self.prefix.len() {
+            let remaining = &self.prefix[self.prefix_pos..];
+            let n = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            self.prefix_pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PeekStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_the_prefix_before_reading_from_the_inner_stream() {
+        let (mut writer, reader) = duplex(64);
+        writer.write_all(b"-wire-bytes").await.unwrap();
+
+        let mut peek = PeekStream::new(reader, b"prefix".to_vec());
+        let mut out = [0u8; 17];
+        peek.read_exact(&mut out).await.unwrap();
+        assert_eq!(&out, b"prefix-wire-bytes");
+    }
+
+    #[tokio::test]
+    async fn a_read_landing_exactly_on_the_prefix_boundary_forwards_both_sides_intact() {
+        let (mut writer, reader) = duplex(64);
+        writer.write_all(b"tail").await.unwrap();
+
+        let mut peek = PeekStream::new(reader, b"head".to_vec());
+        // A 4-byte read buffer lines up exactly with the end of the 4-byte prefix, so this
+        // exercises the prefix being exhausted with nothing left over in the same read call.
+        let mut first = [0u8; 4];
+        peek.read_exact(&mut first).await.unwrap();
+        assert_eq!(&first, b"head");
+
+        let mut second = [0u8; 4];
+        peek.read_exact(&mut second).await.unwrap();
+        assert_eq!(&second, b"tail");
+    }
+
+    #[tokio::test]
+    async fn an_empty_prefix_is_a_transparent_passthrough() {
+        let (mut writer, reader) = duplex(64);
+        writer.write_all(b"hello").await.unwrap();
+
+        let mut peek = PeekStream::new(reader, Vec::new());
+        let mut out = [0u8; 5];
+        peek.read_exact(&mut out).await.unwrap();
+        assert_eq!(&out, b"hello");
+    }
+}
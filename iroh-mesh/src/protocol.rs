@@ -0,0 +1,375 @@
+//! The routing preamble sent at the start of a `MESH_ALPN` stream, ahead of whatever payload the
+//! stream carries afterward.
+//!
+//! [`RouteRequest`](crate::route_request::RouteRequest) already covers "which cluster and client
+//! dialed in", sent on its own uni stream. [`MeshRequest`] is a different, more descriptive frame
+//! that also names the service, namespace, port and transport a bi stream is being opened for,
+//! plus a trace id for correlating the dial across both sides' logs -- and it shares the stream it
+//! precedes rather than getting one of its own, so its encoding is length-prefixed rather than
+//! read-to-end: a reader must be able to stop exactly at the frame's end and leave whatever bytes
+//! follow (the tunneled payload) untouched on the stream.
+//!
+//! Each frame starts with a one-byte version, then a 4-byte big-endian length, then that many
+//! bytes of JSON body -- see [`MeshRequest::write_to`]. The version byte works the same way
+//! [`RouteRequest`](crate::route_request::RouteRequest)'s 4-byte version does: a reader that sees a version
+//! higher than [`PROTOCOL_VERSION`] rejects the frame with
+//! [`MeshError::ProtocolMismatch`](crate::error::MeshError::ProtocolMismatch) instead of guessing
+//! at a layout it doesn't understand, so a newer proxy can grow the body without breaking an older
+//! one mid-upgrade.
+//!
+//! [`MeshResponse`] is the frame the accepting side would send back -- an ack once it starts
+//! relaying, or an error naming why it won't. Nothing in this crate reads or writes either frame
+//! on a real call path yet: [`MeshProxy`](crate::proxy::MeshProxy)'s accept loop
+//! ([`MeshProxy::accept_mesh_connections`](crate::proxy::MeshProxy::accept_mesh_connections)) and
+//! its dial-side counterpart
+//! ([`MeshProxy::forward_tcp_to_service`](crate::proxy::MeshProxy::forward_tcp_to_service)) send
+//! and read a bare [`RouteRequest`](crate::route_request::RouteRequest) instead, which names the
+//! service but not the namespace, port, transport or trace id `MeshRequest` would. This module
+//! only defines the frames and their wire format, ready for either side to adopt in place of
+//! `RouteRequest` once that richer preamble is needed.
+
+use iroh::endpoint::{RecvStream, SendStream};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, ensure};
+
+use crate::error::{
+    FrameTooLargeSnafu, MeshRequestDecodeSnafu, MeshRequestReadSnafu, MeshRequestSendSnafu,
+    MeshResponseDecodeSnafu, MeshResponseReadSnafu, MeshResponseSendSnafu, ProtocolMismatchSnafu,
+    Result,
+};
+
+/// The only frame version this build knows how to encode and decode. See [`MeshRequest::read_from`].
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Frames larger than this are rejected before their body is read: a [`MeshRequest`] or
+/// [`MeshResponse`] only ever holds a handful of short strings, so anything near this size is
+/// either malformed or not one of these frames at all.
+pub const MAX_FRAME_BYTES: usize = 16 * 1024;
+
+/// The transport a [`MeshRequest`] is opening a stream for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportProtocol {
+    /// The tunneled payload is a TCP byte stream, as
+    /// [`MeshProxy::forward_tcp_to_service`](crate::proxy::MeshProxy::forward_tcp_to_service)
+    /// splices today.
+    Tcp,
+    /// The tunneled payload is UDP datagrams. Nothing in this crate tunnels UDP yet.
+    Udp,
+}
+
+/// The routing preamble sent at the start of a `MESH_ALPN` stream, naming the service the rest of
+/// the stream is for. See the [module docs](self) for its wire format and how it relates to
+/// [`RouteRequest`](crate::route_request::RouteRequest).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeshRequest {
+    /// The service being dialed, e.g. `"checkout"`.
+    pub service: String,
+    /// The namespace `service` lives in, if the dialing side's discovery source tracks one.
+    pub namespace: Option<String>,
+    /// The port the tunneled payload should be forwarded to on the accepting side's backend.
+    /// `None` defers to whatever port the accepting side already associates with `service`.
+    pub port: Option<u16>,
+    /// The transport the tunneled payload after this frame speaks.
+    pub protocol: TransportProtocol,
+    /// The cluster id the dialing proxy claims to be, or `None` if it names none.
+    /// [`crate::config::ProxyConfig`] has no field for a proxy's own cluster id.
+    pub source_cluster_id: Option<String>,
+    /// An opaque id for correlating this dial across both sides' logs. `None` if the dialing side
+    /// isn't tracing this request.
+    pub trace_id: Option<String>,
+}
+
+impl MeshRequest {
+    /// Encodes this frame as `[version: u8][len: u32 BE][body: len bytes of JSON]` and writes it
+    /// to `stream`, ahead of whatever payload bytes follow on it.
+    pub async fn write_to(&self, stream: &mut SendStream) -> Result<()> {
+        write_frame(stream, self)
+            .await
+            .map_err(Box::new)
+            .context(MeshRequestSendSnafu)
+    }
+
+    /// Reads and decodes a [`MeshRequest`] previously written by [`Self::write_to`] from `stream`,
+    /// rejecting a frame over [`MAX_FRAME_BYTES`] or carrying a version newer than
+    /// [`PROTOCOL_VERSION`], and leaving any bytes written after it untouched for a subsequent
+    /// read.
+    pub async fn read_from(stream: &mut RecvStream) -> Result<Self> {
+        let mut version = [0u8; 1];
+        stream
+            .read_exact(&mut version)
+            .await
+            .map_err(Box::new)
+            .context(MeshRequestReadSnafu)?;
+        ensure!(
+            version[0] as u32 <= PROTOCOL_VERSION as u32,
+            ProtocolMismatchSnafu {
+                peer_max: version[0] as u32,
+                ours: PROTOCOL_VERSION as u32,
+            }
+        );
+
+        let mut len_buf = [0u8; 4];
+        stream
+            .read_exact(&mut len_buf)
+            .await
+            .map_err(Box::new)
+            .context(MeshRequestReadSnafu)?;
+        let len = u32::from_be_bytes(len_buf);
+        ensure!(
+            (len as usize) <= MAX_FRAME_BYTES,
+            FrameTooLargeSnafu {
+                len,
+                limit: MAX_FRAME_BYTES,
+            }
+        );
+
+        let mut body = vec![0u8; len as usize];
+        stream
+            .read_exact(&mut body)
+            .await
+            .map_err(Box::new)
+            .context(MeshRequestReadSnafu)?;
+        serde_json::from_slice(&body).context(MeshRequestDecodeSnafu)
+    }
+}
+
+/// The accepting side's reply to a [`MeshRequest`]: either it started relaying, or it won't and
+/// says why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshResponse {
+    /// The accepting side is relaying the stream to the requested service.
+    Ack,
+    /// The accepting side is not relaying the stream.
+    Error {
+        /// Human readable reason the request was refused.
+        message: String,
+    },
+}
+
+impl MeshResponse {
+    /// Encodes this frame the same way [`MeshRequest::write_to`] does and writes it to `stream`.
+    pub async fn write_to(&self, stream: &mut SendStream) -> Result<()> {
+        write_frame(stream, self)
+            .await
+            .map_err(Box::new)
+            .context(MeshResponseSendSnafu)
+    }
+
+    /// Reads and decodes a [`MeshResponse`] previously written by [`Self::write_to`] from
+    /// `stream`, the same way [`MeshRequest::read_from`] reads a [`MeshRequest`].
+    pub async fn read_from(stream: &mut RecvStream) -> Result<Self> {
+        let mut version = [0u8; 1];
+        stream
+            .read_exact(&mut version)
+            .await
+            .map_err(Box::new)
+            .context(MeshResponseReadSnafu)?;
+        ensure!(
+            version[0] as u32 <= PROTOCOL_VERSION as u32,
+            ProtocolMismatchSnafu {
+                peer_max: version[0] as u32,
+                ours: PROTOCOL_VERSION as u32,
+            }
+        );
+
+        let mut len_buf = [0u8; 4];
+        stream
+            .read_exact(&mut len_buf)
+            .await
+            .map_err(Box::new)
+            .context(MeshResponseReadSnafu)?;
+        let len = u32::from_be_bytes(len_buf);
+        ensure!(
+            (len as usize) <= MAX_FRAME_BYTES,
+            FrameTooLargeSnafu {
+                len,
+                limit: MAX_FRAME_BYTES,
+            }
+        );
+
+        let mut body = vec![0u8; len as usize];
+        stream
+            .read_exact(&mut body)
+            .await
+            .map_err(Box::new)
+            .context(MeshResponseReadSnafu)?;
+        serde_json::from_slice(&body).context(MeshResponseDecodeSnafu)
+    }
+}
+
+/// Encodes `value` as `[version: u8][len: u32 BE][body: len bytes of JSON]` and writes it to
+/// `stream`. Shared by [`MeshRequest::write_to`] and [`MeshResponse::write_to`], which each wrap
+/// the plain [`iroh::endpoint::WriteError`] this returns in their own error variant.
+async fn write_frame<T: Serialize>(
+    stream: &mut SendStream,
+    value: &T,
+) -> std::result::Result<(), iroh::endpoint::WriteError> {
+    let body = serde_json::to_vec(value).expect("frame always serializes");
+    let len = u32::try_from(body.len()).expect("frame body fits in a u32 length prefix");
+
+    stream.write_all(&[PROTOCOL_VERSION]).await?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(&body).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use iroh::{Endpoint, RelayMode};
+
+    use super::*;
+    use crate::error::BindEndpointSnafu;
+
+    const TEST_ALPN: &[u8] = b"protocol-test";
+
+    async fn bound_endpoint() -> Result<Endpoint> {
+        Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)
+    }
+
+    #[tokio::test]
+    async fn a_mesh_request_survives_a_real_iroh_stream_round_trip_ahead_of_a_payload() -> Result<()>
+    {
+        let server = bound_endpoint().await?;
+        let server_addr = server.addr();
+
+        let accept_task = tokio::spawn(async move {
+            let incoming = server.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let (_send, mut recv) = conn.accept_bi().await.expect("no bi stream arrived");
+            let request = MeshRequest::read_from(&mut recv).await?;
+            let rest = recv.read_to_end(64).await.expect("read failed");
+            Ok::<_, crate::error::MeshError>((request, rest))
+        });
+
+        let client = bound_endpoint().await?;
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+
+        let sent = MeshRequest {
+            service: "checkout".to_string(),
+            namespace: Some("prod".to_string()),
+            port: Some(8080),
+            protocol: TransportProtocol::Tcp,
+            source_cluster_id: Some("cluster-a".to_string()),
+            trace_id: Some("trace-1".to_string()),
+        };
+        sent.write_to(&mut send).await?;
+        send.write_all(b"payload").await.expect("write failed");
+        send.finish().unwrap();
+
+        let (received, rest) = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked")?;
+        assert_eq!(received, sent);
+        assert_eq!(rest, b"payload");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_mesh_response_survives_a_real_iroh_stream_round_trip() -> Result<()> {
+        let server = bound_endpoint().await?;
+        let server_addr = server.addr();
+
+        let accept_task = tokio::spawn(async move {
+            let incoming = server.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let (_send, mut recv) = conn.accept_bi().await.expect("no bi stream arrived");
+            MeshResponse::read_from(&mut recv).await
+        });
+
+        let client = bound_endpoint().await?;
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+
+        let sent = MeshResponse::Error {
+            message: "no such service".to_string(),
+        };
+        sent.write_to(&mut send).await?;
+        send.finish().unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked")?;
+        assert_eq!(received, sent);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_frame_with_a_newer_version_than_ours_is_rejected_as_a_protocol_mismatch()
+    -> Result<()> {
+        let server = bound_endpoint().await?;
+        let server_addr = server.addr();
+
+        let accept_task = tokio::spawn(async move {
+            let incoming = server.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let (_send, mut recv) = conn.accept_bi().await.expect("no bi stream arrived");
+            MeshRequest::read_from(&mut recv).await
+        });
+
+        let client = bound_endpoint().await?;
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        send.write_all(&[PROTOCOL_VERSION + 1])
+            .await
+            .expect("write failed");
+        send.write_all(&0u32.to_be_bytes())
+            .await
+            .expect("write failed");
+        send.finish().unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked");
+        assert!(matches!(
+            result,
+            Err(crate::error::MeshError::ProtocolMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_frame_over_the_size_cap_is_rejected_before_its_body_is_read() -> Result<()> {
+        let server = bound_endpoint().await?;
+        let server_addr = server.addr();
+
+        let accept_task = tokio::spawn(async move {
+            let incoming = server.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let (_send, mut recv) = conn.accept_bi().await.expect("no bi stream arrived");
+            MeshRequest::read_from(&mut recv).await
+        });
+
+        let client = bound_endpoint().await?;
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        send.write_all(&[PROTOCOL_VERSION])
+            .await
+            .expect("write failed");
+        send.write_all(&(MAX_FRAME_BYTES as u32 + 1).to_be_bytes())
+            .await
+            .expect("write failed");
+        send.finish().unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked");
+        assert!(matches!(
+            result,
+            Err(crate::error::MeshError::FrameTooLarge { .. })
+        ));
+        Ok(())
+    }
+}
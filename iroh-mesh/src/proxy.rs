@@ -0,0 +1,6703 @@
+//! The mesh proxy: accepts client connections and forwards them to the current backend for
+//! their route, dialing other clusters over iroh when the route points at one.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use iroh::{
+    Endpoint, EndpointAddr, Watcher,
+    endpoint::{Connection, ConnectionType},
+};
+use iroh_base::EndpointId;
+use n0_future::{FuturesUnordered, StreamExt};
+use n0_watcher::Watchable;
+use snafu::{OptionExt, ResultExt, ensure};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{Semaphore, mpsc},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{Instrument, debug, instrument, warn};
+
+use crate::{
+    affinity::{self, SessionAffinity},
+    authz::AuthzPolicy,
+    config::{
+        BackendTlsConfig, MirrorConfig, PathRoute, ProtocolLimits, ProxyConfig, ProxyMode,
+        TlsConfig, UdpListenerConfig,
+    },
+    connpool::{ConnectionPool, ConnectionPoolConfig},
+    discovery::{ClusterInfo, DiscoveryManager},
+    endpoint_discovery,
+    error::{
+        AuthzDeniedSnafu, BindEndpointSnafu, CircuitOpenSnafu, ConnectSnafu, DialTimeoutSnafu,
+        HostChangedSnafu, InvalidConfigSnafu, MeshError, MeshUnavailableSnafu, NoRouteSnafu,
+        OpenStreamSnafu, Result, RouteChangedSnafu, RoutingKeyNotFoundSnafu, UdpDatagramReadSnafu,
+        UdpDatagramSendSnafu,
+    },
+    health::probe_cluster_reachable,
+    httpsniff, httputil,
+    logsample::LogSampler,
+    metrics::{Metrics, TraceExemplar},
+    outlier::OutlierDetector,
+    peek::PeekStream,
+    proxy_protocol,
+    ratelimit::ConnectionRateLimiter,
+    relay,
+    route_request::RouteRequest,
+    routing::{ConnectionMode, RoutingKey, RoutingStrategy, RoutingStrategyConfig},
+    secret_key,
+    socket::{self, SocketOptions},
+    status::{ClusterStatusResponse, ConnQuality},
+    tracesample::TraceSampler,
+    udp_datagram,
+    versioninfo::VersionInfo,
+};
+
+/// Default ALPN used for mesh hops when no [`ProxyConfig::mesh_name`] is set.
+pub const MESH_ALPN: &[u8] = b"iroh-mesh/0";
+
+/// Largest UDP payload [`MeshProxy::forward_udp_to_service`] reads off its listening socket in
+/// one call, matching the largest possible UDP datagram (an IPv4/IPv6 payload can't exceed
+/// 65,507/65,527 bytes respectively). A datagram this size will still be rejected by
+/// [`iroh::endpoint::Connection::send_datagram`] as [`iroh::endpoint::SendDatagramError::TooLarge`]
+/// long before it gets anywhere near a QUIC packet's own, much smaller, budget.
+const MAX_UDP_PAYLOAD_BYTES: usize = 65_527;
+
+/// Computes the ALPN mesh hops should use, incorporating `mesh_name` so that independent meshes
+/// sharing infrastructure don't accept each other's connections.
+///
+/// Falls back to [`MESH_ALPN`] when `mesh_name` is absent, preserving the ALPN of deployments
+/// that predate this setting.
+pub fn mesh_alpn(mesh_name: Option<&str>) -> Vec<u8> {
+    match mesh_name {
+        Some(name) => format!("iroh-mesh/v1/{name}").into_bytes(),
+        None => MESH_ALPN.to_vec(),
+    }
+}
+
+/// Binds the iroh endpoint [`MeshProxy::bind`] accepts client connections on, using `config` for
+/// its identity, endpoint-discovery, relay, and stream-concurrency settings.
+///
+/// Also used by [`crate::mesh::MeshBuilder`], which binds one such endpoint up front and shares
+/// it with both a [`MeshProxy`] and a [`crate::agent::MeshAgent`] instead of each binding their
+/// own, so the two constructors build an identically-configured endpoint from the same fields.
+pub(crate) async fn bind_endpoint(config: &ProxyConfig) -> Result<Endpoint> {
+    let secret_key = match &config.secret_key {
+        Some(source) => Some(secret_key::load_or_create_secret_key(source).await?),
+        None => None,
+    };
+    bind_endpoint_with_key(config, secret_key).await
+}
+
+/// Like [`bind_endpoint`], but takes an already-loaded `secret_key` instead of resolving
+/// [`ProxyConfig::secret_key`] itself -- everything else about the bound endpoint (ALPN, relay
+/// mode, endpoint discovery, max streams per connection) still comes from `config`. Used by
+/// [`bind_endpoint`] itself, and by [`MeshProxy::rotate_key`] to stand up a second endpoint that
+/// shares `config`'s transport settings but not its identity.
+async fn bind_endpoint_with_key(
+    config: &ProxyConfig,
+    secret_key: Option<iroh_base::SecretKey>,
+) -> Result<Endpoint> {
+    let alpn = mesh_alpn(config.mesh_name.as_deref());
+    let relay_mode = relay::resolve(&config.relay)?;
+    let mut builder =
+        endpoint_discovery::builder(&config.endpoint_discovery, relay_mode).alpns(vec![alpn]);
+    if let Some(secret_key) = secret_key {
+        builder = builder.secret_key(secret_key);
+    }
+    if let Some(max_streams) = config.max_streams_per_connection {
+        let mut transport = quinn::TransportConfig::default();
+        transport.max_concurrent_bidi_streams(max_streams.into());
+        builder = builder.transport_config(transport);
+    }
+    builder
+        .bind()
+        .await
+        .map_err(Box::new)
+        .context(BindEndpointSnafu)
+}
+
+/// How long a dial to another cluster's agent is given to succeed, including iroh's own
+/// direct/relay path racing, before giving up.
+const DIAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often [`MeshProxy::track_stats`] samples a dialed connection's QUIC stats.
+const STATS_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often [`MeshProxy`] re-probes for a reachable peer when
+/// [`ProxyConfig::require_peer_for_ready`] is set.
+const READINESS_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a single readiness probe dial is given before being treated as unreachable. Shorter
+/// than [`DIAL_TIMEOUT`] so one unresponsive peer doesn't stall the next readiness re-evaluation.
+const READINESS_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Generates a short random id correlating one client connection's log lines across the
+/// client-facing accept, the backend dial, and anything a backend itself logs from a forwarded
+/// request, so debugging one connection doesn't mean grepping multiple logs with no shared key.
+fn generate_conn_id() -> String {
+    let mut bytes = [0u8; 4];
+    rand::Rng::fill(&mut rand::rng(), &mut bytes);
+    data_encoding::HEXLOWER.encode(&bytes)
+}
+
+/// A snapshot of which cluster currently serves each service known to discovery, as returned by
+/// [`MeshProxy::routing_table_watcher`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingTable {
+    /// Maps a service name to the id of the cluster currently resolved for it.
+    pub routes: HashMap<String, String>,
+}
+
+impl RoutingTable {
+    /// Builds a snapshot from `discovery`'s current state: for each service advertised by a
+    /// healthy cluster, the first cluster encountered advertising it wins, the same way
+    /// [`DiscoveryManager::find_service`]'s caller picking `.first()` would without session
+    /// affinity or weighting to break ties.
+    fn from_discovery(discovery: &DiscoveryManager) -> Self {
+        let mut routes = HashMap::new();
+        for info in discovery.list_clusters() {
+            for service in &info.services {
+                if !discovery.is_service_healthy(&info.cluster_id, service) {
+                    continue;
+                }
+                routes
+                    .entry(service.clone())
+                    .or_insert_with(|| info.cluster_id.clone());
+            }
+        }
+        Self { routes }
+    }
+}
+
+/// Where a [`RoutingKey`] would currently be forwarded, as resolved by
+/// [`MeshProxy::resolve_route`] without actually connecting anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolvedRoute {
+    /// `key` matched an entry in [`ProxyConfig::routes`] directly; forwarded to this local
+    /// backend address without discovery being consulted at all.
+    Backend(SocketAddr),
+    /// `key` matched no static route, so it was resolved as a service name through discovery,
+    /// the same way [`MeshProxy::dial_service`] would.
+    Cluster {
+        /// The cluster discovery currently resolves `key` to.
+        cluster_id: String,
+        /// The cluster's iroh endpoint id -- what dialing it would actually connect to.
+        endpoint_id: EndpointId,
+        /// How many healthy, non-ejected candidates `key` had to choose among. `1` means `key`
+        /// resolved unambiguously; higher means a future [`MeshProxy::dial_service`] call could
+        /// pick a different one of these candidates.
+        pool_size: usize,
+    },
+}
+
+/// A caller-supplied check run against an inbound mesh connection, see
+/// [`MeshProxy::with_accept_hook`].
+pub type AcceptHookFn = Arc<dyn Fn(&Connection) -> Result<()> + Send + Sync>;
+
+/// Wraps an [`AcceptHookFn`] so [`MeshProxy`] can keep deriving [`fmt::Debug`] despite holding a
+/// boxed closure, which can't derive it itself.
+#[derive(Clone)]
+struct AcceptHook(AcceptHookFn);
+
+impl fmt::Debug for AcceptHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcceptHook").finish_non_exhaustive()
+    }
+}
+
+/// How a proxied connection [`splice`] summarized in a [`ConnectionSummary`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionOutcome {
+    /// Both directions reached EOF and shut down cleanly.
+    Closed,
+    /// Torn down for running past [`ForwardOptions::request_timeout`] (see
+    /// [`ProxyConfig::request_timeout`]).
+    TimedOut,
+}
+
+/// A structured record of one proxied connection's lifetime and outcome, delivered over the
+/// channel registered with [`MeshProxy::with_connection_summary_channel`] once [`splice`]
+/// finishes forwarding it. Meant for embedders doing billing or auditing, as a consumable stream
+/// rather than just this connection's `debug!` access-log lines in [`forward`] and
+/// [`route_and_forward_over`].
+///
+/// Only emitted for connections that reach [`splice`] -- one that fails to resolve a route or
+/// connect to its backend produces no summary, the same as it produces no access-log "connected
+/// to backend" line today.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionSummary {
+    /// See [`generate_conn_id`].
+    pub conn_id: String,
+    /// The client's address, as accepted by [`start_tcp_proxy`].
+    pub source: SocketAddr,
+    /// The route name or `Host`/routing-key value this connection forwarded to, when known --
+    /// `None` for a [`ProxyConfig::listeners`] pinned route, which bypasses the named route table
+    /// (see [`ForwardOptions::backend_tls`]'s docs on `route_key`).
+    pub target_service: Option<String>,
+    /// The cluster a cross-cluster dial forwarded to, when the connection crossed clusters.
+    ///
+    /// Always `None` today: this crate's client-facing forwarding only reaches locally-configured
+    /// backend addresses (see [`ConnectionGuard`]'s docs on the same gap) -- `start_tcp_proxy`'s
+    /// accept loop never calls [`MeshProxy::dial_service`] or [`MeshProxy::dial_cluster`] itself.
+    /// Reserved for when that wiring exists.
+    pub target_cluster: Option<String>,
+    /// Bytes copied from the client to the backend.
+    pub bytes_sent: u64,
+    /// Bytes copied from the backend to the client.
+    pub bytes_received: u64,
+    /// When [`splice`] started forwarding this connection.
+    pub started_at: SystemTime,
+    /// When [`splice`] finished forwarding this connection.
+    pub ended_at: SystemTime,
+    /// How the connection ended.
+    pub outcome: ConnectionOutcome,
+}
+
+/// A secret key rotation in progress, returned by [`MeshProxy::rotate_key`].
+///
+/// Both [`Self::old_endpoint`] and [`Self::new_endpoint`] stay bound -- and reachable at whichever
+/// [`EndpointId`] each was bound with -- until [`Self::finish`] closes the old one, retiring the
+/// old identity so only the new one remains reachable.
+///
+/// This is the building block a full rotation would use, not a complete one: it stands up the new
+/// endpoint and keeps both alive through the overlap window, but doesn't swap which endpoint
+/// [`MeshProxy::dial_cluster`]/[`MeshProxy::connect`] use for new dials, and doesn't touch
+/// discovery at all -- this crate's [`DiscoveryManager`] tracks other clusters this proxy dials,
+/// not a "self" entry this proxy publishes about itself, so there's nothing here to
+/// re-register under the new [`EndpointId`]. An operator rotating a live proxy today still needs
+/// to push the new identity to peers' discovery (the same admin-API/gossip path that registered
+/// the old one) and eventually restart the proxy to make the new endpoint the one it dials, tracks
+/// stats on, and runs [`MeshProxy::accept_mesh_connections`] against.
+#[derive(Debug)]
+pub struct KeyRotationHandle {
+    old_endpoint: Endpoint,
+    new_endpoint: Endpoint,
+}
+
+impl KeyRotationHandle {
+    /// The endpoint bound with the identity being rotated away from. Still accepting connections
+    /// (if anything is driving its accept loop) until [`Self::finish`] closes it.
+    pub fn old_endpoint(&self) -> &Endpoint {
+        &self.old_endpoint
+    }
+
+    /// The endpoint bound with the new identity.
+    pub fn new_endpoint(&self) -> &Endpoint {
+        &self.new_endpoint
+    }
+
+    /// Waits `overlap` for connections against the old identity to be established or drain, then
+    /// closes [`Self::old_endpoint`] so only the new identity remains reachable.
+    pub async fn finish(self, overlap: Duration) {
+        tokio::time::sleep(overlap).await;
+        self.old_endpoint.close().await;
+    }
+}
+
+/// Accepts TCP connections and forwards them to the backend configured for their route,
+/// dialing remote clusters over iroh when the route names one instead of a local address.
+#[derive(Debug)]
+pub struct MeshProxy {
+    config: ProxyConfig,
+    /// `None` when [`ProxyConfig::allow_degraded`] let [`Self::bind`] survive its iroh endpoint
+    /// failing to bind. Everything that needs the mesh -- [`Self::dial_cluster`] chief among
+    /// them -- fails with [`crate::error::MeshError::MeshUnavailable`] in that state instead of
+    /// panicking on a missing endpoint.
+    endpoint: Option<Endpoint>,
+    /// ALPN used for mesh hops, computed once from [`ProxyConfig::mesh_name`] so the accept side
+    /// (set when `endpoint` was bound) and [`Self::dial_cluster`]'s connect side always agree.
+    alpn: Vec<u8>,
+    discovery: Arc<DiscoveryManager>,
+    peer_paths: Arc<RwLock<HashMap<String, String>>>,
+    /// Last QUIC stats sample taken for each cluster this proxy has dialed, by
+    /// [`Self::track_stats`].
+    conn_stats: Arc<RwLock<HashMap<String, ConnQuality>>>,
+    /// Last sampled dial's [`TraceExemplar`] for each cluster this proxy has dialed, by
+    /// [`Self::dial_cluster`]. See [`Self::dial_trace_exemplars`].
+    dial_exemplars: Arc<RwLock<HashMap<String, TraceExemplar>>>,
+    metrics: Arc<Metrics>,
+    /// Bounds concurrently forwarded connections when [`ProxyConfig::max_connections`] is set.
+    connection_limit: Option<Arc<Semaphore>>,
+    /// Per-source-IP connection rate limiting when [`ProxyConfig::rate_limit`] is set.
+    rate_limiter: Option<Arc<ConnectionRateLimiter>>,
+    /// Ejects repeatedly failing destinations from [`Self::dial_service`]'s candidate pool when
+    /// [`ProxyConfig::outlier_detection`] is set.
+    outlier: Option<Arc<OutlierDetector>>,
+    /// Backs [`Self::is_ready`]. Starts `true` (ready immediately) unless
+    /// [`ProxyConfig::require_peer_for_ready`] is set, in which case [`Self::run_on`] spawns a
+    /// background probe loop that keeps this up to date instead.
+    ready: Watchable<bool>,
+    /// Rate-limits the "accepted client connection" and "connected to backend" debug logs (see
+    /// [`ProxyConfig::log_sampling`]). Shared across every connection so the budget is
+    /// proxy-wide, not reset per connection.
+    log_sampler: Arc<LogSampler>,
+    /// Decides at accept time which connections get detailed per-stream tracing spans (see
+    /// [`ProxyConfig::trace_sampling`]). Shared across every connection so
+    /// [`Self::set_trace_sample_rate`] takes effect for all of them at once.
+    trace_sampler: Arc<TraceSampler>,
+    /// Set by [`Self::with_accept_hook`]. See [`Self::check_accept_hook`].
+    accept_hook: Option<AcceptHook>,
+    /// Set by [`Self::with_connection_summary_channel`]. Cloned into each connection's
+    /// [`ForwardOptions`] so [`splice`] can send a [`ConnectionSummary`] once it finishes.
+    connection_summaries: Option<mpsc::Sender<ConnectionSummary>>,
+    /// Dial authorization policy consulted by [`Self::dial_service_for`] and
+    /// [`Self::resolve_route`], behind a lock so [`Self::set_authz`] can swap it atomically:
+    /// a dial in flight sees either the old or the new policy in full, never a partial update,
+    /// and connections already forwarding are unaffected either way since this is only consulted
+    /// before a new dial. Initialized from [`ProxyConfig::authz`].
+    authz: Arc<RwLock<Option<AuthzPolicy>>>,
+    /// Cancelled by [`Self::shutdown`] to stop every accept loop [`Self::run_on_many`] is running
+    /// and let [`Self::run`]/[`Self::run_on`]/[`Self::run_on_many`] return.
+    shutdown: CancellationToken,
+    /// Tracks every per-connection task [`Self::start_tcp_proxy`] spawns, so
+    /// [`Self::run_on_many`] can wait for them to finish forwarding before returning, rather than
+    /// returning the moment its accept loops stop.
+    tasks: TaskTracker,
+    /// Reuses connections dialed by [`Self::forward_tcp_to_service`] across proxied TCP sessions
+    /// when [`ProxyConfig::pool_idle_timeout`] is set. `None` dials a fresh connection for every
+    /// session, matching this crate's behavior before pooling existed.
+    connection_pool: Option<Arc<ConnectionPool>>,
+}
+
+impl MeshProxy {
+    /// Binds an iroh endpoint and creates a proxy that routes through `discovery`.
+    ///
+    /// Uses a freshly generated, unpersisted identity unless [`ProxyConfig::secret_key`] names a
+    /// source to load one from. See [`ProxyConfig::endpoint_discovery`] for which of iroh's own
+    /// endpoint-discovery mechanisms the bound endpoint publishes to and resolves through,
+    /// [`ProxyConfig::relay`] for which relay servers it falls back to, and
+    /// [`ProxyConfig::max_streams_per_connection`] for the cap this places on each peer's
+    /// inbound connection.
+    ///
+    /// If the bind itself fails and [`ProxyConfig::allow_degraded`] is set, logs a warning and
+    /// returns a degraded proxy with no endpoint instead of propagating the error -- see
+    /// [`Self::endpoint`]'s docs for what still works in that state.
+    pub async fn bind(config: ProxyConfig, discovery: Arc<DiscoveryManager>) -> Result<Self> {
+        if let Some(rate_limit) = &config.rate_limit {
+            // Validated here, up front, so a malformed whitelist CIDR fails the bind rather than
+            // surfacing lazily on whichever connection happens to trip it.
+            ConnectionRateLimiter::new(rate_limit)?;
+        }
+        match bind_endpoint(&config).await {
+            Ok(endpoint) => Ok(Self::from_endpoint(config, endpoint, discovery)),
+            Err(err) if config.allow_degraded => {
+                warn!(
+                    %err,
+                    "iroh endpoint bind failed, continuing in degraded (local-routes-only) mode"
+                );
+                Ok(Self::from_endpoint_opt(config, None, discovery))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Builds a proxy around an already-bound `endpoint`, bypassing [`Self::bind`]'s default
+    /// relay configuration.
+    ///
+    /// Exposed to [`crate::testing`] so tests can bind loopback-only endpoints; [`Self::bind`]
+    /// is the entry point for production use. The caller is responsible for having bound
+    /// `endpoint` with the same ALPN [`mesh_alpn`] computes from `config.mesh_name`.
+    pub(crate) fn from_endpoint(
+        config: ProxyConfig,
+        endpoint: Endpoint,
+        discovery: Arc<DiscoveryManager>,
+    ) -> Self {
+        Self::from_endpoint_opt(config, Some(endpoint), discovery)
+    }
+
+    /// Shared by [`Self::from_endpoint`] and [`Self::bind`]'s degraded path -- the only
+    /// difference between a normal and a degraded proxy is whether `endpoint` is `Some`.
+    fn from_endpoint_opt(
+        config: ProxyConfig,
+        endpoint: Option<Endpoint>,
+        discovery: Arc<DiscoveryManager>,
+    ) -> Self {
+        let alpn = mesh_alpn(config.mesh_name.as_deref());
+        let connection_limit = config.max_connections.map(|n| Arc::new(Semaphore::new(n)));
+        let rate_limiter = config.rate_limit.as_ref().map(|cfg| {
+            Arc::new(
+                ConnectionRateLimiter::new(cfg)
+                    .expect("rate limit config already validated by MeshProxy::bind"),
+            )
+        });
+        let outlier = config
+            .outlier_detection
+            .clone()
+            .map(|cfg| Arc::new(OutlierDetector::new(cfg)));
+        let ready = Watchable::new(!config.require_peer_for_ready);
+        let log_sampler = Arc::new(LogSampler::new(config.log_sampling.unwrap_or_default()));
+        let trace_sampler = Arc::new(TraceSampler::new(config.trace_sampling.unwrap_or(0.0)));
+        let authz = Arc::new(RwLock::new(config.authz.clone()));
+        let connection_pool = config.pool_idle_timeout.map(|idle_timeout| {
+            Arc::new(ConnectionPool::new(ConnectionPoolConfig {
+                idle_timeout,
+                max_streams_per_connection: config.pool_max_streams_per_connection,
+            }))
+        });
+        Self {
+            config,
+            alpn,
+            endpoint,
+            discovery,
+            peer_paths: Arc::new(RwLock::new(HashMap::new())),
+            conn_stats: Arc::new(RwLock::new(HashMap::new())),
+            dial_exemplars: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(Metrics::default()),
+            connection_limit,
+            rate_limiter,
+            outlier,
+            ready,
+            log_sampler,
+            trace_sampler,
+            accept_hook: None,
+            connection_summaries: None,
+            authz,
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            connection_pool,
+        }
+    }
+
+    /// Begins graceful shutdown: every accept loop [`Self::run_on_many`] is running stops taking
+    /// new connections, and `run`/`run_on`/`run_on_many` return once connections already in
+    /// flight have finished forwarding.
+    ///
+    /// Safe to call more than once, or before `run`/`run_on`/`run_on_many` is called at all --
+    /// the next call just returns immediately.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// A [`CancellationToken`] cancelled once this proxy has been asked to shut down (see
+    /// [`Self::shutdown`]), for embedders that want to observe shutdown starting rather than only
+    /// waiting for `run`/`run_on`/`run_on_many` to return.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Atomically swaps this proxy's dial authorization policy (see [`ProxyConfig::authz`]), so
+    /// a reload takes effect for new dials without restarting the proxy or disrupting
+    /// connections already forwarding. Pass `None` to allow every dial.
+    ///
+    /// This is the hot-reloadable counterpart of [`ProxyConfig::authz`], which only sets the
+    /// policy a freshly constructed proxy starts with; nothing currently calls this from a
+    /// config file watch or an admin endpoint of its own (the agent's `/admin/reload` reloads
+    /// [`crate::agent::AgentConfig`], a separate struct -- see [`crate::mesh::MeshBuilder`] for
+    /// co-locating a proxy and agent that could be wired together).
+    pub fn set_authz(&self, policy: Option<AuthzPolicy>) {
+        *self.authz.write().expect("lock poisoned") = policy;
+    }
+
+    /// Atomically replaces the fraction of connections sampled for detailed tracing (see
+    /// [`ProxyConfig::trace_sampling`]), clamped to `0.0..=1.0`. Takes effect for connections
+    /// accepted from now on; connections already forwarding keep whichever way their own accept-
+    /// time decision went.
+    ///
+    /// This is the hot-reloadable counterpart of [`ProxyConfig::trace_sampling`], which only sets
+    /// the rate a freshly constructed proxy starts with; nothing currently calls this from an
+    /// admin endpoint of its own, since this crate's HTTP admin surface
+    /// ([`crate::agent::MeshAgent`]'s `/admin/*` routes) lives on the agent, not the proxy -- an
+    /// embedder wiring the two together (see [`crate::mesh::MeshBuilder`]) is what would call this
+    /// in response to one today.
+    pub fn set_trace_sample_rate(&self, rate: f64) {
+        self.trace_sampler.set_rate(rate);
+    }
+
+    /// The fraction of connections currently sampled for detailed tracing. See
+    /// [`Self::set_trace_sample_rate`].
+    pub fn trace_sample_rate(&self) -> f64 {
+        self.trace_sampler.rate()
+    }
+
+    /// Registers `hook` to be checked against every inbound mesh connection by
+    /// [`Self::check_accept_hook`], returning `self` for chaining. Replaces any hook registered
+    /// by an earlier call.
+    ///
+    /// `hook` must be synchronous and fast: it runs inline wherever a connection is checked,
+    /// so an embedder doing anything beyond a quick local decision (looking up a node ID in an
+    /// allowlist, incrementing a metric) should hand the connection off to its own task instead
+    /// of blocking here.
+    ///
+    /// Checked by [`Self::accept_mesh_connections`], the mesh-side accept loop
+    /// [`Self::run_on_many`] runs alongside its client-facing listeners, against every inbound
+    /// mesh connection before it forwards anything from it.
+    pub fn with_accept_hook(mut self, hook: AcceptHookFn) -> Self {
+        self.accept_hook = Some(AcceptHook(hook));
+        self
+    }
+
+    /// Runs the hook registered by [`Self::with_accept_hook`] against `conn`, rejecting it if the
+    /// hook returns an error. Always accepts when no hook is registered.
+    pub fn check_accept_hook(&self, conn: &Connection) -> Result<()> {
+        match &self.accept_hook {
+            Some(hook) => (hook.0)(conn),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts rotating this proxy's identity to `new_key`, standing up a second endpoint bound
+    /// with it alongside the one this proxy was built with, sharing every other setting
+    /// [`Self::bind`] configured (ALPN, relay mode, endpoint discovery, max streams per
+    /// connection). Returns a [`KeyRotationHandle`] the caller drives to completion by calling
+    /// [`KeyRotationHandle::finish`] once the overlap window it wants has elapsed.
+    ///
+    /// See [`KeyRotationHandle`]'s docs for what this doesn't do yet.
+    ///
+    /// Fails with [`crate::error::MeshError::MeshUnavailable`] if this proxy is degraded (see
+    /// [`ProxyConfig::allow_degraded`]) -- there's no old endpoint to rotate away from.
+    pub async fn rotate_key(&self, new_key: iroh_base::SecretKey) -> Result<KeyRotationHandle> {
+        let old_endpoint = self.endpoint.clone().context(MeshUnavailableSnafu)?;
+        let new_endpoint = bind_endpoint_with_key(&self.config, Some(new_key)).await?;
+        Ok(KeyRotationHandle {
+            old_endpoint,
+            new_endpoint,
+        })
+    }
+
+    /// Registers `sender` to receive a [`ConnectionSummary`] for every connection [`splice`]
+    /// finishes forwarding, returning `self` for chaining. Replaces any sender registered by an
+    /// earlier call.
+    ///
+    /// `sender` should be bounded to a size the consumer can realistically keep up with: a
+    /// summary that can't be sent because the channel is full is dropped, not queued or blocked
+    /// on, so a slow consumer loses summaries rather than backing up connection forwarding (see
+    /// [`Metrics::connection_summaries_dropped`]).
+    pub fn with_connection_summary_channel(
+        mut self,
+        sender: mpsc::Sender<ConnectionSummary>,
+    ) -> Self {
+        self.connection_summaries = Some(sender);
+        self
+    }
+
+    /// Splices `tcp` -- a plain local TCP connection -- with `send`/`recv`, the two halves of a
+    /// bidirectional iroh stream to another cluster (see [`Connection::open_bi`] and
+    /// `accept_bi`), translating each side's half-close into the other transport's own equivalent
+    /// the same way [`splice`] does for two plain TCP streams: reading `tcp` to EOF finishes
+    /// `send` ([`iroh::endpoint::SendStream`]'s `AsyncWrite::poll_shutdown` already calls
+    /// [`iroh::endpoint::SendStream::finish`]), and reading `recv` to EOF shuts down `tcp`'s write
+    /// half, in both cases leaving the still-open reverse direction running.
+    ///
+    /// Needs its own function rather than reusing [`splice`] because a bidirectional iroh stream
+    /// is two separate types, one only readable and the other only writable, unlike `splice`'s
+    /// `backend` parameter which is a single type implementing both; the underlying
+    /// copy-then-shut-down behavior ([`copy_and_shutdown`]) is otherwise identical.
+    ///
+    /// [`Self::forward_tcp_to_service`] is the dial-side path that calls this;
+    /// [`accept_mesh_tunnels`] (spawned by [`Self::accept_mesh_connections`]) is the accept-side
+    /// path that calls it on the other end of the same tunnel. It remains the lower-level
+    /// building block for anything else that wants to bridge a local TCP client (or backend) with
+    /// a remote cluster's stream directly.
+    pub async fn splice_tcp_with_iroh_stream(
+        tcp: &mut TcpStream,
+        send: iroh::endpoint::SendStream,
+        recv: iroh::endpoint::RecvStream,
+        io_buffer_size: usize,
+    ) -> Result<()> {
+        let (tcp_read, tcp_write) = tokio::io::split(tcp);
+        tokio::try_join!(
+            copy_and_shutdown(tcp_read, send, io_buffer_size),
+            copy_and_shutdown(recv, tcp_write, io_buffer_size),
+        )?;
+        Ok(())
+    }
+
+    /// Dials whichever cluster currently advertises `service` (see [`Self::dial_service`]), sends
+    /// a [`RouteRequest`] identifying `client`'s address as a routing preamble, opens a
+    /// bidirectional stream on the dialed connection, and splices `client` with it (see
+    /// [`Self::splice_tcp_with_iroh_stream`]) until either side closes.
+    ///
+    /// When [`ProxyConfig::pool_idle_timeout`] is set, the dial (and the outlier-detection
+    /// bookkeeping [`Self::dial_service`] would otherwise do around it) is skipped in favor of
+    /// reusing an already-open connection to the resolved cluster from [`Self::connection_pool`],
+    /// multiplexing this session's stream onto it instead -- see
+    /// [`crate::connpool::ConnectionPool`]. Pooling is opt-in and off by default, so this reuses
+    /// [`Self::dial_service`] verbatim (and gets its outlier bookkeeping) unless configured
+    /// otherwise.
+    ///
+    /// This is the dial side of transparent cross-cluster TCP tunneling: given a plain TCP
+    /// connection and a target service name, it resolves a connection, sends the routing
+    /// preamble, and splices the two streams together, all in one call. The receiving cluster's
+    /// own [`Self::accept_mesh_connections`] (run by [`Self::run_on_many`] alongside its
+    /// client-facing listeners) is what reads that preamble and forwards the tunnel to a local
+    /// backend on the other end -- see its docs for what a route still needs to look like there
+    /// (an entry in [`ProxyConfig::routes`] keyed by `service`). Wiring this into
+    /// [`Self::run_on_many`]'s own client-facing accept loop as another routing outcome, for a
+    /// route that names a cluster instead of a local backend address, is what a deployment
+    /// forwarding straight to another cluster from a plain client connection would need to add
+    /// next.
+    pub async fn forward_tcp_to_service(
+        &self,
+        client: &mut TcpStream,
+        service: &str,
+    ) -> Result<()> {
+        let (slot, send, recv) = match &self.connection_pool {
+            Some(pool) => {
+                let (cluster_id, _pool_size) = self.pick_service_cluster(service, None)?;
+                let pooled = pool
+                    .get_or_dial(&cluster_id, || self.dial_cluster(&cluster_id))
+                    .await?;
+                RouteRequest {
+                    source_cluster_id: None,
+                    original_client_addr: client.peer_addr().ok(),
+                    service: service.to_string(),
+                }
+                .send_on(pooled.connection())
+                .await?;
+                let (send, recv, slot) = pooled.open_bi(&cluster_id).await?;
+                (Some(slot), send, recv)
+            }
+            None => {
+                let conn = self.dial_service(service).await?;
+                RouteRequest {
+                    source_cluster_id: None,
+                    original_client_addr: client.peer_addr().ok(),
+                    service: service.to_string(),
+                }
+                .send_on(&conn)
+                .await?;
+                let (send, recv) =
+                    conn.open_bi()
+                        .await
+                        .map_err(Box::new)
+                        .context(OpenStreamSnafu {
+                            target: service.to_string(),
+                        })?;
+                (None, send, recv)
+            }
+        };
+        let result =
+            Self::splice_tcp_with_iroh_stream(client, send, recv, self.config.io_buffer_size).await;
+        drop(slot);
+        result
+    }
+
+    /// Dials whichever cluster currently advertises `service` (see [`Self::dial_service`]) and
+    /// forwards `socket` to it over QUIC unreliable datagrams until either side errors, framing
+    /// each one with [`udp_datagram`]'s service-routing header so the receiving side's
+    /// [`accept_mesh_datagrams`] (spawned by [`Self::accept_mesh_connections`]) knows which local
+    /// backend to forward it to.
+    ///
+    /// UDP has no notion of "the connection" the way TCP does -- `socket` may hear from any
+    /// number of clients over its lifetime -- so, absent a real session concept, this tracks only
+    /// the single most recently seen source address and sends every reply there, the same
+    /// single-client simplification a plain `socat`-style UDP relay makes. A deployment fronting
+    /// more than one concurrent UDP client on the same listener will see replies cross-talk
+    /// between them.
+    pub async fn forward_udp_to_service(
+        &self,
+        socket: &tokio::net::UdpSocket,
+        service: &str,
+    ) -> Result<()> {
+        let conn = self.dial_service(service).await?;
+        let mut last_client: Option<SocketAddr> = None;
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD_BYTES];
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    let (len, from) = received?;
+                    last_client = Some(from);
+                    let frame = udp_datagram::encode(service, &buf[..len])?;
+                    conn.send_datagram(frame)
+                        .map_err(Box::new)
+                        .context(UdpDatagramSendSnafu {
+                            target: service.to_string(),
+                        })?;
+                }
+                datagram = conn.read_datagram() => {
+                    let datagram = datagram.map_err(Box::new).context(UdpDatagramReadSnafu {
+                        target: service.to_string(),
+                    })?;
+                    let (_service, payload) = udp_datagram::decode(&datagram)?;
+                    if let Some(client) = last_client {
+                        socket.send_to(payload, client).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Binds `listener`'s `bind_address` and runs [`Self::forward_udp_to_service`] on it until
+    /// [`Self::shutdown`] is called, at which point any datagrams already in flight are simply
+    /// dropped -- unlike [`Self::start_tcp_proxy`]'s connections, a UDP forward has no notion of
+    /// "finish gracefully" to drain before stopping.
+    async fn start_udp_proxy(&self, listener: &UdpListenerConfig) -> Result<()> {
+        let socket = tokio::net::UdpSocket::bind(listener.bind_address).await?;
+        tokio::select! {
+            biased;
+            () = self.shutdown.cancelled() => Ok(()),
+            result = self.forward_udp_to_service(&socket, &listener.service) => result,
+        }
+    }
+
+    /// Accepts inbound mesh connections on this proxy's own iroh endpoint and completes the
+    /// cross-cluster tunnels [`Self::forward_tcp_to_service`] and [`Self::forward_udp_to_service`]
+    /// dial: for each connection, it reads every [`RouteRequest`]-prefixed TCP tunnel and every
+    /// [`udp_datagram`]-tagged datagram the dialing side sends, resolves
+    /// [`RouteRequest::service`] (or the datagram's service name) against this proxy's own
+    /// [`ProxyConfig::routes`] -- the same table [`ProxyMode::Tcp`] forwards accepted client
+    /// connections through -- and forwards to that local backend.
+    ///
+    /// Runs alongside [`Self::start_tcp_proxy`]/[`Self::start_udp_proxy`] in
+    /// [`Self::run_on_many`]'s `accept_loops`, so [`Self::shutdown`] stops it the same way. A
+    /// degraded proxy (see [`ProxyConfig::allow_degraded`]) has no endpoint to accept on, so this
+    /// returns immediately.
+    ///
+    /// Every accepted connection is checked against [`Self::check_accept_hook`] up front, same as
+    /// a hook registered by [`Self::with_accept_hook`] was always meant to be. A rejected or
+    /// otherwise failed connection is logged and dropped without affecting any other connection.
+    ///
+    /// Every forwarded tunnel and datagram is also checked against [`Self::authz`] -- see
+    /// [`accept_mesh_tunnels`] and [`accept_mesh_datagrams`] for what cluster identity each one
+    /// is checked against -- and every tunnel additionally competes for
+    /// [`Self::connection_limit`] and is tracked by a [`ConnectionGuard`], the same as a
+    /// client-facing connection [`Self::start_tcp_proxy`] accepts.
+    ///
+    /// **Simplification**: a connection carrying more than one TCP tunnel (sessions sharing a
+    /// [`Self::connection_pool`]ed connection) pairs each `RouteRequest` uni stream with the very
+    /// next bidirectional stream accepted on the same connection -- correct for the common case
+    /// of one session dialing, sending its `RouteRequest`, then immediately opening its
+    /// bidirectional stream, but two sessions racing to open theirs on the same shared connection
+    /// could have their tunnels paired with the wrong `RouteRequest`.
+    async fn accept_mesh_connections(&self) -> Result<()> {
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(());
+        };
+        loop {
+            let incoming = tokio::select! {
+                biased;
+                () = self.shutdown.cancelled() => return Ok(()),
+                incoming = endpoint.accept() => incoming,
+            };
+            let Some(incoming) = incoming else {
+                return Ok(()); // the endpoint itself closed
+            };
+            let routes = self.config.routes.clone();
+            let io_buffer_size = self.config.io_buffer_size;
+            let accept_hook = self.accept_hook.clone();
+            let authz = self.authz.clone();
+            let metrics = self.metrics.clone();
+            let connection_limit = self.connection_limit.clone();
+            self.tasks.spawn(async move {
+                let conn = match incoming.await {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        warn!(%err, "inbound mesh connection handshake failed");
+                        return;
+                    }
+                };
+                if let Some(hook) = &accept_hook {
+                    if let Err(err) = (hook.0)(&conn) {
+                        debug!(%err, "inbound mesh connection rejected by accept hook");
+                        return;
+                    }
+                }
+                let (tunnels, datagrams) = tokio::join!(
+                    accept_mesh_tunnels(
+                        conn.clone(),
+                        &routes,
+                        io_buffer_size,
+                        &authz,
+                        &metrics,
+                        &connection_limit,
+                    ),
+                    accept_mesh_datagrams(conn, &routes, &authz, &metrics),
+                );
+                if let Err(err) = tunnels {
+                    warn!(%err, "accepting mesh tunnels on an inbound connection failed");
+                }
+                if let Err(err) = datagrams {
+                    warn!(%err, "accepting mesh datagrams on an inbound connection failed");
+                }
+            });
+        }
+    }
+
+    /// The iroh endpoint backing this proxy, or `None` if it's running degraded (see
+    /// [`ProxyConfig::allow_degraded`]).
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn endpoint(&self) -> Option<&Endpoint> {
+        self.endpoint.as_ref()
+    }
+
+    /// Returns a [`Watcher`] over the proxy's current routing table -- which cluster each known
+    /// service currently resolves to -- updated whenever discovery registers or forgets a
+    /// cluster in a way that changes it (see [`DiscoveryManager::watch_changes`]).
+    ///
+    /// Mirrors the same first-healthy-candidate choice [`Self::dial_service`] makes with no
+    /// [`crate::config::ProxyConfig::service_weights`] or
+    /// [`crate::config::ProxyConfig::session_affinity`] configured; it doesn't reflect either of
+    /// those, since both only matter per dial, not as a single steady-state table. Meant for
+    /// embedders that want to observe routing decisions programmatically, without scraping
+    /// [`Self::status`] or the agent's HTTP API.
+    pub fn routing_table_watcher(&self) -> impl Watcher<Value = RoutingTable> + use<> {
+        let discovery = self.discovery.clone();
+        self.discovery
+            .watch_changes()
+            .map(move |_| RoutingTable::from_discovery(&discovery))
+            .expect("disconnected")
+    }
+
+    /// Returns the last observed connection path (`"direct"`, `"relay"`, `"mixed"` or
+    /// `"none"`) for each cluster this proxy has dialed.
+    pub fn peer_paths(&self) -> HashMap<String, String> {
+        self.peer_paths.read().expect("lock poisoned").clone()
+    }
+
+    /// Returns the last QUIC stats sample taken for each cluster this proxy has dialed (see
+    /// [`Self::track_stats`]).
+    pub fn conn_stats(&self) -> HashMap<String, ConnQuality> {
+        self.conn_stats.read().expect("lock poisoned").clone()
+    }
+
+    /// Returns the last sampled dial's [`TraceExemplar`] for each cluster this proxy has dialed
+    /// (see [`Self::dial_cluster`]), for clusters whose most recent dial happened to be sampled
+    /// by [`ProxyConfig::trace_sampling`]. A cluster only ever dialed while sampling was off, or
+    /// never dialed at all, is absent rather than reported with an empty exemplar.
+    pub fn dial_trace_exemplars(&self) -> HashMap<String, TraceExemplar> {
+        self.dial_exemplars.read().expect("lock poisoned").clone()
+    }
+
+    /// Whether this proxy is ready to receive traffic.
+    ///
+    /// Always `true` unless [`ProxyConfig::require_peer_for_ready`] is set, in which case it's
+    /// `true` only once [`Self::run_on`]'s background probe loop has successfully reached at
+    /// least one cluster known to discovery, and flips back to `false` if every peer later
+    /// becomes unreachable.
+    pub fn is_ready(&self) -> bool {
+        self.ready.get()
+    }
+
+    /// Returns a [`Watcher`] over [`Self::is_ready`]'s value, for embedders that want to react to
+    /// readiness changes programmatically instead of polling.
+    pub fn readiness_watcher(&self) -> impl Watcher<Value = bool> + use<> {
+        self.ready.watch()
+    }
+
+    /// Probes every cluster known to discovery, in order, stopping at the first one that
+    /// answers, and updates [`Self::is_ready`] with the result (returned for convenience).
+    /// Stops at the first reachable cluster rather than probing all of them, since readiness
+    /// only needs "is at least one peer reachable", not a full health snapshot (see
+    /// [`crate::health::HealthChecker`] for that).
+    pub async fn probe_readiness(&self) -> bool {
+        let reachable = probe_any_peer(&self.discovery, self.endpoint.as_ref(), &self.alpn).await;
+        debug!(reachable, "mesh readiness probe");
+        let _ = self.ready.set(reachable);
+        reachable
+    }
+
+    /// Spawns a task that calls [`Self::probe_readiness`] on [`READINESS_PROBE_INTERVAL`] until
+    /// the proxy stops running, keeping [`Self::is_ready`] continuously up to date.
+    fn spawn_readiness_probe(&self) {
+        let discovery = self.discovery.clone();
+        let endpoint = self.endpoint.clone();
+        let alpn = self.alpn.clone();
+        let ready = self.ready.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(READINESS_PROBE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let reachable = probe_any_peer(&discovery, endpoint.as_ref(), &alpn).await;
+                debug!(reachable, "mesh readiness probe");
+                let _ = ready.set(reachable);
+            }
+        });
+    }
+
+    /// Binds the listen address (and, if configured, the status address,
+    /// [`ProxyConfig::listeners`] and [`ProxyConfig::udp_listeners`]) and forwards connections
+    /// until the process is asked to stop.
+    pub async fn run(&self) -> Result<()> {
+        let listener = socket::bind_listener(self.config.listen_addr, self.config.dual_stack)?;
+        let mut listeners = vec![(listener, None)];
+        for extra in &self.config.listeners {
+            let extra_listener = socket::bind_listener(extra.bind_address, self.config.dual_stack)?;
+            listeners.push((extra_listener, extra.route));
+        }
+        self.run_on_many(listeners).await
+    }
+
+    /// Like [`Self::run`], but reuses an already-bound listener with no pinned route (see
+    /// [`Self::run_on_many`]), ignoring [`ProxyConfig::listeners`] entirely.
+    ///
+    /// Useful for tests that need to know the bound address before the proxy starts accepting
+    /// connections.
+    pub async fn run_on(&self, listener: TcpListener) -> Result<()> {
+        self.run_on_many(vec![(listener, None)]).await
+    }
+
+    /// Like [`Self::run_on`], but accepts on every listener in `listeners` concurrently. Each is
+    /// paired with the pinned route (if any) connections on it always forward to, bypassing
+    /// [`ProxyConfig::mode`]'s usual routing; `None` falls back to that routing instead (see
+    /// [`ProxyConfig::listeners`]).
+    ///
+    /// Returns as soon as any one listener's accept loop fails.
+    pub async fn run_on_many(
+        &self,
+        listeners: Vec<(TcpListener, Option<SocketAddr>)>,
+    ) -> Result<()> {
+        for (listener, _) in &listeners {
+            debug!(addr = %listener.local_addr()?, "mesh proxy listening");
+        }
+
+        let tls_acceptor = match &self.config.tls {
+            Some(tls) => {
+                snafu::ensure!(
+                    self.config.mode == ProxyMode::Http,
+                    InvalidConfigSnafu {
+                        reason: "tls termination requires ProxyMode::Http",
+                    }
+                );
+                Some(build_tls_acceptor(tls).await?)
+            }
+            None => None,
+        };
+        let backend_tls = Arc::new(build_backend_tls_connectors(&self.config.backend_tls).await?);
+        let mirror = Arc::new(self.config.mirror.clone());
+
+        if self.config.require_peer_for_ready {
+            self.spawn_readiness_probe();
+        }
+
+        if let Some(status_addr) = self.config.status_addr {
+            let discovery = self.discovery.clone();
+            let peer_paths = self.peer_paths.clone();
+            let ready = self.ready.clone();
+            let version = Arc::new(VersionInfo::new(
+                self.endpoint.as_ref().map(Endpoint::id),
+                &self.alpn,
+            ));
+            let endpoint = self.endpoint.clone();
+            let conn_stats = self.conn_stats.clone();
+            tokio::spawn(async move {
+                if let Err(err) = httputil::serve(
+                    status_addr,
+                    (discovery, peer_paths, ready, version, endpoint, conn_stats),
+                    status::handle,
+                )
+                .await
+                {
+                    warn!(%err, "status server exited");
+                }
+            });
+        }
+
+        if let Some(metrics_addr) = self.config.metrics_addr {
+            let mut registry = iroh_metrics::Registry::default();
+            registry.register(self.metrics.clone());
+            registry.register(self.discovery.metrics());
+            let registry = Arc::new(registry);
+            tokio::spawn(async move {
+                if let Err(err) =
+                    iroh_metrics::service::start_metrics_server(metrics_addr, registry).await
+                {
+                    warn!(%err, "metrics server exited");
+                }
+            });
+        }
+
+        let mut accept_loops: FuturesUnordered<
+            Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>,
+        > = listeners
+            .into_iter()
+            .map(|(listener, pinned_route)| {
+                Box::pin(self.start_tcp_proxy(
+                    listener,
+                    tls_acceptor.clone(),
+                    backend_tls.clone(),
+                    mirror.clone(),
+                    pinned_route,
+                )) as Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>
+            })
+            .chain(self.config.udp_listeners.iter().map(|listener| {
+                Box::pin(self.start_udp_proxy(listener))
+                    as Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>
+            }))
+            .chain(std::iter::once(Box::pin(self.accept_mesh_connections())
+                as Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>))
+            .collect();
+        let result = async {
+            while let Some(result) = accept_loops.next().await {
+                result?;
+            }
+            Ok(())
+        }
+        .await;
+        // Every accept loop above has already returned -- either because one failed (in which
+        // case `result` carries that error and every other loop was cancelled below on drop, as
+        // `FuturesUnordered` does for its still-pending members) or because `Self::shutdown` was
+        // called -- so only connections already forwarding are left to wait for.
+        self.tasks.close();
+        self.tasks.wait().await;
+        result
+    }
+
+    /// Accepts connections from `listener` and forwards each to a backend, applying the
+    /// configured socket options along the way.
+    ///
+    /// When `pinned_route` is set, every connection forwards straight to it, bypassing
+    /// [`ProxyConfig::mode`]'s routing entirely (see [`ProxyConfig::listeners`]); otherwise
+    /// backends are chosen according to `mode` exactly as [`Self::run_on`] always has.
+    ///
+    /// When [`ProxyConfig::max_connections`] is set, this stops calling `accept` once that many
+    /// connections are in flight, leaving further connections queued in the listen backlog until
+    /// one finishes. `tls_acceptor` is set when [`ProxyConfig::tls`] is configured, and is only
+    /// used alongside [`ProxyMode::Http`] (see [`Self::run_on_many`]).
+    ///
+    /// When [`ProxyConfig::rate_limit`] is set, a connection whose source IP has exhausted its
+    /// per-IP budget (see [`ConnectionRateLimiter::allow`]) is accepted off the socket -- so a
+    /// queued connection behind it isn't starved -- then dropped immediately without being
+    /// forwarded, freeing the [`Self::connection_limit`] permit it never used.
+    ///
+    /// Returns `Ok(())` as soon as [`Self::shutdown`] is called, without waiting for connections
+    /// already forwarding -- [`Self::run_on_many`] is what waits for those to drain, once every
+    /// accept loop it's running has returned.
+    async fn start_tcp_proxy(
+        &self,
+        listener: TcpListener,
+        tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+        backend_tls: Arc<HashMap<String, BackendTlsConnector>>,
+        mirror: Arc<HashMap<String, MirrorConfig>>,
+        pinned_route: Option<SocketAddr>,
+    ) -> Result<()> {
+        loop {
+            let permit = match &self.connection_limit {
+                Some(semaphore) => {
+                    let semaphore = semaphore.clone();
+                    tokio::select! {
+                        biased;
+                        () = self.shutdown.cancelled() => return Ok(()),
+                        permit = semaphore.acquire_owned() => {
+                            Some(permit.expect("connection semaphore is never closed"))
+                        }
+                    }
+                }
+                None => None,
+            };
+            let (client, peer) = tokio::select! {
+                biased;
+                () = self.shutdown.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted?,
+            };
+            if let Some(rate_limiter) = &self.rate_limiter {
+                if !rate_limiter.allow(peer.ip()) {
+                    debug!(%peer, "connection refused: source ip exceeded its rate limit");
+                    drop(client);
+                    drop(permit);
+                    continue;
+                }
+            }
+            let conn_id = generate_conn_id();
+            let guard = ConnectionGuard::new(self.metrics.clone());
+            let socket = self.config.socket.clone();
+            let options = ForwardOptions {
+                request_timeout: self.config.request_timeout,
+                send_proxy_protocol: self.config.send_proxy_protocol,
+                io_buffer_size: self.config.io_buffer_size,
+                max_retries: self.config.retry.as_ref().map_or(0, |r| r.max_retries),
+                log_sampler: self.log_sampler.clone(),
+                trace_sampled: self.trace_sampler.sample(),
+                limits: self.config.limits,
+                backend_tls: backend_tls.clone(),
+                connection_summaries: self.connection_summaries.clone(),
+                mirror: mirror.clone(),
+            };
+            if let Some(backend_addr) = pinned_route {
+                self.tasks.spawn(async move {
+                    if let Err(err) = forward(
+                        client,
+                        peer,
+                        &socket,
+                        backend_addr,
+                        &[],
+                        None,
+                        &options,
+                        &guard,
+                        &conn_id,
+                    )
+                    .await
+                    {
+                        warn!(%peer, %conn_id, %err, "connection forwarding failed");
+                    }
+                    drop(guard);
+                    drop(permit);
+                });
+                continue;
+            }
+            match self.config.mode {
+                ProxyMode::Tcp => {
+                    let route = self.default_route()?.to_owned();
+                    let backend_addr = *self
+                        .config
+                        .routes
+                        .get(&route)
+                        .context(NoRouteSnafu { target: &route })?;
+                    let backend_addr = override_backend_addr(
+                        &route,
+                        backend_addr,
+                        &self.config.backend_address_override,
+                    );
+                    let retries = self
+                        .config
+                        .retry_routes
+                        .get(&route)
+                        .cloned()
+                        .unwrap_or_default();
+                    self.tasks.spawn(async move {
+                        if let Err(err) = forward(
+                            client,
+                            peer,
+                            &socket,
+                            backend_addr,
+                            &retries,
+                            Some(&route),
+                            &options,
+                            &guard,
+                            &conn_id,
+                        )
+                        .await
+                        {
+                            warn!(%peer, %conn_id, %err, "connection forwarding failed");
+                        }
+                        drop(guard);
+                        drop(permit);
+                    });
+                }
+                ProxyMode::Http => {
+                    let routes = self.config.routes.clone();
+                    let retry_routes = self.config.retry_routes.clone();
+                    let overrides = self.config.backend_address_override.clone();
+                    let path_routes = self.config.path_routes.clone();
+                    let tls_acceptor = tls_acceptor.clone();
+                    let routing = self.config.routing.clone();
+                    let enable_interception = self.config.enable_interception;
+                    self.tasks.spawn(async move {
+                        let result = match &tls_acceptor {
+                            Some(tls_acceptor) => {
+                                route_and_forward_tls(
+                                    client,
+                                    peer,
+                                    &routes,
+                                    &retry_routes,
+                                    &overrides,
+                                    &path_routes,
+                                    &socket,
+                                    tls_acceptor,
+                                    &options,
+                                    &guard,
+                                    &conn_id,
+                                )
+                                .await
+                            }
+                            // The default routing strategy keeps using its original path
+                            // unchanged (see crate::routing's module docs for why); only the
+                            // other strategies go through the newer, more general one.
+                            None if routing == RoutingStrategyConfig::Host => {
+                                route_and_forward(
+                                    client,
+                                    peer,
+                                    &routes,
+                                    &retry_routes,
+                                    &overrides,
+                                    &path_routes,
+                                    &socket,
+                                    &options,
+                                    &guard,
+                                    &conn_id,
+                                )
+                                .await
+                            }
+                            None => {
+                                let orig_dst = if enable_interception {
+                                    socket::original_dst(&client)
+                                } else {
+                                    None
+                                };
+                                route_and_forward_with_strategy(
+                                    client,
+                                    peer,
+                                    orig_dst,
+                                    routing.build().as_ref(),
+                                    &routes,
+                                    &retry_routes,
+                                    &overrides,
+                                    &socket,
+                                    &options,
+                                    &guard,
+                                    &conn_id,
+                                )
+                                .await
+                            }
+                        };
+                        if let Err(err) = result {
+                            warn!(%peer, %conn_id, %err, "connection forwarding failed");
+                        }
+                        drop(guard);
+                        drop(permit);
+                    });
+                }
+            }
+        }
+    }
+
+    /// Returns the single configured route, used by [`ProxyMode::Tcp`].
+    fn default_route(&self) -> Result<&str> {
+        self.config
+            .routes
+            .keys()
+            .next()
+            .map(String::as_str)
+            .context(NoRouteSnafu {
+                target: "<none configured>",
+            })
+    }
+
+    /// Dials the agent for `cluster_id` over iroh and starts tracking its connection path and
+    /// QUIC stats (see [`Self::track_path`] and [`Self::track_stats`]).
+    ///
+    /// The dial's [`EndpointAddr`] carries both the relay URL and the direct addresses known
+    /// from discovery, so a stale direct address doesn't fail the connection outright: iroh
+    /// races the direct path against the relay and falls back to the relay on its own if the
+    /// direct path doesn't pan out. Whether that fallback happened is reported once the
+    /// connection's first path is observed (see [`Self::track_path`]).
+    ///
+    /// When a [`crate::discovery::ClusterInfo`] carries neither -- an endpoint id known only
+    /// from gossip or a
+    /// static registration that never learned its addresses -- the resulting [`EndpointAddr`]
+    /// carries nothing but that endpoint id, which iroh's own endpoint discovery then tries to
+    /// resolve on its own (see [`ProxyConfig::endpoint_discovery`]). Dialing such a cluster
+    /// succeeds exactly when that discovery does.
+    ///
+    /// Reuses [`Self::trace_sampler`] to decide whether this dial also gets a [`TraceExemplar`]
+    /// recorded into [`Self::dial_trace_exemplars`] -- see [`TraceExemplar`] for why that's a
+    /// side channel rather than something attached to [`Metrics::dial_duration_seconds`] itself.
+    ///
+    /// Fails with [`crate::error::MeshError::MeshUnavailable`] if this proxy is degraded (see
+    /// [`ProxyConfig::allow_degraded`]) -- there's no endpoint to dial with.
+    pub async fn dial_cluster(&self, cluster_id: &str) -> Result<Connection> {
+        let endpoint = self.endpoint.as_ref().context(MeshUnavailableSnafu)?;
+        let info = self
+            .discovery
+            .get_cluster(cluster_id)
+            .context(NoRouteSnafu { target: cluster_id })?;
+        let addr = info.endpoint_addr()?;
+
+        let dial_started = std::time::Instant::now();
+        let conn = tokio::time::timeout(DIAL_TIMEOUT, self.connect(endpoint, addr))
+            .await
+            .ok()
+            .context(DialTimeoutSnafu { cluster_id })?
+            .map_err(Box::new)
+            .context(ConnectSnafu { cluster_id })?;
+        let dial_duration = dial_started.elapsed();
+        self.metrics.record_dial_duration(cluster_id, dial_duration);
+        if self.trace_sampler.sample() {
+            self.dial_exemplars.write().expect("lock poisoned").insert(
+                cluster_id.to_string(),
+                TraceExemplar {
+                    trace_id: generate_conn_id(),
+                    duration: dial_duration,
+                },
+            );
+        }
+        self.track_path(cluster_id.to_string(), &conn);
+        self.track_duration(cluster_id.to_string(), conn.clone());
+        self.track_stats(cluster_id.to_string(), conn.clone());
+        Ok(conn)
+    }
+
+    /// Connects to `addr`, applying [`ProxyConfig::keepalive_interval`] and
+    /// [`ProxyConfig::keepalive_timeout`] when either is set so a black-holed connection is
+    /// noticed (and torn down) by QUIC's own keepalive ping/idle-timeout mechanism instead of
+    /// only surfacing once a proxied byte fails to send.
+    async fn connect(
+        &self,
+        endpoint: &Endpoint,
+        addr: EndpointAddr,
+    ) -> std::result::Result<Connection, iroh::endpoint::ConnectError> {
+        if self.config.keepalive_interval.is_none() && self.config.keepalive_timeout.is_none() {
+            return endpoint.connect(addr, &self.alpn).await;
+        }
+        let mut transport = iroh::endpoint::TransportConfig::default();
+        if let Some(interval) = self.config.keepalive_interval {
+            transport.keep_alive_interval(Some(interval));
+        }
+        if let Some(timeout) = self.config.keepalive_timeout {
+            if let Ok(idle_timeout) = quinn::IdleTimeout::try_from(timeout) {
+                transport.max_idle_timeout(Some(idle_timeout));
+            }
+        }
+        let options =
+            iroh::endpoint::ConnectOptions::new().with_transport_config(Arc::new(transport));
+        let connecting = endpoint
+            .connect_with_opts(addr, &self.alpn, options)
+            .await?;
+        Ok(connecting.await?)
+    }
+
+    /// Like [`Self::dial_service_for`], but without a client to key session affinity on -- picks
+    /// by [`ProxyConfig::service_weights`] when `service` has an entry there, and otherwise the
+    /// first remaining candidate.
+    pub async fn dial_service(&self, service: &str) -> Result<Connection> {
+        self.dial_service_for(service, None).await
+    }
+
+    /// Waits until some cluster advertises `service`, or `timeout` elapses.
+    ///
+    /// Resolves immediately if a candidate is already known. Delegates to
+    /// [`DiscoveryManager::wait_for_cluster`]'s change-notification wait rather than polling
+    /// [`DiscoveryManager::find_service`] in a loop; see its docs for that behavior. There's no
+    /// namespace to scope `service` within -- this crate scopes a service by the cluster that
+    /// advertises it instead (see [`DiscoveryManager::find_service_port`]'s docs) -- so unlike a
+    /// Kubernetes-style wait this only takes the service name.
+    ///
+    /// Returns [`crate::error::MeshError::Timeout`] if no cluster advertises `service` once
+    /// `timeout` elapses. Meant for test harnesses and startup ordering, alongside
+    /// [`DiscoveryManager::wait_for_cluster`].
+    pub async fn wait_for_service(&self, service: &str, timeout: Duration) -> Result<ClusterInfo> {
+        if let Some(info) = self.discovery.find_service(service).into_iter().next() {
+            return Ok(info);
+        }
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut watcher = self.discovery.watch_changes();
+        tokio::time::timeout_at(deadline, async {
+            loop {
+                watcher.updated().await.expect("disconnected");
+                if let Some(info) = self.discovery.find_service(service).into_iter().next() {
+                    return info;
+                }
+            }
+        })
+        .await
+        .ok()
+        .context(crate::error::TimeoutSnafu {
+            what: format!("service {service}"),
+        })
+    }
+
+    /// Dials a cluster hosting `service`, skipping any destination currently ejected by outlier
+    /// detection, and feeds the dial's outcome back into the detector.
+    ///
+    /// When [`ProxyConfig::service_weights`] has an entry for `service`, a candidate is picked
+    /// with probability proportional to its weight (see [`affinity::pick_weighted`]), for
+    /// canary/blue-green rollouts. Otherwise, when [`ProxyConfig::session_affinity`] is set and
+    /// `client_ip` is given, the candidate is picked consistently for that client (see
+    /// [`affinity::pick`]); failing both, the first remaining candidate is used. Either way,
+    /// candidates aren't retried against each other on failure, since a failed dial here is
+    /// exactly the signal outlier detection uses to decide whether to eject this destination for
+    /// later calls.
+    ///
+    /// When discovery has no candidate for `service` at all, falls through to
+    /// [`ProxyConfig::fallthrough_routes`] before giving up with
+    /// [`crate::error::MeshError::NoRoute`], for hub-and-spoke topologies where an unrecognized
+    /// service should still reach a designated cluster rather than failing outright.
+    pub async fn dial_service_for(
+        &self,
+        service: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Connection> {
+        let (cluster_id, pool_size) = self.pick_service_cluster(service, client_ip)?;
+
+        match self.dial_cluster(&cluster_id).await {
+            Ok(conn) => {
+                if let Some(outlier) = &self.outlier {
+                    outlier.record_success(&cluster_id);
+                }
+                Ok(conn)
+            }
+            Err(err) => {
+                if let Some(outlier) = &self.outlier {
+                    outlier.record_failure(&cluster_id, pool_size);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Picks which cluster a dial for `service` should target -- applying
+    /// [`ProxyConfig::service_weights`] or [`ProxyConfig::session_affinity`] and falling through
+    /// to [`ProxyConfig::fallthrough_routes`], exactly as [`Self::dial_service_for`] does -- then
+    /// checks [`ProxyConfig::authz`], all without dialing anything. Returns the chosen cluster id
+    /// alongside how many candidates were available, for [`OutlierDetector::record_failure`]'s
+    /// ejection-fraction bookkeeping.
+    ///
+    /// Factored out of [`Self::dial_service_for`] so [`Self::forward_tcp_to_service`] can resolve
+    /// a target cluster id up front to key [`Self::connection_pool`] with, before deciding
+    /// whether a dial is even needed.
+    fn pick_service_cluster(
+        &self,
+        service: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(String, usize)> {
+        let candidates =
+            discover_service_candidates(&self.discovery, self.outlier.as_deref(), service)?;
+        let pool_size = candidates.len();
+        let picked = match self.config.service_weights.get(service) {
+            Some(weights) => affinity::pick_weighted(&mut rand::rng(), &candidates, weights),
+            None => match (self.config.session_affinity, client_ip) {
+                (Some(SessionAffinity::ClientIp), Some(ip)) => affinity::pick(ip, &candidates),
+                _ => candidates.first(),
+            },
+        };
+        let cluster_id = match picked {
+            Some(info) => info.cluster_id.clone(),
+            None => self
+                .config
+                .fallthrough_cluster_for(service)
+                .map(str::to_string)
+                .context(NoRouteSnafu { target: service })?,
+        };
+
+        if let Some(authz) = self.authz.read().expect("lock poisoned").as_ref() {
+            if !authz.is_allowed(&cluster_id, service) {
+                self.metrics.record_authz_denied(&cluster_id, service);
+                return AuthzDeniedSnafu {
+                    cluster_id,
+                    service,
+                }
+                .fail();
+            }
+        }
+
+        Ok((cluster_id, pool_size))
+    }
+
+    /// Resolves where `key` would currently be forwarded, without opening any connection:
+    /// checks [`ProxyConfig::routes`] first, then falls back to the same discovery-backed
+    /// candidate selection [`Self::dial_service`] uses (outlier filtering, then
+    /// [`ProxyConfig::service_weights`] or else the first remaining candidate) and the same
+    /// [`ProxyConfig::authz`] check a real dial would be subject to.
+    ///
+    /// Lets operators test and explain routing decisions before sending traffic, e.g. from
+    /// `iroh-proxy resolve <key>`. A service with weighted targets configured can resolve to a
+    /// different cluster on each call, exactly as a real dial through [`Self::dial_service`]
+    /// would.
+    pub fn resolve_route(&self, key: &RoutingKey) -> Result<ResolvedRoute> {
+        if let Some(backend) = self.config.routes.get(key.as_str()) {
+            return Ok(ResolvedRoute::Backend(*backend));
+        }
+
+        let service = key.as_str();
+        let candidates =
+            discover_service_candidates(&self.discovery, self.outlier.as_deref(), service)?;
+        let pool_size = candidates.len();
+        let picked = match self.config.service_weights.get(service) {
+            Some(weights) => affinity::pick_weighted(&mut rand::rng(), &candidates, weights),
+            None => candidates.first(),
+        };
+        let info = picked.context(NoRouteSnafu { target: service })?;
+
+        if let Some(authz) = self.authz.read().expect("lock poisoned").as_ref() {
+            if !authz.is_allowed(&info.cluster_id, service) {
+                return AuthzDeniedSnafu {
+                    cluster_id: info.cluster_id.clone(),
+                    service,
+                }
+                .fail();
+            }
+        }
+
+        Ok(ResolvedRoute::Cluster {
+            cluster_id: info.cluster_id.clone(),
+            endpoint_id: info.endpoint_id,
+            pool_size,
+        })
+    }
+
+    /// Spawns a task that follows `conn`'s connection-type watcher and records the path type
+    /// for `cluster_id` as it changes over the connection's lifetime.
+    ///
+    /// The first reported type is also used to count dials that had to fall back off the direct
+    /// path (see [`Metrics::record_dial_fallback`]). Every later transition onto or off of a
+    /// direct path is counted as a hole-punch success or fallback (see [`classify_transition`])
+    /// -- a peer whose path flaps between direct and relay is counted each time it does, not
+    /// just once.
+    fn track_path(&self, cluster_id: String, conn: &Connection) {
+        let Ok(remote_id) = conn.remote_id() else {
+            return;
+        };
+        let Some(endpoint) = self.endpoint.as_ref() else {
+            return;
+        };
+        let Some(watcher) = endpoint.conn_type(remote_id) else {
+            return;
+        };
+        let peer_paths = self.peer_paths.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let mut stream = watcher.stream();
+            let mut previous: Option<ConnectionType> = None;
+            while let Some(conn_type) = stream.next().await {
+                let label = path_label(&conn_type);
+                match classify_transition(previous.as_ref(), &conn_type) {
+                    Some(PathTransition::DialFallback) => metrics.record_dial_fallback(&cluster_id),
+                    Some(PathTransition::HolepunchSuccess) => {
+                        metrics.record_holepunch_success(&cluster_id);
+                    }
+                    Some(PathTransition::HolepunchFallback) => {
+                        metrics.record_holepunch_fallback(&cluster_id);
+                    }
+                    None => {}
+                }
+                previous = Some(conn_type);
+                peer_paths
+                    .write()
+                    .expect("lock poisoned")
+                    .insert(cluster_id.clone(), label.to_string());
+                metrics.record_path(&cluster_id, label);
+            }
+        });
+    }
+
+    /// Spawns a task that records `conn`'s total open duration once it closes.
+    fn track_duration(&self, cluster_id: String, conn: Connection) {
+        let opened = std::time::Instant::now();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            conn.closed().await;
+            metrics.record_connection_duration(&cluster_id, opened.elapsed());
+        });
+    }
+
+    /// Spawns a task that samples `conn`'s QUIC stats every [`STATS_SAMPLE_INTERVAL`], recording
+    /// round-trip time and lost packets for `cluster_id` into [`Self::conn_stats`] and
+    /// [`Metrics::record_conn_stats`] until the connection closes.
+    ///
+    /// Sampling stops as soon as `conn` closes rather than on the next tick, so a connection that
+    /// closes between samples never produces a sample reflecting a connection that's already
+    /// gone; [`Self::conn_stats`] simply keeps whatever was last observed until the next dial to
+    /// the same cluster overwrites it.
+    fn track_stats(&self, cluster_id: String, conn: Connection) {
+        let conn_stats = self.conn_stats.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STATS_SAMPLE_INTERVAL);
+            interval.tick().await; // first tick fires immediately; skip it to sample after traffic flows
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let path = conn.stats().path;
+                        let rtt = conn.rtt();
+                        metrics.record_conn_stats(&cluster_id, rtt, path.lost_packets);
+                        conn_stats.write().expect("lock poisoned").insert(
+                            cluster_id.clone(),
+                            ConnQuality {
+                                rtt_seconds: rtt.as_secs_f64(),
+                                lost_packets: path.lost_packets,
+                            },
+                        );
+                    }
+                    _ = conn.closed() => break,
+                }
+            }
+        });
+    }
+
+    /// Builds a snapshot of the proxy's current view of the mesh for the status API.
+    pub fn status(&self) -> ClusterStatusResponse {
+        ClusterStatusResponse::build(
+            &self.discovery,
+            self.peer_paths(),
+            self.active_relay(),
+            self.conn_stats(),
+            self.endpoint.is_none(),
+        )
+    }
+
+    /// The relay this proxy's endpoint currently reports as its home relay, if any. `None` when
+    /// this proxy is degraded (see [`ProxyConfig::allow_degraded`]), in addition to whenever iroh
+    /// hasn't settled on a home relay yet. See [`crate::relay`]'s module docs for how iroh picks
+    /// among multiple configured relays.
+    pub fn active_relay(&self) -> Option<String> {
+        self.endpoint
+            .as_ref()?
+            .addr()
+            .relay_url()
+            .map(ToString::to_string)
+    }
+}
+
+/// Probes every cluster `discovery` currently knows about, in order, returning `true` on the
+/// first one that answers. Always `false` when `endpoint` is `None` -- a degraded proxy (see
+/// [`ProxyConfig::allow_degraded`]) has nothing to probe with.
+async fn probe_any_peer(
+    discovery: &DiscoveryManager,
+    endpoint: Option<&Endpoint>,
+    alpn: &[u8],
+) -> bool {
+    let Some(endpoint) = endpoint else {
+        return false;
+    };
+    for cluster in discovery.list_clusters() {
+        if probe_cluster_reachable(endpoint, alpn, &cluster, READINESS_PROBE_TIMEOUT).await {
+            return true;
+        }
+    }
+    false
+}
+
+fn path_label(conn_type: &ConnectionType) -> &'static str {
+    match conn_type {
+        ConnectionType::Direct(_) => "direct",
+        ConnectionType::Relay(_) => "relay",
+        ConnectionType::Mixed(_, _) => "mixed",
+        ConnectionType::None => "none",
+    }
+}
+
+/// What a connection's path changing from `previous` to `current` means for hole-punch
+/// visibility, decided by [`MeshProxy::track_path`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PathTransition {
+    /// The first observed path for this connection was not direct.
+    DialFallback,
+    /// The connection moved onto a direct path after starting elsewhere.
+    HolepunchSuccess,
+    /// The connection moved off of a direct path it had previously reached.
+    HolepunchFallback,
+}
+
+/// Classifies the transition from `previous` (`None` before any path has been observed for this
+/// connection) to `current`. Returns `None` for a transition that isn't interesting by itself,
+/// e.g. relay to mixed, or a path repeating itself.
+fn classify_transition(
+    previous: Option<&ConnectionType>,
+    current: &ConnectionType,
+) -> Option<PathTransition> {
+    let is_direct = matches!(current, ConnectionType::Direct(_));
+    match previous {
+        None if !is_direct => Some(PathTransition::DialFallback),
+        Some(ConnectionType::Direct(_)) if !is_direct => Some(PathTransition::HolepunchFallback),
+        Some(prev) if !matches!(prev, ConnectionType::Direct(_)) && is_direct => {
+            Some(PathTransition::HolepunchSuccess)
+        }
+        _ => None,
+    }
+}
+
+/// Pairs [`Metrics::record_connection_opened`] with [`Metrics::record_connection_closed`] for
+/// one client connection's spawned forwarding task, so [`Metrics::active_connections`] comes back
+/// down when that task ends for *any* reason -- including a panic partway through [`forward`] or
+/// [`route_and_forward`], which would otherwise unwind straight past a plain end-of-task call to
+/// `record_connection_closed` and leak the gauge forever.
+///
+/// Note for what this doesn't cover: [`start_tcp_proxy`]'s client-facing forwarding and
+/// [`accept_mesh_tunnels`]'s inbound mesh forwarding are both TCP-to-something -- TCP-to-TCP and
+/// TCP-to-iroh-stream respectively -- so in neither case is there a separate iroh connection
+/// lifecycle for this guard to track beyond [`Metrics::active_connections`] itself.
+/// [`MeshProxy::dial_cluster`]'s outbound side is the one place that still hands back a raw
+/// [`iroh::endpoint::Connection`] with nothing tracked here, since a dial's caller -- not this
+/// guard -- owns that connection's lifetime.
+struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl ConnectionGuard {
+    fn new(metrics: Arc<Metrics>) -> Self {
+        metrics.record_connection_opened();
+        Self { metrics }
+    }
+}
+
+impl std::ops::Deref for ConnectionGuard {
+    type Target = Metrics;
+
+    fn deref(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.record_connection_closed();
+    }
+}
+
+/// Per-connection settings [`start_tcp_proxy`] threads from [`ProxyConfig`] into [`forward`],
+/// [`route_and_forward`] and [`splice`], grouped so those functions don't have to take each one
+/// as a separate argument.
+#[derive(Debug)]
+struct ForwardOptions {
+    request_timeout: Option<Duration>,
+    send_proxy_protocol: bool,
+    io_buffer_size: usize,
+    max_retries: u32,
+    /// Rate-limits this connection's "accepted client connection"/"connected to backend" debug
+    /// logs (see [`ProxyConfig::log_sampling`]). Shared with every other in-flight connection, so
+    /// sampling is proxy-wide rather than per connection.
+    log_sampler: Arc<LogSampler>,
+    /// This connection's accept-time trace sampling decision (see [`ProxyConfig::trace_sampling`]
+    /// and [`MeshProxy::set_trace_sample_rate`]): whether [`splice`] opens the detailed
+    /// `splice_streams` span for it. Metrics are recorded for every connection regardless.
+    trace_sampled: bool,
+    /// Caps on the data-plane parsers' attacker-controlled buffers, see [`ProxyConfig::limits`].
+    limits: ProtocolLimits,
+    /// Prebuilt TLS client session originators, keyed by route (see [`ProxyConfig::backend_tls`]
+    /// and [`build_backend_tls_connectors`]).
+    backend_tls: Arc<HashMap<String, BackendTlsConnector>>,
+    /// Set by [`MeshProxy::with_connection_summary_channel`]. [`splice`] sends a
+    /// [`ConnectionSummary`] here once it finishes forwarding this connection.
+    connection_summaries: Option<mpsc::Sender<ConnectionSummary>>,
+    /// Mirror destinations, keyed by route (see [`ProxyConfig::mirror`] and [`maybe_mirror`]).
+    mirror: Arc<HashMap<String, MirrorConfig>>,
+}
+
+/// Looks up `service`'s candidate destinations and applies outlier filtering, distinguishing
+/// [`crate::error::MeshError::CircuitOpen`] (candidates exist but every one is currently ejected)
+/// from an empty pool because no candidate was ever known at all -- the latter is left for the
+/// caller to turn into [`crate::error::MeshError::NoRoute`] (or a fallthrough route), the same as
+/// before outlier detection existed.
+fn discover_service_candidates(
+    discovery: &DiscoveryManager,
+    outlier: Option<&OutlierDetector>,
+    service: &str,
+) -> Result<Vec<ClusterInfo>> {
+    let unfiltered = discovery.find_service(service);
+    let Some(outlier) = outlier else {
+        return Ok(unfiltered);
+    };
+    let filtered = outlier.filter_candidates(unfiltered.clone());
+    if filtered.is_empty() && !unfiltered.is_empty() {
+        return CircuitOpenSnafu { service }.fail();
+    }
+    Ok(filtered)
+}
+
+/// Resolves the backend address a route actually dials, applying
+/// [`ProxyConfig::backend_address_override`] over `routed_addr` (the address `routes` itself
+/// names for `key`) when `overrides` has an entry for `key`.
+fn override_backend_addr(
+    key: &str,
+    routed_addr: SocketAddr,
+    overrides: &HashMap<String, SocketAddr>,
+) -> SocketAddr {
+    overrides.get(key).copied().unwrap_or(routed_addr)
+}
+
+/// Picks the backend `host`'s request should forward to, preferring the longest
+/// [`PathRoute::path_prefix`] in `path_routes` that matches `path` and falling back to `host`'s
+/// plain entry in `routes` when none do (or `path_routes` has no rules for `host` at all, or
+/// `path` couldn't be parsed from the request line). See [`ProxyConfig::path_routes`].
+fn resolve_path_or_host_backend(
+    host: &str,
+    path: Option<&str>,
+    routes: &HashMap<String, SocketAddr>,
+    path_routes: &HashMap<String, Vec<PathRoute>>,
+) -> Option<SocketAddr> {
+    if let Some(path) = path {
+        if let Some(rules) = path_routes.get(host) {
+            let best = rules
+                .iter()
+                .filter(|rule| path.starts_with(rule.path_prefix.as_str()))
+                .max_by_key(|rule| rule.path_prefix.len());
+            if let Some(rule) = best {
+                return Some(rule.backend);
+            }
+        }
+    }
+    routes.get(host).copied()
+}
+
+/// Connects to `primary`, falling back to each of `retries` in order (up to `max_retries` of
+/// them) if it fails, returning the first connection to succeed or `primary`'s error if every
+/// candidate does.
+///
+/// Only a failure to connect at all is retried: once a backend has accepted the TCP connection,
+/// [`splice`] copies bytes in both directions without buffering them, so there's no way to tell
+/// afterwards whether the client already saw a response byte before the backend later failed --
+/// retrying then could replay a side effect the client has already observed the result of.
+async fn connect_backend_with_retry(
+    primary: SocketAddr,
+    retries: &[SocketAddr],
+    max_retries: u32,
+) -> Result<TcpStream> {
+    let primary_err = match TcpStream::connect(primary).await {
+        Ok(stream) => return Ok(stream),
+        Err(err) => err,
+    };
+    for &candidate in retries.iter().take(max_retries as usize) {
+        if let Ok(stream) = TcpStream::connect(candidate).await {
+            return Ok(stream);
+        }
+    }
+    Err(primary_err.into())
+}
+
+/// Opens a connection to `backend_addr` (retrying against `retries` on failure, see
+/// [`connect_backend_with_retry`]) and splices `client` with it, applying `socket` to both sides
+/// and tearing the exchange down if it runs past [`ForwardOptions::request_timeout`].
+///
+/// When [`ForwardOptions::send_proxy_protocol`] is set, a PROXY protocol v2 header naming `peer`
+/// as the original client is written to `backend` before splicing begins (see
+/// [`proxy_protocol`]).
+///
+/// `conn_id` (see [`generate_conn_id`]) is recorded on this connection's span as well as on the
+/// explicit accept/dial events below, so every log line for this connection -- both the
+/// client-facing accept and the backend dial -- carries the same value.
+///
+/// `route_key` is the route name `backend_addr` was resolved from, used to look up
+/// [`ForwardOptions::backend_tls`]; it's `None` for a [`ProxyConfig::listeners`] pinned route,
+/// which bypasses the named route table entirely and so has nothing to look `backend_tls` up by.
+#[instrument(skip(client, socket, retries, metrics), fields(%conn_id))]
+#[allow(clippy::too_many_arguments)]
+async fn forward(
+    mut client: TcpStream,
+    peer: SocketAddr,
+    socket: &SocketOptions,
+    backend_addr: SocketAddr,
+    retries: &[SocketAddr],
+    route_key: Option<&str>,
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+) -> Result<()> {
+    socket.apply(&client)?;
+    let mode = ConnectionMode::TransparentTcp;
+    metrics.record_connection_mode(mode);
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %peer, %mode, "accepted client connection");
+    }
+    let mut backend =
+        connect_backend_with_retry(backend_addr, retries, options.max_retries).await?;
+    socket.apply(&backend)?;
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %backend_addr, "connected to backend");
+    }
+    if options.send_proxy_protocol {
+        let header = proxy_protocol::encode_v2(peer, client.local_addr()?);
+        backend.write_all(&header).await?;
+    }
+    let backend = originate_backend_tls(backend, route_key, &options.backend_tls).await?;
+    let mut backend = maybe_mirror(backend, route_key, &options.mirror, conn_id);
+
+    splice(
+        &mut client,
+        &mut backend,
+        options,
+        metrics,
+        conn_id,
+        peer,
+        route_key,
+    )
+    .await
+}
+
+/// The cluster identity an inbound mesh tunnel or datagram is checked against `authz` for: the
+/// dialing proxy's own claimed [`RouteRequest::source_cluster_id`] when one is available (an
+/// inbound TCP tunnel always has one, even if it's `None`), falling back to the dialing
+/// connection's iroh node id, which won't match a rule scoped to a specific `cluster_id` (see
+/// [`crate::authz`]'s module docs).
+fn accept_side_cluster_identity(conn: &Connection, source_cluster_id: Option<&str>) -> String {
+    match source_cluster_id {
+        Some(cluster_id) => cluster_id.to_string(),
+        None => conn
+            .remote_id()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|_| "unknown".to_string()),
+    }
+}
+
+/// The TCP-tunnel half of [`MeshProxy::accept_mesh_connections`]: reads every
+/// [`RouteRequest`]-prefixed tunnel `conn` sends until it closes, resolving each one's
+/// [`RouteRequest::service`] against `routes` and splicing it with a freshly dialed local
+/// backend (see [`MeshProxy::splice_tcp_with_iroh_stream`]).
+///
+/// Before dialing that backend, checks `authz` against
+/// [`accept_side_cluster_identity`]-and-service, the same way [`MeshProxy::pick_service_cluster`]
+/// checks the egress side of a dial -- a denied tunnel is logged and dropped like any other
+/// per-tunnel failure. An allowed tunnel then competes for a `connection_limit` permit (mirroring
+/// [`MeshProxy::start_tcp_proxy`]'s client-facing connections) and is tracked by a
+/// [`ConnectionGuard`] for the duration of its forwarding, so [`Metrics::active_connections`]
+/// counts inbound mesh traffic the same as client-facing traffic.
+///
+/// A per-tunnel failure -- a denied policy, an unroutable service name, a backend that refuses
+/// the connection, a forwarding error -- is logged and this moves on to the next tunnel rather
+/// than tearing down `conn`, since other tunnels sharing it (see
+/// [`crate::connpool::ConnectionPool`]) are otherwise unaffected. Returns once `conn` itself
+/// closes.
+///
+/// **Simplification**: unlike [`MeshProxy::start_tcp_proxy`], this doesn't apply
+/// [`ProxyConfig::rate_limit`] (there's no per-source-IP notion for a mesh peer the way there is
+/// for a TCP client), backend TLS origination, or mirroring -- an inbound mesh tunnel forwards
+/// to its backend in the clear, unmirrored, same as before this function checked `authz` at all.
+async fn accept_mesh_tunnels(
+    conn: Connection,
+    routes: &HashMap<String, SocketAddr>,
+    io_buffer_size: usize,
+    authz: &RwLock<Option<AuthzPolicy>>,
+    metrics: &Arc<Metrics>,
+    connection_limit: &Option<Arc<Semaphore>>,
+) -> Result<()> {
+    loop {
+        let recv = match conn.accept_uni().await {
+            Ok(recv) => recv,
+            Err(_) => return Ok(()), // the peer closed the connection
+        };
+        let route_request = match RouteRequest::read_from(recv).await {
+            Ok(route_request) => route_request,
+            Err(err) => {
+                warn!(%err, "failed to read RouteRequest on an inbound mesh connection");
+                continue;
+            }
+        };
+        let (send, recv) = match conn.accept_bi().await {
+            Ok(streams) => streams,
+            Err(_) => return Ok(()),
+        };
+        let service = route_request.service;
+        let cluster_id =
+            accept_side_cluster_identity(&conn, route_request.source_cluster_id.as_deref());
+        if let Some(authz) = authz.read().expect("lock poisoned").as_ref() {
+            if !authz.is_allowed(&cluster_id, &service) {
+                metrics.record_authz_denied(&cluster_id, &service);
+                warn!(%cluster_id, %service, "inbound mesh tunnel denied by authz policy");
+                continue;
+            }
+        }
+        let Some(&backend_addr) = routes.get(&service) else {
+            warn!(%service, "inbound mesh tunnel named a service with no configured route");
+            continue;
+        };
+        let permit = match connection_limit {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("connection semaphore is never closed"),
+            ),
+            None => None,
+        };
+        let guard = ConnectionGuard::new(metrics.clone());
+        let mut backend = match TcpStream::connect(backend_addr).await {
+            Ok(backend) => backend,
+            Err(err) => {
+                warn!(%err, %service, %backend_addr, "failed to connect to local backend for an inbound mesh tunnel");
+                continue;
+            }
+        };
+        if let Err(err) =
+            MeshProxy::splice_tcp_with_iroh_stream(&mut backend, send, recv, io_buffer_size).await
+        {
+            warn!(%err, %service, "inbound mesh tunnel forwarding failed");
+        }
+        drop(guard);
+        drop(permit);
+    }
+}
+
+/// The UDP-datagram half of [`MeshProxy::accept_mesh_connections`]: reads every
+/// [`udp_datagram`]-tagged datagram `conn` sends until it closes, resolving each one's service
+/// name against `routes` and forwarding its payload to that local backend over a UDP socket
+/// dedicated to the (connection, service) pair -- opened, and its own reply-relaying task spawned
+/// via [`spawn_udp_reply_relay`], the first time each service name is seen on `conn`.
+///
+/// Checks `authz` the first time each service name is seen on `conn`, against
+/// [`accept_side_cluster_identity`] -- unlike an inbound tunnel's `RouteRequest`, a datagram
+/// carries no claimed cluster id at all, so this always falls back to `conn`'s iroh node id. A
+/// denied service is cached in `denied` so a peer spamming datagrams for it doesn't re-check
+/// `authz` on every single one; nothing is ever forwarded to its backend either way.
+///
+/// Mirrors [`MeshProxy::forward_udp_to_service`]'s dial-side simplification: replies for a
+/// service flow back over whichever single backend socket that service's first datagram opened,
+/// so more than one dial-side UDP listener sharing a connection and forwarding to the same
+/// service would cross-talk on the reply path exactly as that dial side's own clients would.
+async fn accept_mesh_datagrams(
+    conn: Connection,
+    routes: &HashMap<String, SocketAddr>,
+    authz: &RwLock<Option<AuthzPolicy>>,
+    metrics: &Arc<Metrics>,
+) -> Result<()> {
+    let mut backends: HashMap<String, Arc<tokio::net::UdpSocket>> = HashMap::new();
+    let mut denied: std::collections::HashSet<String> = std::collections::HashSet::new();
+    loop {
+        let datagram = match conn.read_datagram().await {
+            Ok(datagram) => datagram,
+            Err(_) => return Ok(()), // the peer closed the connection
+        };
+        let (service, payload) = match udp_datagram::decode(&datagram) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                warn!(%err, "failed to decode an inbound UDP datagram on a mesh connection");
+                continue;
+            }
+        };
+        if denied.contains(service) {
+            continue;
+        }
+        let socket = match backends.get(service) {
+            Some(socket) => socket.clone(),
+            None => {
+                let cluster_id = accept_side_cluster_identity(&conn, None);
+                if let Some(authz) = authz.read().expect("lock poisoned").as_ref() {
+                    if !authz.is_allowed(&cluster_id, service) {
+                        metrics.record_authz_denied(&cluster_id, service);
+                        warn!(%cluster_id, %service, "inbound mesh datagram denied by authz policy");
+                        denied.insert(service.to_string());
+                        continue;
+                    }
+                }
+                let Some(&backend_addr) = routes.get(service) else {
+                    warn!(%service, "inbound mesh datagram named a service with no configured route");
+                    continue;
+                };
+                let socket = match connect_udp_backend(backend_addr).await {
+                    Ok(socket) => Arc::new(socket),
+                    Err(err) => {
+                        warn!(%err, %service, %backend_addr, "failed to reach local backend for an inbound mesh datagram");
+                        continue;
+                    }
+                };
+                backends.insert(service.to_string(), socket.clone());
+                spawn_udp_reply_relay(conn.clone(), service.to_string(), socket.clone());
+                socket
+            }
+        };
+        if let Err(err) = socket.send(payload).await {
+            warn!(%err, %service, "failed to forward an inbound mesh datagram to its local backend");
+        }
+    }
+}
+
+/// Binds an ephemeral local UDP socket and connects it to `backend_addr`, so
+/// [`accept_mesh_datagrams`] and [`spawn_udp_reply_relay`] can use `send`/`recv` on it without
+/// naming an address on every call.
+async fn connect_udp_backend(backend_addr: SocketAddr) -> Result<tokio::net::UdpSocket> {
+    let bind_addr: SocketAddr = if backend_addr.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = tokio::net::UdpSocket::bind(bind_addr).await?;
+    socket.connect(backend_addr).await?;
+    Ok(socket)
+}
+
+/// Relays every datagram `socket` receives back to `conn`, tagged for `service` with
+/// [`udp_datagram::encode`], until either `socket` errors or `conn` closes. Spawned once per
+/// (connection, service) pair by [`accept_mesh_datagrams`], the first time it opens a backend
+/// socket for that service.
+fn spawn_udp_reply_relay(conn: Connection, service: String, socket: Arc<tokio::net::UdpSocket>) {
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD_BYTES];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(_) => return,
+            };
+            let frame = match udp_datagram::encode(&service, &buf[..len]) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    warn!(%err, %service, "failed to encode a reply UDP datagram");
+                    continue;
+                }
+            };
+            if conn.send_datagram(frame).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Writes a structured HTTP error response for `err` onto `client`, for one of the HTTP-mode
+/// forwarding paths ([`route_and_forward_over`], [`route_and_forward_with_strategy`]) that failed
+/// before any bytes were spliced to a backend. The response carries a JSON body
+/// (`{"error": ..., "reason": ...}`) and an `X-Iroh-Mesh-Error` header, both derived from
+/// [`MeshError::reason_code`], so operators can alert and debug on a stable code instead of
+/// [`MeshError`]'s prose message.
+///
+/// A failure to write the response itself (the client may already have gone away) is logged and
+/// otherwise ignored -- the caller drops the connection either way once this returns.
+async fn write_http_error_response<C: AsyncWrite + Unpin>(client: &mut C, err: &MeshError) {
+    let status = err.http_status();
+    let reason = err.reason_code();
+    let body = serde_json::json!({ "error": err.to_string(), "reason": reason }).to_string();
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         X-Iroh-Mesh-Error: {reason}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        http_status_reason_phrase(status),
+        body.len(),
+    );
+    if let Err(write_err) = client.write_all(response.as_bytes()).await {
+        debug!(%write_err, "failed to write structured error response to client");
+    }
+}
+
+/// Reason phrase for the closed set of statuses [`MeshError::http_status`] can produce.
+fn http_status_reason_phrase(status: u16) -> &'static str {
+    match status {
+        403 => "Forbidden",
+        404 => "Not Found",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Reads `client`'s first request far enough to learn its `Host` header (and, if `path_routes`
+/// has rules for that host, its request path -- see [`ProxyConfig::path_routes`]), routes it to
+/// the matching backend, and splices the rest of the connection to it verbatim.
+///
+/// The connection stays bound to the route its first request picked for its whole lifetime:
+/// later requests pipelined into the bytes already read alongside the first one are checked
+/// against that route and the connection is closed if their `Host` differs, but requests that
+/// arrive after the socket has been handed off to the backend are not re-parsed (see
+/// [`httpsniff`] for why).
+///
+/// If the first request is a protocol upgrade (e.g. a WebSocket handshake), the bytes after its
+/// header block aren't further HTTP and aren't scanned for a Host change -- they're forwarded to
+/// the backend as-is and the connection switches straight to raw splicing.
+///
+/// `conn_id` (see [`generate_conn_id`]) is recorded on this connection's span and its explicit
+/// accept/dial events, the same as [`forward`].
+#[instrument(
+    skip(client, routes, retry_routes, overrides, path_routes, socket, metrics),
+    fields(%conn_id)
+)]
+#[allow(clippy::too_many_arguments)]
+async fn route_and_forward(
+    client: TcpStream,
+    peer: SocketAddr,
+    routes: &HashMap<String, SocketAddr>,
+    retry_routes: &HashMap<String, Vec<SocketAddr>>,
+    overrides: &HashMap<String, SocketAddr>,
+    path_routes: &HashMap<String, Vec<PathRoute>>,
+    socket: &SocketOptions,
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+) -> Result<()> {
+    socket.apply(&client)?;
+    let mode = ConnectionMode::Http;
+    metrics.record_connection_mode(mode);
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %peer, %mode, "accepted client connection");
+    }
+    let local_addr = client.local_addr()?;
+    route_and_forward_over(
+        client,
+        peer,
+        local_addr,
+        routes,
+        retry_routes,
+        overrides,
+        path_routes,
+        socket,
+        options,
+        metrics,
+        conn_id,
+    )
+    .await
+}
+
+/// Like [`route_and_forward`], but first terminates TLS on `client` using `tls_acceptor` (see
+/// [`ProxyConfig::tls`]), and routes the decrypted stream instead of the raw bytes. SNI from the
+/// handshake isn't used to pick a route -- the decrypted request's `Host` header, read exactly
+/// like the plaintext path, is the single source of truth a route is picked from.
+#[instrument(
+    skip(
+        client,
+        routes,
+        retry_routes,
+        overrides,
+        path_routes,
+        socket,
+        tls_acceptor,
+        metrics
+    ),
+    fields(%conn_id)
+)]
+#[allow(clippy::too_many_arguments)]
+async fn route_and_forward_tls(
+    client: TcpStream,
+    peer: SocketAddr,
+    routes: &HashMap<String, SocketAddr>,
+    retry_routes: &HashMap<String, Vec<SocketAddr>>,
+    overrides: &HashMap<String, SocketAddr>,
+    path_routes: &HashMap<String, Vec<PathRoute>>,
+    socket: &SocketOptions,
+    tls_acceptor: &tokio_rustls::TlsAcceptor,
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+) -> Result<()> {
+    socket.apply(&client)?;
+    let mode = ConnectionMode::Http;
+    metrics.record_connection_mode(mode);
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %peer, %mode, "accepted client connection");
+    }
+    let local_addr = client.local_addr()?;
+    let client = tls_acceptor.accept(client).await?;
+    route_and_forward_over(
+        client,
+        peer,
+        local_addr,
+        routes,
+        retry_routes,
+        overrides,
+        path_routes,
+        socket,
+        options,
+        metrics,
+        conn_id,
+    )
+    .await
+}
+
+/// Shared implementation behind [`route_and_forward`] and [`route_and_forward_tls`], generic
+/// over whether `client` is a raw [`TcpStream`] or a TLS stream wrapping one. `local_addr` is
+/// `client`'s address before any TLS wrapping, since a TLS stream doesn't expose it directly.
+///
+/// The bytes [`httpsniff::read_host_header`] consumed while sniffing the `Host` header are
+/// replayed onto `client` through [`PeekStream`] before splicing, so they reach the backend
+/// exactly once and in order regardless of whether the connection turned out to be HTTP at all --
+/// a protocol that never produces a header terminator is spliced with everything it already sent
+/// still intact.
+#[allow(clippy::too_many_arguments)]
+async fn route_and_forward_over<C>(
+    mut client: C,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    routes: &HashMap<String, SocketAddr>,
+    retry_routes: &HashMap<String, Vec<SocketAddr>>,
+    overrides: &HashMap<String, SocketAddr>,
+    path_routes: &HashMap<String, Vec<PathRoute>>,
+    socket: &SocketOptions,
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut backend, host, prefix) = match route_and_forward_setup(
+        &mut client,
+        peer,
+        local_addr,
+        routes,
+        retry_routes,
+        overrides,
+        path_routes,
+        socket,
+        options,
+        conn_id,
+    )
+    .await
+    {
+        Ok(setup) => setup,
+        Err(err) => {
+            write_http_error_response(&mut client, &err).await;
+            return Err(err);
+        }
+    };
+
+    let mut client = PeekStream::new(client, prefix);
+    splice(
+        &mut client,
+        &mut backend,
+        options,
+        metrics,
+        conn_id,
+        peer,
+        Some(&host),
+    )
+    .await
+}
+
+/// The fallible steps of [`route_and_forward_over`] that happen before any bytes are spliced to
+/// a backend: sniffing the client's `Host` header (and request path, for `path_routes`),
+/// resolving them to a route, dialing the backend and originating backend TLS if configured.
+/// Split out so [`route_and_forward_over`] can send a structured HTTP error response for anything
+/// that fails here -- something only safe to do before splicing starts, since a splice already in
+/// progress may have written backend bytes to the client that an HTTP response layered on top
+/// would corrupt.
+#[allow(clippy::too_many_arguments)]
+async fn route_and_forward_setup<C>(
+    client: &mut C,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    routes: &HashMap<String, SocketAddr>,
+    retry_routes: &HashMap<String, Vec<SocketAddr>>,
+    overrides: &HashMap<String, SocketAddr>,
+    path_routes: &HashMap<String, Vec<PathRoute>>,
+    socket: &SocketOptions,
+    options: &ForwardOptions,
+    conn_id: &str,
+) -> Result<(BackendStream, String, Vec<u8>)>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let parsed = httpsniff::read_host_header(client, options.limits.max_header_bytes).await?;
+    let host = parsed.host.context(NoRouteSnafu {
+        target: "<no Host header>",
+    })?;
+    if !parsed.is_upgrade {
+        if let Some(found) = httpsniff::find_mismatched_host(&parsed.trailing, &host) {
+            return HostChangedSnafu {
+                expected: host,
+                found,
+            }
+            .fail();
+        }
+    }
+    let backend_addr =
+        resolve_path_or_host_backend(&host, parsed.path.as_deref(), routes, path_routes)
+            .context(NoRouteSnafu { target: &host })?;
+    let backend_addr = override_backend_addr(&host, backend_addr, overrides);
+    let empty_retries = Vec::new();
+    let retries = retry_routes.get(&host).unwrap_or(&empty_retries);
+
+    let mut backend =
+        connect_backend_with_retry(backend_addr, retries, options.max_retries).await?;
+    socket.apply(&backend)?;
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %backend_addr, "connected to backend");
+    }
+    if options.send_proxy_protocol {
+        let header = proxy_protocol::encode_v2(peer, local_addr);
+        backend.write_all(&header).await?;
+    }
+    let backend = originate_backend_tls(backend, Some(&host), &options.backend_tls).await?;
+    let backend = maybe_mirror(backend, Some(&host), &options.mirror, conn_id);
+
+    let mut prefix = parsed.head;
+    prefix.extend_from_slice(&parsed.trailing);
+    Ok((backend, host, prefix))
+}
+
+/// Reads from `client` until `strategy` extracts a [`RoutingKey`] from the bytes seen so far,
+/// looping over partial reads the same way [`httpsniff::read_host_header`] does. Returns the key
+/// alongside everything read, so it can be replayed onto the connection through [`PeekStream`]
+/// once routing is done.
+///
+/// Fails with [`crate::error::MeshError::RoutingKeyNotFound`] if `strategy` hasn't found a key
+/// within `max_peek_bytes` (see [`RoutingStrategy::max_peek_bytes`]), including if the
+/// connection closes first.
+async fn peek_routing_key<C: AsyncRead + Unpin>(
+    client: &mut C,
+    strategy: &dyn RoutingStrategy,
+    orig_dst: Option<SocketAddr>,
+    max_peek_bytes: usize,
+) -> Result<(RoutingKey, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(key) = strategy.extract_key(&buf, orig_dst) {
+            return Ok((key, buf));
+        }
+        ensure!(
+            buf.len() < max_peek_bytes,
+            RoutingKeyNotFoundSnafu {
+                limit: max_peek_bytes,
+            }
+        );
+        let n = client.read(&mut chunk).await?;
+        if n == 0 {
+            return RoutingKeyNotFoundSnafu {
+                limit: max_peek_bytes,
+            }
+            .fail();
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Like [`route_and_forward`], but extracts the routing key through `strategy` (see
+/// [`crate::routing::RoutingStrategy`]) instead of hardcoding `Host` header parsing. Used for
+/// every [`RoutingStrategyConfig`] variant except the default [`RoutingStrategyConfig::Host`],
+/// which keeps [`route_and_forward`]'s original path unchanged (see [`crate::routing`]'s module
+/// docs for why).
+///
+/// `orig_dst` is the connection's pre-NAT destination (see [`crate::socket::original_dst`]),
+/// looked up by the caller when [`crate::config::ProxyConfig::enable_interception`] is set;
+/// `None` otherwise, which every strategy but [`crate::routing::OriginalDestinationPortStrategy`]
+/// ignores anyway.
+#[instrument(
+    skip(client, strategy, routes, retry_routes, overrides, socket, metrics),
+    fields(%conn_id)
+)]
+#[allow(clippy::too_many_arguments)]
+async fn route_and_forward_with_strategy(
+    mut client: TcpStream,
+    peer: SocketAddr,
+    orig_dst: Option<SocketAddr>,
+    strategy: &dyn RoutingStrategy,
+    routes: &HashMap<String, SocketAddr>,
+    retry_routes: &HashMap<String, Vec<SocketAddr>>,
+    overrides: &HashMap<String, SocketAddr>,
+    socket: &SocketOptions,
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+) -> Result<()> {
+    socket.apply(&client)?;
+    let mode = strategy.connection_mode();
+    metrics.record_connection_mode(mode);
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %peer, %mode, "accepted client connection");
+    }
+    let local_addr = client.local_addr()?;
+    let max_peek_bytes = strategy.max_peek_bytes(&options.limits);
+    let setup = route_and_forward_with_strategy_setup(
+        &mut client,
+        peer,
+        local_addr,
+        orig_dst,
+        strategy,
+        routes,
+        retry_routes,
+        overrides,
+        socket,
+        options,
+        conn_id,
+        max_peek_bytes,
+    )
+    .await;
+    let (mut backend, key, peeked) = match setup {
+        Ok(setup) => setup,
+        Err(err) => {
+            write_http_error_response(&mut client, &err).await;
+            return Err(err);
+        }
+    };
+
+    let mut client = PeekStream::new(client, peeked);
+    splice(
+        &mut client,
+        &mut backend,
+        options,
+        metrics,
+        conn_id,
+        peer,
+        Some(key.as_str()),
+    )
+    .await
+}
+
+/// The fallible steps of [`route_and_forward_with_strategy`] that happen before any bytes are
+/// spliced to a backend, mirroring [`route_and_forward_setup`] but keyed by `strategy`'s
+/// [`RoutingKey`] instead of hardcoding `Host`.
+#[allow(clippy::too_many_arguments)]
+async fn route_and_forward_with_strategy_setup(
+    client: &mut TcpStream,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    orig_dst: Option<SocketAddr>,
+    strategy: &dyn RoutingStrategy,
+    routes: &HashMap<String, SocketAddr>,
+    retry_routes: &HashMap<String, Vec<SocketAddr>>,
+    overrides: &HashMap<String, SocketAddr>,
+    socket: &SocketOptions,
+    options: &ForwardOptions,
+    conn_id: &str,
+    max_peek_bytes: usize,
+) -> Result<(BackendStream, RoutingKey, Vec<u8>)> {
+    let (key, peeked) = peek_routing_key(client, strategy, orig_dst, max_peek_bytes).await?;
+    if !strategy.is_upgrade(&peeked) {
+        if let Some(found) = strategy.find_mismatched_route(&peeked, &key) {
+            return RouteChangedSnafu {
+                expected: key.to_string(),
+                found: found.to_string(),
+            }
+            .fail();
+        }
+    }
+    let backend_addr = *routes.get(key.as_str()).context(NoRouteSnafu {
+        target: key.as_str(),
+    })?;
+    let backend_addr = override_backend_addr(key.as_str(), backend_addr, overrides);
+    let empty_retries = Vec::new();
+    let retries = retry_routes.get(key.as_str()).unwrap_or(&empty_retries);
+
+    let mut backend =
+        connect_backend_with_retry(backend_addr, retries, options.max_retries).await?;
+    socket.apply(&backend)?;
+    if options.log_sampler.allow() {
+        debug!(%conn_id, %backend_addr, "connected to backend");
+    }
+    if options.send_proxy_protocol {
+        let header = proxy_protocol::encode_v2(peer, local_addr);
+        backend.write_all(&header).await?;
+    }
+    let backend = originate_backend_tls(backend, Some(key.as_str()), &options.backend_tls).await?;
+    let backend = maybe_mirror(backend, Some(key.as_str()), &options.mirror, conn_id);
+
+    Ok((backend, key, peeked))
+}
+
+/// Splices `client` and `backend` together until either side closes, tearing both down and
+/// recording [`Metrics::record_request_timeout`] if [`ForwardOptions::request_timeout`] elapses
+/// first.
+///
+/// Each direction is copied independently through a reusable [`ForwardOptions::io_buffer_size`]
+/// -byte buffer (see [`copy_and_shutdown`]), rather than [`tokio::io::copy_bidirectional`]'s
+/// fixed internal buffer, so callers handling high-throughput flows can size it for fewer,
+/// larger syscalls. A direction that hits EOF shuts down its write half and keeps the other
+/// direction running, matching `copy_bidirectional`'s half-close behavior; the whole splice only
+/// finishes once both directions have done so.
+///
+/// The two directions are joined with [`tokio::try_join`] rather than a bare `select!` on
+/// purpose: racing them naively on first-to-finish would tear down the still-open direction the
+/// moment the other side does a graceful half-close, which is exactly the case this function is
+/// meant to keep forwarding through. An abrupt disconnect (a read or write failing outright, e.g.
+/// a reset) is a genuine `Err`, not a clean finish, and `try_join` already bails out -- cancelling
+/// the other direction and returning immediately -- the instant either side produces one; no
+/// additional signalling between the two tasks is needed for that case. [`ConnectionGuard`] is
+/// what guarantees the caller's connection count still comes back down no matter which path out
+/// of this function, or a panic, ends a spliced connection's spawned task.
+///
+/// The copy runs inside a `splice_streams` span when [`ForwardOptions::trace_sampled`] is set for
+/// this connection (see [`ProxyConfig::trace_sampling`]), and inside a disabled
+/// [`tracing::Span::none`] otherwise -- either way every byte still moves and every metric below
+/// is still recorded, so an unsampled connection is only cheaper to trace, never handled
+/// differently.
+///
+/// When [`ForwardOptions::connection_summaries`] is set, a [`ConnectionSummary`] for this
+/// connection is sent to it once forwarding finishes, dropped (and counted in
+/// [`Metrics::connection_summaries_dropped`]) if the channel is full. `peer` and `route_key`
+/// become [`ConnectionSummary::source`] and [`ConnectionSummary::target_service`]; `conn_id`
+/// becomes [`ConnectionSummary::conn_id`]. No summary is sent for a connection that ends in an
+/// `Err` here -- see [`ConnectionSummary`]'s docs on scope.
+#[allow(clippy::too_many_arguments)]
+async fn splice<C, B>(
+    client: &mut C,
+    backend: &mut B,
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+    peer: SocketAddr,
+    route_key: Option<&str>,
+) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let started_at = SystemTime::now();
+    let (client_read, client_write) = tokio::io::split(&mut *client);
+    let (backend_read, backend_write) = tokio::io::split(&mut *backend);
+    let span = if options.trace_sampled {
+        tracing::debug_span!("splice_streams")
+    } else {
+        tracing::Span::none()
+    };
+    let copy = async {
+        tokio::try_join!(
+            copy_and_shutdown(client_read, backend_write, options.io_buffer_size),
+            copy_and_shutdown(backend_read, client_write, options.io_buffer_size),
+        )
+    }
+    .instrument(span);
+    let Some(request_timeout) = options.request_timeout else {
+        let (bytes_sent, bytes_received) = copy.await?;
+        metrics.record_bytes(route_key, bytes_sent, bytes_received);
+        send_connection_summary(
+            options,
+            metrics,
+            conn_id,
+            peer,
+            route_key,
+            bytes_sent,
+            bytes_received,
+            started_at,
+            ConnectionOutcome::Closed,
+        );
+        return Ok(());
+    };
+    match tokio::time::timeout(request_timeout, copy).await {
+        Ok(result) => {
+            let (bytes_sent, bytes_received) = result?;
+            metrics.record_bytes(route_key, bytes_sent, bytes_received);
+            send_connection_summary(
+                options,
+                metrics,
+                conn_id,
+                peer,
+                route_key,
+                bytes_sent,
+                bytes_received,
+                started_at,
+                ConnectionOutcome::Closed,
+            );
+        }
+        Err(_) => {
+            metrics.record_request_timeout();
+            let _ = client.shutdown().await;
+            let _ = backend.shutdown().await;
+            // The two directions' byte counts aren't available here -- they were consumed along
+            // with `copy` when it timed out -- so a timed-out connection's summary reports zero
+            // for both. Distinguishing this from a genuinely empty connection is what
+            // `ConnectionOutcome::TimedOut` is for.
+            send_connection_summary(
+                options,
+                metrics,
+                conn_id,
+                peer,
+                route_key,
+                0,
+                0,
+                started_at,
+                ConnectionOutcome::TimedOut,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Builds a [`ConnectionSummary`] and sends it to [`ForwardOptions::connection_summaries`], if
+/// set, dropping it (and counting the drop in [`Metrics::connection_summaries_dropped`]) if the
+/// channel is full. A no-op when no channel is registered.
+#[allow(clippy::too_many_arguments)]
+fn send_connection_summary(
+    options: &ForwardOptions,
+    metrics: &Metrics,
+    conn_id: &str,
+    peer: SocketAddr,
+    route_key: Option<&str>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    started_at: SystemTime,
+    outcome: ConnectionOutcome,
+) {
+    let Some(sender) = &options.connection_summaries else {
+        return;
+    };
+    let summary = ConnectionSummary {
+        conn_id: conn_id.to_string(),
+        source: peer,
+        target_service: route_key.map(str::to_string),
+        target_cluster: None,
+        bytes_sent,
+        bytes_received,
+        started_at,
+        ended_at: SystemTime::now(),
+        outcome,
+    };
+    if sender.try_send(summary).is_err() {
+        metrics.connection_summaries_dropped.inc();
+    }
+}
+
+/// Either side of a connected backend: a plain [`TcpStream`], or one wrapped in a TLS client
+/// session by [`originate_backend_tls`] (see [`ProxyConfig::backend_tls`]).
+///
+/// [`splice`] is generic over the backend type, so this only exists to give the three forwarding
+/// functions a single concrete type to hand it regardless of which branch they took -- same
+/// reason [`PeekStream`] exists on the client side.
+enum BackendStream {
+    /// Forwarding bytes straight through, unencrypted.
+    Plain(TcpStream),
+    /// Originating TLS to the backend. Boxed since [`tokio_rustls::client::TlsStream`] is
+    /// considerably larger than [`TcpStream`], and most connections don't use this variant.
+    Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+    /// Wrapping another variant to also copy every byte written to it -- the client-to-backend
+    /// direction only -- onto `sender`, for [`ProxyConfig::mirror`]. See [`maybe_mirror`].
+    Mirrored(Box<BackendStream>, mpsc::Sender<Vec<u8>>),
+}
+
+impl AsyncRead for BackendStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+            Self::Mirrored(inner, _) => Pin::new(inner.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for BackendStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+            Self::Mirrored(inner, sender) => {
+                let result = Pin::new(inner.as_mut()).poll_write(cx, buf);
+                if let std::task::Poll::Ready(Ok(written)) = &result {
+                    // `try_send` rather than blocking on the mirror keeping up: a slow or
+                    // backed-up mirror destination should lose mirrored bytes, never stall the
+                    // primary connection this is mirrored from.
+                    let _ = sender.try_send(buf[..*written].to_vec());
+                }
+                result
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+            Self::Mirrored(inner, _) => Pin::new(inner.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+            Self::Mirrored(inner, _) => Pin::new(inner.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Wraps `backend` to also copy every byte written to it -- the client-to-backend direction,
+/// exactly what a client sent -- to `mirror`'s destination for `route_key`, if any, and if this
+/// connection was sampled per its [`MirrorConfig::sample_rate`]. See [`ProxyConfig::mirror`].
+///
+/// The mirror destination is dialed once, in a spawned task started here; [`BackendStream`]'s
+/// `Mirrored` variant only ever hands it bytes through a bounded channel with `try_send`; see its
+/// `poll_write` impl for why. Nothing the mirror destination sends back is ever read, per
+/// [`ProxyConfig::mirror`]'s "responses discarded" contract; the mirror connection is simply
+/// dropped, along with the spawned task, once the primary connection's [`BackendStream`] is.
+fn maybe_mirror(
+    backend: BackendStream,
+    route_key: Option<&str>,
+    mirror: &HashMap<String, MirrorConfig>,
+    conn_id: &str,
+) -> BackendStream {
+    let Some(cfg) = route_key.and_then(|key| mirror.get(key)) else {
+        return backend;
+    };
+    if !sampled(cfg.sample_rate) {
+        return backend;
+    }
+    let (sender, receiver) = mpsc::channel(MIRROR_CHANNEL_CAPACITY);
+    spawn_mirror_writer(cfg.addr, receiver, conn_id.to_string());
+    BackendStream::Mirrored(Box::new(backend), sender)
+}
+
+/// Number of mirrored writes buffered for a mirror destination that's momentarily slower than
+/// the primary connection, before [`BackendStream::poll_write`] starts dropping them.
+const MIRROR_CHANNEL_CAPACITY: usize = 64;
+
+/// Decides whether one connection should be mirrored, given [`MirrorConfig::sample_rate`]. Same
+/// exact-at-the-extremes idiom as [`crate::tracesample::TraceSampler::sample`], so a rate of
+/// `0.0` or `1.0` never draws a random number.
+fn sampled(rate: f64) -> bool {
+    if rate <= 0.0 {
+        false
+    } else if rate >= 1.0 {
+        true
+    } else {
+        rand::Rng::random::<f64>(&mut rand::rng()) < rate
+    }
+}
+
+/// Dials `addr` and forwards every chunk received on `receiver` to it until either the primary
+/// connection's [`BackendStream`] is dropped (closing `receiver`) or a write fails, logging
+/// either outcome rather than surfacing it anywhere, since a failing mirror never affects the
+/// primary connection (see [`ProxyConfig::mirror`]). Nothing sent back by `addr` is ever read.
+fn spawn_mirror_writer(addr: SocketAddr, mut receiver: mpsc::Receiver<Vec<u8>>, conn_id: String) {
+    tokio::spawn(async move {
+        let mut stream = match TcpStream::connect(addr).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                debug!(%conn_id, %addr, %err, "mirror destination unreachable, dropping mirrored traffic");
+                return;
+            }
+        };
+        while let Some(chunk) = receiver.recv().await {
+            if let Err(err) = stream.write_all(&chunk).await {
+                debug!(
+                    %conn_id, %addr, %err,
+                    "mirror write failed, dropping the rest of this connection's mirrored traffic"
+                );
+                break;
+            }
+        }
+    });
+}
+
+/// A prebuilt TLS client session originator for one route's backend (see
+/// [`ProxyConfig::backend_tls`]), built once up front by [`build_backend_tls_connectors`] so a
+/// bad CA path fails before any connection is accepted rather than on the first dial to it.
+#[derive(Clone)]
+pub(crate) struct BackendTlsConnector {
+    connector: tokio_rustls::TlsConnector,
+    server_name: rustls::pki_types::ServerName<'static>,
+}
+
+impl fmt::Debug for BackendTlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendTlsConnector")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Accepts every certificate without verifying it, for [`BackendTlsConfig::insecure_skip_verify`].
+/// Mirrors `iroh_relay::client`'s `NoCertVerifier`, used the same way for the relay client's own
+/// test-only dangerous config.
+#[derive(Debug)]
+struct NoCertVerifier;
+
+impl rustls::client::danger::ServerCertVerifier for NoCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer,
+        _intermediates: &[rustls::pki_types::CertificateDer],
+        _server_name: &rustls::pki_types::ServerName,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Builds the [`tokio_rustls::TlsConnector`] and target server name `cfg` describes, eagerly
+/// loading and parsing `cfg.ca_path` (when set) so an unreadable or unparsable CA certificate is
+/// reported here rather than on the first connection that would have used it.
+pub(crate) async fn build_backend_tls_connector(
+    cfg: &BackendTlsConfig,
+) -> Result<BackendTlsConnector> {
+    let server_name =
+        rustls::pki_types::ServerName::try_from(cfg.server_name.clone()).map_err(|err| {
+            InvalidConfigSnafu {
+                reason: format!(
+                    "invalid backend tls server_name {:?}: {err}",
+                    cfg.server_name
+                ),
+            }
+            .build()
+        })?;
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("ring supports the default TLS protocol versions");
+    let config = if cfg.insecure_skip_verify {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoCertVerifier))
+            .with_no_client_auth()
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        match &cfg.ca_path {
+            Some(ca_path) => {
+                let certs = load_certs(ca_path).await?;
+                let (added, ignored) = roots.add_parsable_certificates(certs);
+                snafu::ensure!(
+                    ignored == 0 && added > 0,
+                    InvalidConfigSnafu {
+                        reason: format!("no valid certificate found in {}", ca_path.display()),
+                    }
+                );
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(BackendTlsConnector {
+        connector: tokio_rustls::TlsConnector::from(Arc::new(config)),
+        server_name,
+    })
+}
+
+/// Builds [`BackendTlsConnector`]s for every route in `backend_tls`, failing on the first one
+/// whose CA path doesn't load (see [`build_backend_tls_connector`]) -- done once up front, the
+/// same as [`build_tls_acceptor`], so a bad config is reported before the proxy starts accepting
+/// connections rather than surfacing as a dial failure against a real client.
+async fn build_backend_tls_connectors(
+    backend_tls: &HashMap<String, BackendTlsConfig>,
+) -> Result<HashMap<String, BackendTlsConnector>> {
+    let mut connectors = HashMap::with_capacity(backend_tls.len());
+    for (route, cfg) in backend_tls {
+        connectors.insert(route.clone(), build_backend_tls_connector(cfg).await?);
+    }
+    Ok(connectors)
+}
+
+/// Wraps `backend` in a TLS client session when `route_key` has an entry in `connectors`,
+/// otherwise forwards it unchanged. See [`ProxyConfig::backend_tls`].
+async fn originate_backend_tls(
+    backend: TcpStream,
+    route_key: Option<&str>,
+    connectors: &HashMap<String, BackendTlsConnector>,
+) -> Result<BackendStream> {
+    let Some(tls) = route_key.and_then(|key| connectors.get(key)) else {
+        return Ok(BackendStream::Plain(backend));
+    };
+    let stream = tls
+        .connector
+        .connect(tls.server_name.clone(), backend)
+        .await?;
+    Ok(BackendStream::Tls(Box::new(stream)))
+}
+
+/// Builds a [`tokio_rustls::TlsAcceptor`] from `tls`'s certificate and key, for terminating TLS
+/// on accepted connections (see [`ProxyConfig::tls`], [`route_and_forward_tls`], and
+/// [`crate::agent::AgentConfig::api_tls`]).
+pub(crate) async fn build_tls_acceptor(tls: &TlsConfig) -> Result<tokio_rustls::TlsAcceptor> {
+    let certs = load_certs(&tls.cert_path).await?;
+    let key = load_private_key(&tls.key_path).await?;
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("ring supports the default TLS protocol versions")
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .map_err(|err| {
+        InvalidConfigSnafu {
+            reason: format!("invalid tls certificate or key: {err}"),
+        }
+        .build()
+    })?;
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Reads a PEM-encoded certificate chain from `path`.
+async fn load_certs(path: &Path) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    let bytes = tokio::fs::read(path).await?;
+    let certs: std::io::Result<Vec<_>> =
+        rustls_pemfile::certs(&mut std::io::Cursor::new(bytes)).collect();
+    Ok(certs?)
+}
+
+/// Reads a single PEM-encoded private key (PKCS#1, PKCS#8 or SEC1) from `path`.
+async fn load_private_key(path: &Path) -> Result<rustls::pki_types::PrivateKeyDer<'static>> {
+    let bytes = tokio::fs::read(path).await?;
+    let mut reader = std::io::Cursor::new(bytes);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::Pkcs1Key(key)) => return Ok(key.into()),
+            Some(rustls_pemfile::Item::Pkcs8Key(key)) => return Ok(key.into()),
+            Some(rustls_pemfile::Item::Sec1Key(key)) => return Ok(key.into()),
+            Some(_) => continue,
+            None => {
+                return InvalidConfigSnafu {
+                    reason: format!("no private key found in {}", path.display()),
+                }
+                .fail();
+            }
+        }
+    }
+}
+
+/// Copies `reader` into `writer` through a reusable `buf_size`-byte buffer until `reader` hits
+/// EOF, then shuts `writer` down to propagate the half-close. Returns the number of bytes copied,
+/// for [`ConnectionSummary::bytes_sent`]/[`ConnectionSummary::bytes_received`].
+///
+/// Each read waits for the previous write to finish, so a fast reader can't run ahead of a slow
+/// writer and buffer unboundedly -- the buffer's size is the only slack between the two sides.
+async fn copy_and_shutdown(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    buf_size: usize,
+) -> std::io::Result<u64> {
+    let mut buf = vec![0u8; buf_size];
+    let mut copied = 0u64;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n]).await?;
+        copied += n as u64;
+    }
+    writer.shutdown().await?;
+    Ok(copied)
+}
+
+mod status {
+    use std::sync::Arc;
+
+    use hyper::{Request, Response, StatusCode, body::Incoming};
+    use n0_watcher::Watchable;
+
+    use super::*;
+    use crate::{
+        httputil::{Body, json_response, query_param_is, text_response},
+        topology::TopologyGraph,
+        versioninfo::VersionInfo,
+    };
+
+    type State = (
+        Arc<DiscoveryManager>,
+        Arc<RwLock<HashMap<String, String>>>,
+        Watchable<bool>,
+        Arc<VersionInfo>,
+        Option<Endpoint>,
+        Arc<RwLock<HashMap<String, ConnQuality>>>,
+    );
+
+    pub(super) async fn handle(req: Request<Incoming>, state: State) -> Response<Body> {
+        let (discovery, peer_paths, ready, version, endpoint, conn_stats) = state;
+        let degraded = endpoint.is_none();
+        match req.uri().path().trim_matches('/') {
+            "healthz" => json_response(StatusCode::OK, &serde_json::json!({"status": "ok"})),
+            "status" => json_response(
+                StatusCode::OK,
+                &ClusterStatusResponse::build(
+                    &discovery,
+                    peer_paths.read().expect("lock poisoned").clone(),
+                    endpoint
+                        .as_ref()
+                        .and_then(|endpoint| endpoint.addr().relay_url().map(ToString::to_string)),
+                    conn_stats.read().expect("lock poisoned").clone(),
+                    degraded,
+                ),
+            ),
+            "version" => json_response(StatusCode::OK, version.as_ref()),
+            "topology" => {
+                let graph = TopologyGraph::build(
+                    discovery.list_clusters(),
+                    &peer_paths.read().expect("lock poisoned"),
+                );
+                if query_param_is(req.uri().query(), "format", "dot") {
+                    text_response(StatusCode::OK, "text/vnd.graphviz", graph.to_dot())
+                } else {
+                    json_response(StatusCode::OK, &graph)
+                }
+            }
+            "readyz" if ready.get() => json_response(
+                StatusCode::OK,
+                &serde_json::json!({"ready": true, "degraded": degraded}),
+            ),
+            "readyz" => json_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &serde_json::json!({"ready": false, "degraded": degraded}),
+            ),
+            _ => json_response(
+                StatusCode::NOT_FOUND,
+                &serde_json::json!({"error": "not found"}),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use iroh::RelayMode;
+
+    use super::*;
+    use crate::{
+        authz::{AuthzEffect, AuthzPolicy, AuthzRule},
+        discovery::ClusterRegistration,
+        outlier::OutlierDetectionConfig,
+        ratelimit::ConnectionRateLimitConfig,
+    };
+
+    #[tokio::test]
+    async fn accept_hook_rejects_a_specific_node_id_and_allows_others() -> Result<()> {
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let proxy_addr = endpoint.addr();
+
+        let blocked_client = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let blocked_id = blocked_client.id();
+        let allowed_client = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery).with_accept_hook(
+            Arc::new(move |conn: &Connection| {
+                if conn.remote_id().ok() == Some(blocked_id) {
+                    return NoRouteSnafu {
+                        target: "blocked node id",
+                    }
+                    .fail();
+                }
+                Ok(())
+            }),
+        );
+
+        let accept_blocked = tokio::spawn({
+            let endpoint = proxy
+                .endpoint()
+                .expect("test proxy always binds a real endpoint")
+                .clone();
+            async move {
+                endpoint
+                    .accept()
+                    .await
+                    .expect("endpoint closed")
+                    .await
+                    .expect("proxy should accept the incoming connection")
+            }
+        });
+        blocked_client
+            .connect(proxy_addr.clone(), MESH_ALPN)
+            .await
+            .expect("blocked client should still complete the QUIC handshake");
+        let blocked_conn = accept_blocked.await.expect("accept task panicked");
+        assert!(
+            proxy.check_accept_hook(&blocked_conn).is_err(),
+            "the hook should reject the blocked node id"
+        );
+
+        let accept_allowed = tokio::spawn({
+            let endpoint = proxy
+                .endpoint()
+                .expect("test proxy always binds a real endpoint")
+                .clone();
+            async move {
+                endpoint
+                    .accept()
+                    .await
+                    .expect("endpoint closed")
+                    .await
+                    .expect("proxy should accept the incoming connection")
+            }
+        });
+        allowed_client
+            .connect(proxy_addr, MESH_ALPN)
+            .await
+            .expect("allowed client should complete the QUIC handshake");
+        let allowed_conn = accept_allowed.await.expect("accept task panicked");
+        assert!(
+            proxy.check_accept_hook(&allowed_conn).is_ok(),
+            "the hook should allow a node id it wasn't configured to reject"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_reports_direct_path() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::bind(config, discovery).await?;
+
+        proxy.dial_cluster("cluster-b").await.unwrap();
+        accept_task.await.expect("accept task panicked").unwrap();
+
+        let path = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(path) = proxy.peer_paths().get("cluster-b") {
+                    return path.clone();
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for a connection path to be reported");
+
+        assert_eq!(path, "direct");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_tcp_to_service_tunnels_bytes_over_a_dialed_clusters_bi_stream() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let response = b"response-after-client-closed".to_vec();
+        let backend_response = response.clone();
+        let accept_task = tokio::spawn(async move {
+            let incoming = remote.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let route_request_recv = conn.accept_uni().await.expect("no uni stream arrived");
+            let route_request = RouteRequest::read_from(route_request_recv)
+                .await
+                .expect("failed to decode RouteRequest");
+            let (mut send, mut recv) = conn.accept_bi().await.expect("no bi stream arrived");
+            let request = recv
+                .read_to_end(1024)
+                .await
+                .expect("failed to read request");
+            send.write_all(&backend_response)
+                .await
+                .expect("failed to write response");
+            send.finish().expect("failed to finish stream");
+            (route_request, request, conn)
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["checkout".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::bind(config, discovery).await?);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let (mut accepted, _) = listener.accept().await.expect("accept failed");
+                proxy
+                    .forward_tcp_to_service(&mut accepted, "checkout")
+                    .await
+                    .expect("forward_tcp_to_service failed");
+            }
+        });
+
+        let mut client = TcpStream::connect(listen_addr).await?;
+        client.write_all(b"request-then-close").await?;
+        client.shutdown().await?;
+
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, response,
+            "the still-open remote-to-client direction should keep forwarding after the \
+             client closed its own write half"
+        );
+
+        let (route_request, request, remote_conn) =
+            accept_task.await.expect("accept task panicked");
+        assert_eq!(request, b"request-then-close");
+        assert_eq!(route_request.source_cluster_id, None);
+        assert!(route_request.original_client_addr.is_some());
+        drop(remote_conn);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_udp_to_service_relays_datagrams_both_ways() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let echo_task = tokio::spawn(async move {
+            let incoming = remote.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let datagram = conn.read_datagram().await.expect("no datagram arrived");
+            let (service, payload) = udp_datagram::decode(&datagram).expect("decode failed");
+            assert_eq!(service, "dns");
+            let reply = udp_datagram::encode("dns", payload).expect("encode failed");
+            conn.send_datagram(reply).expect("send_datagram failed");
+            // Keep the connection open until the reply has had a chance to actually reach the
+            // peer -- dropping it here would close it (and, with it, the reply's delivery)
+            // before the datagram is necessarily flushed.
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            payload.to_vec()
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["dns".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::bind(config, discovery).await?);
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let listen_addr = socket.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let _ = proxy.forward_udp_to_service(&socket, "dns").await;
+            }
+        });
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        client.connect(listen_addr).await?;
+        client.send(b"who is example.com?").await?;
+
+        let mut buf = [0u8; 1024];
+        let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+            .await
+            .expect("timed out waiting for a reply")?;
+        assert_eq!(&buf[..len], b"who is example.com?");
+
+        let received = tokio::time::timeout(Duration::from_secs(5), echo_task)
+            .await
+            .expect("echo task timed out")
+            .expect("echo task panicked");
+        assert_eq!(received, b"who is example.com?");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_tcp_to_service_reaches_a_real_backend_through_the_receiving_clusters_own_accept_loop()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let remote_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote_endpoint.addr();
+        let remote_config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("checkout", backend.addr());
+        let remote_proxy = MeshProxy::from_endpoint(
+            remote_config,
+            remote_endpoint,
+            Arc::new(DiscoveryManager::new()),
+        );
+        tokio::spawn(async move { remote_proxy.run_on_many(Vec::new()).await });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["checkout".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::bind(config, discovery).await?);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let (mut accepted, _) = listener.accept().await.expect("accept failed");
+                proxy
+                    .forward_tcp_to_service(&mut accepted, "checkout")
+                    .await
+                    .expect("forward_tcp_to_service failed");
+            }
+        });
+
+        let mut client = TcpStream::connect(listen_addr).await?;
+        client.write_all(b"through the accept loop").await?;
+        client.shutdown().await?;
+
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(echoed, b"through the accept loop");
+        assert_eq!(backend.connection_count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_udp_to_service_reaches_a_real_backend_through_the_receiving_clusters_own_accept_loop()
+    -> Result<()> {
+        let backend = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let backend_addr = backend.local_addr()?;
+        let backend_task = tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let (len, from) = backend.recv_from(&mut buf).await.expect("recv failed");
+            backend
+                .send_to(&buf[..len], from)
+                .await
+                .expect("send failed");
+        });
+
+        let remote_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote_endpoint.addr();
+        let remote_config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("dns", backend_addr);
+        let remote_proxy = MeshProxy::from_endpoint(
+            remote_config,
+            remote_endpoint,
+            Arc::new(DiscoveryManager::new()),
+        );
+        tokio::spawn(async move { remote_proxy.run_on_many(Vec::new()).await });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["dns".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::bind(config, discovery).await?);
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let listen_addr = socket.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let _ = proxy.forward_udp_to_service(&socket, "dns").await;
+            }
+        });
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        client.connect(listen_addr).await?;
+        client.send(b"through the accept loop").await?;
+
+        let mut buf = [0u8; 1024];
+        let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+            .await
+            .expect("timed out waiting for a reply")?;
+        assert_eq!(&buf[..len], b"through the accept loop");
+
+        tokio::time::timeout(Duration::from_secs(5), backend_task)
+            .await
+            .expect("backend task timed out")
+            .expect("backend task panicked");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn an_inbound_mesh_tunnel_a_receiving_clusters_authz_policy_denies_never_reaches_the_backend()
+    -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let remote_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote_endpoint.addr();
+        let remote_config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("checkout", backend.addr())
+            .with_authz(AuthzPolicy {
+                default: AuthzEffect::Allow,
+                rules: vec![AuthzRule {
+                    cluster: None,
+                    service: Some("checkout".to_string()),
+                    effect: AuthzEffect::Deny,
+                }],
+            });
+        let remote_proxy = MeshProxy::from_endpoint(
+            remote_config,
+            remote_endpoint,
+            Arc::new(DiscoveryManager::new()),
+        );
+        tokio::spawn(async move { remote_proxy.run_on_many(Vec::new()).await });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["checkout".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::bind(config, discovery).await?);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let (mut accepted, _) = listener.accept().await.expect("accept failed");
+                let _ = proxy
+                    .forward_tcp_to_service(&mut accepted, "checkout")
+                    .await;
+            }
+        });
+
+        let mut client = TcpStream::connect(listen_addr).await?;
+        client.write_all(b"should never reach the backend").await?;
+        client.shutdown().await?;
+
+        let mut echoed = Vec::new();
+        use tokio::io::AsyncReadExt;
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut echoed))
+            .await
+            .expect("timed out waiting for the connection to be dropped")?;
+        assert!(
+            echoed.is_empty(),
+            "a denied tunnel should never be echoed back"
+        );
+        assert_eq!(
+            backend.connection_count(),
+            0,
+            "a denied tunnel should never reach the backend"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn an_inbound_mesh_datagram_a_receiving_clusters_authz_policy_denies_never_reaches_the_backend()
+    -> Result<()> {
+        let backend = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let backend_addr = backend.local_addr()?;
+        let backend_task = tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            tokio::time::timeout(Duration::from_secs(2), backend.recv_from(&mut buf)).await
+        });
+
+        let remote_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote_endpoint.addr();
+        let remote_config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("dns", backend_addr)
+            .with_authz(AuthzPolicy {
+                default: AuthzEffect::Allow,
+                rules: vec![AuthzRule {
+                    cluster: None,
+                    service: Some("dns".to_string()),
+                    effect: AuthzEffect::Deny,
+                }],
+            });
+        let remote_proxy = MeshProxy::from_endpoint(
+            remote_config,
+            remote_endpoint,
+            Arc::new(DiscoveryManager::new()),
+        );
+        tokio::spawn(async move { remote_proxy.run_on_many(Vec::new()).await });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["dns".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::bind(config, discovery).await?);
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let listen_addr = socket.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let _ = proxy.forward_udp_to_service(&socket, "dns").await;
+            }
+        });
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        client.connect(listen_addr).await?;
+        client.send(b"should never reach the backend").await?;
+
+        let result = tokio::time::timeout(Duration::from_secs(5), backend_task)
+            .await
+            .expect("backend task timed out")
+            .expect("backend task panicked");
+        assert!(
+            result.is_err(),
+            "a denied datagram should never reach the backend"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_records_dial_and_connection_duration() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::bind(config, discovery).await?;
+
+        let conn = proxy.dial_cluster("cluster-b").await.unwrap();
+        let remote_conn = accept_task.await.expect("accept task panicked").unwrap();
+
+        assert_eq!(
+            proxy.metrics.dial_duration_seconds.count(),
+            1,
+            "dialing once should record exactly one dial duration sample"
+        );
+        let dial_bucket = proxy
+            .metrics
+            .dial_duration_seconds
+            .buckets()
+            .into_iter()
+            .find(|(_, cumulative)| *cumulative >= 1)
+            .map(|(upper_bound, _)| upper_bound)
+            .expect("a loopback dial should land in some bucket");
+        assert!(
+            dial_bucket <= 10.0,
+            "a loopback dial should land well within the configured bucket range, got upper bound {dial_bucket}"
+        );
+
+        conn.close(0u32.into(), b"done");
+        remote_conn.close(0u32.into(), b"done");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if proxy.metrics.connection_duration_seconds.count() == 1 {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for the connection duration to be recorded");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_records_a_trace_exemplar_for_a_sampled_dial() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_trace_sampling(1.0);
+        let proxy = MeshProxy::bind(config, discovery).await?;
+
+        assert!(
+            proxy.dial_trace_exemplars().is_empty(),
+            "no dial has happened yet"
+        );
+
+        let conn = proxy.dial_cluster("cluster-b").await.unwrap();
+        let remote_conn = accept_task.await.expect("accept task panicked").unwrap();
+
+        let exemplars = proxy.dial_trace_exemplars();
+        let exemplar = exemplars
+            .get("cluster-b")
+            .expect("a fully sampled dial should record a trace exemplar");
+        assert!(
+            !exemplar.trace_id.is_empty(),
+            "the exemplar should carry a trace id"
+        );
+        assert_eq!(
+            proxy.metrics.dial_duration_seconds.count(),
+            1,
+            "the same dial should have landed in the histogram too"
+        );
+
+        conn.close(0u32.into(), b"done");
+        remote_conn.close(0u32.into(), b"done");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_records_no_trace_exemplar_when_not_sampled() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        // Default `ProxyConfig` leaves trace sampling off.
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::bind(config, discovery).await?;
+
+        let conn = proxy.dial_cluster("cluster-b").await.unwrap();
+        let remote_conn = accept_task.await.expect("accept task panicked").unwrap();
+
+        assert_eq!(
+            proxy.metrics.dial_duration_seconds.count(),
+            1,
+            "the dial should still land in the histogram"
+        );
+        assert!(
+            proxy.dial_trace_exemplars().is_empty(),
+            "an unsampled dial should record no trace exemplar"
+        );
+
+        conn.close(0u32.into(), b"done");
+        remote_conn.close(0u32.into(), b"done");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_records_an_rtt_sample_after_traffic_flows() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                let conn = incoming.await.unwrap();
+                let (mut send, mut recv) = conn.accept_bi().await.unwrap();
+                let mut buf = [0u8; 5];
+                recv.read_exact(&mut buf).await.unwrap();
+                send.write_all(b"pong").await.unwrap();
+                send.finish().unwrap();
+                conn
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::bind(config, discovery).await?;
+
+        let conn = proxy.dial_cluster("cluster-b").await.unwrap();
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        send.write_all(b"ping!").await.unwrap();
+        send.finish().unwrap();
+        let mut pong = [0u8; 4];
+        recv.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+        let remote_conn = accept_task.await.expect("accept task panicked");
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if proxy.conn_stats().contains_key("cluster-b") {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for an rtt sample to be recorded");
+
+        let status = proxy.status();
+        assert!(
+            status.conn_stats["cluster-b"].rtt_seconds >= 0.0,
+            "a sampled rtt should be a valid non-negative duration"
+        );
+        drop(remote_conn);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn max_streams_per_connection_blocks_a_remote_past_the_configured_cap() -> Result<()> {
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_max_streams_per_connection(2)
+            .with_relay(relay::RelayModeConfig::Disabled)
+            .with_endpoint_discovery(endpoint_discovery::EndpointDiscoveryConfig { dns: false });
+        let proxy = MeshProxy::bind(config, Arc::new(DiscoveryManager::new())).await?;
+        let proxy_addr = proxy
+            .endpoint()
+            .expect("test proxy always binds a real endpoint")
+            .addr();
+
+        let accept_task = tokio::spawn({
+            let endpoint = proxy
+                .endpoint()
+                .expect("test proxy always binds a real endpoint")
+                .clone();
+            async move {
+                let incoming = endpoint.accept().await.expect("endpoint closed");
+                incoming.await.expect("inbound connection failed")
+            }
+        });
+
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let conn = remote
+            .connect(proxy_addr, MESH_ALPN)
+            .await
+            .expect("failed to connect to the proxy's endpoint");
+        let inbound = accept_task.await.expect("accept task panicked");
+
+        // Opening up to the configured cap succeeds promptly.
+        let (mut first_send, _first_recv) = conn.open_bi().await.unwrap();
+        let _second = conn.open_bi().await.unwrap();
+
+        // A third stream is beyond the cap: the remote can't open it until a slot frees up.
+        let blocked = tokio::time::timeout(Duration::from_millis(200), conn.open_bi()).await;
+        assert!(
+            blocked.is_err(),
+            "a third stream should block while the cap of 2 is already held"
+        );
+
+        // Accepting and finishing one of the held streams on the proxy's side frees a slot. The
+        // peer only learns a stream exists once something is actually sent on it (merely opening
+        // one sends nothing), so the client's side has to finish first.
+        first_send.finish().unwrap();
+        let (mut accepted_send, mut accepted_recv) = inbound.accept_bi().await.unwrap();
+        accepted_recv.read_to_end(0).await.unwrap();
+        accepted_send.finish().unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), conn.open_bi())
+            .await
+            .expect("freeing a held stream should unblock opening a new one")
+            .unwrap();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keepalive_timeout_closes_a_connection_the_peer_goes_quiet_on() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        // Our own keepalive interval is set far longer than the timeout below, so it never fires
+        // during this test; the remote's endpoint still sends its own default once-a-second
+        // keepalive ping (see `Endpoint::bind`), which is also far slower than this timeout. The
+        // connection times out from silence well before either ping would have arrived -- the
+        // same outcome a truly unreachable peer would produce.
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_keepalive(Duration::from_secs(60), Duration::from_millis(200));
+        let proxy = MeshProxy::bind(config, discovery).await?;
+
+        let conn = proxy.dial_cluster("cluster-b").await.unwrap();
+        accept_task.await.expect("accept task panicked").unwrap();
+
+        let reason = tokio::time::timeout(Duration::from_secs(5), conn.closed())
+            .await
+            .expect("idle timeout should have closed the connection");
+        assert!(
+            !matches!(reason, iroh::endpoint::ConnectionError::LocallyClosed),
+            "expected the connection to time out rather than be closed locally, got {reason}"
+        );
+
+        Ok(())
+    }
+
+    /// Binds a proxy with `mesh_name` and registers `remote` under `cluster_id` in its
+    /// discovery, returning the proxy.
+    async fn proxy_dialing(
+        mesh_name: Option<&str>,
+        cluster_id: &str,
+        remote_addr: EndpointAddr,
+    ) -> Result<MeshProxy> {
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: cluster_id.to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![mesh_alpn(mesh_name)])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let mut config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        config.mesh_name = mesh_name.map(str::to_string);
+        Ok(MeshProxy::from_endpoint(config, endpoint, discovery))
+    }
+
+    #[tokio::test]
+    async fn a_mesh_name_mismatch_prevents_a_dial_but_matching_names_succeed() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![mesh_alpn(Some("prod"))])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                while let Some(incoming) = remote.accept().await {
+                    tokio::spawn(async move {
+                        let _ = incoming.await;
+                    });
+                }
+            }
+        });
+
+        let mismatched = proxy_dialing(Some("staging"), "cluster-b", remote.addr()).await?;
+        assert!(
+            mismatched.dial_cluster("cluster-b").await.is_err(),
+            "a dial using a different mesh name's ALPN should fail to connect"
+        );
+
+        let matching = proxy_dialing(Some("prod"), "cluster-b", remote.addr()).await?;
+        matching
+            .dial_cluster("cluster-b")
+            .await
+            .expect("a dial using the same mesh name's ALPN should succeed");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn routing_table_watcher_updates_when_a_cluster_registers_a_service() -> Result<()> {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery.clone());
+
+        let mut watcher = proxy.routing_table_watcher();
+        assert_eq!(watcher.get(), RoutingTable::default());
+
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let updated = watcher
+            .updated()
+            .await
+            .expect("watcher should still be connected to a live discovery manager");
+        assert_eq!(updated.routes.get("svc"), Some(&"cluster-a".to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_for_service_resolves_immediately_when_already_advertised() -> Result<()> {
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        let info = proxy
+            .wait_for_service("svc", Duration::from_secs(5))
+            .await
+            .expect("service is already advertised");
+        assert_eq!(info.cluster_id, "cluster-a");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_for_service_resolves_once_advertised_after_the_wait_started() -> Result<()> {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = Arc::new(MeshProxy::from_endpoint(
+            config,
+            endpoint,
+            discovery.clone(),
+        ));
+
+        let waiter = tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.wait_for_service("svc", Duration::from_secs(5)).await }
+        });
+
+        tokio::task::yield_now().await;
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let info = waiter
+            .await
+            .expect("waiter task panicked")
+            .expect("service advertised before the timeout");
+        assert_eq!(info.cluster_id, "cluster-a");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_for_service_times_out_when_no_cluster_ever_advertises_it() -> Result<()> {
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+
+        let err = proxy
+            .wait_for_service("svc", Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::Timeout { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_route_reports_a_static_route_without_touching_discovery() -> Result<()> {
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let backend: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc-a", backend);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+
+        let resolved = proxy.resolve_route(&RoutingKey::new("svc-a"))?;
+        assert_eq!(resolved, ResolvedRoute::Backend(backend));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_route_reports_the_cluster_a_service_would_dial() -> Result<()> {
+        let endpoint_id = iroh_base::SecretKey::generate(&mut rand::rng()).public();
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id,
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        let resolved = proxy.resolve_route(&RoutingKey::new("svc"))?;
+        assert_eq!(
+            resolved,
+            ResolvedRoute::Cluster {
+                cluster_id: "cluster-a".to_string(),
+                endpoint_id,
+                pool_size: 1,
+            }
+        );
+
+        let err = proxy
+            .resolve_route(&RoutingKey::new("no-such-service"))
+            .unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::NoRoute { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_falls_back_to_relay_when_direct_address_is_bogus() -> Result<()> {
+        let _ = tracing_subscriber::fmt::try_init();
+        let (relay_map, relay_url, _relay_guard) = iroh::test_utils::run_relay_server()
+            .await
+            .expect("failed to start test relay");
+
+        let remote = Endpoint::empty_builder(RelayMode::Custom(relay_map.clone()))
+            .insecure_skip_relay_cert_verify(true)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        remote.online().await;
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-c".to_string(),
+            endpoint_id: remote.id(),
+            relay_url: Some(relay_url.to_string()),
+            direct_addresses: vec!["127.0.0.1:1".parse().unwrap()],
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Custom(relay_map))
+            .insecure_skip_relay_cert_verify(true)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        endpoint.online().await;
+        let proxy = MeshProxy::from_endpoint(
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()),
+            endpoint,
+            discovery,
+        );
+
+        proxy.dial_cluster("cluster-c").await.unwrap();
+        accept_task.await.expect("accept task panicked").unwrap();
+
+        assert_eq!(proxy.metrics.dial_relay_fallback.get(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_cluster_resolves_an_endpoint_id_only_cluster_via_discovery() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                incoming.await
+            }
+        });
+
+        // Stands in for a real discovery service (DNS, pkarr, mDNS, ...): seeded directly with
+        // the remote's address rather than learning it from the network, but exercised by the
+        // dialing endpoint exactly as a real discovery mechanism would be.
+        let static_discovery = iroh::discovery::static_provider::StaticProvider::new();
+        static_discovery.add_endpoint_info(remote_addr.clone());
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .discovery(static_discovery)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-d".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let proxy = MeshProxy::from_endpoint(
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()),
+            endpoint,
+            discovery,
+        );
+
+        proxy.dial_cluster("cluster-d").await.unwrap();
+        accept_task.await.expect("accept task panicked").unwrap();
+        Ok(())
+    }
+
+    /// Sends a raw HTTP/1.1 GET request over `addr` and returns `(status, body)`.
+    async fn get(addr: std::net::SocketAddr, path: &str) -> (u16, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).await.unwrap();
+        let mut parts = raw.splitn(2, "\r\n\r\n");
+        let head = parts.next().unwrap_or_default();
+        let body = parts.next().unwrap_or_default().to_string();
+        let status = head
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn topology_json_lists_known_clusters() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let secret_key = iroh_base::SecretKey::generate(&mut rand::rng());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: secret_key.public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let peer_paths = Arc::new(RwLock::new(HashMap::from([(
+            "cluster-a".to_string(),
+            "direct".to_string(),
+        )])));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let version = Arc::new(VersionInfo::new(Some(secret_key.public()), MESH_ALPN));
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        tokio::spawn(httputil::serve_on(
+            listener,
+            (
+                discovery,
+                peer_paths,
+                n0_watcher::Watchable::new(true),
+                version,
+                Some(endpoint),
+                Arc::new(RwLock::new(HashMap::new())),
+            ),
+            status::handle,
+        ));
+
+        let (code, body) = get(addr, "/topology").await;
+        assert_eq!(code, 200);
+        let graph: crate::topology::TopologyGraph = serde_json::from_str(&body).unwrap();
+        assert_eq!(graph.nodes.len(), 1);
+        assert_eq!(graph.nodes[0].cluster_id, "cluster-a");
+        assert!(graph.nodes[0].reachable);
+        assert!(graph.nodes[0].services.is_empty());
+        assert_eq!(graph.edges.len(), 1);
+
+        let (code, body) = get(addr, "/topology?format=dot").await;
+        assert_eq!(code, 200);
+        assert!(body.contains("digraph mesh"));
+        assert!(body.contains("\"cluster-a\""));
+    }
+
+    #[tokio::test]
+    async fn version_reports_the_endpoints_node_id_and_a_non_empty_version() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let peer_paths = Arc::new(RwLock::new(HashMap::new()));
+        let node_id = iroh_base::SecretKey::generate(&mut rand::rng()).public();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let version = Arc::new(VersionInfo::new(Some(node_id), MESH_ALPN));
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        tokio::spawn(httputil::serve_on(
+            listener,
+            (
+                discovery,
+                peer_paths,
+                n0_watcher::Watchable::new(true),
+                version,
+                Some(endpoint),
+                Arc::new(RwLock::new(HashMap::new())),
+            ),
+            status::handle,
+        ));
+
+        let (code, body) = get(addr, "/version").await;
+        assert_eq!(code, 200);
+        let info: VersionInfo = serde_json::from_str(&body).unwrap();
+        assert_eq!(info.node_id, Some(node_id));
+        assert_eq!(info.alpn, String::from_utf8_lossy(MESH_ALPN));
+        assert!(!info.version.is_empty());
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_ok_regardless_of_readiness() {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let peer_paths = Arc::new(RwLock::new(HashMap::new()));
+        let version = Arc::new(VersionInfo::new(None, MESH_ALPN));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(httputil::serve_on(
+            listener,
+            (
+                discovery,
+                peer_paths,
+                n0_watcher::Watchable::new(false),
+                version,
+                None,
+                Arc::new(RwLock::new(HashMap::new())),
+            ),
+            status::handle,
+        ));
+
+        let (code, body) = get(addr, "/healthz").await;
+        assert_eq!(code, 200);
+        assert_eq!(body, r#"{"status":"ok"}"#);
+    }
+
+    #[tokio::test]
+    async fn http_mode_routes_by_host_and_rejects_a_host_change_mid_connection() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let request = b"GET / HTTP/1.1\r\nHost: svc-a\r\nConnection: close\r\n\r\nhello".to_vec();
+        client.write_all(&request).await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, request,
+            "the full request, not just the body, should reach the backend"
+        );
+
+        let mut mismatched = tokio::net::TcpStream::connect(listen_addr).await?;
+        mismatched
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: svc-a\r\n\r\nGET / HTTP/1.1\r\nHost: svc-b\r\n\r\n",
+            )
+            .await?;
+        let mut raw = Vec::new();
+        mismatched.read_to_end(&mut raw).await?;
+        let raw = String::from_utf8(raw).expect("error response is valid utf-8");
+        assert!(
+            raw.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "a mid-connection host change should be rejected with a structured error instead of \
+             being forwarded: {raw}"
+        );
+        assert!(raw.contains("X-Iroh-Mesh-Error: no-route\r\n"), "{raw}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn path_routes_picks_the_longest_matching_prefix() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let api_backend = crate::testing::EchoBackend::spawn().await?;
+        let api_v2_backend = crate::testing::EchoBackend::spawn().await?;
+        let web_backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("example.com", web_backend.addr())
+            .with_path_route("example.com", PathRoute::new("/api", api_backend.addr()))
+            .with_path_route(
+                "example.com",
+                PathRoute::new("/api/v2", api_v2_backend.addr()),
+            );
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        // "/api/v2/widgets" matches both "/api" and the longer "/api/v2" -- the latter should win.
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let request =
+            b"GET /api/v2/widgets HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n"
+                .to_vec();
+        client.write_all(&request).await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(echoed, request);
+        assert_eq!(
+            api_v2_backend.connection_count(),
+            1,
+            "the longest matching prefix should have received the connection"
+        );
+        assert_eq!(api_backend.connection_count(), 0);
+        assert_eq!(web_backend.connection_count(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_path_with_no_matching_path_route_falls_back_to_the_host_only_route() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let api_backend = crate::testing::EchoBackend::spawn().await?;
+        let web_backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("example.com", web_backend.addr())
+            .with_path_route("example.com", PathRoute::new("/api", api_backend.addr()));
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let request =
+            b"GET /home HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n".to_vec();
+        client.write_all(&request).await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(echoed, request);
+        assert_eq!(
+            web_backend.connection_count(),
+            1,
+            "a path matching no rule should fall back to the host-only route"
+        );
+        assert_eq!(api_backend.connection_count(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_and_transparent_tcp_connections_are_tagged_with_different_connection_modes()
+    -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let tcp_backend = crate::testing::EchoBackend::spawn().await?;
+        let tcp_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let tcp_config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", tcp_backend.addr());
+        let tcp_proxy =
+            MeshProxy::from_endpoint(tcp_config, tcp_endpoint, Arc::new(DiscoveryManager::new()));
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let tcp_addr = tcp_listener.local_addr()?;
+        let tcp_metrics = tcp_proxy.metrics.clone();
+        tokio::spawn(async move { tcp_proxy.run_on(tcp_listener).await });
+        tokio::net::TcpStream::connect(tcp_addr)
+            .await?
+            .write_all(b"ping")
+            .await?;
+
+        let http_backend = crate::testing::EchoBackend::spawn().await?;
+        let http_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let http_config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", http_backend.addr());
+        let http_proxy = MeshProxy::from_endpoint(
+            http_config,
+            http_endpoint,
+            Arc::new(DiscoveryManager::new()),
+        );
+        let http_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let http_addr = http_listener.local_addr()?;
+        let http_metrics = http_proxy.metrics.clone();
+        tokio::spawn(async move { http_proxy.run_on(http_listener).await });
+        tokio::net::TcpStream::connect(http_addr)
+            .await?
+            .write_all(b"GET / HTTP/1.1\r\nHost: svc-a\r\nConnection: close\r\n\r\n")
+            .await?;
+
+        while tcp_metrics.connection_mode_transparent_tcp.get() == 0 {
+            tokio::task::yield_now().await;
+        }
+        while http_metrics.connection_mode_http.get() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(tcp_metrics.connection_mode_http.get(), 0);
+        assert_eq!(http_metrics.connection_mode_transparent_tcp.get(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sni_routing_rejects_a_client_hello_peek_just_over_its_configured_cap() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        const CAP: usize = 64;
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_routing(RoutingStrategyConfig::Sni)
+            .with_limits(ProtocolLimits {
+                max_sni_peek: CAP,
+                ..Default::default()
+            })
+            .with_route("example.test", "127.0.0.1:1".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        // A TLS record header claiming a record far larger than fits in CAP, and no complete
+        // ClientHello within it -- extract_key keeps returning None until the cap is hit.
+        let mut oversized = vec![0x16, 0x03, 0x01, 0xff, 0xff];
+        oversized.extend(std::iter::repeat_n(0u8, CAP + 1 - oversized.len()));
+        client.write_all(&oversized).await?;
+        let mut raw = Vec::new();
+        client.read_to_end(&mut raw).await?;
+        let raw = String::from_utf8(raw).expect("error response is valid utf-8");
+        assert!(
+            raw.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "a connection whose routing key never resolves within the configured cap should be \
+             rejected with a structured error instead of being forwarded: {raw}"
+        );
+        assert!(raw.contains("X-Iroh-Mesh-Error: no-route\r\n"), "{raw}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_mode_reports_cluster_unreachable_when_the_backend_refuses_the_connection()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Binding then immediately dropping a listener frees the port while leaving nothing
+        // there to accept a connection, so a later connect to it is refused (see
+        // `a_retryable_route_falls_over_to_its_next_backend_when_the_first_refuses`).
+        let dead_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let dead_addr = dead_listener.local_addr()?;
+        drop(dead_listener);
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", dead_addr);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: svc-a\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut raw = Vec::new();
+        client.read_to_end(&mut raw).await?;
+        let raw = String::from_utf8(raw).expect("error response is valid utf-8");
+        assert!(
+            raw.starts_with("HTTP/1.1 502 Bad Gateway\r\n"),
+            "a refused backend connection should be reported as cluster-unreachable: {raw}"
+        );
+        assert!(
+            raw.contains("X-Iroh-Mesh-Error: cluster-unreachable\r\n"),
+            "{raw}"
+        );
+        assert!(raw.contains(r#""reason":"cluster-unreachable""#), "{raw}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backend_address_override_redirects_a_route_to_a_different_address() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        // Nothing listens on this address; if the override didn't take effect, the connection
+        // would fail to forward instead of echoing the request back.
+        let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", unreachable)
+            .with_backend_address_override("svc-a", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let request = b"GET / HTTP/1.1\r\nHost: svc-a\r\nConnection: close\r\n\r\nhello".to_vec();
+        client.write_all(&request).await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, request,
+            "the override address should be dialed instead of the route's configured, \
+             unreachable one"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_mirrored_route_tees_request_bytes_to_the_mirror_without_touching_the_primary_response()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let mirror_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mirror_addr = mirror_listener.local_addr()?;
+        let mirrored = tokio::spawn(async move {
+            let (mut conn, _) = mirror_listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            conn.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", backend.addr())
+            .with_mirror("svc-a", MirrorConfig::new(mirror_addr));
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let request = b"GET / HTTP/1.1\r\nHost: svc-a\r\nConnection: close\r\n\r\nhello".to_vec();
+        client.write_all(&request).await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, request,
+            "the primary connection's response should be unaffected by mirroring"
+        );
+
+        let received = tokio::time::timeout(Duration::from_secs(5), mirrored)
+            .await
+            .expect("the mirror destination should receive the mirrored bytes promptly")
+            .expect("the mirror task should not panic");
+        assert_eq!(
+            received, request,
+            "the mirror destination should receive a copy of the request bytes"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn header_routing_strategy_routes_by_a_configured_header_and_rejects_a_change()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_routing(RoutingStrategyConfig::Header("x-routing-key".to_string()))
+            .with_route("tenant-a", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let request =
+            b"GET / HTTP/1.1\r\nx-routing-key: tenant-a\r\nConnection: close\r\n\r\nhello".to_vec();
+        client.write_all(&request).await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, request,
+            "the full request should reach the backend picked by the custom header"
+        );
+
+        let mut no_route = tokio::net::TcpStream::connect(listen_addr).await?;
+        no_route
+            .write_all(b"GET / HTTP/1.1\r\nHost: whatever\r\n\r\n")
+            .await?;
+        no_route.shutdown().await?;
+        let mut raw = Vec::new();
+        no_route.read_to_end(&mut raw).await?;
+        let raw = String::from_utf8(raw).expect("error response is valid utf-8");
+        assert!(
+            raw.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "a request with no matching routing header should get a structured no-route error: \
+             {raw}"
+        );
+        assert!(raw.contains("X-Iroh-Mesh-Error: no-route\r\n"), "{raw}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readiness_requires_a_reachable_peer_and_flips_back_when_none_remain() -> Result<()> {
+        let discovery = Arc::new(DiscoveryManager::new());
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_require_peer_for_ready(true);
+        let proxy = MeshProxy::bind(config, discovery.clone()).await?;
+
+        assert!(
+            !proxy.is_ready(),
+            "a proxy requiring a peer for readiness should start unready"
+        );
+        assert!(!proxy.probe_readiness().await, "no peer is registered yet");
+        assert!(!proxy.is_ready());
+
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                while let Some(incoming) = remote.accept().await {
+                    tokio::spawn(async move {
+                        let _ = incoming.await;
+                    });
+                }
+            }
+        });
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        assert!(
+            proxy.probe_readiness().await,
+            "the now-reachable loopback peer should satisfy readiness"
+        );
+        assert!(proxy.is_ready());
+
+        remote.close().await;
+        accept_task.abort();
+        assert!(
+            !proxy.probe_readiness().await,
+            "readiness should flip back once the only known peer becomes unreachable"
+        );
+        assert!(!proxy.is_ready());
+
+        Ok(())
+    }
+
+    /// Minimal [`tracing::Subscriber`] that records the value of every event's `conn_id` field,
+    /// for asserting that the client-accept and backend-dial log lines of one connection share
+    /// the same id.
+    #[derive(Clone, Default)]
+    struct ConnIdRecorder {
+        conn_ids: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl tracing::Subscriber for ConnIdRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct ConnIdVisitor(Option<String>);
+            impl tracing::field::Visit for ConnIdVisitor {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    if field.name() == "conn_id" {
+                        self.0 = Some(format!("{value:?}"));
+                    }
+                }
+            }
+            let mut visitor = ConnIdVisitor(None);
+            event.record(&mut visitor);
+            if let Some(conn_id) = visitor.0 {
+                self.conn_ids.lock().expect("lock poisoned").push(conn_id);
+            }
+        }
+
+        fn enter(&self, _span: &tracing::span::Id) {}
+        fn exit(&self, _span: &tracing::span::Id) {}
+    }
+
+    #[tokio::test]
+    async fn conn_id_correlates_the_client_accept_and_backend_dial_log_lines() -> Result<()> {
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+
+        let recorder = ConnIdRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+
+        let conn_ids = recorder.conn_ids.lock().expect("lock poisoned");
+        assert_eq!(
+            conn_ids.len(),
+            2,
+            "expected one event from the client accept and one from the backend dial, got {conn_ids:?}"
+        );
+        assert_eq!(
+            conn_ids[0], conn_ids[1],
+            "both sides of the connection should log the same conn_id: {conn_ids:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_mode_splices_raw_frames_after_an_upgrade_handshake() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let handshake =
+            b"GET /ws HTTP/1.1\r\nHost: svc-a\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+        client.write_all(handshake).await?;
+        let mut echoed_handshake = vec![0u8; handshake.len()];
+        client.read_exact(&mut echoed_handshake).await?;
+        assert_eq!(echoed_handshake, handshake);
+
+        // Raw WebSocket frames flowing both ways after the handshake, not further HTTP: the
+        // echo backend bounces them back verbatim, same as it would the handshake bytes above.
+        for frame in [&b"client-frame-1"[..], b"client-frame-2"] {
+            client.write_all(frame).await?;
+            let mut buf = vec![0u8; frame.len()];
+            client.read_exact(&mut buf).await?;
+            assert_eq!(buf, frame);
+        }
+
+        Ok(())
+    }
+
+    /// Minimal [`tracing::Subscriber`] that records the name of every span opened, for asserting
+    /// whether [`splice`]'s `splice_streams` span was actually entered.
+    #[derive(Clone, Default)]
+    struct SpanNameRecorder {
+        names: Arc<std::sync::Mutex<Vec<&'static str>>>,
+    }
+
+    impl tracing::Subscriber for SpanNameRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            self.names
+                .lock()
+                .expect("lock poisoned")
+                .push(span.metadata().name());
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, _event: &tracing::Event<'_>) {}
+
+        fn enter(&self, _span: &tracing::span::Id) {}
+        fn exit(&self, _span: &tracing::span::Id) {}
+    }
+
+    async fn splice_streams_span_count(trace_sampling: Option<f64>) -> Result<usize> {
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let mut config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr());
+        config.trace_sampling = trace_sampling;
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+
+        let recorder = SpanNameRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+
+        let names = recorder.names.lock().expect("lock poisoned");
+        Ok(names
+            .iter()
+            .filter(|name| **name == "splice_streams")
+            .count())
+    }
+
+    #[tokio::test]
+    async fn a_trace_sample_rate_of_zero_emits_no_detailed_splice_span() -> Result<()> {
+        assert_eq!(splice_streams_span_count(Some(0.0)).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_trace_sample_rate_of_one_always_emits_the_detailed_splice_span() -> Result<()> {
+        assert_eq!(splice_streams_span_count(Some(1.0)).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_trace_sampling_configured_emits_no_detailed_splice_span() -> Result<()> {
+        assert_eq!(splice_streams_span_count(None).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bytes_pipelined_right_at_the_header_terminator_are_forwarded_intact() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("svc-a", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let head = b"GET /svc HTTP/1.1\r\nHost: svc-a\r\n\r\n";
+        // Not a second HTTP request: a non-HTTP payload landing in the very same read() as the
+        // header terminator, exercising the byte right at the sniff boundary rather than one
+        // arriving in a later, separate read.
+        let body = b"\x00\x01not-http-at-all\x02\x03";
+        let mut sent = head.to_vec();
+        sent.extend_from_slice(body);
+        client.write_all(&sent).await?;
+
+        let mut echoed = vec![0u8; sent.len()];
+        client.read_exact(&mut echoed).await?;
+        assert_eq!(echoed, sent);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_on_many_forwards_each_listener_to_its_own_pinned_route() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend_a = crate::testing::EchoBackend::spawn().await?;
+        let backend_b = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        // No routes configured at all: every connection must reach its backend via the listener
+        // it was accepted on, not `ProxyConfig::mode`'s usual routing.
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+
+        let listener_a = TcpListener::bind("127.0.0.1:0").await?;
+        let addr_a = listener_a.local_addr()?;
+        let listener_b = TcpListener::bind("127.0.0.1:0").await?;
+        let addr_b = listener_b.local_addr()?;
+        tokio::spawn(async move {
+            proxy
+                .run_on_many(vec![
+                    (listener_a, Some(backend_a.addr())),
+                    (listener_b, Some(backend_b.addr())),
+                ])
+                .await
+        });
+
+        for addr in [addr_a, addr_b] {
+            let mut client = tokio::net::TcpStream::connect(addr).await?;
+            client.write_all(b"hello").await?;
+            client.shutdown().await?;
+            let mut echoed = Vec::new();
+            client.read_to_end(&mut echoed).await?;
+            assert_eq!(echoed, b"hello");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dual_stack_accepts_both_ipv4_and_ipv6_clients() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("[::]:0".parse().unwrap())
+            .with_route("svc", backend.addr())
+            .with_dual_stack(true);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = socket::bind_listener("[::]:0".parse().unwrap(), true)?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        for addr in [
+            SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, port)),
+        ] {
+            let mut client = tokio::net::TcpStream::connect(addr).await?;
+            client.write_all(b"ping").await?;
+            let mut buf = [0u8; 4];
+            client.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping", "connection over {addr} should be forwarded");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn max_connections_queues_connections_past_the_limit() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", backend.addr())
+            .with_max_connections(2);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        // Fill both permits with long-lived connections.
+        let mut held = Vec::new();
+        for _ in 0..2 {
+            let mut conn = tokio::net::TcpStream::connect(listen_addr).await?;
+            conn.write_all(b"ping").await?;
+            let mut buf = [0u8; 4];
+            conn.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping");
+            held.push(conn);
+        }
+
+        // A third connection is accepted by the OS listen backlog but the proxy never calls
+        // `accept` for it while both permits are held, so it sees no response yet.
+        let mut queued = tokio::net::TcpStream::connect(listen_addr).await?;
+        queued.write_all(b"ping").await?;
+        let mut buf = [0u8; 4];
+        let timed_out = tokio::time::timeout(
+            std::time::Duration::from_millis(200),
+            queued.read_exact(&mut buf),
+        )
+        .await
+        .is_err();
+        assert!(
+            timed_out,
+            "a connection past the limit should not be forwarded yet"
+        );
+
+        // Freeing a permit lets the proxy accept and forward the queued connection.
+        held.pop();
+        queued.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limiting_refuses_excess_connections_from_one_source_ip_but_not_another()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Loopback covers the whole 127.0.0.0/8 range on Linux, so binding the client side to
+        // distinct addresses within it is enough to simulate two different source IPs without
+        // any real network setup.
+        async fn connect_from(local_ip: &str, target: SocketAddr) -> std::io::Result<TcpStream> {
+            let socket = tokio::net::TcpSocket::new_v4()?;
+            socket.bind(format!("{local_ip}:0").parse().unwrap())?;
+            socket.connect(target).await
+        }
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", backend.addr())
+            .with_rate_limit(ConnectionRateLimitConfig {
+                rate_per_second: 0.0,
+                burst: 2,
+                whitelist: Vec::new(),
+            });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        for _ in 0..2 {
+            let mut conn = connect_from("127.0.0.2", listen_addr).await?;
+            conn.write_all(b"ping").await?;
+            let mut buf = [0u8; 4];
+            conn.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping", "within the burst should be forwarded");
+        }
+
+        let mut throttled = connect_from("127.0.0.2", listen_addr).await?;
+        let mut buf = [0u8; 4];
+        // The proxy drops the connection outright without reading from it, so the client sees
+        // either a clean EOF or a reset (if its own write raced ahead of that drop) -- either
+        // way, never the "ping" echo a forwarded connection would produce.
+        match throttled.read(&mut buf).await {
+            Ok(0) => {}
+            Err(_) => {}
+            Ok(n) => panic!(
+                "a third connection from the same source ip should be refused, not forwarded \
+                 ({n} bytes read)"
+            ),
+        }
+
+        let mut other = connect_from("127.0.0.3", listen_addr).await?;
+        other.write_all(b"ping").await?;
+        let mut buf = [0u8; 4];
+        other.read_exact(&mut buf).await?;
+        assert_eq!(
+            &buf, b"ping",
+            "a different source ip should be unaffected by the first one's rate limit"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_the_accept_loop_and_drains_in_flight_connections() -> Result<()> {
+        // Whether a fresh connection to `addr` gets its "ping" echoed back within a short
+        // window. Once shutdown drops the listener (see below), a connection attempt might be
+        // refused outright, reset mid-write, or simply never answered -- any of those count as
+        // "not served".
+        async fn probe_serves_ping(addr: SocketAddr) -> bool {
+            let Ok(mut stream) = TcpStream::connect(addr).await else {
+                return false;
+            };
+            if stream.write_all(b"ping").await.is_err() {
+                return false;
+            }
+            let mut buf = [0u8; 4];
+            matches!(
+                tokio::time::timeout(Duration::from_millis(200), stream.read_exact(&mut buf)).await,
+                Ok(Ok(_)),
+            ) && &buf == b"ping"
+        }
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let shutdown = proxy.shutdown_token();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        let run_task = tokio::spawn(async move { proxy.run_on(listener).await });
+
+        // Round-trip once so the connection is known-established, then keep it open (no EOF
+        // sent either way) so its forward task is still running once shutdown is requested.
+        let mut in_flight = TcpStream::connect(listen_addr).await?;
+        in_flight.write_all(b"ping").await?;
+        let mut buf = [0u8; 4];
+        in_flight.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+
+        assert!(!shutdown.is_cancelled());
+        shutdown.cancel();
+
+        // The accept loop stopped taking new connections the moment it observed cancellation, so
+        // a connection attempted after that is never forwarded.
+        assert!(
+            !probe_serves_ping(listen_addr).await,
+            "a connection opened after shutdown should never be forwarded"
+        );
+
+        // `run_on` doesn't return yet: it's waiting for `in_flight`'s forward task to drain.
+        let mut run_task = run_task;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), &mut run_task)
+                .await
+                .is_err(),
+            "run_on should still be draining the in-flight connection"
+        );
+
+        drop(in_flight);
+        tokio::time::timeout(Duration::from_secs(5), run_task)
+            .await
+            .expect("run_on should return promptly once the in-flight connection finishes")
+            .expect("task panicked")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_service_skips_an_ejected_destination_and_reinstates_it_later() -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                while let Some(incoming) = remote.accept().await {
+                    tokio::spawn(async move {
+                        let _ = incoming.await;
+                    });
+                }
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_outlier_detection(
+            OutlierDetectionConfig {
+                consecutive_errors: 1,
+                base_ejection_time: Duration::from_millis(50),
+                max_ejection_percent: 100,
+            },
+        );
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        // Simulate cluster-a having just failed a proxied connection, as dial_service would
+        // record on a real dial failure.
+        let outlier = proxy
+            .outlier
+            .as_ref()
+            .expect("outlier detection configured");
+        outlier.record_failure("cluster-a", 1);
+        assert!(outlier.is_ejected("cluster-a"));
+
+        let err = proxy.dial_service("svc").await.unwrap_err();
+        assert!(
+            matches!(err, crate::error::MeshError::CircuitOpen { .. }),
+            "an ejected destination should be skipped even though it's the only candidate, and \
+             reported distinctly from a service with no candidates at all: {err}"
+        );
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+
+        proxy
+            .dial_service("svc")
+            .await
+            .expect("the destination should be reinstated once its ejection window elapses");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dial_service_falls_through_to_a_configured_cluster_for_an_unknown_service()
+    -> Result<()> {
+        let hub = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let hub_addr = hub.addr();
+        tokio::spawn({
+            let hub = hub.clone();
+            async move {
+                while let Some(incoming) = hub.accept().await {
+                    tokio::spawn(async move {
+                        let _ = incoming.await;
+                    });
+                }
+            }
+        });
+
+        // Discovery knows about the hub cluster, but never advertises "prod-unknown" as one of
+        // its services -- the fallthrough pattern is what's expected to route to it.
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "hub".to_string(),
+            endpoint_id: hub_addr.endpoint_id,
+            relay_url: hub_addr.relay_url().map(ToString::to_string),
+            direct_addresses: hub_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_fallthrough_route("prod-*", "hub");
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        proxy
+            .dial_service("prod-unknown")
+            .await
+            .expect("a service matching the fallthrough pattern should reach the hub cluster");
+
+        let err = proxy.dial_service("staging-unknown").await.unwrap_err();
+        assert!(
+            matches!(err, crate::error::MeshError::NoRoute { .. }),
+            "a service matching no fallthrough pattern should still fail to route: {err}"
+        );
+
+        Ok(())
+    }
+
+    /// A [`DiscoveryManager`] registered with one cluster serving `svc`, without actually
+    /// binding a reachable endpoint for it -- fine for authz tests, since a denied dial never
+    /// reaches the network.
+    fn discovery_with_unreachable_cluster(
+        cluster_id: &str,
+        service: &str,
+    ) -> Arc<DiscoveryManager> {
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: cluster_id.to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: vec![service.to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        discovery
+    }
+
+    #[tokio::test]
+    async fn authz_denies_a_dial_a_matching_deny_rule_targets() -> Result<()> {
+        let discovery = discovery_with_unreachable_cluster("cluster-a", "svc");
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_authz(AuthzPolicy {
+            default: AuthzEffect::Allow,
+            rules: vec![AuthzRule {
+                cluster: Some("cluster-a".to_string()),
+                service: Some("svc".to_string()),
+                effect: AuthzEffect::Deny,
+            }],
+        });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        let err = proxy.dial_service("svc").await.unwrap_err();
+        assert!(
+            matches!(err, crate::error::MeshError::AuthzDenied { .. }),
+            "a matching deny rule should refuse the dial before it reaches the network"
+        );
+        assert_eq!(proxy.metrics.authz_denied_total.get(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authz_allows_a_dial_a_matching_allow_rule_targets_under_a_default_deny_policy()
+    -> Result<()> {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                while let Some(incoming) = remote.accept().await {
+                    tokio::spawn(async move {
+                        let _ = incoming.await;
+                    });
+                }
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_authz(AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: vec![AuthzRule {
+                cluster: Some("cluster-a".to_string()),
+                service: Some("svc".to_string()),
+                effect: AuthzEffect::Allow,
+            }],
+        });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        proxy
+            .dial_service("svc")
+            .await
+            .expect("an explicit allow rule should let the dial through");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_default_deny_policy_refuses_a_dial_no_rule_mentions() -> Result<()> {
+        let discovery = discovery_with_unreachable_cluster("cluster-a", "svc");
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_authz(AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: Vec::new(),
+        });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        let err = proxy.dial_service("svc").await.unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::AuthzDenied { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_authz_lets_a_previously_denied_cluster_dial_succeed_on_a_new_attempt() -> Result<()>
+    {
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        tokio::spawn(async move {
+            while let Some(incoming) = remote.accept().await {
+                tokio::spawn(async move {
+                    let _ = incoming.await;
+                });
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-a".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: vec!["svc".to_string()],
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_authz(AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: Vec::new(),
+        });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, discovery);
+
+        let err = proxy.dial_service("svc").await.unwrap_err();
+        assert!(
+            matches!(err, crate::error::MeshError::AuthzDenied { .. }),
+            "cluster-a should start out denied by the default-deny policy"
+        );
+
+        proxy.set_authz(Some(AuthzPolicy {
+            default: AuthzEffect::Deny,
+            rules: vec![AuthzRule {
+                cluster: Some("cluster-a".to_string()),
+                service: Some("svc".to_string()),
+                effect: AuthzEffect::Allow,
+            }],
+        }));
+
+        proxy
+            .dial_service("svc")
+            .await
+            .expect("a reloaded policy allowing cluster-a should let a new attempt connect");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_timeout_tears_down_an_exchange_that_outlives_it() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend that accepts a connection and then holds it open without reading or writing
+        // anything, simulating one that's hung.
+        let stalling_backend = TcpListener::bind("127.0.0.1:0").await?;
+        let backend_addr = stalling_backend.local_addr()?;
+        tokio::spawn(async move {
+            if let Ok((stream, _)) = stalling_backend.accept().await {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                drop(stream);
+            }
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", backend_addr)
+            .with_request_timeout(Duration::from_millis(50));
+        let proxy = Arc::new(MeshProxy::from_endpoint(
+            config,
+            endpoint,
+            Arc::new(DiscoveryManager::new()),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run_on(listener).await }
+        });
+
+        let start = tokio::time::Instant::now();
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"ping").await?;
+
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+            .await
+            .expect("the client side should observe the deadline, not hang indefinitely")?;
+        let elapsed = start.elapsed();
+
+        assert_eq!(
+            read, 0,
+            "the client side should see the connection close once the deadline passes"
+        );
+        assert!(
+            elapsed >= Duration::from_millis(50),
+            "the connection shouldn't be torn down before its deadline (took {elapsed:?})"
+        );
+        assert_eq!(proxy.metrics.request_timeouts.get(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_degraded_bind_still_serves_local_routes_and_rejects_cross_cluster_dials()
+    -> Result<()> {
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        // An `Inline` secret key that doesn't parse fails `bind_endpoint` before it ever gets to
+        // binding a socket, which is a deterministic stand-in here for the sandbox/no-UDP-egress
+        // failures `ProxyConfig::allow_degraded` actually exists for.
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", backend.addr())
+            .with_secret_key(crate::secret_key::SecretKeySource::Inline(
+                "not a valid secret key".to_string(),
+            ))
+            .with_allow_degraded(true);
+        let proxy = Arc::new(MeshProxy::bind(config, Arc::new(DiscoveryManager::new())).await?);
+        assert!(proxy.status().degraded);
+        assert!(proxy.endpoint().is_none());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run_on(listener).await }
+        });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(
+            &buf, b"hello",
+            "local routes should still forward while degraded"
+        );
+
+        let err = proxy.dial_cluster("cluster-a").await.unwrap_err();
+        assert!(
+            matches!(err, crate::error::MeshError::MeshUnavailable { .. }),
+            "a degraded proxy has no endpoint to dial cross-cluster routes with, got {err:?}"
+        );
+        assert_eq!(err.reason_code(), "mesh-unavailable");
+        assert_eq!(err.http_status(), 503);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_proxy_protocol_prepends_a_v2_header_naming_the_real_client() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let raw_backend = TcpListener::bind("127.0.0.1:0").await?;
+        let backend_addr = raw_backend.local_addr()?;
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = raw_backend.accept().await.unwrap();
+            let mut header = [0u8; 28];
+            stream.read_exact(&mut header).await.unwrap();
+            header
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", backend_addr)
+            .with_send_proxy_protocol(true);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let client_addr = client.local_addr()?;
+        client.write_all(b"ping").await?;
+
+        let header = received.await.expect("backend task panicked");
+        let expected = proxy_protocol::encode_v2(client_addr, listen_addr);
+        assert_eq!(&header[..], &expected[..]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_retryable_route_falls_over_to_its_next_backend_when_the_first_refuses() -> Result<()>
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Binding then immediately dropping a listener frees the port while leaving nothing
+        // there to accept a connection, so a later connect to it is refused.
+        let dead_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let dead_addr = dead_listener.local_addr()?;
+        drop(dead_listener);
+
+        let backend = TcpListener::bind("127.0.0.1:0").await?;
+        let backend_addr = backend.local_addr()?;
+        tokio::spawn(async move {
+            let (mut stream, _) = backend.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(b"second").await.unwrap();
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", dead_addr)
+            .with_retry_route("svc", vec![backend_addr])
+            .with_retry(crate::config::RetryConfig { max_retries: 1 });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await?;
+        assert_eq!(
+            response, b"second",
+            "the client should transparently get the second backend's response"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn splice_keeps_forwarding_a_still_open_direction_after_the_other_half_closes()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend that waits for the client to finish sending (EOF) before writing its own
+        // response, so the test only passes if the client's half-close doesn't tear down the
+        // direction still carrying the backend's reply.
+        let raw_backend = TcpListener::bind("127.0.0.1:0").await?;
+        let backend_addr = raw_backend.local_addr()?;
+        let response = b"response-after-client-closed".to_vec();
+        let backend_response = response.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = raw_backend.accept().await.unwrap();
+            let mut request = Vec::new();
+            stream.read_to_end(&mut request).await.unwrap();
+            assert_eq!(request, b"request-then-close");
+            stream.write_all(&backend_response).await.unwrap();
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend_addr);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"request-then-close").await?;
+        client.shutdown().await?;
+
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, response,
+            "the still-open backend-to-client direction should keep forwarding after the \
+             client closed its own write half"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn iroh_stream_half_close_lets_the_remote_side_reply_after_the_client_closes()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The remote end of the iroh stream, standing in for a receiving cluster's mesh accept
+        // loop and its backend: it waits for EOF before replying, just like the plain-TCP half-close test
+        // above, but over an iroh bidirectional stream instead of a second TCP connection.
+        let remote = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let remote_addr = remote.addr();
+        let response = b"response-after-client-closed".to_vec();
+        let backend_response = response.clone();
+        let accept_task = tokio::spawn({
+            let remote = remote.clone();
+            async move {
+                let incoming = remote.accept().await.expect("endpoint closed");
+                let conn = incoming.await.unwrap();
+                let (mut send, mut recv) = conn.accept_bi().await.unwrap();
+                let request = recv.read_to_end(1024).await.unwrap();
+                send.write_all(&backend_response).await.unwrap();
+                send.finish().unwrap();
+                (request, conn)
+            }
+        });
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        discovery.register_cluster(ClusterRegistration {
+            cluster_id: "cluster-b".to_string(),
+            endpoint_id: remote_addr.endpoint_id,
+            relay_url: remote_addr.relay_url().map(ToString::to_string),
+            direct_addresses: remote_addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        });
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::bind(config, discovery).await?;
+        let conn = proxy.dial_cluster("cluster-b").await.unwrap();
+        let (send, recv) = conn.open_bi().await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut accepted, _) = listener.accept().await.unwrap();
+            MeshProxy::splice_tcp_with_iroh_stream(&mut accepted, send, recv, 8192)
+                .await
+                .unwrap();
+        });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"request-then-close").await?;
+        client.shutdown().await?;
+
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, response,
+            "the still-open remote-to-client direction should keep forwarding after the \
+             client closed its own write half"
+        );
+
+        let (request, remote_conn) = accept_task.await.expect("accept task panicked");
+        assert_eq!(request, b"request-then-close");
+        drop(remote_conn);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn splice_transfers_data_larger_than_the_configured_io_buffer_without_corruption()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_route("svc", backend.addr())
+            .with_io_buffer_size(64);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let payload: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let write_payload = payload.clone();
+        let writer = tokio::spawn(async move {
+            client.write_all(&write_payload).await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut echoed = Vec::new();
+            client.read_to_end(&mut echoed).await.unwrap();
+            echoed
+        });
+
+        let echoed = writer.await.expect("writer task panicked");
+        assert_eq!(
+            echoed, payload,
+            "a transfer much larger than the io buffer should still arrive intact"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn an_abruptly_reset_client_tears_down_the_backend_connection_and_frees_the_connection_count()
+    -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend that keeps writing after the client goes away, so the test only passes if
+        // an abrupt client-side reset tears down the backend-to-client direction too, instead of
+        // leaving it spinning on a client that's never coming back.
+        let backend_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let backend_addr = backend_listener.local_addr()?;
+        let backend_closed = Arc::new(tokio::sync::Notify::new());
+        let backend_task = tokio::spawn({
+            let backend_closed = backend_closed.clone();
+            async move {
+                let (mut stream, _) = backend_listener.accept().await.unwrap();
+                let chunk = [0u8; 4096];
+                loop {
+                    if stream.write_all(&chunk).await.is_err() {
+                        backend_closed.notify_one();
+                        return;
+                    }
+                }
+            }
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend_addr);
+        let proxy = Arc::new(MeshProxy::from_endpoint(
+            config,
+            endpoint,
+            Arc::new(DiscoveryManager::new()),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run_on(listener).await }
+        });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        let mut buf = [0u8; 4096];
+        client.read_exact(&mut buf).await?;
+        // Forces an immediate RST on drop instead of a graceful FIN, simulating a client that
+        // vanished mid-transfer rather than one that politely closed its side.
+        client.set_linger(Some(Duration::ZERO))?;
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(5), backend_closed.notified())
+            .await
+            .expect("the backend connection should be torn down once the client is reset, not left open");
+        backend_task.await.expect("backend task panicked");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while proxy.metrics.active_connections.get() != 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the active-connection count should return to zero once forwarding tears down");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_mode_terminates_tls_and_routes_the_decrypted_request_by_host() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path =
+            std::env::temp_dir().join(format!("iroh-mesh-test-{}.crt", listen_addr.port()));
+        let key_path = cert_path.with_extension("key");
+        tokio::fs::write(&cert_path, cert.cert.pem()).await?;
+        tokio::fs::write(&key_path, cert.signing_key.serialize_pem()).await?;
+
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Http)
+            .with_route("localhost", backend.addr())
+            .with_tls(TlsConfig {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            });
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let server_name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+
+        let tcp = tokio::net::TcpStream::connect(listen_addr).await?;
+        let mut tls = connector.connect(server_name, tcp).await?;
+
+        let request =
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\nhello".to_vec();
+        tls.write_all(&request).await?;
+        tls.shutdown().await?;
+        let mut echoed = Vec::new();
+        tls.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, request,
+            "the decrypted request should route to the backend for its Host header"
+        );
+
+        let _ = tokio::fs::remove_file(&cert_path).await;
+        let _ = tokio::fs::remove_file(&key_path).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backend_tls_originates_tls_to_the_backend_and_round_trips_data() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A minimal TLS-terminating echo backend, standing in for something like a database that
+        // requires TLS even on an internal hop.
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = std::env::temp_dir().join(format!(
+            "iroh-mesh-test-backend-tls-{}.crt",
+            std::process::id()
+        ));
+        let key_path = cert_path.with_extension("key");
+        tokio::fs::write(&cert_path, cert.cert.pem()).await?;
+        tokio::fs::write(&key_path, cert.signing_key.serialize_pem()).await?;
+
+        let backend_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let backend_addr = backend_listener.local_addr()?;
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert.cert.der().clone()],
+            rustls::pki_types::PrivatePkcs8KeyDer::from(cert.signing_key.serialize_der()).into(),
+        )
+        .unwrap();
+        let tls_acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        tokio::spawn(async move {
+            let (stream, _) = backend_listener.accept().await.expect("accept failed");
+            let mut tls = tls_acceptor.accept(stream).await.expect("handshake failed");
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = tls.read(&mut buf).await.expect("read failed");
+                if n == 0 {
+                    break;
+                }
+                tls.write_all(&buf[..n]).await.expect("write failed");
+            }
+            let _ = tls.shutdown().await;
+        });
+
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Tcp)
+            .with_route("backend", backend_addr)
+            .with_backend_tls(
+                "backend",
+                BackendTlsConfig {
+                    server_name: "localhost".to_string(),
+                    ca_path: Some(cert_path.clone()),
+                    insecure_skip_verify: false,
+                },
+            );
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello over backend tls").await?;
+        client.shutdown().await?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        assert_eq!(
+            echoed, b"hello over backend tls",
+            "bytes should round-trip through the backend's TLS session unchanged"
+        );
+
+        let _ = tokio::fs::remove_file(&cert_path).await;
+        let _ = tokio::fs::remove_file(&key_path).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backend_tls_rejects_a_ca_path_that_does_not_parse() {
+        let bogus_ca = std::env::temp_dir().join(format!(
+            "iroh-mesh-test-bogus-ca-{}.crt",
+            std::process::id()
+        ));
+        tokio::fs::write(&bogus_ca, b"not a certificate")
+            .await
+            .unwrap();
+
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap())
+            .with_mode(ProxyMode::Tcp)
+            .with_route("backend", "127.0.0.1:1".parse().unwrap())
+            .with_backend_tls(
+                "backend",
+                BackendTlsConfig {
+                    server_name: "localhost".to_string(),
+                    ca_path: Some(bogus_ca.clone()),
+                    insecure_skip_verify: false,
+                },
+            );
+
+        let err = build_backend_tls_connectors(&config.backend_tls)
+            .await
+            .expect_err("an unparsable CA certificate should be rejected");
+        assert!(
+            matches!(err, crate::error::MeshError::InvalidConfig { .. }),
+            "{err:?}"
+        );
+
+        let _ = tokio::fs::remove_file(&bogus_ca).await;
+    }
+
+    #[test]
+    fn classify_transition_counts_every_flap_not_just_the_first() {
+        use std::net::{Ipv4Addr, SocketAddrV4};
+
+        let direct = ConnectionType::Direct(std::net::SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::LOCALHOST,
+            4242,
+        )));
+        let relay: ConnectionType =
+            ConnectionType::Relay("https://relay.example".parse().expect("valid relay url"));
+
+        // A connection that never reaches direct is only counted once, as a dial fallback.
+        assert_eq!(
+            classify_transition(None, &relay),
+            Some(PathTransition::DialFallback)
+        );
+        assert_eq!(classify_transition(Some(&relay), &relay), None);
+
+        // Starting direct isn't a fallback or a hole-punch.
+        assert_eq!(classify_transition(None, &direct), None);
+
+        // Flapping direct -> relay -> direct -> relay is counted on every transition.
+        assert_eq!(
+            classify_transition(Some(&direct), &relay),
+            Some(PathTransition::HolepunchFallback)
+        );
+        assert_eq!(
+            classify_transition(Some(&relay), &direct),
+            Some(PathTransition::HolepunchSuccess)
+        );
+        assert_eq!(
+            classify_transition(Some(&direct), &relay),
+            Some(PathTransition::HolepunchFallback)
+        );
+    }
+
+    #[test]
+    fn holepunch_metrics_track_a_simulated_flapping_connection() {
+        let metrics = Metrics::default();
+        let cluster_id = "cluster-a";
+
+        // Simulate the sequence track_path would observe for a peer that reaches a direct path,
+        // loses it, and regains it.
+        metrics.record_dial_fallback(cluster_id);
+        metrics.record_holepunch_success(cluster_id);
+        metrics.record_holepunch_fallback(cluster_id);
+        metrics.record_holepunch_success(cluster_id);
+
+        assert_eq!(metrics.dial_relay_fallback.get(), 1);
+        assert_eq!(metrics.holepunch_success.get(), 2);
+        assert_eq!(metrics.holepunch_fallback.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_closed_connection_produces_a_populated_summary_on_the_channel() -> Result<()> {
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr());
+        let (tx, mut rx) = mpsc::channel(8);
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()))
+            .with_connection_summary_channel(tx);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut echoed = vec![0u8; 5];
+        client.read_exact(&mut echoed).await?;
+        client.shutdown().await?;
+
+        let summary = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("a summary should be sent once the connection closes")
+            .expect("the sender shouldn't have been dropped without sending");
+
+        assert_eq!(summary.source, client.local_addr()?);
+        assert_eq!(summary.target_service.as_deref(), Some("svc"));
+        assert_eq!(summary.target_cluster, None);
+        assert_eq!(summary.bytes_sent, 5);
+        assert_eq!(summary.bytes_received, 5);
+        assert_eq!(summary.outcome, ConnectionOutcome::Closed);
+        assert!(summary.ended_at >= summary.started_at);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_dropped_summary_is_counted_when_the_channel_is_full() -> Result<()> {
+        let backend = crate::testing::EchoBackend::spawn().await?;
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let config =
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr());
+        // Capacity 1, and never drained: the connection below fills it, so its own summary has
+        // nowhere to go.
+        let (tx, _rx) = mpsc::channel(1);
+        tx.try_send(ConnectionSummary {
+            conn_id: "prewarm".to_string(),
+            source: "127.0.0.1:1".parse().unwrap(),
+            target_service: None,
+            target_cluster: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            started_at: SystemTime::now(),
+            ended_at: SystemTime::now(),
+            outcome: ConnectionOutcome::Closed,
+        })
+        .expect("channel just created with capacity 1");
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()))
+            .with_connection_summary_channel(tx);
+        let metrics = proxy.metrics.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+        tokio::spawn(async move { proxy.run_on(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(listen_addr).await?;
+        client.write_all(b"hello").await?;
+        let mut echoed = vec![0u8; 5];
+        client.read_exact(&mut echoed).await?;
+        client.shutdown().await?;
+        let mut eof = [0u8; 1];
+        assert_eq!(client.read(&mut eof).await?, 0);
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while metrics.connection_summaries_dropped.get() == 0 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "the full channel should cause the connection's own summary to be dropped"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(metrics.connection_summaries_dropped.get(), 1);
+
+        Ok(())
+    }
+
+    /// How long [`assert_reachable`]/[`assert_unreachable`] give a dial to succeed or fail.
+    const ROTATION_DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Dials `addr` and expects the connection to succeed within [`ROTATION_DIAL_TIMEOUT`], for
+    /// asserting an identity is reachable during a [`KeyRotationHandle`]'s overlap window.
+    async fn assert_reachable(dialer: &Endpoint, addr: EndpointAddr) {
+        tokio::time::timeout(ROTATION_DIAL_TIMEOUT, dialer.connect(addr, MESH_ALPN))
+            .await
+            .expect("dial should not hang")
+            .expect("identity should still be reachable");
+    }
+
+    /// Dials `addr` and expects the connection to fail or time out within
+    /// [`ROTATION_DIAL_TIMEOUT`], for asserting an identity is no longer reachable once
+    /// [`KeyRotationHandle::finish`] has retired it.
+    async fn assert_unreachable(dialer: &Endpoint, addr: EndpointAddr) {
+        let result = tokio::time::timeout(ROTATION_DIAL_TIMEOUT, dialer.connect(addr, MESH_ALPN))
+            .await
+            .map(|r| r.is_ok());
+        assert!(
+            !matches!(result, Ok(true)),
+            "identity should no longer be reachable"
+        );
+    }
+
+    #[tokio::test]
+    async fn rotate_key_keeps_both_identities_reachable_then_retires_the_old_one() -> Result<()> {
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let old_addr = endpoint.addr();
+        let config = ProxyConfig::new("127.0.0.1:0".parse().unwrap());
+        let proxy = MeshProxy::from_endpoint(config, endpoint, Arc::new(DiscoveryManager::new()));
+
+        let rotation = proxy
+            .rotate_key(iroh_base::SecretKey::generate(&mut rand::rng()))
+            .await?;
+        let new_addr = rotation.new_endpoint().addr();
+        assert_ne!(
+            old_addr.endpoint_id, new_addr.endpoint_id,
+            "rotation should bind a fresh identity, not reuse the old one"
+        );
+
+        // Drive each endpoint's accept loop directly -- the proxy's own mesh accept loop (see
+        // MeshProxy::with_accept_hook's docs) only runs under run_on_many, which this test never
+        // starts.
+        let old_accept = tokio::spawn({
+            let old_endpoint = rotation.old_endpoint().clone();
+            async move {
+                while let Some(incoming) = old_endpoint.accept().await {
+                    let _ = incoming.await;
+                }
+            }
+        });
+        let new_accept = tokio::spawn({
+            let new_endpoint = rotation.new_endpoint().clone();
+            async move {
+                while let Some(incoming) = new_endpoint.accept().await {
+                    let _ = incoming.await;
+                }
+            }
+        });
+
+        let dialer = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![MESH_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        assert_reachable(&dialer, old_addr.clone()).await;
+        assert_reachable(&dialer, new_addr.clone()).await;
+
+        rotation.finish(Duration::from_millis(50)).await;
+
+        assert_unreachable(&dialer, old_addr).await;
+        assert_reachable(&dialer, new_addr).await;
+
+        old_accept.abort();
+        new_accept.abort();
+        Ok(())
+    }
+}
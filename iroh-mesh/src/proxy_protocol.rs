@@ -0,0 +1,126 @@
+//! Encoding of PROXY protocol v2 headers.
+//!
+//! See <https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt>. Sent ahead of a backend
+//! connection when [`crate::config::ProxyConfig::send_proxy_protocol`] is set, so the backend can
+//! recover the original client's address instead of only seeing the proxy's own.
+
+use std::net::SocketAddr;
+
+/// The fixed 12-byte signature every PROXY protocol v2 header starts with.
+const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// Version 2, `PROXY` command (as opposed to `LOCAL`), packed into the header's second byte.
+const VERSION_COMMAND: u8 = 0x21;
+
+/// `AF_INET` (IPv4) address family with `SOCK_STREAM` (TCP) transport.
+const FAMILY_INET_STREAM: u8 = 0x11;
+
+/// `AF_INET6` (IPv6) address family with `SOCK_STREAM` (TCP) transport.
+const FAMILY_INET6_STREAM: u8 = 0x21;
+
+/// `AF_UNSPEC` with no transport, used when `client` and `destination` don't share an address
+/// family (a v2 header can't represent a mixed pair) -- this variant carries no address block and
+/// is always valid, it just tells the backend nothing more than "a proxied connection happened".
+const FAMILY_UNSPEC: u8 = 0x00;
+
+/// Encodes a PROXY protocol v2 header describing a TCP connection from `client` to `destination`.
+pub fn encode_v2(client: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = Vec::with_capacity(SIGNATURE.len() + 2 + 36);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_COMMAND);
+
+    match (client, destination) {
+        (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+            header.push(FAMILY_INET_STREAM);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&src.ip().octets());
+            header.extend_from_slice(&dst.ip().octets());
+            header.extend_from_slice(&src.port().to_be_bytes());
+            header.extend_from_slice(&dst.port().to_be_bytes());
+        }
+        (SocketAddr::V6(src), SocketAddr::V6(dst)) => {
+            header.push(FAMILY_INET6_STREAM);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&src.ip().octets());
+            header.extend_from_slice(&dst.ip().octets());
+            header.extend_from_slice(&src.port().to_be_bytes());
+            header.extend_from_slice(&dst.port().to_be_bytes());
+        }
+        _ => {
+            header.push(FAMILY_UNSPEC);
+            header.extend_from_slice(&0u16.to_be_bytes());
+        }
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn encodes_an_ipv4_header_matching_the_spec() {
+        let client: SocketAddr = (Ipv4Addr::new(192, 0, 2, 1), 56_789).into();
+        let destination: SocketAddr = (Ipv4Addr::new(198, 51, 100, 2), 443).into();
+
+        let header = encode_v2(client, destination);
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            // Signature.
+            0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+            // Version 2, PROXY command.
+            0x21,
+            // AF_INET, STREAM.
+            0x11,
+            // Address block length: 2 * 4-byte addresses + 2 * 2-byte ports.
+            0x00, 0x0C,
+            // Source address 192.0.2.1.
+            192, 0, 2, 1,
+            // Destination address 198.51.100.2.
+            198, 51, 100, 2,
+            // Source port 56789.
+            0xDD, 0xD5,
+            // Destination port 443.
+            0x01, 0xBB,
+        ];
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn encodes_an_ipv6_header_matching_the_spec() {
+        let client: SocketAddr = (Ipv6Addr::LOCALHOST, 1).into();
+        let destination: SocketAddr = (Ipv6Addr::UNSPECIFIED, 2).into();
+
+        let header = encode_v2(client, destination);
+
+        let mut expected = SIGNATURE.to_vec();
+        expected.push(VERSION_COMMAND);
+        expected.push(FAMILY_INET6_STREAM);
+        expected.extend_from_slice(&36u16.to_be_bytes());
+        expected.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        expected.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets());
+        expected.extend_from_slice(&1u16.to_be_bytes());
+        expected.extend_from_slice(&2u16.to_be_bytes());
+        assert_eq!(header, expected);
+        assert_eq!(header.len(), 16 + 36);
+    }
+
+    #[test]
+    fn a_mixed_address_family_pair_falls_back_to_the_addressless_unspec_variant() {
+        let client: SocketAddr = (Ipv4Addr::new(127, 0, 0, 1), 1).into();
+        let destination: SocketAddr = (Ipv6Addr::LOCALHOST, 2).into();
+
+        let header = encode_v2(client, destination);
+
+        let mut expected = SIGNATURE.to_vec();
+        expected.push(VERSION_COMMAND);
+        expected.push(FAMILY_UNSPEC);
+        expected.extend_from_slice(&0u16.to_be_bytes());
+        assert_eq!(header, expected);
+    }
+}
@@ -0,0 +1,297 @@
+//! Per-source-IP token-bucket connection rate limiting.
+//!
+//! [`crate::config::ProxyConfig::max_connections`] already bounds how many connections
+//! [`crate::proxy::MeshProxy::start_tcp_proxy`] accepts across every source together, but a
+//! single misbehaving client opening connections in a tight loop can still consume that whole
+//! shared budget and crowd out everyone else. [`ConnectionRateLimiter`] adds a second, per-source
+//! budget on top: each source IP gets its own token bucket, so one IP running its bucket dry
+//! doesn't affect any other.
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use snafu::ensure;
+use tokio::time::Instant;
+
+use crate::error::{InvalidConfigSnafu, Result};
+
+/// Configuration for [`ConnectionRateLimiter`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ConnectionRateLimitConfig {
+    /// Sustained connections per second allowed from a single source IP.
+    pub rate_per_second: f64,
+    /// Extra connections a source IP may open in a burst above its sustained rate before being
+    /// throttled. Also a bucket's starting balance, so a source seen for the first time can
+    /// immediately use its whole burst rather than being throttled from its very first connection.
+    pub burst: u32,
+    /// Source IPs exempt from rate limiting entirely, as CIDR blocks (e.g. `"10.0.0.0/8"`), for
+    /// callers like a health checker or another proxy in front of this one. Parsed once, in
+    /// [`ConnectionRateLimiter::new`]; a malformed entry there is a configuration error, not
+    /// something that can fail per connection.
+    #[serde(default)]
+    pub whitelist: Vec<String>,
+}
+
+/// A parsed `address/prefix-length` CIDR block, e.g. `10.0.0.0/8` or `::1/128`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CidrBlock {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl CidrBlock {
+    fn parse(raw: &str) -> Result<Self> {
+        let (addr, prefix_len) = raw.split_once('/').ok_or_else(|| {
+            InvalidConfigSnafu {
+                reason: format!(
+                    "rate limit whitelist entry {raw:?} must be in CIDR form, e.g. \"10.0.0.0/8\""
+                ),
+            }
+            .build()
+        })?;
+        let network: IpAddr = addr.parse().map_err(|_| {
+            InvalidConfigSnafu {
+                reason: format!("rate limit whitelist entry {raw:?} has an invalid IP address"),
+            }
+            .build()
+        })?;
+        let max_prefix_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len: u8 = prefix_len.parse().map_err(|_| {
+            InvalidConfigSnafu {
+                reason: format!("rate limit whitelist entry {raw:?} has an invalid prefix length"),
+            }
+            .build()
+        })?;
+        ensure!(
+            prefix_len <= max_prefix_len,
+            InvalidConfigSnafu {
+                reason: format!(
+                    "rate limit whitelist entry {raw:?} has a prefix length greater than \
+                     {max_prefix_len}"
+                ),
+            }
+        );
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask_u32(self.prefix_len);
+                (u32::from(network) & mask) == (u32::from(ip) & mask)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask_u128(self.prefix_len);
+                (u128::from(network) & mask) == (u128::from(ip) & mask)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Computes a 32-bit network mask with the top `prefix_len` bits set. `prefix_len == 0` matches
+/// everything, which `u32::MAX << 32` would overflow computing directly.
+fn mask_u32(prefix_len: u8) -> u32 {
+    if prefix_len == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix_len as u32)
+    }
+}
+
+/// Computes a 128-bit network mask with the top `prefix_len` bits set. See [`mask_u32`].
+fn mask_u128(prefix_len: u8) -> u128 {
+    if prefix_len == 0 {
+        0
+    } else {
+        u128::MAX << (128 - prefix_len as u32)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Per-source-IP token-bucket connection rate limiter. See the module docs for how this relates
+/// to [`crate::config::ProxyConfig::max_connections`].
+#[derive(Debug)]
+pub struct ConnectionRateLimiter {
+    rate_per_second: f64,
+    burst: u32,
+    whitelist: Vec<CidrBlock>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl ConnectionRateLimiter {
+    /// Builds a limiter from `config`, eagerly parsing [`ConnectionRateLimitConfig::whitelist`]
+    /// so a malformed CIDR entry is reported at startup rather than on the first connection that
+    /// would have matched it.
+    pub fn new(config: &ConnectionRateLimitConfig) -> Result<Self> {
+        let whitelist = config
+            .whitelist
+            .iter()
+            .map(|raw| CidrBlock::parse(raw))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            rate_per_second: config.rate_per_second,
+            burst: config.burst,
+            whitelist,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether `ip` is exempt from rate limiting per [`ConnectionRateLimitConfig::whitelist`].
+    pub fn is_whitelisted(&self, ip: IpAddr) -> bool {
+        self.whitelist.iter().any(|block| block.contains(ip))
+    }
+
+    /// Decides whether a new connection from `ip` should be accepted right now, consuming one
+    /// token from its bucket if so.
+    ///
+    /// Each source IP has its own bucket, refilled continuously at
+    /// [`ConnectionRateLimitConfig::rate_per_second`] up to a maximum of
+    /// [`ConnectionRateLimitConfig::burst`] tokens; a source seen for the first time starts with
+    /// a full bucket. A source whose bucket is empty has the connection refused outright rather
+    /// than delayed, since stalling [`crate::proxy::MeshProxy::start_tcp_proxy`]'s accept loop to
+    /// wait out one source IP's refill would also delay every other source waiting behind it.
+    ///
+    /// A whitelisted source (see [`Self::is_whitelisted`]) always returns `true` without
+    /// touching its bucket.
+    ///
+    /// Buckets for sources that stop connecting are never pruned, the same simplifying choice
+    /// [`crate::outlier::OutlierDetector`] makes for its own per-destination map; a deployment
+    /// fronted by enough distinct source IPs to make that map's growth a concern should rely on a
+    /// layer in front of this one (e.g. a cloud load balancer) to narrow the address space first.
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        if self.is_whitelisted(ip) {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().expect("lock poisoned");
+        let now = Instant::now();
+        let bucket = buckets.entry(ip).or_insert_with(|| Bucket {
+            tokens: self.burst as f64,
+            last_refill: now,
+        });
+        let elapsed = now
+            .saturating_duration_since(bucket.last_refill)
+            .as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate_per_second).min(self.burst as f64);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rate_per_second: f64, burst: u32, whitelist: &[&str]) -> ConnectionRateLimitConfig {
+        ConnectionRateLimitConfig {
+            rate_per_second,
+            burst,
+            whitelist: whitelist.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn a_malformed_whitelist_entry_is_rejected() {
+        assert!(ConnectionRateLimiter::new(&config(1.0, 1, &["not-a-cidr"])).is_err());
+        assert!(ConnectionRateLimiter::new(&config(1.0, 1, &["10.0.0.0/40"])).is_err());
+        assert!(ConnectionRateLimiter::new(&config(1.0, 1, &["10.0.0.0"])).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_source_may_burst_up_to_its_limit_then_is_refused() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 3, &[])).unwrap();
+        let ip: IpAddr = "203.0.113.1".parse().unwrap();
+
+        assert!(limiter.allow(ip));
+        assert!(limiter.allow(ip));
+        assert!(limiter.allow(ip));
+        assert!(
+            !limiter.allow(ip),
+            "a fourth connection within the same instant should exceed the burst"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_different_source_ip_has_its_own_independent_bucket() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 1, &[])).unwrap();
+        let noisy: IpAddr = "203.0.113.1".parse().unwrap();
+        let quiet: IpAddr = "203.0.113.2".parse().unwrap();
+
+        assert!(limiter.allow(noisy));
+        assert!(
+            !limiter.allow(noisy),
+            "the noisy source exhausted its own burst"
+        );
+        assert!(
+            limiter.allow(quiet),
+            "a different source ip should be unaffected by the noisy one"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_bucket_refills_over_time_up_to_the_burst_cap() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 2, &[])).unwrap();
+        let ip: IpAddr = "203.0.113.1".parse().unwrap();
+
+        assert!(limiter.allow(ip));
+        assert!(limiter.allow(ip));
+        assert!(!limiter.allow(ip));
+
+        tokio::time::advance(std::time::Duration::from_millis(500)).await;
+        assert!(
+            !limiter.allow(ip),
+            "half a token isn't enough for another connection"
+        );
+
+        tokio::time::advance(std::time::Duration::from_millis(500)).await;
+        assert!(limiter.allow(ip), "a full token has now refilled");
+        assert!(!limiter.allow(ip));
+    }
+
+    #[test]
+    fn a_whitelisted_source_is_never_throttled() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 1, &["203.0.113.0/24"])).unwrap();
+        let ip: IpAddr = "203.0.113.5".parse().unwrap();
+
+        for _ in 0..100 {
+            assert!(limiter.allow(ip));
+        }
+    }
+
+    #[test]
+    fn whitelist_matching_respects_the_prefix_length() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 1, &["203.0.113.0/24"])).unwrap();
+        assert!(limiter.is_whitelisted("203.0.113.5".parse().unwrap()));
+        assert!(!limiter.is_whitelisted("203.0.114.5".parse().unwrap()));
+    }
+
+    #[test]
+    fn a_zero_length_prefix_whitelists_every_address_of_that_family() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 1, &["0.0.0.0/0"])).unwrap();
+        assert!(limiter.is_whitelisted("1.2.3.4".parse().unwrap()));
+        assert!(!limiter.is_whitelisted("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn ipv6_whitelisting_works_alongside_ipv4() {
+        let limiter = ConnectionRateLimiter::new(&config(1.0, 1, &["fd00::/8"])).unwrap();
+        assert!(limiter.is_whitelisted("fd00::1".parse().unwrap()));
+        assert!(!limiter.is_whitelisted("fe80::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,168 @@
+//! Keeps this agent's own cluster registered in [`DiscoveryManager`] with its current connect
+//! info, instead of a one-shot registration that goes stale the moment the endpoint's address
+//! changes.
+//!
+//! [`crate::agent`]'s module docs already describe [`crate::agent::MeshAgent`] as registering the
+//! services it exposes "so that other clusters' proxies can reach them", but nothing in this
+//! crate did that self-registration yet -- every existing [`DiscoveryManager::register_cluster`]
+//! call registers a *peer* cluster, learned from the HTTP API, a standalone peers file, or a
+//! health probe. [`spawn_self_registration`] is that missing piece: it registers `cluster` once
+//! immediately, then again every time [`iroh::Endpoint::watch_addr`] reports a new
+//! [`EndpointAddr`] -- a NAT rebind, a newly advertised direct address, or a relay switch --
+//! instead of leaving [`ClusterRegistration::relay_url`] and
+//! [`ClusterRegistration::direct_addresses`] fixed at whatever they were when the agent started.
+
+use std::{sync::Arc, time::SystemTime};
+
+use iroh::{Endpoint, EndpointAddr};
+use n0_watcher::Watcher;
+use tokio_util::sync::CancellationToken;
+
+use crate::discovery::{ClusterRegistration, DiscoveryManager, ServicePort};
+
+/// The parts of this agent's own [`ClusterRegistration`] that don't change while it runs. What
+/// does change -- [`ClusterRegistration::relay_url`] and [`ClusterRegistration::direct_addresses`]
+/// -- comes from `endpoint.watch_addr()` instead, see [`spawn_self_registration`].
+#[derive(Debug, Clone)]
+pub struct SelfClusterInfo {
+    /// This agent's own cluster id, as it should appear to the rest of the mesh.
+    pub cluster_id: String,
+    /// Services this cluster advertises. See [`ClusterRegistration::services`].
+    pub services: Vec<String>,
+    /// Named ports for [`Self::services`]. See [`ClusterRegistration::service_ports`].
+    pub service_ports: Vec<ServicePort>,
+}
+
+/// Registers `cluster` into `discovery` immediately, then re-registers it every time `endpoint`'s
+/// advertised [`EndpointAddr`] changes, until the returned handle is dropped or `shutdown` is
+/// cancelled.
+///
+/// Each re-registration is a fresh, non-[pinned](ClusterRegistration::pinned) registration with
+/// [`ClusterRegistration::updated_at`] set to now, so it always wins over a stale registration of
+/// the same cluster id from another source (see [`DiscoveryManager::register_cluster`]).
+pub fn spawn_self_registration(
+    endpoint: &Endpoint,
+    discovery: Arc<DiscoveryManager>,
+    cluster: SelfClusterInfo,
+    shutdown: CancellationToken,
+) -> SelfRegistrationHandle {
+    let mut watcher = endpoint.watch_addr();
+    let task = tokio::spawn(async move {
+        loop {
+            let addr = watcher.get();
+            discovery.register_cluster(registration_from_addr(&cluster, addr));
+            tokio::select! {
+                biased;
+                () = shutdown.cancelled() => return,
+                updated = watcher.updated() => {
+                    if updated.is_err() {
+                        // The endpoint was dropped; nothing left to watch.
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    SelfRegistrationHandle { task }
+}
+
+/// Builds this pass's [`ClusterRegistration`] for `cluster`, from `addr`'s current relay url and
+/// direct addresses.
+fn registration_from_addr(cluster: &SelfClusterInfo, addr: EndpointAddr) -> ClusterRegistration {
+    ClusterRegistration {
+        cluster_id: cluster.cluster_id.clone(),
+        endpoint_id: addr.endpoint_id,
+        relay_url: addr.relay_url().map(ToString::to_string),
+        direct_addresses: addr.direct_addresses().copied().collect(),
+        services: cluster.services.clone(),
+        service_ports: cluster.service_ports.clone(),
+        updated_at: SystemTime::now(),
+        pinned: false,
+    }
+}
+
+/// Handle to a self-registration loop spawned with [`spawn_self_registration`]; dropping it stops
+/// re-registering.
+#[derive(Debug)]
+pub struct SelfRegistrationHandle {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for SelfRegistrationHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::RelayMode;
+    use snafu::ResultExt;
+
+    use super::*;
+    use crate::error::BindEndpointSnafu;
+
+    async fn bind_endpoint() -> crate::error::Result<Endpoint> {
+        Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)
+    }
+
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("condition never became true within the timeout");
+    }
+
+    #[tokio::test]
+    async fn registers_the_cluster_immediately_with_the_endpoints_id() {
+        let endpoint = bind_endpoint().await.unwrap();
+        let node_id = endpoint.id();
+        let discovery = Arc::new(DiscoveryManager::new());
+        let _handle = spawn_self_registration(
+            &endpoint,
+            discovery.clone(),
+            SelfClusterInfo {
+                cluster_id: "self".to_string(),
+                services: vec!["svc".to_string()],
+                service_ports: Vec::new(),
+            },
+            CancellationToken::new(),
+        );
+
+        wait_until(|| discovery.get_cluster("self").is_some()).await;
+
+        let registered = discovery.get_cluster("self").unwrap();
+        assert_eq!(registered.endpoint_id, node_id);
+        assert_eq!(registered.services, vec!["svc".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_handle_stops_further_registration() {
+        let endpoint = bind_endpoint().await.unwrap();
+        let discovery = Arc::new(DiscoveryManager::new());
+        let handle = spawn_self_registration(
+            &endpoint,
+            discovery.clone(),
+            SelfClusterInfo {
+                cluster_id: "self".to_string(),
+                services: Vec::new(),
+                service_ports: Vec::new(),
+            },
+            CancellationToken::new(),
+        );
+        wait_until(|| discovery.get_cluster("self").is_some()).await;
+
+        drop(handle);
+        assert!(discovery.remove_cluster("self"));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        assert!(discovery.get_cluster("self").is_none());
+    }
+}
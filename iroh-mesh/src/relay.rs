@@ -0,0 +1,163 @@
+//! Which relay servers a [`crate::proxy::MeshProxy`] or [`crate::agent::MeshAgent`] endpoint
+//! falls back to when it can't reach a peer directly, e.g. because both sides are behind NAT.
+//!
+//! [`RelayModeConfig`] mirrors [`iroh::RelayMode`], existing only because that type isn't
+//! serializable: it holds a live [`iroh::RelayMap`] rather than the URLs this crate's config
+//! files spell it as.
+//!
+//! [`RelayModeConfig::Custom`] takes one or more relay URLs rather than pinning a single one, so
+//! an operator-run relay deployment can spread load across, or survive the loss of, any one
+//! relay. This crate doesn't pick among them itself: iroh's own endpoint already measures each
+//! relay's reachability and latency and prefers the best one on its own (see
+//! [`iroh::Endpoint::addr`]'s `relay_url`, which reports whichever one it's currently chosen),
+//! falling back automatically when that one stops answering. Listing more than one URL here is
+//! enough to hand it a real choice to make.
+
+use iroh::{RelayMap, RelayMode, RelayUrl};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{InvalidConfigSnafu, Result};
+
+/// See this module's docs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayModeConfig {
+    /// iroh's own production relay servers, run by Number 0.
+    #[default]
+    Default,
+    /// iroh's staging relay servers, for testing against infrastructure separate from
+    /// production traffic.
+    Staging,
+    /// No relay. Connections only succeed when the peers can reach each other directly, which is
+    /// enough for a LAN-only mesh but not one that has to cross NATs.
+    Disabled,
+    /// One or more operator-run relays, e.g. for enterprises running their own relay
+    /// infrastructure or pinning a region. Listed in the order given, but that order is only a
+    /// preference iroh's own relay selection may or may not honor -- see the [module docs](self).
+    Custom(Vec<String>),
+}
+
+/// Resolves `config` into the [`RelayMode`] passed to an endpoint builder, parsing and
+/// validating every URL in [`RelayModeConfig::Custom`].
+pub(crate) fn resolve(config: &RelayModeConfig) -> Result<RelayMode> {
+    Ok(match config {
+        RelayModeConfig::Default => RelayMode::Default,
+        RelayModeConfig::Staging => RelayMode::Staging,
+        RelayModeConfig::Disabled => RelayMode::Disabled,
+        RelayModeConfig::Custom(urls) => {
+            let urls: Vec<RelayUrl> = urls
+                .iter()
+                .map(|url| {
+                    url.parse().map_err(|err| {
+                        InvalidConfigSnafu {
+                            reason: format!("invalid relay URL {url:?}: {err}"),
+                        }
+                        .build()
+                    })
+                })
+                .collect::<Result<_>>()?;
+            RelayMode::Custom(urls.into_iter().collect::<RelayMap>())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_resolves_to_iroh_s_default_relay_mode() {
+        assert_eq!(
+            resolve(&RelayModeConfig::default()).unwrap(),
+            RelayMode::Default
+        );
+    }
+
+    #[test]
+    fn a_custom_url_resolves_to_a_relay_map_containing_just_that_url() {
+        let mode = resolve(&RelayModeConfig::Custom(vec![
+            "https://relay.example.com".to_string(),
+        ]))
+        .unwrap();
+        let RelayMode::Custom(map) = mode else {
+            panic!("expected a custom relay mode, got {mode:?}");
+        };
+        assert_eq!(
+            map.urls::<Vec<_>>(),
+            vec!["https://relay.example.com/".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn a_custom_list_resolves_to_a_relay_map_containing_every_url() {
+        let mode = resolve(&RelayModeConfig::Custom(vec![
+            "https://relay-a.example.com".to_string(),
+            "https://relay-b.example.com".to_string(),
+        ]))
+        .unwrap();
+        let RelayMode::Custom(map) = mode else {
+            panic!("expected a custom relay mode, got {mode:?}");
+        };
+        assert_eq!(map.len(), 2);
+        assert!(map.contains_endpoint(&"https://relay-a.example.com/".parse().unwrap()));
+        assert!(map.contains_endpoint(&"https://relay-b.example.com/".parse().unwrap()));
+    }
+
+    #[test]
+    fn an_invalid_url_is_rejected() {
+        let err = resolve(&RelayModeConfig::Custom(vec!["not a url".to_string()])).unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::InvalidConfig { .. }));
+    }
+
+    #[tokio::test]
+    async fn an_endpoint_built_with_a_custom_relay_advertises_that_relay() {
+        use iroh::Endpoint;
+
+        let (_relay_map, relay_url, _relay_guard) = iroh::test_utils::run_relay_server()
+            .await
+            .expect("failed to start test relay");
+
+        let relay_mode = resolve(&RelayModeConfig::Custom(vec![relay_url.to_string()]))
+            .expect("valid relay URL");
+        let endpoint = Endpoint::empty_builder(relay_mode)
+            .insecure_skip_relay_cert_verify(true)
+            .bind()
+            .await
+            .expect("failed to bind endpoint");
+        endpoint.online().await;
+
+        assert_eq!(endpoint.addr().relay_url(), Some(&relay_url));
+    }
+
+    #[tokio::test]
+    async fn an_endpoint_built_with_two_relays_reports_one_of_them_as_active() {
+        use iroh::Endpoint;
+
+        let (_map_a, relay_url_a, _guard_a) = iroh::test_utils::run_relay_server()
+            .await
+            .expect("failed to start first test relay");
+        let (_map_b, relay_url_b, _guard_b) = iroh::test_utils::run_relay_server()
+            .await
+            .expect("failed to start second test relay");
+
+        let relay_mode = resolve(&RelayModeConfig::Custom(vec![
+            relay_url_a.to_string(),
+            relay_url_b.to_string(),
+        ]))
+        .expect("valid relay URLs");
+        let endpoint = Endpoint::empty_builder(relay_mode)
+            .insecure_skip_relay_cert_verify(true)
+            .bind()
+            .await
+            .expect("failed to bind endpoint");
+        endpoint.online().await;
+
+        // Which of the two iroh actually picked as this endpoint's active relay is up to its own
+        // latency-based selection (see this module's docs) -- both are valid, reachable answers.
+        let active = endpoint.addr().relay_url().cloned();
+        assert!(
+            active == Some(relay_url_a) || active == Some(relay_url_b),
+            "expected the active relay to be one of the two configured, got {active:?}"
+        );
+    }
+}
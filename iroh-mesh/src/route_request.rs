@@ -0,0 +1,215 @@
+//! A small frame carrying the client-side routing decision across a dialed cluster-to-cluster
+//! connection, so the remote side's backend can authorize (or just log) based on the original
+//! caller instead of only the mesh hop it arrived over.
+//!
+//! [`MeshProxy::dial_cluster`](crate::proxy::MeshProxy::dial_cluster) hands back a raw
+//! [`iroh::endpoint::Connection`] with nothing written to or read from it -- this crate has no
+//! general request/response framing over that connection yet (see
+//! [`crate::error::MeshError::ProtocolMismatch`]'s docs). [`RouteRequest`] does not try to add
+//! one; it only defines a single frame, sent once up front on its own uni stream and encoded the
+//! same way this crate already encodes things for wire transfer
+//! ([`VersionInfo`](crate::versioninfo::VersionInfo)'s JSON over HTTP), the same shape
+//! [`crate::health::probe_cluster_reachable`] already opens a uni stream to send (there, an empty
+//! one) on a dialed connection.
+//!
+//! **Trust.** This frame only carries information the *sending* proxy already knows locally --
+//! which cluster it is and which client address dialed in -- and says nothing about the iroh
+//! connection's authenticity. The iroh handshake already authenticates the *transport* peer, but
+//! nothing here stops that peer from lying about `source_cluster_id` or `original_client_addr`
+//! inside the frame itself. Treat a decoded [`RouteRequest`] as "what the dialing proxy says",
+//! only meaningful once the connection it arrived on is otherwise known to be the cluster it
+//! claims -- the same caveat [`crate::authz`]'s module docs spell out for
+//! [`crate::authz::AuthzPolicy`].
+//!
+//! [`MeshProxy::forward_tcp_to_service`](crate::proxy::MeshProxy::forward_tcp_to_service) sends
+//! one of these ahead of the bidirectional stream it tunnels a client over, and
+//! `MeshProxy`'s own accept loop (started by [`MeshProxy::run_on_many`] alongside its client-facing
+//! listeners) reads it back to learn [`RouteRequest::service`] -- which local backend, from its own
+//! [`crate::config::ProxyConfig::routes`], to splice the following bidirectional stream with.
+
+use std::net::SocketAddr;
+
+use iroh::endpoint::Connection;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, ensure};
+
+use crate::error::{
+    ProtocolMismatchSnafu, Result, RouteRequestDecodeSnafu, RouteRequestReadSnafu,
+    RouteRequestSendSnafu,
+};
+
+/// Frames larger than this are rejected before any attempt to parse them: a [`RouteRequest`]
+/// only ever holds a cluster id, a socket address and a service name, so anything near this size is either
+/// malformed or not a [`RouteRequest`] at all.
+pub const MAX_ROUTE_REQUEST_BYTES: usize = 4 * 1024;
+
+/// The only frame version this build knows how to encode and decode. See [`RouteRequest::read_from`].
+const FRAME_VERSION: u32 = 1;
+
+/// Client-side routing context sent ahead of a dial, for the remote side's backend to authorize
+/// or log against. See the [module docs](self) for what this frame does and does not guarantee.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteRequest {
+    /// The cluster id the dialing proxy claims to be, or `None` if it names none -- which this
+    /// crate's proxy always does, since [`crate::config::ProxyConfig`] has no field for its own
+    /// cluster id.
+    pub source_cluster_id: Option<String>,
+    /// The address of the original local client whose connection this dial is forwarding.
+    /// `None` if the dial isn't forwarding a specific client connection.
+    pub original_client_addr: Option<SocketAddr>,
+    /// The service name the dialing side resolved this tunnel for, e.g. via
+    /// [`crate::proxy::MeshProxy::dial_service`]. The accepting side looks this up in its own
+    /// [`crate::config::ProxyConfig::routes`] to pick a local backend for the bidirectional
+    /// stream that follows.
+    #[serde(default)]
+    pub service: String,
+}
+
+impl RouteRequest {
+    /// Encodes this frame and sends it on a fresh uni stream opened on `conn`, then finishes
+    /// that stream. Mirrors [`crate::health::probe_cluster_reachable`]'s own
+    /// open-a-uni-stream-and-finish-it shape, but carries a body instead of an empty liveness
+    /// ping.
+    pub async fn send_on(&self, conn: &Connection) -> Result<()> {
+        let body = serde_json::to_vec(self).expect("RouteRequest always serializes");
+        let mut frame = Vec::with_capacity(4 + body.len());
+        frame.extend_from_slice(&FRAME_VERSION.to_be_bytes());
+        frame.extend_from_slice(&body);
+
+        let mut stream = conn
+            .open_uni()
+            .await
+            .map_err(iroh::endpoint::WriteError::from)
+            .map_err(Box::new)
+            .context(RouteRequestSendSnafu)?;
+        stream
+            .write_all(&frame)
+            .await
+            .map_err(Box::new)
+            .context(RouteRequestSendSnafu)?;
+        stream
+            .finish()
+            .map_err(iroh::endpoint::WriteError::from)
+            .map_err(Box::new)
+            .context(RouteRequestSendSnafu)?;
+        Ok(())
+    }
+
+    /// Reads and decodes a [`RouteRequest`] previously sent by [`Self::send_on`] from `stream`,
+    /// rejecting anything over [`MAX_ROUTE_REQUEST_BYTES`] or carrying a frame version newer than
+    /// [`FRAME_VERSION`].
+    pub async fn read_from(mut stream: iroh::endpoint::RecvStream) -> Result<Self> {
+        let raw = stream
+            .read_to_end(MAX_ROUTE_REQUEST_BYTES)
+            .await
+            .map_err(Box::new)
+            .context(RouteRequestReadSnafu)?;
+        // A frame shorter than the version header can't be a valid one; fall through to
+        // decoding it anyway (and so failing as a decode error) rather than a separate check.
+        let Some((version, body)) = raw.split_first_chunk::<4>() else {
+            return serde_json::from_slice(&raw).context(RouteRequestDecodeSnafu);
+        };
+        let version = u32::from_be_bytes(*version);
+        ensure!(
+            version <= FRAME_VERSION,
+            ProtocolMismatchSnafu {
+                peer_max: version,
+                ours: FRAME_VERSION,
+            }
+        );
+        serde_json::from_slice(body).context(RouteRequestDecodeSnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use iroh::{Endpoint, RelayMode};
+
+    use super::*;
+    use crate::error::BindEndpointSnafu;
+
+    const TEST_ALPN: &[u8] = b"route-request-test";
+
+    #[tokio::test]
+    async fn a_route_request_survives_a_real_iroh_stream_round_trip() -> Result<()> {
+        let server = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let server_addr = server.addr();
+
+        let accept_task = tokio::spawn(async move {
+            let incoming = server.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let recv = conn.accept_uni().await.expect("no uni stream arrived");
+            RouteRequest::read_from(recv).await
+        });
+
+        let client = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+
+        let sent = RouteRequest {
+            source_cluster_id: Some("cluster-a".to_string()),
+            original_client_addr: Some("127.0.0.1:4242".parse().unwrap()),
+            service: "checkout".to_string(),
+        };
+        sent.send_on(&conn).await?;
+
+        let received = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked")?;
+        assert_eq!(received.source_cluster_id.as_deref(), Some("cluster-a"));
+        assert_eq!(received, sent);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_route_request_with_no_client_addr_round_trips_as_none() -> Result<()> {
+        let server = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let server_addr = server.addr();
+
+        let accept_task = tokio::spawn(async move {
+            let incoming = server.accept().await.expect("endpoint closed");
+            let conn = incoming.await.expect("handshake failed");
+            let recv = conn.accept_uni().await.expect("no uni stream arrived");
+            RouteRequest::read_from(recv).await
+        });
+
+        let client = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+
+        let sent = RouteRequest {
+            source_cluster_id: None,
+            original_client_addr: None,
+            service: String::new(),
+        };
+        sent.send_on(&conn).await?;
+
+        let received = tokio::time::timeout(Duration::from_secs(5), accept_task)
+            .await
+            .expect("accept side timed out")
+            .expect("accept task panicked")?;
+        assert_eq!(received, sent);
+        Ok(())
+    }
+}
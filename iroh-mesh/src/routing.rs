@@ -0,0 +1,504 @@
+//! Pluggable extraction of the key a [`crate::proxy::MeshProxy`] running in
+//! [`crate::config::ProxyMode::Http`] routes an accepted connection by.
+//!
+//! The proxy's interception mechanics -- peeking a connection's leading bytes without consuming
+//! them for good (see [`crate::peek::PeekStream`]) -- don't care what key those bytes resolve to;
+//! [`RoutingStrategy`] is the extension point that decides, and
+//! [`crate::config::ProxyConfig::routing`] selects among the built-in implementations in this
+//! module. The default, [`HostHeaderStrategy`], matches this crate's behavior before this setting
+//! existed and keeps using its original, directly-tested code path in [`crate::proxy`] rather than
+//! being rebuilt on top of this trait, so choosing it is a guaranteed no-op. The other strategies
+//! route through a newer, more general path (see `crate::proxy::route_and_forward_with_strategy`).
+//!
+//! Unlike [`crate::election::LeaseStore`] or [`crate::clusterevents::ClusterEventRecorder`],
+//! [`RoutingStrategy`] isn't a slot for callers to plug an external implementation into today --
+//! [`crate::config::ProxyConfig`] is fully (de)serializable, so its `routing` field is the closed
+//! [`RoutingStrategyConfig`] enum rather than a trait object. The trait still exists as the real
+//! extension point, exercised directly by this module's tests; giving [`ProxyConfig`] a way to
+//! carry a caller-supplied `Arc<dyn RoutingStrategy>` would need it to stop deriving
+//! `Serialize`/`Deserialize`, which is a bigger change than this module makes on its own.
+//!
+//! [`ProxyConfig`]: crate::config::ProxyConfig
+
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ProtocolLimits, httpsniff};
+
+/// The value a connection is routed by, looked up against [`crate::config::ProxyConfig::routes`]
+/// the same way a `Host` header always has been.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoutingKey(String);
+
+impl RoutingKey {
+    /// Builds a key to resolve or route by directly, e.g. for
+    /// [`crate::proxy::MeshProxy::resolve_route`], bypassing [`RoutingStrategy::extract_key`].
+    pub fn new(key: impl Into<String>) -> Self {
+        Self(key.into())
+    }
+
+    /// Borrows this key as a route table lookup key.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RoutingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The protocol/mode an accepted connection was forwarded under, detected during routing-key
+/// extraction (see [`RoutingStrategy::connection_mode`]) and reused as-is for both
+/// `crate::metrics::Metrics::record_connection_mode` and the access log in `crate::proxy`, so the
+/// two can't drift apart the way two independently-chosen string labels could.
+///
+/// [`Self::Socks5`] and [`Self::Udp`] aren't reachable today -- this crate's proxy has no SOCKS5
+/// handshake of its own, and the UDP it relays between clusters (see
+/// [`crate::proxy::MeshProxy::forward_udp_to_service`]) isn't tagged with a connection mode -- they
+/// exist so a dashboard or log query for them finds nothing rather than an unrecognized label,
+/// and so that adding either protocol later slots into this enum instead of needing a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionMode {
+    /// [`crate::config::ProxyMode::Tcp`], or a pinned listener (see
+    /// `crate::config::ProxyConfig::listeners`): bytes are forwarded without being parsed as any
+    /// application protocol.
+    TransparentTcp,
+    /// [`crate::config::ProxyMode::Http`] routing by a parsed HTTP request, whether or not TLS
+    /// was terminated first (see `crate::proxy::route_and_forward_tls`).
+    Http,
+    /// [`SniStrategy`]: routed by the SNI server name from a TLS `ClientHello`, without
+    /// terminating TLS.
+    TlsPassthrough,
+    /// A SOCKS5 proxy handshake. Never produced today -- see this enum's docs.
+    Socks5,
+    /// Raw UDP traffic. Never produced today -- see this enum's docs.
+    Udp,
+}
+
+impl fmt::Display for ConnectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TransparentTcp => "transparent-tcp",
+            Self::Http => "http",
+            Self::TlsPassthrough => "tls-passthrough",
+            Self::Socks5 => "socks5",
+            Self::Udp => "udp",
+        })
+    }
+}
+
+/// Extracts the key a connection is routed by from its leading bytes.
+///
+/// `crate::proxy::route_and_forward_with_strategy` calls [`Self::extract_key`] repeatedly as more
+/// bytes arrive off the wire, buffering everything seen so far into `peeked`, so implementations
+/// return `None` to ask for more before a decision can be made -- exactly like the header-based
+/// strategies do until a complete header block has arrived. Giving up for good isn't
+/// representable here: a strategy that will never find a key in `peeked` (e.g. a non-TLS
+/// connection under [`SniStrategy`]) just keeps returning `None` until the caller's own size cap
+/// closes the connection.
+pub trait RoutingStrategy: Send + Sync + fmt::Debug {
+    /// Attempts to extract a routing key from `peeked` and `orig_dst` (the connection's original
+    /// destination before interception, if the platform exposes one -- see
+    /// [`OriginalDestinationPortStrategy`]).
+    fn extract_key(&self, peeked: &[u8], orig_dst: Option<SocketAddr>) -> Option<RoutingKey>;
+
+    /// The [`ConnectionMode`] a connection routed by this strategy should be tagged with, for
+    /// `crate::metrics::Metrics::record_connection_mode` and the access log in `crate::proxy`.
+    /// Defaults to [`ConnectionMode::Http`], since every built-in strategy but [`SniStrategy`]
+    /// extracts its key from a parsed (or about-to-be-parsed) HTTP request rather than leaving
+    /// the connection encrypted end to end.
+    fn connection_mode(&self) -> ConnectionMode {
+        ConnectionMode::Http
+    }
+
+    /// How many bytes `crate::proxy::route_and_forward_with_strategy` buffers from `peeked`
+    /// before giving up on [`Self::extract_key`] ever finding a key, failing the connection with
+    /// [`crate::error::MeshError::RoutingKeyNotFound`]. Defaults to
+    /// [`ProtocolLimits::max_header_bytes`], since every built-in strategy but [`SniStrategy`]
+    /// looks for a key in what's ultimately a parsed HTTP request.
+    fn max_peek_bytes(&self, limits: &ProtocolLimits) -> usize {
+        limits.max_header_bytes
+    }
+
+    /// Whether `peeked` (the bytes [`Self::extract_key`] found a key in, and everything read
+    /// alongside them) asked to switch protocols, e.g. a WebSocket handshake -- such a
+    /// connection's bytes past that point aren't further requests and shouldn't be checked by
+    /// [`Self::find_mismatched_route`]. Strategies with no notion of this always return `false`.
+    fn is_upgrade(&self, _peeked: &[u8]) -> bool {
+        false
+    }
+
+    /// Checks whether a pipelined request already buffered in `peeked` asks for a different
+    /// route than `expected`, the same smuggling check [`crate::httpsniff::find_mismatched_host`]
+    /// has always done for `Host` routing. Strategies with no such notion always return `None`.
+    fn find_mismatched_route(&self, _peeked: &[u8], _expected: &RoutingKey) -> Option<RoutingKey> {
+        None
+    }
+}
+
+/// Routes by the `Host` header of the connection's first HTTP/1.1 request.
+///
+/// This is [`RoutingStrategyConfig`]'s default, matching this crate's behavior before routing
+/// strategies existed; see this module's docs for why choosing it doesn't change which code path
+/// a connection takes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostHeaderStrategy;
+
+impl RoutingStrategy for HostHeaderStrategy {
+    fn extract_key(&self, peeked: &[u8], _orig_dst: Option<SocketAddr>) -> Option<RoutingKey> {
+        httpsniff::header_value_in_complete_block(peeked, "host").map(RoutingKey)
+    }
+
+    fn is_upgrade(&self, peeked: &[u8]) -> bool {
+        httpsniff::first_header_block(peeked).is_some_and(httpsniff::is_upgrade_request)
+    }
+
+    fn find_mismatched_route(&self, peeked: &[u8], expected: &RoutingKey) -> Option<RoutingKey> {
+        let trailing = httpsniff::trailing_after_first_header_block(peeked)?;
+        httpsniff::find_mismatched_host(trailing, expected.as_str()).map(RoutingKey)
+    }
+}
+
+/// Routes by an arbitrary HTTP header, for deployments that stamp their own routing key (e.g. a
+/// sidecar setting `x-routing-key`) rather than relying on `Host`.
+#[derive(Debug, Clone)]
+pub struct HeaderStrategy {
+    header_name: String,
+}
+
+impl HeaderStrategy {
+    /// Routes by `header_name`'s value, matched case-insensitively like every HTTP header.
+    pub fn new(header_name: impl Into<String>) -> Self {
+        Self {
+            header_name: header_name.into(),
+        }
+    }
+}
+
+impl RoutingStrategy for HeaderStrategy {
+    fn extract_key(&self, peeked: &[u8], _orig_dst: Option<SocketAddr>) -> Option<RoutingKey> {
+        httpsniff::header_value_in_complete_block(peeked, &self.header_name).map(RoutingKey)
+    }
+
+    fn is_upgrade(&self, peeked: &[u8]) -> bool {
+        httpsniff::first_header_block(peeked).is_some_and(httpsniff::is_upgrade_request)
+    }
+
+    fn find_mismatched_route(&self, peeked: &[u8], expected: &RoutingKey) -> Option<RoutingKey> {
+        let trailing = httpsniff::trailing_after_first_header_block(peeked)?;
+        httpsniff::find_mismatched_header(trailing, &self.header_name, expected.as_str())
+            .map(RoutingKey)
+    }
+}
+
+/// Routes by the SNI server name from a TLS `ClientHello`, without terminating TLS -- for
+/// deployments that want to route encrypted traffic by SNI and let the backend itself terminate
+/// TLS, as an alternative to [`crate::config::ProxyConfig::tls`] terminating it locally.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SniStrategy;
+
+impl RoutingStrategy for SniStrategy {
+    fn extract_key(&self, peeked: &[u8], _orig_dst: Option<SocketAddr>) -> Option<RoutingKey> {
+        parse_sni(peeked).map(RoutingKey)
+    }
+
+    fn connection_mode(&self) -> ConnectionMode {
+        ConnectionMode::TlsPassthrough
+    }
+
+    fn max_peek_bytes(&self, limits: &ProtocolLimits) -> usize {
+        limits.max_sni_peek
+    }
+}
+
+/// Routes by the port a connection was originally addressed to before interception, e.g. one an
+/// iptables `REDIRECT` rule preserved as `SO_ORIGINAL_DST`.
+///
+/// The `orig_dst` this strategy reads comes from [`crate::socket::original_dst`], which the
+/// accept loop only calls when [`crate::config::ProxyConfig::enable_interception`] is set;
+/// otherwise it's always `None` here, same as for every other strategy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OriginalDestinationPortStrategy;
+
+impl RoutingStrategy for OriginalDestinationPortStrategy {
+    fn extract_key(&self, _peeked: &[u8], orig_dst: Option<SocketAddr>) -> Option<RoutingKey> {
+        orig_dst.map(|addr| RoutingKey(addr.port().to_string()))
+    }
+}
+
+/// Selects which [`RoutingStrategy`] a [`crate::proxy::MeshProxy`] running in
+/// [`crate::config::ProxyMode::Http`] extracts its routing key with. See
+/// [`crate::config::ProxyConfig::routing`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingStrategyConfig {
+    /// [`HostHeaderStrategy`]: route by the `Host` header of the connection's first HTTP/1.1
+    /// request. This crate's behavior before [`crate::config::ProxyConfig::routing`] existed.
+    #[default]
+    Host,
+    /// [`HeaderStrategy`]: route by an arbitrary HTTP header's value.
+    Header(String),
+    /// [`SniStrategy`]: route by the SNI server name from a TLS `ClientHello`, without
+    /// terminating TLS.
+    Sni,
+    /// [`OriginalDestinationPortStrategy`]: route by the connection's original destination port.
+    OriginalDestinationPort,
+}
+
+impl RoutingStrategyConfig {
+    /// Builds the [`RoutingStrategy`] this variant selects.
+    pub(crate) fn build(&self) -> Arc<dyn RoutingStrategy> {
+        match self {
+            Self::Host => Arc::new(HostHeaderStrategy),
+            Self::Header(name) => Arc::new(HeaderStrategy::new(name.clone())),
+            Self::Sni => Arc::new(SniStrategy),
+            Self::OriginalDestinationPort => Arc::new(OriginalDestinationPortStrategy),
+        }
+    }
+}
+
+/// Extracts the SNI server name from a TLS `ClientHello` held in full within `buf`, tolerating
+/// `buf` not yet holding one at all: returns `None` for anything that isn't (yet, or ever) a
+/// complete `ClientHello` carrying an SNI extension, matching
+/// [`RoutingStrategy::extract_key`]'s "ask for more" contract.
+fn parse_sni(buf: &[u8]) -> Option<String> {
+    const RECORD_HEADER_LEN: usize = 5;
+    if buf.len() < RECORD_HEADER_LEN || buf[0] != 0x16 {
+        return None;
+    }
+    let record_len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    let payload = buf.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + record_len)?;
+
+    let handshake_type = *payload.first()?;
+    if handshake_type != 0x01 {
+        return None;
+    }
+    let handshake_len =
+        u32::from_be_bytes([0, *payload.get(1)?, *payload.get(2)?, *payload.get(3)?]) as usize;
+    let body = payload.get(4..4 + handshake_len)?;
+    server_name_from_client_hello(body)
+}
+
+fn server_name_from_client_hello(body: &[u8]) -> Option<String> {
+    let mut pos = 2 + 32; // client_version, random
+    let session_id_len = *body.get(pos)? as usize;
+    pos += 1 + session_id_len;
+    let cipher_suites_len = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+    pos += 2 + cipher_suites_len;
+    let compression_methods_len = *body.get(pos)? as usize;
+    pos += 1 + compression_methods_len;
+    let extensions_len = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+    pos += 2;
+    let extensions = body.get(pos..pos + extensions_len)?;
+    server_name_from_extensions(extensions)
+}
+
+fn server_name_from_extensions(mut extensions: &[u8]) -> Option<String> {
+    while extensions.len() >= 4 {
+        let extension_type = u16::from_be_bytes([extensions[0], extensions[1]]);
+        let extension_len = u16::from_be_bytes([extensions[2], extensions[3]]) as usize;
+        let data = extensions.get(4..4 + extension_len)?;
+        if extension_type == 0x0000 {
+            return server_name_from_sni_extension(data);
+        }
+        extensions = &extensions[4 + extension_len..];
+    }
+    None
+}
+
+fn server_name_from_sni_extension(data: &[u8]) -> Option<String> {
+    let list_len = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+    let mut list = data.get(2..2 + list_len)?;
+    while list.len() >= 3 {
+        let name_type = list[0];
+        let name_len = u16::from_be_bytes([list[1], list[2]]) as usize;
+        let name = list.get(3..3 + name_len)?;
+        if name_type == 0x00 {
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+        list = &list[3 + name_len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_header_strategy_waits_for_a_complete_header_block() {
+        let strategy = HostHeaderStrategy;
+        assert_eq!(
+            strategy.extract_key(b"GET / HTTP/1.1\r\nHost: svc-a", None),
+            None
+        );
+
+        let complete = b"GET / HTTP/1.1\r\nHost: svc-a\r\n\r\n";
+        assert_eq!(
+            strategy.extract_key(complete, None).unwrap().as_str(),
+            "svc-a"
+        );
+    }
+
+    #[test]
+    fn host_header_strategy_detects_upgrades_and_mismatched_routes() {
+        let strategy = HostHeaderStrategy;
+        let upgrade =
+            b"GET /ws HTTP/1.1\r\nHost: svc-a\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+        assert!(strategy.is_upgrade(upgrade));
+        assert!(!strategy.is_upgrade(b"GET / HTTP/1.1\r\nHost: svc-a\r\n\r\n"));
+
+        let expected = RoutingKey("svc-a".to_string());
+        let mut peeked = b"GET / HTTP/1.1\r\nHost: svc-a\r\n\r\n".to_vec();
+        peeked.extend_from_slice(b"GET /other HTTP/1.1\r\nHost: svc-b\r\n\r\n");
+        assert_eq!(
+            strategy
+                .find_mismatched_route(&peeked, &expected)
+                .unwrap()
+                .as_str(),
+            "svc-b"
+        );
+    }
+
+    #[test]
+    fn header_strategy_extracts_a_custom_header_case_insensitively() {
+        let strategy = HeaderStrategy::new("X-Routing-Key");
+        let request = b"GET / HTTP/1.1\r\nx-routing-key: tenant-a\r\n\r\n";
+        assert_eq!(
+            strategy.extract_key(request, None).unwrap().as_str(),
+            "tenant-a"
+        );
+    }
+
+    #[test]
+    fn sni_strategy_extracts_the_server_name_from_a_client_hello() {
+        let hello = sample_client_hello("example.test");
+        assert_eq!(
+            SniStrategy.extract_key(&hello, None).unwrap().as_str(),
+            "example.test"
+        );
+    }
+
+    #[test]
+    fn sni_strategy_asks_for_more_bytes_on_a_truncated_client_hello() {
+        let hello = sample_client_hello("example.test");
+        assert_eq!(
+            SniStrategy.extract_key(&hello[..hello.len() - 10], None),
+            None
+        );
+    }
+
+    #[test]
+    fn sni_strategy_ignores_non_tls_bytes() {
+        assert_eq!(
+            SniStrategy.extract_key(b"GET / HTTP/1.1\r\n\r\n", None),
+            None
+        );
+    }
+
+    #[test]
+    fn original_destination_port_strategy_reads_the_port_when_given_one() {
+        let addr: SocketAddr = "10.0.0.5:9443".parse().unwrap();
+        assert_eq!(
+            OriginalDestinationPortStrategy
+                .extract_key(b"", Some(addr))
+                .unwrap()
+                .as_str(),
+            "9443"
+        );
+        assert_eq!(OriginalDestinationPortStrategy.extract_key(b"", None), None);
+    }
+
+    #[test]
+    fn connection_mode_defaults_to_http_except_for_sni_routing() {
+        assert_eq!(HostHeaderStrategy.connection_mode(), ConnectionMode::Http);
+        assert_eq!(
+            HeaderStrategy::new("x-routing-key").connection_mode(),
+            ConnectionMode::Http
+        );
+        assert_eq!(
+            OriginalDestinationPortStrategy.connection_mode(),
+            ConnectionMode::Http
+        );
+        assert_eq!(
+            SniStrategy.connection_mode(),
+            ConnectionMode::TlsPassthrough
+        );
+    }
+
+    #[test]
+    fn max_peek_bytes_defaults_to_max_header_bytes_except_for_sni_routing() {
+        let limits = ProtocolLimits {
+            max_header_bytes: 1024,
+            max_sni_peek: 256,
+        };
+        assert_eq!(HostHeaderStrategy.max_peek_bytes(&limits), 1024);
+        assert_eq!(
+            HeaderStrategy::new("x-routing-key").max_peek_bytes(&limits),
+            1024
+        );
+        assert_eq!(
+            OriginalDestinationPortStrategy.max_peek_bytes(&limits),
+            1024
+        );
+        assert_eq!(SniStrategy.max_peek_bytes(&limits), 256);
+    }
+
+    #[test]
+    fn routing_strategy_config_builds_the_matching_strategy() {
+        assert_eq!(
+            RoutingStrategyConfig::Host
+                .build()
+                .extract_key(b"GET / HTTP/1.1\r\nHost: svc-a\r\n\r\n", None)
+                .unwrap()
+                .as_str(),
+            "svc-a"
+        );
+        assert_eq!(
+            RoutingStrategyConfig::OriginalDestinationPort
+                .build()
+                .extract_key(b"", "127.0.0.1:443".parse().ok())
+                .unwrap()
+                .as_str(),
+            "443"
+        );
+    }
+
+    /// Builds a minimal TLS 1.2 `ClientHello` record carrying `server_name` as its sole SNI
+    /// extension, for exercising [`SniStrategy`] without a real TLS handshake.
+    fn sample_client_hello(server_name: &str) -> Vec<u8> {
+        let name = server_name.as_bytes();
+
+        let mut server_name_entry = vec![0x00]; // name_type: host_name
+        server_name_entry.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        server_name_entry.extend_from_slice(name);
+
+        let mut server_name_list = (server_name_entry.len() as u16).to_be_bytes().to_vec();
+        server_name_list.extend_from_slice(&server_name_entry);
+
+        let mut sni_extension = vec![0x00, 0x00]; // extension_type: server_name
+        sni_extension.extend_from_slice(&(server_name_list.len() as u16).to_be_bytes());
+        sni_extension.extend_from_slice(&server_name_list);
+
+        let mut body = vec![0x03, 0x03]; // client_version: TLS 1.2
+        body.extend_from_slice(&[0u8; 32]); // random
+        body.push(0x00); // session_id_len
+        body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // cipher_suites
+        body.push(0x01); // compression_methods_len
+        body.push(0x00); // compression_methods: null
+        body.extend_from_slice(&(sni_extension.len() as u16).to_be_bytes());
+        body.extend_from_slice(&sni_extension);
+
+        let mut handshake = vec![0x01]; // handshake_type: client_hello
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]); // 24-bit length
+        handshake.extend_from_slice(&body);
+
+        let mut record = vec![0x16, 0x03, 0x01]; // content_type: handshake, version: TLS 1.0
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+}
@@ -0,0 +1,113 @@
+//! JSON Schema export for [`crate::config::ProxyConfig`] and [`crate::agent::AgentConfig`], so
+//! external validation tooling (editors, CI config linters) can check a TOML or JSON config
+//! against this crate's actual shape without running it.
+
+use schemars::Schema;
+
+/// The JSON Schema for [`crate::config::ProxyConfig`].
+pub fn proxy_config_schema() -> Schema {
+    schemars::schema_for!(crate::config::ProxyConfig)
+}
+
+/// The JSON Schema for [`crate::agent::AgentConfig`].
+pub fn agent_config_schema() -> Schema {
+    schemars::schema_for!(crate::agent::AgentConfig)
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonschema::validator_for;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{agent::AgentConfig, config::ProxyConfig};
+
+    /// A minimal [`ProxyConfig`], relying on `#[serde(default)]` for everything else, the same
+    /// way a hand-written TOML config naming only `listen_addr` would deserialize.
+    fn minimal_proxy_config() -> serde_json::Value {
+        serde_json::to_value(json!({"listen_addr": "127.0.0.1:0"}))
+            .and_then(serde_json::from_value::<ProxyConfig>)
+            .map(|c| serde_json::to_value(c).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn proxy_config_schema_validates_a_minimal_config() {
+        let schema = serde_json::to_value(proxy_config_schema()).unwrap();
+        let validator = validator_for(&schema).unwrap();
+
+        let instance = minimal_proxy_config();
+        assert!(
+            validator.is_valid(&instance),
+            "a minimal ProxyConfig should satisfy its own schema: {:?}",
+            validator.iter_errors(&instance).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn proxy_config_schema_rejects_a_wrong_typed_field() {
+        let schema = serde_json::to_value(proxy_config_schema()).unwrap();
+        let validator = validator_for(&schema).unwrap();
+
+        let mut instance = minimal_proxy_config();
+        // `listen_addr` is a `SocketAddr`, which schemars (and the real `Deserialize` impl)
+        // expects to be a string, not a number.
+        instance["listen_addr"] = json!(1234);
+
+        assert!(!validator.is_valid(&instance));
+    }
+
+    #[test]
+    fn agent_config_schema_validates_a_minimal_config() {
+        let schema = serde_json::to_value(agent_config_schema()).unwrap();
+        let validator = validator_for(&schema).unwrap();
+
+        let instance = serde_json::to_value(AgentConfig {
+            api_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery: None,
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: None,
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload: None,
+            self_registration: None,
+            metrics_addr: None,
+        })
+        .unwrap();
+        assert!(
+            validator.is_valid(&instance),
+            "errors: {:?}",
+            validator.iter_errors(&instance).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn agent_config_schema_rejects_a_wrong_typed_field() {
+        let schema = serde_json::to_value(agent_config_schema()).unwrap();
+        let validator = validator_for(&schema).unwrap();
+
+        let mut instance = serde_json::to_value(AgentConfig {
+            api_addr: "127.0.0.1:0".parse().unwrap(),
+            discovery: None,
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: None,
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload: None,
+            self_registration: None,
+            metrics_addr: None,
+        })
+        .unwrap();
+        instance["dual_stack"] = json!("not a bool");
+
+        assert!(!validator.is_valid(&instance));
+    }
+}
@@ -0,0 +1,176 @@
+//! Loading the iroh secret key that identifies a [`crate::proxy::MeshProxy`] or
+//! [`crate::agent::MeshAgent`]'s endpoint, from a file, an environment variable, or inline.
+
+use std::path::PathBuf;
+
+use iroh_base::SecretKey;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{InvalidConfigSnafu, Result};
+
+/// Where to load an iroh secret key from, for [`load_or_create_secret_key`].
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretKeySource {
+    /// Reads the key from a file at this path, generating and persisting a new one there if it
+    /// doesn't exist yet.
+    File(PathBuf),
+    /// Reads the key from the environment variable named here, e.g. one populated from a
+    /// Kubernetes Secret. Never written back anywhere.
+    Env(String),
+    /// Uses the key given directly. Never written back anywhere.
+    Inline(String),
+}
+
+/// Loads the secret key identified by `source`.
+///
+/// A [`SecretKeySource::File`] pointing at a path that doesn't exist yet gets a freshly generated
+/// key persisted there, so the same identity is reused on the next call; `Env` and `Inline` are
+/// parsed directly and never touch disk. A missing environment variable, or an empty value from
+/// any source, is rejected with [`crate::error::MeshError::InvalidConfig`].
+pub async fn load_or_create_secret_key(source: &SecretKeySource) -> Result<SecretKey> {
+    match source {
+        SecretKeySource::File(path) => load_or_create_from_file(path).await,
+        SecretKeySource::Env(var) => {
+            let value = std::env::var(var).map_err(|_| {
+                InvalidConfigSnafu {
+                    reason: format!("environment variable {var} is not set"),
+                }
+                .build()
+            })?;
+            parse_key(&value)
+        }
+        SecretKeySource::Inline(value) => parse_key(value),
+    }
+}
+
+async fn load_or_create_from_file(path: &std::path::Path) -> Result<SecretKey> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => parse_key(&contents),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let key = SecretKey::generate(&mut rand::rng());
+            write_key_file(path, data_encoding::HEXLOWER.encode(&key.to_bytes())).await?;
+            Ok(key)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes freshly generated key material to `path` with `0o600` permissions from the moment the
+/// file is created, the same way SSH and TLS private keys are handled -- so it's never briefly
+/// (or, under a permissive umask, permanently) group- or world-readable.
+#[cfg(unix)]
+async fn write_key_file(path: &std::path::Path, contents: String) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(contents.as_bytes())
+    })
+    .await
+    .expect("blocking key file write task panicked")?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn write_key_file(path: &std::path::Path, contents: String) -> Result<()> {
+    tokio::fs::write(path, contents).await?;
+    Ok(())
+}
+
+fn parse_key(value: &str) -> Result<SecretKey> {
+    let value = value.trim();
+    snafu::ensure!(
+        !value.is_empty(),
+        InvalidConfigSnafu {
+            reason: "secret key value is empty",
+        }
+    );
+    value.parse().map_err(|err| {
+        InvalidConfigSnafu {
+            reason: format!("invalid secret key: {err}"),
+        }
+        .build()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn each_source_variant_round_trips_to_the_same_node_id() {
+        let original = SecretKey::generate(&mut rand::rng());
+        let hex = data_encoding::HEXLOWER.encode(&original.to_bytes());
+
+        let inline = load_or_create_secret_key(&SecretKeySource::Inline(hex.clone()))
+            .await
+            .unwrap();
+        assert_eq!(inline.public(), original.public());
+
+        // SAFETY: this variable's name is unique to this test, so no other test reads or writes
+        // it while it's set.
+        let var = "IROH_MESH_TEST_SECRET_KEY_ROUND_TRIP";
+        unsafe {
+            std::env::set_var(var, &hex);
+        }
+        let from_env = load_or_create_secret_key(&SecretKeySource::Env(var.to_string()))
+            .await
+            .unwrap();
+        unsafe {
+            std::env::remove_var(var);
+        }
+        assert_eq!(from_env.public(), original.public());
+
+        let path =
+            std::env::temp_dir().join(format!("iroh-mesh-test-secret-key-{}", original.public()));
+        let from_file = load_or_create_secret_key(&SecretKeySource::File(path.clone()))
+            .await
+            .unwrap();
+        // The file didn't exist yet, so a fresh key was generated and persisted rather than
+        // matching `original` -- round-trip it against itself by reading it back.
+        let reloaded = load_or_create_secret_key(&SecretKeySource::File(path.clone()))
+            .await
+            .unwrap();
+        assert_eq!(from_file.public(), reloaded.public());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_freshly_created_key_file_is_not_group_or_world_readable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!(
+            "iroh-mesh-test-secret-key-perms-{}",
+            SecretKey::generate(&mut rand::rng()).public()
+        ));
+        load_or_create_secret_key(&SecretKeySource::File(path.clone()))
+            .await
+            .unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn a_missing_env_var_is_rejected() {
+        let result =
+            load_or_create_secret_key(&SecretKeySource::Env("IROH_MESH_TEST_MISSING".to_string()))
+                .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn an_empty_inline_value_is_rejected() {
+        let result = load_or_create_secret_key(&SecretKeySource::Inline(String::new())).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Kubernetes-style equality-based label selector matching.
+//!
+//! Standalone for now: this crate has no Kubernetes client integration yet (no
+//! `KubernetesConfig`, no service enumeration), so there's nothing to filter against on/off this
+//! module's own matching. It exists so that whichever discovery source eventually lists services
+//! (Kubernetes or otherwise) has a ready-made, independently testable way to honor an operator's
+//! `label_selector` string, the same way `kubectl get pods -l ...` does.
+
+use std::collections::HashMap;
+
+use snafu::ensure;
+
+use crate::error::{InvalidConfigSnafu, Result};
+
+/// A single `key=value` or `key!=value` requirement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Requirement {
+    key: String,
+    value: String,
+    negated: bool,
+}
+
+/// A parsed equality-based label selector, e.g. `"mesh.iroh.io/export=true,tier!=internal"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+impl LabelSelector {
+    /// Parses a comma-separated list of `key=value` / `key!=value` requirements.
+    ///
+    /// An empty or whitespace-only string parses to a selector that matches every label set.
+    pub fn parse(selector: &str) -> Result<Self> {
+        let selector = selector.trim();
+        if selector.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut requirements = Vec::new();
+        for term in selector.split(',') {
+            let term = term.trim();
+            let (key, value, negated) = if let Some((key, value)) = term.split_once("!=") {
+                (key, value, true)
+            } else if let Some((key, value)) = term.split_once('=') {
+                (key, value, false)
+            } else {
+                return InvalidConfigSnafu {
+                    reason: format!("label selector requirement {term:?} is missing '=' or '!='"),
+                }
+                .fail();
+            };
+            let (key, value) = (key.trim(), value.trim());
+            ensure!(
+                !key.is_empty(),
+                InvalidConfigSnafu {
+                    reason: format!("label selector requirement {term:?} has an empty key"),
+                }
+            );
+            requirements.push(Requirement {
+                key: key.to_string(),
+                value: value.to_string(),
+                negated,
+            });
+        }
+        Ok(Self { requirements })
+    }
+
+    /// Whether `labels` satisfies every requirement in this selector.
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.requirements.iter().all(|req| {
+            let equals = labels.get(&req.key).is_some_and(|v| *v == req.value);
+            equals != req.negated
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn an_empty_selector_matches_everything() {
+        let selector = LabelSelector::parse("").unwrap();
+        assert!(selector.matches(&HashMap::new()));
+        assert!(selector.matches(&labels(&[("tier", "frontend")])));
+    }
+
+    #[test]
+    fn an_equality_requirement_matches_only_the_exact_value() {
+        let selector = LabelSelector::parse("mesh.iroh.io/export=true").unwrap();
+
+        assert!(selector.matches(&labels(&[("mesh.iroh.io/export", "true")])));
+        assert!(!selector.matches(&labels(&[("mesh.iroh.io/export", "false")])));
+        assert!(
+            !selector.matches(&HashMap::new()),
+            "a missing label shouldn't match"
+        );
+    }
+
+    #[test]
+    fn a_negated_requirement_matches_a_different_value_or_a_missing_label() {
+        let selector = LabelSelector::parse("tier!=internal").unwrap();
+
+        assert!(selector.matches(&labels(&[("tier", "frontend")])));
+        assert!(selector.matches(&HashMap::new()));
+        assert!(!selector.matches(&labels(&[("tier", "internal")])));
+    }
+
+    #[test]
+    fn multiple_comma_separated_requirements_must_all_hold() {
+        let selector = LabelSelector::parse(" mesh.iroh.io/export=true , tier!=internal ").unwrap();
+
+        assert!(selector.matches(&labels(&[
+            ("mesh.iroh.io/export", "true"),
+            ("tier", "frontend"),
+        ])));
+        assert!(!selector.matches(&labels(&[
+            ("mesh.iroh.io/export", "true"),
+            ("tier", "internal"),
+        ])));
+        assert!(!selector.matches(&labels(&[("tier", "frontend")])));
+    }
+
+    #[test]
+    fn a_requirement_missing_an_operator_is_rejected() {
+        let err = LabelSelector::parse("tier").unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::InvalidConfig { .. }));
+    }
+
+    #[test]
+    fn a_requirement_with_an_empty_key_is_rejected() {
+        let err = LabelSelector::parse("=true").unwrap_err();
+        assert!(matches!(err, crate::error::MeshError::InvalidConfig { .. }));
+    }
+}
@@ -0,0 +1,271 @@
+//! Caching of per-cluster service discovery, so repeated callers within a short window share one
+//! lookup instead of each triggering their own.
+//!
+//! Motivated by the same gap [`crate::election`] and [`crate::debounce`] document: once something
+//! actually implements [`crate::discovery::DiscoveryManager`]'s `discover_local_services` against
+//! a real Kubernetes API, [`crate::status`], registration, and the discovery task would otherwise
+//! each call it independently, multiplying API calls for data that's only refreshed on a watch
+//! event. [`CachedServiceSource`] sits in front of any [`ServiceSource`], returning the last
+//! fetched value for up to `ttl` before recomputing, with [`CachedServiceSource::invalidate`]
+//! letting a caller (e.g. an informer's watch event) force the next call to recompute regardless
+//! of `ttl`.
+//!
+//! [`WatchedServiceSource`] is the in-memory half of that watcher: a [`ServiceSource`] whose
+//! contents are pushed into it via [`WatchedServiceSource::apply`] instead of pulled from a
+//! backend on every [`ServiceSource::fetch`]. It only needs wiring to a real watch stream to
+//! become that missing piece.
+//!
+//! **Status: partial, not a real watcher.** This module is the piece a `kube::runtime::watcher`
+//! stream over `v1/Service` would feed -- not that watcher. There is still no `kube` dependency in
+//! this crate (see [`ServiceSource`]'s docs for why), so `DiscoveryManager::discover_local_services`
+//! is unchanged and nothing here talks to a real API server; [`WatchedServiceSource`] is exercised
+//! by hand-fed [`WatchEvent`]s in this module's tests.
+
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use tokio::time::{Duration, Instant};
+
+use crate::topology::ServiceInfo;
+
+/// Fetches the current set of services for a cluster. The source [`CachedServiceSource`]
+/// coalesces repeated calls to.
+///
+/// This crate has no Kubernetes client dependency (no `kube`, no generated informer), so nothing
+/// currently implements this against a real API server -- it's exercised by the fake source in
+/// this module's tests, and by [`WatchedServiceSource`] once something feeds it events.
+pub trait ServiceSource: Send + Sync + fmt::Debug {
+    /// Fetches the current services, hitting whatever backs this source.
+    fn fetch(&self) -> Vec<ServiceInfo>;
+}
+
+/// One update from a service watch, shaped like `kube::runtime::watcher::Event` (`Applied`,
+/// `Deleted`, `Restarted`) so a real watcher's stream could be mapped onto this directly once this
+/// crate takes on the `kube` dependency -- see the [module docs](self) and [`ServiceSource`]'s for
+/// why it doesn't yet. Defined locally rather than depending on `kube` for just this one type.
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    /// A service was added, or an existing one changed.
+    Applied(ServiceInfo),
+    /// A service was removed.
+    Deleted(ServiceInfo),
+    /// The watch (re)started, replacing the entire known set with this one -- what `kube`'s
+    /// watcher emits after an initial list or a relist following a lost connection.
+    Restarted(Vec<ServiceInfo>),
+}
+
+/// A [`ServiceSource`] whose contents are maintained by discrete [`WatchEvent`]s applied as they
+/// happen, rather than by polling a backend on every [`ServiceSource::fetch`] call.
+///
+/// This is the in-memory cache [`crate::discovery::DiscoveryManager`]'s `discover_local_services`
+/// needs; what's still missing is a real `kube::runtime::watcher` stream translating `v1/Service`
+/// (and optionally `EndpointSlice`) watch events into calls to [`Self::apply`], since this crate
+/// has no `kube` dependency to build one with (see the [module docs](self)).
+#[derive(Debug, Default)]
+pub struct WatchedServiceSource {
+    services: Mutex<HashMap<String, ServiceInfo>>,
+}
+
+impl WatchedServiceSource {
+    /// Creates an empty source, as if a watch had not yet delivered its initial list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies one update to the in-memory set, keyed by [`ServiceInfo::name`]. Takes effect
+    /// immediately, visible to the very next [`Self::fetch`] call -- there's no TTL to wait out
+    /// here, unlike [`CachedServiceSource`], because a watch reports changes as they happen
+    /// instead of needing to be repolled.
+    pub fn apply(&self, event: WatchEvent) {
+        let mut services = self.services.lock().expect("lock poisoned");
+        match event {
+            WatchEvent::Applied(service) => {
+                services.insert(service.name.clone(), service);
+            }
+            WatchEvent::Deleted(service) => {
+                services.remove(&service.name);
+            }
+            WatchEvent::Restarted(all) => {
+                *services = all.into_iter().map(|s| (s.name.clone(), s)).collect();
+            }
+        }
+    }
+}
+
+impl ServiceSource for WatchedServiceSource {
+    fn fetch(&self) -> Vec<ServiceInfo> {
+        self.services
+            .lock()
+            .expect("lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct CachedState {
+    services: Vec<ServiceInfo>,
+    fetched_at: Instant,
+}
+
+/// Wraps a [`ServiceSource`], returning a cached result for up to `ttl` before calling through
+/// again, safe under concurrent access.
+#[derive(Debug)]
+pub struct CachedServiceSource {
+    source: std::sync::Arc<dyn ServiceSource>,
+    ttl: Duration,
+    state: Mutex<Option<CachedState>>,
+}
+
+impl CachedServiceSource {
+    /// Creates a cache in front of `source`, reusing a fetched result for up to `ttl`.
+    pub fn new(source: std::sync::Arc<dyn ServiceSource>, ttl: Duration) -> Self {
+        Self {
+            source,
+            ttl,
+            state: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached services, fetching from the underlying [`ServiceSource`] if there is no
+    /// cached value or it's older than `ttl`.
+    pub fn get(&self) -> Vec<ServiceInfo> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if let Some(cached) = state.as_ref()
+            && cached.fetched_at.elapsed() < self.ttl
+        {
+            return cached.services.clone();
+        }
+        let services = self.source.fetch();
+        *state = Some(CachedState {
+            services: services.clone(),
+            fetched_at: Instant::now(),
+        });
+        services
+    }
+
+    /// Discards the cached value, so the next [`Self::get`] call fetches regardless of `ttl`.
+    ///
+    /// Meant to be called by an informer on a watch event, so a change is reflected before `ttl`
+    /// would otherwise have expired.
+    pub fn invalidate(&self) {
+        *self.state.lock().expect("lock poisoned") = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct CountingSource {
+        fetches: AtomicUsize,
+    }
+
+    impl ServiceSource for CountingSource {
+        fn fetch(&self) -> Vec<ServiceInfo> {
+            let count = self.fetches.fetch_add(1, Ordering::SeqCst) + 1;
+            vec![ServiceInfo {
+                name: format!("fetch-{count}"),
+            }]
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn two_rapid_calls_within_the_ttl_hit_the_backend_once() {
+        let source = Arc::new(CountingSource::default());
+        let cache = CachedServiceSource::new(source.clone(), Duration::from_secs(10));
+
+        let first = cache.get();
+        let second = cache.get();
+
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(first[0].name, second[0].name);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invalidate_forces_a_refetch_even_within_the_ttl() {
+        let source = Arc::new(CountingSource::default());
+        let cache = CachedServiceSource::new(source.clone(), Duration::from_secs(10));
+
+        let first = cache.get();
+        cache.invalidate();
+        let second = cache.get();
+
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 2);
+        assert_ne!(first[0].name, second[0].name);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_call_after_the_ttl_elapses_refetches() {
+        let source = Arc::new(CountingSource::default());
+        let cache = CachedServiceSource::new(source.clone(), Duration::from_millis(50));
+
+        cache.get();
+        tokio::time::advance(Duration::from_millis(60)).await;
+        cache.get();
+
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    fn names(services: Vec<ServiceInfo>) -> Vec<String> {
+        let mut names: Vec<String> = services.into_iter().map(|s| s.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn a_watched_source_starts_empty() {
+        let source = WatchedServiceSource::new();
+        assert!(source.fetch().is_empty());
+    }
+
+    #[test]
+    fn applied_events_add_and_update_services_by_name() {
+        let source = WatchedServiceSource::new();
+        source.apply(WatchEvent::Applied(ServiceInfo {
+            name: "checkout".to_string(),
+        }));
+        source.apply(WatchEvent::Applied(ServiceInfo {
+            name: "cart".to_string(),
+        }));
+
+        assert_eq!(names(source.fetch()), vec!["cart", "checkout"]);
+    }
+
+    #[test]
+    fn a_deleted_event_removes_the_matching_service() {
+        let source = WatchedServiceSource::new();
+        source.apply(WatchEvent::Applied(ServiceInfo {
+            name: "checkout".to_string(),
+        }));
+        source.apply(WatchEvent::Deleted(ServiceInfo {
+            name: "checkout".to_string(),
+        }));
+
+        assert!(source.fetch().is_empty());
+    }
+
+    #[test]
+    fn a_restarted_event_replaces_the_entire_set() {
+        let source = WatchedServiceSource::new();
+        source.apply(WatchEvent::Applied(ServiceInfo {
+            name: "stale".to_string(),
+        }));
+        source.apply(WatchEvent::Restarted(vec![
+            ServiceInfo {
+                name: "checkout".to_string(),
+            },
+            ServiceInfo {
+                name: "cart".to_string(),
+            },
+        ]));
+
+        assert_eq!(names(source.fetch()), vec!["cart", "checkout"]);
+    }
+}
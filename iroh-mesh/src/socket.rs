@@ -0,0 +1,224 @@
+//! Portable TCP socket tuning for proxied connections.
+
+use std::{net::SocketAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use snafu::ensure;
+use socket2::{Domain, SockRef, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::{InvalidConfigSnafu, Result};
+
+/// How many pending connections the OS is allowed to queue ahead of `accept`, matching the
+/// default [`TcpListener::bind`] uses internally.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Binds a TCP listener on `addr`, optionally as a dual-stack (IPv4-and-IPv6) listener.
+///
+/// `dual_stack` clears `IPV6_V6ONLY` on the underlying socket so an IPv6 wildcard address like
+/// `[::]:port` also accepts IPv4 clients (as IPv4-mapped IPv6 addresses); it's only meaningful
+/// for an IPv6 `addr` and is rejected otherwise.
+pub fn bind_listener(addr: SocketAddr, dual_stack: bool) -> Result<TcpListener> {
+    ensure!(
+        !dual_stack || addr.is_ipv6(),
+        InvalidConfigSnafu {
+            reason: format!("dual_stack requires an IPv6 bind address, got {addr}"),
+        }
+    );
+
+    let domain = if addr.is_ipv6() {
+        Domain::IPV6
+    } else {
+        Domain::IPV4
+    };
+    let socket = Socket::new(domain, Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// TCP socket options applied to both the client-facing and backend-facing sockets of a
+/// proxied connection.
+///
+/// Mesh traffic is latency sensitive and hops through at least one extra proxy, so batching
+/// small writes via Nagle's algorithm tends to hurt more than it helps, and dead peers should
+/// be noticed quickly rather than via application-level timeouts.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct SocketOptions {
+    /// Disable Nagle's algorithm (`TCP_NODELAY`).
+    pub nodelay: bool,
+    /// Enable TCP keepalive with the given idle time before the first probe is sent.
+    ///
+    /// `None` leaves the OS default keepalive behaviour (typically disabled) in place.
+    #[serde(with = "humantime_serde::option")]
+    #[schemars(with = "Option<String>")]
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Applies these options to an already-connected [`TcpStream`].
+    pub fn apply(&self, stream: &TcpStream) -> Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(idle) = self.keepalive {
+            let sock_ref = SockRef::from(stream);
+            let keepalive = socket2::TcpKeepalive::new().with_time(idle);
+            sock_ref.set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads `stream`'s pre-NAT destination address, as recorded by an iptables `REDIRECT` rule that
+/// intercepted it, via `SO_ORIGINAL_DST` -- see [`crate::config::ProxyConfig::enable_interception`]
+/// and [`crate::routing::OriginalDestinationPortStrategy`], the routing strategy this feeds.
+///
+/// Returns `None` if the lookup fails, which on an unintercepted connection (no matching
+/// `REDIRECT` rule) is the expected outcome rather than an error worth propagating -- the caller
+/// falls back to whatever [`crate::routing::RoutingStrategy`] does with no original destination.
+///
+/// `SO_ORIGINAL_DST` is a Linux-only, iptables-specific socket option with no portable
+/// equivalent; on every other platform this always returns `None`.
+#[cfg(target_os = "linux")]
+pub fn original_dst(stream: &TcpStream) -> Option<SocketAddr> {
+    use std::os::fd::AsRawFd;
+
+    // Neither option is defined by libc itself -- both come from netfilter's `iptables(8)`
+    // headers, which libc doesn't vendor.
+    const SO_ORIGINAL_DST: libc::c_int = 80;
+    const IP6T_SO_ORIGINAL_DST: libc::c_int = 80;
+
+    let fd = stream.as_raw_fd();
+    let local_is_v6 = stream.local_addr().ok()?.is_ipv6();
+    if local_is_v6 {
+        let mut addr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_IPV6,
+                IP6T_SO_ORIGINAL_DST,
+                std::ptr::from_mut(&mut addr).cast(),
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return None;
+        }
+        let ip = std::net::Ipv6Addr::from(addr.sin6_addr.s6_addr);
+        Some(SocketAddr::from((ip, u16::from_be(addr.sin6_port))))
+    } else {
+        let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_IP,
+                SO_ORIGINAL_DST,
+                std::ptr::from_mut(&mut addr).cast(),
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return None;
+        }
+        let ip = std::net::Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+        Some(SocketAddr::from((ip, u16::from_be(addr.sin_port))))
+    }
+}
+
+/// See the `target_os = "linux"` version's docs -- `SO_ORIGINAL_DST` has no equivalent outside
+/// Linux's netfilter, so this always returns `None` here.
+#[cfg(not(target_os = "linux"))]
+pub fn original_dst(_stream: &TcpStream) -> Option<SocketAddr> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dual_stack_is_rejected_on_an_ipv4_address() {
+        let err = bind_listener("127.0.0.1:0".parse().unwrap(), true)
+            .expect_err("dual_stack on an IPv4 address should be rejected");
+        assert!(err.to_string().contains("dual_stack"));
+    }
+
+    #[tokio::test]
+    async fn dual_stack_listener_accepts_both_families_on_loopback() -> Result<()> {
+        let listener = bind_listener("[::]:0".parse().unwrap(), true)?;
+        let port = listener.local_addr()?.port();
+        let accept = tokio::spawn(async move {
+            for _ in 0..2 {
+                listener
+                    .accept()
+                    .await
+                    .map_err(crate::error::MeshError::from)?;
+            }
+            Result::<()>::Ok(())
+        });
+
+        for addr in [
+            SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, port)),
+        ] {
+            TcpStream::connect(addr).await?;
+        }
+
+        accept.await.expect("accept task panicked")?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn original_dst_returns_none_without_an_iptables_redirect_rule() -> Result<()> {
+        // Exercising the `SO_ORIGINAL_DST` success path needs an actual iptables `REDIRECT` rule
+        // in front of the connection, which isn't something a unit test can set up portably or
+        // without root -- this only checks that an un-intercepted connection reports `None`
+        // rather than a stale or garbage address.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = tokio::spawn(async move { listener.accept().await });
+        let client = TcpStream::connect(addr).await?;
+        let (server, _) = accept.await.expect("accept task panicked")?;
+
+        assert_eq!(original_dst(&client), None);
+        assert_eq!(original_dst(&server), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn applies_nodelay() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let accept = tokio::spawn(async move { listener.accept().await });
+        let client = TcpStream::connect(addr).await?;
+        let (server, _) = accept.await.expect("accept task panicked")?;
+
+        let opts = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+        };
+        opts.apply(&client)?;
+        opts.apply(&server)?;
+
+        assert!(client.nodelay()?);
+        assert!(server.nodelay()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+//! A static, file-based discovery source for deployments with nothing else to feed a
+//! [`DiscoveryManager`] from -- an edge or dev node that knows its peer clusters and the services
+//! it exposes up front, rather than discovering either live.
+//!
+//! This crate has no Kubernetes client of its own (see [`crate::election`]'s module docs for the
+//! same gap elsewhere), so there's no in-cluster discovery path this module is a fallback from --
+//! every deployment of this crate already has to supply clusters from somewhere external, either
+//! through the agent's `POST /clusters` API ([`crate::api`]) or, with this module, a TOML file.
+//! [`load_peers`] reads it once; there's no file watch here, so a caller that wants to pick up
+//! edits calls it again on whatever trigger fits (a timer, a SIGHUP handler, an admin endpoint) --
+//! [`DiscoveryManager::register_cluster`] merging each entry into what's already known, rather
+//! than replacing it outright, is what makes a repeated call behave as a reload instead of
+//! duplicating entries.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::ensure;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::{
+    discovery::{ClusterRegistration, DiscoveryManager},
+    error::{InvalidConfigSnafu, Result},
+    service_cache::ServiceSource,
+    topology::ServiceInfo,
+};
+
+/// Smallest non-zero [`StandaloneReloadConfig::interval`] [`StandaloneReloadConfig::validate`]
+/// accepts, short of disabling the periodic reload outright with [`Duration::ZERO`]. Guards
+/// against a misconfigured agent re-reading the file in a tight loop.
+const MIN_RELOAD_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The file format [`load_peers`] and [`StandaloneServiceSource`] read: every peer cluster and
+/// locally exposed service a standalone node should start with, in one place.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StandaloneFile {
+    /// Services this node exposes to the rest of the mesh.
+    #[serde(default)]
+    pub local_services: Vec<ServiceInfo>,
+    /// Peer clusters this node should route to, normally learned from Kubernetes or gossip.
+    #[serde(default)]
+    pub peers: Vec<ClusterRegistration>,
+}
+
+impl StandaloneFile {
+    /// Reads and parses `path` as TOML, the same format [`crate::config::ProxyConfig`]'s and
+    /// [`crate::agent::AgentConfig`]'s own config files use.
+    pub async fn load(path: &Path) -> Result<Self> {
+        let raw = tokio::fs::read_to_string(path).await?;
+        toml::from_str(&raw).map_err(|e| {
+            InvalidConfigSnafu {
+                reason: format!("standalone discovery file {}: {e}", path.display()),
+            }
+            .build()
+        })
+    }
+}
+
+/// Registers every [`StandaloneFile::peers`] entry read from `path` with `discovery`, returning
+/// how many were read. See the [module docs](self) for how to reload edits.
+pub async fn load_peers(discovery: &DiscoveryManager, path: &Path) -> Result<usize> {
+    let file = StandaloneFile::load(path).await?;
+    let count = file.peers.len();
+    for peer in file.peers {
+        discovery.register_cluster(peer);
+    }
+    Ok(count)
+}
+
+/// Configuration for periodically re-invoking [`load_peers`], one of the "whatever trigger fits"
+/// options the [module docs](self) mention as an alternative to a caller invoking it manually on
+/// a SIGHUP handler or admin endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct StandaloneReloadConfig {
+    /// Path to the [`StandaloneFile`] to periodically re-read.
+    pub path: PathBuf,
+    /// How often to re-invoke [`load_peers`], after an initial load as soon as
+    /// [`spawn_reload`] is called.
+    ///
+    /// [`Duration::ZERO`] disables the periodic reload after that initial load, leaving further
+    /// reloads to whichever other trigger fits (see the [module docs](self)). Any other value
+    /// below [`Self::validate`]'s minimum is rejected rather than silently clamped.
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub interval: Duration,
+}
+
+impl StandaloneReloadConfig {
+    /// Rejects an [`Self::interval`] that would either hammer the file with re-reads (anything
+    /// nonzero below [`MIN_RELOAD_INTERVAL`]) or never reload by mistake -- callers that actually
+    /// want only the initial load should set it to exactly [`Duration::ZERO`].
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.interval.is_zero() || self.interval >= MIN_RELOAD_INTERVAL,
+            InvalidConfigSnafu {
+                reason: format!(
+                    "standalone reload interval {:?} is too small; use {:?} or larger, or {:?} \
+                     to reload only once at startup",
+                    self.interval,
+                    MIN_RELOAD_INTERVAL,
+                    Duration::ZERO,
+                ),
+            }
+        );
+        Ok(())
+    }
+}
+
+/// Spawns a background task that loads `config.path` into `discovery` immediately, then again on
+/// [`StandaloneReloadConfig::interval`] until the returned handle is dropped or `shutdown` is
+/// cancelled. A failed load is logged and skipped rather than ending the task, since a transient
+/// failure (the file mid-write, say) shouldn't be worse than one skipped reload -- the next tick
+/// recovers on its own once the file is readable again.
+///
+/// Callers should validate `config` with [`StandaloneReloadConfig::validate`] before spawning;
+/// this doesn't re-validate it.
+pub fn spawn_reload(
+    discovery: Arc<DiscoveryManager>,
+    config: StandaloneReloadConfig,
+    shutdown: CancellationToken,
+) -> StandaloneReloadHandle {
+    let task = tokio::spawn(async move {
+        reload_once(&discovery, &config.path).await;
+        if config.interval.is_zero() {
+            shutdown.cancelled().await;
+            return;
+        }
+        let mut ticker = tokio::time::interval(config.interval);
+        ticker.tick().await; // the immediate first tick; the initial load above already covered it
+        loop {
+            tokio::select! {
+                biased;
+                () = shutdown.cancelled() => return,
+                _ = ticker.tick() => {}
+            }
+            reload_once(&discovery, &config.path).await;
+        }
+    });
+    StandaloneReloadHandle { task }
+}
+
+/// Calls [`load_peers`], logging rather than propagating a failure -- see [`spawn_reload`].
+async fn reload_once(discovery: &DiscoveryManager, path: &Path) {
+    if let Err(error) = load_peers(discovery, path).await {
+        warn!(%error, path = %path.display(), "standalone peers reload failed");
+    }
+}
+
+/// Handle to a reload task spawned with [`spawn_reload`]; dropping it stops reloading.
+#[derive(Debug)]
+pub struct StandaloneReloadHandle {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for StandaloneReloadHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A [`ServiceSource`] that re-reads [`StandaloneFile::local_services`] from a file on every
+/// [`ServiceSource::fetch`] call, so wrapping it in a
+/// [`crate::service_cache::CachedServiceSource`] gives the same "reload on a TTL" behavior a live
+/// Kubernetes informer would otherwise provide (see [`crate::service_cache`]'s module docs for
+/// the gap this fills in place of).
+///
+/// A read or parse failure is treated as "no services" rather than returned, since
+/// [`ServiceSource::fetch`] has no way to report an error to its caller and a transient failure
+/// (the file mid-write, say) shouldn't be worse than one empty fetch -- the next call recovers on
+/// its own once the file is readable again.
+#[derive(Debug)]
+pub struct StandaloneServiceSource {
+    path: PathBuf,
+}
+
+impl StandaloneServiceSource {
+    /// Reads local services from `path` on every [`ServiceSource::fetch`] call.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ServiceSource for StandaloneServiceSource {
+    fn fetch(&self) -> Vec<ServiceInfo> {
+        let Ok(raw) = std::fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        toml::from_str::<StandaloneFile>(&raw)
+            .map(|file| file.local_services)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh path under the system temp dir, named after the calling test so parallel tests
+    /// don't collide.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("iroh-mesh-test-standalone-{name}.toml"))
+    }
+
+    fn fresh_endpoint_id() -> iroh_base::EndpointId {
+        iroh_base::SecretKey::generate(&mut rand::rng()).public()
+    }
+
+    #[tokio::test]
+    async fn load_peers_registers_every_peer_so_find_service_resolves_it() {
+        let path = temp_path("registers-peers");
+        let endpoint_id = fresh_endpoint_id();
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let discovery = DiscoveryManager::new();
+        let registered = load_peers(&discovery, &path).await.unwrap();
+        assert_eq!(registered, 1);
+
+        let matches = discovery.find_service("svc");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].cluster_id, "cluster-a");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn load_peers_called_again_after_an_edit_merges_the_new_services_in() {
+        let path = temp_path("reload-merges");
+        let endpoint_id = fresh_endpoint_id();
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc"]
+                "#
+            ),
+        )
+        .unwrap();
+        let discovery = DiscoveryManager::new();
+        load_peers(&discovery, &path).await.unwrap();
+
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc", "svc-b"]
+                "#
+            ),
+        )
+        .unwrap();
+        load_peers(&discovery, &path).await.unwrap();
+
+        assert_eq!(discovery.find_service("svc-b").len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn standalone_service_source_reads_local_services() {
+        let path = temp_path("local-services");
+        std::fs::write(
+            &path,
+            r#"
+            [[local_services]]
+            name = "svc"
+            "#,
+        )
+        .unwrap();
+
+        let source = StandaloneServiceSource::new(path.clone());
+        let services = source.fetch();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "svc");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn standalone_service_source_returns_no_services_for_a_missing_file() {
+        let source = StandaloneServiceSource::new("/nonexistent/standalone.toml");
+        assert!(source.fetch().is_empty());
+    }
+
+    #[test]
+    fn reload_interval_of_zero_is_valid() {
+        let config = StandaloneReloadConfig {
+            path: PathBuf::from("/dev/null"),
+            interval: Duration::ZERO,
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn a_nonzero_reload_interval_below_the_minimum_is_rejected() {
+        let config = StandaloneReloadConfig {
+            path: PathBuf::from("/dev/null"),
+            interval: Duration::from_millis(1),
+        };
+        assert!(config.validate().is_err());
+    }
+
+    /// Polls `condition` in a loop with real (unpaused) sleeps between attempts, for asserting on
+    /// [`spawn_reload`]'s background task -- its file reads run on tokio's blocking thread pool,
+    /// which a paused clock doesn't drive, so tests below use real time instead.
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never became true within the timeout");
+    }
+
+    #[tokio::test]
+    async fn spawn_reload_loads_immediately_then_again_on_each_tick() {
+        let path = temp_path("spawn-reload-ticks");
+        let endpoint_id = fresh_endpoint_id();
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        let shutdown = CancellationToken::new();
+        let handle = spawn_reload(
+            discovery.clone(),
+            StandaloneReloadConfig {
+                path: path.clone(),
+                interval: Duration::from_millis(50),
+            },
+            shutdown.clone(),
+        );
+
+        // The initial load happens as soon as the task is spawned, before any tick.
+        wait_until(|| !discovery.find_service("svc").is_empty()).await;
+
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc", "svc-b"]
+                "#
+            ),
+        )
+        .unwrap();
+        wait_until(|| !discovery.find_service("svc-b").is_empty()).await;
+
+        shutdown.cancel();
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn spawn_reload_with_a_zero_interval_only_loads_once() {
+        let path = temp_path("spawn-reload-once");
+        let endpoint_id = fresh_endpoint_id();
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let discovery = Arc::new(DiscoveryManager::new());
+        let shutdown = CancellationToken::new();
+        let handle = spawn_reload(
+            discovery.clone(),
+            StandaloneReloadConfig {
+                path: path.clone(),
+                interval: Duration::ZERO,
+            },
+            shutdown.clone(),
+        );
+        wait_until(|| !discovery.find_service("svc").is_empty()).await;
+
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[peers]]
+                cluster_id = "cluster-a"
+                endpoint_id = "{endpoint_id}"
+                services = ["svc", "svc-b"]
+                "#
+            ),
+        )
+        .unwrap();
+        // No further tick is scheduled with a zero interval, so give any (incorrect) reload
+        // ample real time to happen before concluding it didn't.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(
+            discovery.find_service("svc-b").is_empty(),
+            "a zero interval should reload only once, at startup"
+        );
+
+        shutdown.cancel();
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,171 @@
+//! Types served by [`crate::proxy::MeshProxy`]'s status API.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::{ClusterInfo, DiscoveryManager};
+
+/// How long it may go without a cluster registration before [`ClusterStatusResponse::build`]
+/// flags discovery as stale.
+///
+/// This crate has no dedicated background discovery task to measure a poll interval from --
+/// registrations arrive via [`DiscoveryManager::register_cluster`], whether pushed by a health
+/// checker, an external controller, or the agent API -- so staleness is judged against a single
+/// fixed threshold rather than one derived from a source-specific polling schedule.
+pub const DEFAULT_STALE_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// A snapshot of what a [`crate::proxy::MeshProxy`] currently knows about the mesh.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClusterStatusResponse {
+    /// Clusters known through discovery.
+    pub clusters: Vec<ClusterInfo>,
+    /// Last observed connection path (`"direct"`, `"relay"`, `"mixed"` or `"none"`) for each
+    /// cluster this proxy has dialed.
+    pub peer_paths: HashMap<String, String>,
+    /// Total number of services advertised across all known clusters.
+    pub service_count: usize,
+    /// When the last cluster registration was received, in milliseconds since the Unix epoch.
+    /// `None` if none has been received yet.
+    pub last_discovery_at: Option<u64>,
+    /// Whether it's been longer than [`DEFAULT_STALE_THRESHOLD`] since the last registration --
+    /// or there's never been one -- which usually means whatever feeds discovery has gone silent.
+    pub discovery_stale: bool,
+    /// The relay this proxy's endpoint currently reports as its home relay, if any. Which one
+    /// iroh picks when more than one is configured (see [`crate::relay`]) is entirely iroh's own
+    /// choice; this just reports the answer.
+    pub active_relay: Option<String>,
+    /// The last QUIC-level stats sample taken for each cluster this proxy has dialed (see
+    /// [`crate::proxy::MeshProxy`]'s periodic stats sampler).
+    pub conn_stats: HashMap<String, ConnQuality>,
+    /// Whether the proxy is running without a working iroh endpoint (see
+    /// [`crate::config::ProxyConfig::allow_degraded`]). [`crate::config::ProxyConfig::routes`]
+    /// still forward normally in this state; anything requiring the mesh does not.
+    pub degraded: bool,
+}
+
+/// A QUIC-level connection quality sample for one dialed cluster, last observed by
+/// [`crate::proxy::MeshProxy`]'s periodic sampling of [`iroh::endpoint::Connection::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnQuality {
+    /// Round-trip time, in seconds.
+    pub rtt_seconds: f64,
+    /// Cumulative lost packets over the connection's lifetime so far.
+    pub lost_packets: u64,
+}
+
+impl ClusterStatusResponse {
+    /// Builds a status response reflecting `discovery`'s current state, `peer_paths`, the
+    /// endpoint's `active_relay` (see [`iroh::Endpoint::addr`]'s `relay_url`), the latest
+    /// `conn_stats` sample per cluster, and whether the proxy is currently `degraded` (see
+    /// [`Self::degraded`]).
+    pub fn build(
+        discovery: &DiscoveryManager,
+        peer_paths: HashMap<String, String>,
+        active_relay: Option<String>,
+        conn_stats: HashMap<String, ConnQuality>,
+        degraded: bool,
+    ) -> Self {
+        let clusters = discovery.list_clusters();
+        Self {
+            service_count: clusters.iter().map(|info| info.services.len()).sum(),
+            last_discovery_at: discovery.last_registered_at().map(unix_millis),
+            discovery_stale: discovery.is_stale(DEFAULT_STALE_THRESHOLD),
+            clusters,
+            peer_paths,
+            active_relay,
+            conn_stats,
+            degraded,
+        }
+    }
+}
+
+fn unix_millis(at: SystemTime) -> u64 {
+    at.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::ClusterRegistration;
+
+    fn registration(cluster_id: &str, services: &[&str]) -> ClusterRegistration {
+        ClusterRegistration {
+            cluster_id: cluster_id.to_string(),
+            endpoint_id: iroh_base::SecretKey::generate(&mut rand::rng()).public(),
+            relay_url: None,
+            direct_addresses: Vec::new(),
+            services: services.iter().map(ToString::to_string).collect(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn a_fresh_discovery_manager_reports_no_timestamp_and_counts_as_stale() {
+        let discovery = DiscoveryManager::new();
+        let status =
+            ClusterStatusResponse::build(&discovery, HashMap::new(), None, HashMap::new(), false);
+
+        assert_eq!(status.service_count, 0);
+        assert_eq!(status.last_discovery_at, None);
+        assert!(status.discovery_stale);
+        assert_eq!(status.active_relay, None);
+        assert!(status.conn_stats.is_empty());
+        assert!(!status.degraded);
+    }
+
+    #[test]
+    fn a_degraded_proxy_is_reported_as_such() {
+        let discovery = DiscoveryManager::new();
+        let status =
+            ClusterStatusResponse::build(&discovery, HashMap::new(), None, HashMap::new(), true);
+        assert!(status.degraded);
+    }
+
+    #[test]
+    fn a_recent_registration_is_timestamped_and_counted_and_not_stale() {
+        let discovery = DiscoveryManager::new();
+        discovery.register_cluster(registration("cluster-a", &["svc-a", "svc-b"]));
+        discovery.register_cluster(registration("cluster-b", &["svc-c"]));
+
+        let conn_stats = HashMap::from([(
+            "cluster-a".to_string(),
+            ConnQuality {
+                rtt_seconds: 0.025,
+                lost_packets: 2,
+            },
+        )]);
+        let status = ClusterStatusResponse::build(
+            &discovery,
+            HashMap::new(),
+            Some("https://relay.example.com/".to_string()),
+            conn_stats,
+            false,
+        );
+        assert_eq!(status.service_count, 3);
+        assert_eq!(
+            status.active_relay.as_deref(),
+            Some("https://relay.example.com/")
+        );
+        assert_eq!(status.conn_stats["cluster-a"].lost_packets, 2);
+        let timestamp = status
+            .last_discovery_at
+            .expect("a registration just happened");
+        let now = unix_millis(SystemTime::now());
+        assert!(
+            now.saturating_sub(timestamp) < 5_000,
+            "the timestamp should reflect the registration that just happened"
+        );
+        assert!(
+            !status.discovery_stale,
+            "a registration that just happened shouldn't be stale"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! In-process helpers for testing the mesh proxy and agent without real relays or Kubernetes.
+//!
+//! Gated behind the `test-util` feature; always available for this crate's own tests via
+//! `#[cfg(any(test, feature = "test-util"))]`, the same pattern `iroh` uses for its own
+//! `test_utils` module.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use iroh::{Endpoint, RelayMode};
+use snafu::ResultExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+use crate::{
+    agent::{AgentConfig, MeshAgent},
+    config::ProxyConfig,
+    discovery::{ClusterRegistration, DiscoveryManager},
+    error::{BindEndpointSnafu, Result},
+    proxy::{self, MeshProxy},
+};
+
+/// A TCP echo server bound to loopback, for use as a mock backend service in tests.
+///
+/// Echoes back everything it reads on each accepted connection. Stops accepting new
+/// connections when dropped.
+#[derive(Debug)]
+pub struct EchoBackend {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl EchoBackend {
+    /// Binds to an ephemeral loopback port and starts echoing.
+    pub async fn spawn() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let connections = Arc::new(AtomicUsize::new(0));
+        let task_connections = connections.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                task_connections.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = stream.read(&mut buf).await {
+                        if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            addr,
+            task,
+            connections,
+        })
+    }
+
+    /// The address this server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of connections accepted so far, for tests distinguishing which of several backends
+    /// actually received a forwarded connection.
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for EchoBackend {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A [`MeshProxy`] bound to loopback (relay disabled) and running in the background.
+///
+/// Stops accepting connections when dropped.
+#[derive(Debug)]
+pub struct TestProxy {
+    /// The cluster id this proxy's endpoint is registered under in its peers' discovery.
+    pub cluster_id: String,
+    /// Address the proxy accepts client TCP connections on.
+    pub listen_addr: SocketAddr,
+    proxy: Arc<MeshProxy>,
+    task: JoinHandle<()>,
+}
+
+impl TestProxy {
+    /// Binds a [`MeshProxy`] on loopback with `config` (whose `listen_addr` is ignored in
+    /// favor of an ephemeral port) and starts serving it in the background.
+    pub async fn spawn(
+        cluster_id: impl Into<String>,
+        discovery: Arc<DiscoveryManager>,
+        config: ProxyConfig,
+    ) -> Result<Self> {
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .alpns(vec![proxy::mesh_alpn(config.mesh_name.as_deref())])
+            .bind()
+            .await
+            .map_err(Box::new)
+            .context(BindEndpointSnafu)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listen_addr = listener.local_addr()?;
+
+        let proxy = Arc::new(MeshProxy::from_endpoint(config, endpoint, discovery));
+        let task = tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                let _ = proxy.run_on(listener).await;
+            }
+        });
+
+        Ok(Self {
+            cluster_id: cluster_id.into(),
+            listen_addr,
+            proxy,
+            task,
+        })
+    }
+
+    /// The [`ClusterRegistration`] other clusters should use to reach this proxy's iroh
+    /// endpoint.
+    pub fn registration(&self) -> ClusterRegistration {
+        let addr = self
+            .proxy
+            .endpoint()
+            .expect("TestProxy always binds a real endpoint")
+            .addr();
+        ClusterRegistration {
+            cluster_id: self.cluster_id.clone(),
+            endpoint_id: addr.endpoint_id,
+            relay_url: addr.relay_url().map(ToString::to_string),
+            direct_addresses: addr.direct_addresses().copied().collect(),
+            services: Vec::new(),
+            service_ports: Vec::new(),
+            updated_at: std::time::SystemTime::now(),
+            pinned: false,
+        }
+    }
+
+    /// Dials `cluster_id` over this proxy's iroh endpoint. See [`MeshProxy::dial_cluster`].
+    pub async fn dial_cluster(&self, cluster_id: &str) -> Result<iroh::endpoint::Connection> {
+        self.proxy.dial_cluster(cluster_id).await
+    }
+
+    /// The iroh endpoint backing this proxy, for tests that need to accept inbound mesh
+    /// connections directly instead of through the proxy's own mesh accept loop -- e.g. to
+    /// exercise a raw handshake or a protocol violation the real accept loop wouldn't produce.
+    pub fn endpoint(&self) -> &Endpoint {
+        self.proxy
+            .endpoint()
+            .expect("TestProxy always binds a real endpoint")
+    }
+}
+
+impl Drop for TestProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Two [`TestProxy`]s on loopback, each with its own [`DiscoveryManager`] pre-seeded to know
+/// about the other's cluster, ready to dial each other over iroh.
+#[derive(Debug)]
+pub struct TestMesh {
+    /// The first proxy, registered as cluster id `"cluster-a"` in `b`'s discovery.
+    pub a: TestProxy,
+    /// The second proxy, registered as cluster id `"cluster-b"` in `a`'s discovery.
+    pub b: TestProxy,
+}
+
+impl TestMesh {
+    /// Spawns both proxies with `config_a`/`config_b` and cross-registers them.
+    pub async fn spawn(config_a: ProxyConfig, config_b: ProxyConfig) -> Result<Self> {
+        let discovery_a = Arc::new(DiscoveryManager::new());
+        let discovery_b = Arc::new(DiscoveryManager::new());
+        let a = TestProxy::spawn("cluster-a", discovery_a.clone(), config_a).await?;
+        let b = TestProxy::spawn("cluster-b", discovery_b.clone(), config_b).await?;
+        discovery_a.register_cluster(b.registration());
+        discovery_b.register_cluster(a.registration());
+        Ok(Self { a, b })
+    }
+}
+
+/// A [`MeshAgent`] bound to loopback and running in the background.
+///
+/// Stops accepting connections when dropped.
+#[derive(Debug)]
+pub struct TestAgent {
+    /// Address the agent's HTTP API listens on.
+    pub api_addr: SocketAddr,
+    agent: Arc<MeshAgent>,
+    task: JoinHandle<()>,
+}
+
+impl TestAgent {
+    /// Binds a [`MeshAgent`]'s HTTP API on an ephemeral loopback port and starts serving it in
+    /// the background.
+    pub async fn spawn() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let api_addr = listener.local_addr()?;
+        let agent = Arc::new(MeshAgent::new(AgentConfig {
+            api_addr,
+            discovery: None,
+            dual_stack: false,
+            mesh_name: None,
+            secret_key: None,
+            admin_token: None,
+            endpoint_discovery: Default::default(),
+            relay: Default::default(),
+            api_auth: None,
+            api_tls: None,
+            standalone_reload: None,
+            self_registration: None,
+            metrics_addr: None,
+        }));
+        let task = tokio::spawn({
+            let agent = agent.clone();
+            async move {
+                let _ = agent.run_on(listener).await;
+            }
+        });
+        Ok(Self {
+            api_addr,
+            agent,
+            task,
+        })
+    }
+
+    /// A handle to this agent's discovery state.
+    pub fn discovery(&self) -> Arc<DiscoveryManager> {
+        self.agent.discovery()
+    }
+}
+
+impl Drop for TestAgent {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Wires a TCP client through two separate proxies to a shared backend echo server
+    /// (forwarding), while discovery lets each proxy dial the other's iroh endpoint directly
+    /// (routing/discovery) — exercising the pieces the rest of this crate's tests cover, end to
+    /// end, without any real relay or Kubernetes dependency.
+    #[tokio::test]
+    async fn request_flows_through_either_proxy_to_shared_backend() -> Result<()> {
+        let backend = EchoBackend::spawn().await?;
+        let mesh = TestMesh::spawn(
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr()),
+            ProxyConfig::new("127.0.0.1:0".parse().unwrap()).with_route("svc", backend.addr()),
+        )
+        .await?;
+
+        for proxy in [&mesh.a, &mesh.b] {
+            let mut client = tokio::net::TcpStream::connect(proxy.listen_addr).await?;
+            client.write_all(b"hello").await?;
+            let mut buf = [0u8; 5];
+            client.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+        }
+
+        // `mesh.b`'s own mesh accept loop (started alongside its client-facing listener) accepts
+        // this for us now, so a successful dial is proof enough that discovery pointed `mesh.a`
+        // at the right endpoint.
+        mesh.a.dial_cluster("cluster-b").await.unwrap();
+
+        Ok(())
+    }
+}
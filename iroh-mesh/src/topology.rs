@@ -0,0 +1,97 @@
+//! Assembles the mesh topology graph served by [`crate::proxy::MeshProxy`]'s `GET /topology`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::ClusterInfo;
+
+/// A service a cluster exposes through the mesh.
+///
+/// [`TopologyGraph::build`] leaves every node's `services` empty: it doesn't copy in the service
+/// names discovery holds for each cluster.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    /// Name of the service, e.g. a Kubernetes service name.
+    pub name: String,
+}
+
+/// A cluster known through discovery, as a node in the topology graph.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopologyNode {
+    /// Unique identifier of the cluster.
+    pub cluster_id: String,
+    /// The iroh endpoint that terminates mesh connections for this cluster.
+    pub endpoint_id: iroh_base::EndpointId,
+    /// Whether this proxy currently has an established, non-`"none"` path to the cluster.
+    pub reachable: bool,
+    /// Services the cluster exposes through the mesh.
+    pub services: Vec<ServiceInfo>,
+}
+
+/// An observed connection from this proxy to a remote cluster.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopologyEdge {
+    /// The cluster this proxy has dialed.
+    pub cluster_id: String,
+    /// The last observed connection path (`"direct"`, `"relay"`, `"mixed"` or `"none"`).
+    pub path: String,
+}
+
+/// A snapshot of the mesh as this proxy sees it: known clusters and the paths to them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TopologyGraph {
+    /// Clusters known through discovery.
+    pub nodes: Vec<TopologyNode>,
+    /// Connections this proxy has observed to those clusters.
+    pub edges: Vec<TopologyEdge>,
+}
+
+impl TopologyGraph {
+    /// Builds a graph from the clusters known to discovery and the connection paths observed by
+    /// the proxy, keyed by cluster id.
+    pub fn build(clusters: Vec<ClusterInfo>, peer_paths: &HashMap<String, String>) -> Self {
+        let nodes = clusters
+            .into_iter()
+            .map(|cluster| TopologyNode {
+                reachable: peer_paths
+                    .get(&cluster.cluster_id)
+                    .is_some_and(|path| path != "none"),
+                cluster_id: cluster.cluster_id,
+                endpoint_id: cluster.endpoint_id,
+                services: Vec::new(),
+            })
+            .collect();
+        let edges = peer_paths
+            .iter()
+            .map(|(cluster_id, path)| TopologyEdge {
+                cluster_id: cluster_id.clone(),
+                path: path.clone(),
+            })
+            .collect();
+        Self { nodes, edges }
+    }
+
+    /// Renders the graph as Graphviz DOT, with this proxy as the `"local"` node.
+    pub fn to_dot(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::from("digraph mesh {\n  \"local\";\n");
+        for node in &self.nodes {
+            let _ = writeln!(
+                out,
+                "  \"{}\" [reachable={}];",
+                node.cluster_id, node.reachable
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "  \"local\" -> \"{}\" [label=\"{}\"];",
+                edge.cluster_id, edge.path
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+}
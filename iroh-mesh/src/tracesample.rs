@@ -0,0 +1,91 @@
+//! A probabilistic sampler deciding which connections get detailed per-stream tracing spans.
+//!
+//! Instrumenting every stream direction of every connection is cheap in isolation but adds up at
+//! high connection rates -- exactly the situations where broad log sampling (see
+//! [`crate::logsample`]) already exists to keep noise down. [`TraceSampler`] makes the same
+//! tradeoff for spans instead of log lines: a fraction of connections, decided once at accept
+//! time, get the detailed spans that make following one connection's lifetime easy; the rest
+//! still update every counter, they just don't pay for or emit the finer-grained tracing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Decides, once per connection, whether it should get detailed per-stream tracing spans.
+///
+/// The sample rate is stored as the bit pattern of an `f64` in an [`AtomicU64`] so it can be
+/// adjusted at runtime (e.g. from [`crate::proxy::MeshProxy::set_trace_sample_rate`]) without a
+/// lock: readers always see either the old rate or the new one, never a partially written value.
+#[derive(Debug)]
+pub struct TraceSampler {
+    rate_bits: AtomicU64,
+}
+
+impl TraceSampler {
+    /// Starts sampling at `rate`, clamped to `0.0..=1.0`.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate_bits: AtomicU64::new(rate.clamp(0.0, 1.0).to_bits()),
+        }
+    }
+
+    /// The current sample rate.
+    pub fn rate(&self) -> f64 {
+        f64::from_bits(self.rate_bits.load(Ordering::Relaxed))
+    }
+
+    /// Replaces the sample rate, clamped to `0.0..=1.0`, effective for connections accepted from
+    /// now on. Connections already forwarding are unaffected either way, since the decision is
+    /// only made once, at accept time.
+    pub fn set_rate(&self, rate: f64) {
+        self.rate_bits
+            .store(rate.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Decides whether the connection being accepted right now should get detailed spans.
+    /// A rate of `0.0` never samples and a rate of `1.0` always does, without drawing a random
+    /// number in either case, so both ends of the range are exact rather than merely likely.
+    pub fn sample(&self) -> bool {
+        let rate = self.rate();
+        if rate <= 0.0 {
+            false
+        } else if rate >= 1.0 {
+            true
+        } else {
+            rand::Rng::random::<f64>(&mut rand::rng()) < rate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_zero_rate_never_samples() {
+        let sampler = TraceSampler::new(0.0);
+        for _ in 0..100 {
+            assert!(!sampler.sample());
+        }
+    }
+
+    #[test]
+    fn a_rate_of_one_always_samples() {
+        let sampler = TraceSampler::new(1.0);
+        for _ in 0..100 {
+            assert!(sampler.sample());
+        }
+    }
+
+    #[test]
+    fn out_of_range_rates_are_clamped() {
+        assert_eq!(TraceSampler::new(-1.0).rate(), 0.0);
+        assert_eq!(TraceSampler::new(2.0).rate(), 1.0);
+    }
+
+    #[test]
+    fn set_rate_takes_effect_immediately() {
+        let sampler = TraceSampler::new(0.0);
+        assert!(!sampler.sample());
+        sampler.set_rate(1.0);
+        assert!(sampler.sample());
+    }
+}
@@ -0,0 +1,116 @@
+//! A small binary header prepended to each QUIC unreliable datagram carrying UDP traffic across
+//! the mesh (see [`MeshProxy::forward_udp_to_service`](crate::proxy::MeshProxy::forward_udp_to_service)),
+//! naming which service's listener the payload is for.
+//!
+//! Datagrams are unreliable and unordered by design (see
+//! [`iroh::endpoint::Connection::send_datagram`]), the same as the UDP traffic they carry -- so
+//! unlike [`RouteRequest`](crate::route_request::RouteRequest) and
+//! [`crate::protocol::MeshRequest`], which each get their own stream, this header rides on every
+//! single datagram, and is encoded as a fixed binary layout rather than JSON to keep it as small
+//! as the QUIC packet budget (see [`iroh::endpoint::Connection::max_datagram_size`]) allows.
+//!
+//! `MeshProxy`'s own accept loop (started by
+//! [`MeshProxy::run_on_many`](crate::proxy::MeshProxy::run_on_many) alongside its client-facing
+//! listeners) decodes this header for every inbound datagram, forwarding its payload to whichever
+//! local backend [`crate::config::ProxyConfig::routes`] names for the service it's tagged with.
+
+use bytes::Bytes;
+use snafu::{OptionExt, ensure};
+
+use crate::error::{InvalidConfigSnafu, Result, UdpDatagramDecodeSnafu};
+
+/// Service names longer than this can't be encoded: the header's length prefix is a single byte.
+pub const MAX_SERVICE_NAME_BYTES: usize = u8::MAX as usize;
+
+/// Encodes `payload` as `[service_len: u8][service: service_len bytes][payload: remaining
+/// bytes]`, addressed to `service`. See the [module docs](self).
+pub fn encode(service: &str, payload: &[u8]) -> Result<Bytes> {
+    ensure!(
+        service.len() <= MAX_SERVICE_NAME_BYTES,
+        InvalidConfigSnafu {
+            reason: format!(
+                "UDP service name {service:?} is {} bytes, over the {MAX_SERVICE_NAME_BYTES} byte limit",
+                service.len()
+            ),
+        }
+    );
+    let mut frame = Vec::with_capacity(1 + service.len() + payload.len());
+    frame.push(service.len() as u8);
+    frame.extend_from_slice(service.as_bytes());
+    frame.extend_from_slice(payload);
+    Ok(Bytes::from(frame))
+}
+
+/// Decodes a frame previously produced by [`encode`], returning the service name it's addressed
+/// to and the payload that follows it.
+pub fn decode(frame: &[u8]) -> Result<(&str, &[u8])> {
+    let (&service_len, rest) = frame.split_first().context(UdpDatagramDecodeSnafu {
+        reason: "datagram is empty",
+    })?;
+    let service_len = service_len as usize;
+    ensure!(
+        rest.len() >= service_len,
+        UdpDatagramDecodeSnafu {
+            reason: format!(
+                "declared service name length {service_len} exceeds the {} bytes remaining",
+                rest.len()
+            ),
+        }
+    );
+    let (service, payload) = rest.split_at(service_len);
+    let service = std::str::from_utf8(service)
+        .ok()
+        .context(UdpDatagramDecodeSnafu {
+            reason: "service name is not valid UTF-8",
+        })?;
+    Ok((service, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_datagram_round_trips_through_encode_and_decode() {
+        let frame = encode("checkout", b"hello").unwrap();
+        let (service, payload) = decode(&frame).unwrap();
+        assert_eq!(service, "checkout");
+        assert_eq!(payload, b"hello");
+    }
+
+    #[test]
+    fn an_empty_payload_round_trips() {
+        let frame = encode("dns", b"").unwrap();
+        let (service, payload) = decode(&frame).unwrap();
+        assert_eq!(service, "dns");
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn a_service_name_over_the_limit_is_rejected_at_encode_time() {
+        let too_long = "x".repeat(MAX_SERVICE_NAME_BYTES + 1);
+        let result = encode(&too_long, b"payload");
+        assert!(matches!(
+            result,
+            Err(crate::error::MeshError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn an_empty_frame_is_rejected_as_a_decode_error() {
+        let result = decode(&[]);
+        assert!(matches!(
+            result,
+            Err(crate::error::MeshError::UdpDatagramDecode { .. })
+        ));
+    }
+
+    #[test]
+    fn a_frame_whose_declared_service_length_overruns_the_buffer_is_rejected() {
+        let result = decode(&[10, b'a', b'b']);
+        assert!(matches!(
+            result,
+            Err(crate::error::MeshError::UdpDatagramDecode { .. })
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+//! Build and identity metadata, served at `/version` by both
+//! [`crate::proxy::MeshProxy`] and [`crate::agent::MeshAgent`].
+//!
+//! Neither [`crate::config::ProxyConfig`] nor [`crate::agent::AgentConfig`] has a notion of "this
+//! node's own cluster id" to report here -- `cluster_id` throughout this crate always names a
+//! *remote* cluster tracked in [`crate::discovery::DiscoveryManager`], never the local one. Rather
+//! than invent a new identity field nothing else in the crate would read, [`VersionInfo`] reports
+//! [`VersionInfo::node_id`] (this node's actual stable identity) and [`VersionInfo::alpn`] (which
+//! mesh it's part of) as the fields that already mean something here.
+
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+
+/// This crate's version, from `Cargo.toml` at build time.
+const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The commit this binary was built from, when the build pipeline sets `GIT_SHA` (the way a
+/// `vergen`-style build script would). This crate doesn't carry a build script of its own, so
+/// locally built binaries report `None` here; CI is expected to set the environment variable
+/// before invoking `cargo build` if it wants this populated.
+fn git_sha() -> Option<&'static str> {
+    option_env!("GIT_SHA")
+}
+
+/// Identifies exactly which binary and identity a running proxy or agent is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionInfo {
+    /// This crate's version, e.g. `"0.93.2"`.
+    pub version: String,
+    /// The commit this binary was built from, if the build pipeline set `GIT_SHA`. `None` for
+    /// locally built binaries.
+    pub git_sha: Option<String>,
+    /// This node's iroh identity, stable for as long as [`crate::config::ProxyConfig::secret_key`]
+    /// (or [`crate::agent::AgentConfig::secret_key`]) points at the same persisted key. `None` if
+    /// this node hasn't bound an iroh endpoint at all -- an agent running with no
+    /// [`crate::agent::AgentConfig::discovery`] configured never does.
+    pub node_id: Option<EndpointId>,
+    /// The mesh ALPN this node accepts and dials connections with (see
+    /// [`crate::proxy::mesh_alpn`]), decoded as text since it's always ASCII.
+    pub alpn: String,
+}
+
+impl VersionInfo {
+    /// Builds the version info for a node identified by `node_id` (absent if it hasn't bound an
+    /// iroh endpoint), accepting connections on `alpn`.
+    pub fn new(node_id: Option<EndpointId>, alpn: &[u8]) -> Self {
+        Self {
+            version: CRATE_VERSION.to_string(),
+            git_sha: git_sha().map(ToString::to_string),
+            node_id,
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_non_empty_version_and_the_given_node_id_and_alpn() {
+        let node_id = iroh_base::SecretKey::generate(&mut rand::rng()).public();
+        let info = VersionInfo::new(Some(node_id), b"iroh-mesh/0");
+
+        assert!(!info.version.is_empty());
+        assert_eq!(info.node_id, Some(node_id));
+        assert_eq!(info.alpn, "iroh-mesh/0");
+    }
+
+    #[test]
+    fn reports_no_node_id_when_absent() {
+        let info = VersionInfo::new(None, b"iroh-mesh/0");
+        assert_eq!(info.node_id, None);
+    }
+}